@@ -0,0 +1,224 @@
+//! User accounts (REGISTER / IDENTIFY) behind a trait, like `auth.rs`, so
+//! a deployment can keep them somewhere else than the TOML file of
+//! `accounts.file`.
+
+use std::{collections::HashMap, fmt::Debug, fs, future::Future, path::PathBuf, pin::Pin};
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::{
+    crypto::{constant_time_eq, from_hex, pbkdf2_sha256, random_bytes, to_hex},
+    types::ChannelName,
+};
+
+// Stored as `pbkdf2-sha256$<iterations>$<salt>$<hash>`, the scheme name lets
+// a later format sit next to this one while records are migrated
+const PASSWORD_SCHEME: &str = "pbkdf2-sha256";
+const PASSWORD_ITERATIONS: u32 = if cfg!(test) { 1_000 } else { 600_000 };
+const PASSWORD_SALT_LEN: usize = 16;
+const PASSWORD_HASH_LEN: usize = 32;
+
+/// The stored form of `password`, with a random salt. None when the
+/// system has no random source to draw it from.
+fn hash_password(password: &str) -> Option<String> {
+    let salt = random_bytes(PASSWORD_SALT_LEN)
+        .map_err(|e| error!("No random salt for a password: {e}"))
+        .ok()?;
+    let hash = pbkdf2_sha256(
+        password.as_bytes(),
+        &salt,
+        PASSWORD_ITERATIONS,
+        PASSWORD_HASH_LEN,
+    );
+    Some(format!(
+        "{PASSWORD_SCHEME}${PASSWORD_ITERATIONS}${}${}",
+        to_hex(&salt),
+        to_hex(&hash)
+    ))
+}
+
+/// Whether `password` matches `stored`, false for a format it doesn't know.
+fn verify_password(stored: &str, password: &str) -> bool {
+    let mut fields = stored.split('$');
+    let (Some(PASSWORD_SCHEME), Some(iterations), Some(salt), Some(hash), None) = (
+        fields.next(),
+        fields.next().and_then(|n| n.parse::<u32>().ok()),
+        fields.next().and_then(from_hex),
+        fields.next().and_then(from_hex),
+        fields.next(),
+    ) else {
+        return false;
+    };
+    let computed = pbkdf2_sha256(password.as_bytes(), &salt, iterations, hash.len());
+    constant_time_eq(&computed, &hash)
+}
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Accounts and the channels they own. Account names are case-insensitive.
+pub trait AccountStore: Debug + Send + Sync {
+    /// Creates `account`, false when it already exists.
+    fn register<'a>(&'a self, account: &'a str, password: &'a str) -> StoreFuture<'a, bool>;
+    /// The account name as registered, None when the password doesn't match.
+    fn verify<'a>(&'a self, account: &'a str, password: &'a str)
+    -> StoreFuture<'a, Option<String>>;
+    /// The account owning `channel`, if any.
+    fn channel_owner<'a>(&'a self, channel: &'a ChannelName) -> StoreFuture<'a, Option<String>>;
+    /// Records `account` as the owner of `channel`, false when already owned.
+    fn claim_channel<'a>(
+        &'a self,
+        channel: &'a ChannelName,
+        account: &'a str,
+    ) -> StoreFuture<'a, bool>;
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct AccountRecord {
+    name: String,
+    // See `hash_password`, salt and parameters included
+    password_hash: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct AccountsData {
+    // By lowercased account name
+    #[serde(default)]
+    accounts: HashMap<String, AccountRecord>,
+    // Channel name -> owning account
+    #[serde(default)]
+    channels: HashMap<String, String>,
+}
+
+/// The default store: in memory, written back to `path` after every
+/// change when there is one.
+#[derive(Debug, Default)]
+pub struct LocalAccountStore {
+    path: Option<PathBuf>,
+    data: RwLock<AccountsData>,
+}
+
+impl LocalAccountStore {
+    /// Reads `path`, a missing file is an empty store. Without a path the
+    /// accounts are lost on restart.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let data = match &path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+                    error!("Failed to parse accounts file {}: {e}", path.display());
+                    AccountsData::default()
+                }),
+                Err(e) => {
+                    warn!("No accounts file {} yet: {e}", path.display());
+                    AccountsData::default()
+                }
+            },
+            None => AccountsData::default(),
+        };
+        LocalAccountStore {
+            path,
+            data: RwLock::new(data),
+        }
+    }
+
+    async fn save(&self, data: &AccountsData) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        match toml::to_string(data) {
+            Ok(text) => {
+                if let Err(e) = tokio::fs::write(path, text).await {
+                    error!("Failed to write accounts file {}: {e}", path.display());
+                }
+            }
+            Err(e) => error!("Failed to serialize accounts: {e}"),
+        }
+    }
+}
+
+impl AccountStore for LocalAccountStore {
+    fn register<'a>(&'a self, account: &'a str, password: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let key = account.to_lowercase();
+            if self.data.read().await.accounts.contains_key(&key) {
+                return false;
+            }
+            // Deliberately slow, kept off the runtime's worker threads
+            let password = password.to_owned();
+            let Ok(Some(password_hash)) =
+                tokio::task::spawn_blocking(move || hash_password(&password)).await
+            else {
+                return false;
+            };
+            let mut data = self.data.write().await;
+            if data.accounts.contains_key(&key) {
+                return false;
+            }
+            let record = AccountRecord {
+                name: account.to_owned(),
+                password_hash,
+            };
+            data.accounts.insert(key, record);
+            self.save(&data).await;
+            true
+        })
+    }
+
+    fn verify<'a>(
+        &'a self,
+        account: &'a str,
+        password: &'a str,
+    ) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let (name, stored) = {
+                let data = self.data.read().await;
+                let record = data.accounts.get(&account.to_lowercase())?;
+                (record.name.clone(), record.password_hash.clone())
+            };
+            let password = password.to_owned();
+            let matches = tokio::task::spawn_blocking(move || verify_password(&stored, &password))
+                .await
+                .unwrap_or(false);
+            matches.then_some(name)
+        })
+    }
+
+    fn channel_owner<'a>(&'a self, channel: &'a ChannelName) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move { self.data.read().await.channels.get(&channel.0).cloned() })
+    }
+
+    fn claim_channel<'a>(
+        &'a self,
+        channel: &'a ChannelName,
+        account: &'a str,
+    ) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let mut data = self.data.write().await;
+            if data.channels.contains_key(&channel.0) {
+                return false;
+            }
+            data.channels.insert(channel.0.clone(), account.to_owned());
+            self.save(&data).await;
+            true
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hashes_are_salted_and_versioned() {
+        let first = hash_password("s3cret").unwrap();
+        let second = hash_password("s3cret").unwrap();
+        assert!(first.starts_with("pbkdf2-sha256$1000$"), "{first}");
+        assert_ne!(first, second);
+        assert!(verify_password(&first, "s3cret"));
+        assert!(verify_password(&second, "s3cret"));
+        assert!(!verify_password(&first, "s3cret!"));
+        // The 64-bit hashes of before can't be checked, and aren't accepted
+        assert!(!verify_password("5f3a9c01e2b7d468", "s3cret"));
+    }
+}
@@ -0,0 +1,339 @@
+//! Read-only admin HTTP API
+//!
+//! A deliberately small HTTP/1.1 responder for dashboards and probes: one
+//! GET request per connection, answered with JSON and closed.
+//!
+//! ```text
+//! GET /health     {"status":"ok","users":<n>,"channels":<n>}
+//! GET /channels   [{"name":"#chan","members":<n>,"modes":"+nt"}, ...]
+//! GET /users      [{"nick":"alice","host":"1.2.3.4","channels":["#chan"]}, ...]
+//! GET /audit      [{"time":<unix>,"nick":"oper","command":"KILL","args":"bob :spam"}, ...]
+//! ```
+//!
+//! Every request must carry `Authorization: Bearer <admin.token>`.
+
+use std::{sync::Arc, time::Duration};
+
+use log::{error, info};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+
+use crate::{crypto::constant_time_eq, server_state::ServerState};
+
+// Request line and headers; nothing legitimate comes close
+const MAX_REQUEST_HEAD: usize = 8192;
+// Time a client gets to send its request head, so idle sockets can't pile up
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(if cfg!(test) { 1 } else { 10 });
+// Connections served at once, the others are answered 503 at once
+const MAX_CONNECTIONS: usize = 16;
+
+/// Serves the admin API on `listener` until the task is dropped.
+pub async fn run_admin_api(listener: TcpListener, server_state: ServerState, token: String) {
+    let slots = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Admin API failed to accept: {e:?}");
+                continue;
+            }
+        };
+        info!("Admin API request from {addr:?}");
+        let (server_state, token) = (server_state.clone(), token.clone());
+        let slot = slots.clone().try_acquire_owned();
+        tokio::spawn(async move {
+            let Ok(_slot) = slot else {
+                error!("Admin API busy, refusing {addr:?}");
+                let _ = respond(socket, "503 Service Unavailable", r#"{"error":"busy"}"#).await;
+                return;
+            };
+            if let Err(e) = handle_admin_connection(socket, &server_state, &token).await {
+                error!("Admin API connection {addr:?} failed: {e:?}");
+            }
+        });
+    }
+}
+
+async fn handle_admin_connection(
+    mut socket: TcpStream,
+    server_state: &ServerState,
+    token: &str,
+) -> std::io::Result<()> {
+    let Ok(head) = tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(&mut socket)).await
+    else {
+        info!("Admin API request timed out");
+        return Ok(());
+    };
+    let Some(head) = head? else {
+        return Ok(());
+    };
+    let head = String::from_utf8_lossy(&head);
+    let (status, body) = admin_response(&head, server_state, token).await;
+    respond(socket, status, &body).await
+}
+
+/// The request line and headers, None when the client closes or sends
+/// more than `MAX_REQUEST_HEAD` bytes before the blank line.
+async fn read_request_head(socket: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = socket.read(&mut chunk).await?;
+        if read == 0 || head.len() + read > MAX_REQUEST_HEAD {
+            return Ok(None);
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+    Ok(Some(head))
+}
+
+async fn respond(mut socket: TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+async fn admin_response(
+    head: &str,
+    server_state: &ServerState,
+    token: &str,
+) -> (&'static str, String) {
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (method, path) = (
+        request_line.next().unwrap_or_default(),
+        request_line.next().unwrap_or_default(),
+    );
+    let expected = format!("Bearer {token}");
+    let is_authorized = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.eq_ignore_ascii_case("Authorization")
+                && constant_time_eq(value.trim().as_bytes(), expected.as_bytes())
+        })
+    });
+
+    if !is_authorized {
+        return ("401 Unauthorized", r#"{"error":"unauthorized"}"#.to_owned());
+    }
+    if method != "GET" {
+        return (
+            "405 Method Not Allowed",
+            r#"{"error":"method not allowed"}"#.to_owned(),
+        );
+    }
+    match path {
+        "/health" => ("200 OK", health_json(server_state)),
+        "/channels" => ("200 OK", channels_json(server_state).await),
+        "/users" => ("200 OK", users_json(server_state).await),
+        "/audit" => ("200 OK", audit_json(server_state).await),
+        _ => ("404 Not Found", r#"{"error":"not found"}"#.to_owned()),
+    }
+}
+
+fn health_json(server_state: &ServerState) -> String {
+    format!(
+        r#"{{"status":"ok","users":{},"channels":{}}}"#,
+        server_state.users.len(),
+        server_state.channels.len()
+    )
+}
+
+async fn channels_json(server_state: &ServerState) -> String {
+    let channels = server_state
+        .channels
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect::<Vec<_>>();
+    let mut entries = Vec::new();
+    for channel in channels {
+        let modes = channel.modes.read().await.to_mode_string(false);
+        entries.push(format!(
+            r#"{{"name":{},"members":{},"modes":{}}}"#,
+            json_string(&channel.name.0),
+            channel.members.len(),
+            json_string(&modes)
+        ));
+    }
+    entries.sort();
+    format!("[{}]", entries.join(","))
+}
+
+async fn users_json(server_state: &ServerState) -> String {
+    let users = server_state
+        .users
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect::<Vec<_>>();
+    let mut entries = Vec::new();
+    for user_state in users {
+        let caracs = user_state.get_caracs().await;
+        let host = caracs.displayed_host();
+        let Some(nick) = caracs.nick.filter(|_| caracs.registered) else {
+            continue;
+        };
+        let mut channels = caracs
+            .member_of
+            .iter()
+            .map(|channel| json_string(&channel.0))
+            .collect::<Vec<_>>();
+        channels.sort();
+        entries.push(format!(
+            r#"{{"nick":{},"host":{},"channels":[{}]}}"#,
+            json_string(&nick.0),
+            json_string(&host),
+            channels.join(",")
+        ));
+    }
+    entries.sort();
+    format!("[{}]", entries.join(","))
+}
+
+// Oldest first, as recorded
+async fn audit_json(server_state: &ServerState) -> String {
+    let entries = server_state
+        .oper_audit
+        .read()
+        .await
+        .iter()
+        .map(|entry| {
+            format!(
+                r#"{{"time":{},"nick":{},"command":{},"args":{}}}"#,
+                entry.time,
+                json_string(&entry.nick.0),
+                json_string(&entry.command),
+                json_string(&entry.args)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", entries.join(","))
+}
+
+// JSON string literal; nicks and channel names are client-chosen
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{TestClient, has_numeric},
+        types::Nickname,
+    };
+
+    async fn get(addr: std::net::SocketAddr, path: &str, token: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {token}\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_channels_endpoint_lists_joined_channel() {
+        let server_state = ServerState::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run_admin_api(
+            listener,
+            server_state.clone(),
+            "s3cret".to_owned(),
+        ));
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #rust").await.unwrap();
+
+        let response = get(addr, "/channels", "s3cret").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r##"[{"name":"#rust","members":1,"modes":"+"}]"##));
+
+        let response = get(addr, "/users", "wrong").await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_kill_is_recorded_in_audit_endpoint() {
+        let server_state = ServerState::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run_admin_api(
+            listener,
+            server_state.clone(),
+            "s3cret".to_owned(),
+        ));
+        let mut oper = TestClient::registered(&server_state, "oper").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+
+        // Refused commands are not audited
+        carol.send(&server_state, "KILL bob :nope").await.unwrap();
+        assert!(has_numeric(&carol.drain(), "481"));
+
+        oper.user_state.user.write().await.modes.insert('o');
+        oper.send(&server_state, "KILL bob :flooding")
+            .await
+            .unwrap();
+        assert!(
+            bob.drain()
+                .contains(&"ERROR :Closing Link: bob (Killed (oper (flooding)))".to_owned())
+        );
+        assert!(server_state.nick.get(&Nickname("bob".to_owned())).is_none());
+
+        let response = get(addr, "/audit", "s3cret").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        assert!(body.starts_with(r#"[{"time":"#));
+        assert!(body.ends_with(r#","nick":"oper","command":"KILL","args":"bob :flooding"}]"#));
+    }
+
+    #[tokio::test]
+    async fn test_idle_connections_time_out_and_are_capped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run_admin_api(
+            listener,
+            ServerState::default(),
+            "s3cret".to_owned(),
+        ));
+
+        // Sockets that never finish their request take every slot
+        let mut idle = Vec::new();
+        for _ in 0..MAX_CONNECTIONS {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"GET /health HTTP/1.1\r\n").await.unwrap();
+            idle.push(stream);
+        }
+        let response = get(addr, "/health", "s3cret").await;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // They are closed once the request timeout passes
+        for mut stream in idle {
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            assert!(response.is_empty());
+        }
+        // Slots are released just after the sockets close
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let response = get(addr, "/health", "s3cret").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
@@ -0,0 +1,47 @@
+//! Credential checks behind a trait, so a deployment can swap the
+//! `[[opers]]` accounts of the config for its own backend (HTTP, SQLite...).
+
+use std::{fmt::Debug, future::Future, pin::Pin, sync::Arc};
+
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthResult {
+    Accepted,
+    Rejected,
+}
+
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = AuthResult> + Send + 'a>>;
+
+/// Checks a name and password pair, for OPER. Boxed futures rather than an
+/// `async fn` so the provider can live in `ServerState` as a trait object.
+pub trait AuthProvider: Debug + Send + Sync {
+    fn verify<'a>(&'a self, user: &'a str, password: &'a str) -> AuthFuture<'a>;
+}
+
+/// The default provider: the `[[opers]]` accounts, read on every check so
+/// a REHASH applies to the next OPER.
+#[derive(Debug)]
+pub struct ConfigAuthProvider {
+    config: Arc<RwLock<Config>>,
+}
+
+impl ConfigAuthProvider {
+    pub fn new(config: Arc<RwLock<Config>>) -> Self {
+        ConfigAuthProvider { config }
+    }
+}
+
+impl AuthProvider for ConfigAuthProvider {
+    fn verify<'a>(&'a self, user: &'a str, password: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            if self.config.read().await.is_valid_oper(user, password) {
+                AuthResult::Accepted
+            } else {
+                AuthResult::Rejected
+            }
+        })
+    }
+}
@@ -2,10 +2,13 @@ use std::sync::Arc;
 
 use clap::Parser;
 use flexi_logger::{Duplicate, Logger};
+use irc_server::admin::run_admin_api;
 use irc_server::config::Config;
 use irc_server::constants::SERVER_NAME;
 use irc_server::handlers::client::handle_client;
+use irc_server::heartbeat::run_heartbeat;
 use irc_server::server_state::ServerState;
+use irc_server::shutdown::shutdown;
 use log::info;
 use tokio::net::TcpListener;
 
@@ -23,7 +26,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let config = Config::load(&args.config).expect("Failed to load config");
     SERVER_NAME
-        .set(config.server.name)
+        .set(config.server.name.clone())
         .expect("Server name already set!");
     Logger::try_with_str("debug")
         .and_then(|op| // log level||
@@ -36,24 +39,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.network.bind_address, config.network.port
     ))
     .await?;
-    let server_state = Arc::new(ServerState::new());
+    let server_state = Arc::new(ServerState::new(config.clone()));
+
+    if let Some((admin_bind, admin_token)) = config.get_admin_api() {
+        let admin_listener = TcpListener::bind(admin_bind).await?;
+        info!("Admin API listening on {admin_bind}");
+        tokio::spawn(run_admin_api(
+            admin_listener,
+            (*server_state).clone(),
+            admin_token.to_owned(),
+        ));
+    }
+
+    if let Some(interval) = config.get_stats_interval() {
+        tokio::spawn(run_heartbeat((*server_state).clone(), interval));
+    }
 
     loop {
-        let (socket, addr) = listener.accept().await?;
+        let (socket, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = tokio::signal::ctrl_c() => {
+                info!("Interrupted");
+                break;
+            }
+            _ = server_state.shutdown_requested.notified() => break,
+        };
         info!("Client connected: {addr:?}");
-        let ip = addr.ip();
         let state = server_state.clone();
-        // 1. Pre-check: Increment and validate
-        {
-            let mut count = server_state.ip_counts.entry(ip).or_insert(0);
-            if *count >= config.limits.max_connections_per_ip {
-                eprintln!("Rejecting IP {}: too many connections", ip);
-                continue; // Drop the stream immediately
-            }
-            *count += 1;
-        }
+        // Per-IP limits are checked first thing in handle_client
         tokio::spawn(async move {
             handle_client(socket, addr, &state).await;
         });
     }
+    // Stop accepting before the drain
+    drop(listener);
+    shutdown(&server_state).await;
+    Ok(())
 }
@@ -1,17 +1,28 @@
+use std::collections::VecDeque;
+
 use dashmap::DashSet;
 use log::{error, info};
 use tokio::sync::{RwLock, broadcast};
 
 use crate::{
-    message_models::BroadcastIrcMessage,
-    types::{ChannelName, ClientId, Topic},
+    message_models::IrcMessage,
+    replies::IrcReply,
+    types::{ChannelName, ClientId, Nickname, Topic, Username},
+    utils::{unix_timestamp, unix_timestamp_millis, wildcard_match},
 };
 
+// Number of channel messages kept for CHATHISTORY playback
+pub const CHANNEL_HISTORY_SIZE: usize = 1000;
+
+// RFC 2811 4.2.1: the identity every member of a +a channel shows up as
+pub const ANONYMOUS_NAME: &str = "anonymous";
+pub const ANONYMOUS_HOST: &str = "anonymous.";
+
 /// Control message sent from Server Broker to a Client Writer Task
 pub enum SubscriptionControl {
     Subscribe {
         channel_name: ChannelName,
-        receiver: broadcast::Receiver<BroadcastIrcMessage>,
+        receiver: broadcast::Receiver<IrcMessage>,
     },
     Unsubscribe(ChannelName),
 }
@@ -24,20 +35,62 @@ pub enum ChannelType {
     Safe,     // '!'
 }
 
+impl ChannelType {
+    pub fn from_name(name: &ChannelName) -> Self {
+        match name.0.chars().next() {
+            Some('&') => ChannelType::Local,
+            Some('+') => ChannelType::Modeless,
+            Some('!') => ChannelType::Safe,
+            _ => ChannelType::Network,
+        }
+    }
+}
+
+/// A member's standing in a channel, the highest one when it has several.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Operator,
+    Voiced,
+    Member,
+}
+
+impl Role {
+    /// The NAMES, WHO and WHOIS prefix: `@`, `+` or nothing.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Role::Operator => "@",
+            Role::Voiced => "+",
+            Role::Member => "",
+        }
+    }
+}
+
+/// A channel message kept for playback, `time` in milliseconds
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub time: u64,
+    pub msgid: String,
+    pub line: String,
+}
+
 // Use Tokio's RwLock for async/await support
 #[derive(Debug)]
 pub struct IrcChannel {
     pub name: ChannelName,
     // Immutable
     pub kind: ChannelType,
+    pub created_at: u64,
     pub topic: RwLock<Option<Topic>>,
     pub topic_set_by: RwLock<Option<usize>>,
     pub topic_set_at: RwLock<Option<u64>>,
     pub members: DashSet<ClientId>,
     pub operators: DashSet<ClientId>,
     pub voiced: DashSet<ClientId>,
+    // Pending INVITEs, consumed by the invited user's next JOIN
+    pub invited: DashSet<ClientId>,
     pub modes: RwLock<ChannelModes>,
-    pub tx: broadcast::Sender<BroadcastIrcMessage>,
+    pub history: RwLock<VecDeque<HistoryEntry>>,
+    pub tx: broadcast::Sender<IrcMessage>,
 }
 
 impl IrcChannel {
@@ -45,24 +98,37 @@ impl IrcChannel {
         let tx = broadcast::channel(5000).0;
 
         IrcChannel {
+            kind: ChannelType::from_name(&name),
             name,
-            kind: ChannelType::Network,
+            created_at: unix_timestamp(),
             topic: RwLock::new(None),
             topic_set_by: RwLock::new(None),
             topic_set_at: RwLock::new(None),
             members: DashSet::new(),
             operators: DashSet::new(),
             voiced: DashSet::new(),
+            invited: DashSet::new(),
             modes: RwLock::new(ChannelModes::default()),
+            history: RwLock::new(VecDeque::with_capacity(CHANNEL_HISTORY_SIZE)),
             tx,
         }
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<BroadcastIrcMessage> {
+    pub fn subscribe(&self) -> broadcast::Receiver<IrcMessage> {
         self.tx.subscribe()
     }
 
-    pub fn broadcast_message(&self, message: BroadcastIrcMessage) {
+    pub fn broadcast_message(&self, mut message: IrcMessage) {
+        // A message relayed back into the channel it came from would be
+        // received and relayed again, forever
+        if message.origin.as_ref() == Some(&self.name) {
+            error!(
+                "Refusing to broadcast back into {}: {:?}",
+                self.name, message
+            );
+            return;
+        }
+        message.origin.get_or_insert_with(|| self.name.clone());
         // works perfectly with &self
         info!(
             "Broadcasting to {}: {} receivers",
@@ -75,6 +141,29 @@ impl IrcChannel {
         }
     }
 
+    /// Appends a delivered message to the ring buffer, dropping the oldest
+    /// one once CHANNEL_HISTORY_SIZE is reached.
+    pub async fn record_history(&self, msgid: String, line: String) {
+        let mut history = self.history.write().await;
+        if history.len() >= CHANNEL_HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(HistoryEntry {
+            time: unix_timestamp_millis(),
+            msgid,
+            line,
+        });
+    }
+
+    /// Drops a redacted message from the history. False when `msgid` isn't
+    /// one of the recent messages of this channel.
+    pub async fn redact_history(&self, msgid: &str) -> bool {
+        let mut history = self.history.write().await;
+        let before = history.len();
+        history.retain(|entry| entry.msgid != msgid);
+        history.len() != before
+    }
+
     pub fn add_member(&self, client_id: ClientId) -> bool {
         self.members.insert(client_id)
     }
@@ -87,14 +176,76 @@ impl IrcChannel {
         self.operators.insert(client_id)
     }
 
-    pub async fn is_banned(&self, client_id: ClientId) -> bool {
-        let modes = self.modes.read().await;
-        modes.ban_list.contains(&client_id)
+    pub fn is_operator(&self, client_id: ClientId) -> bool {
+        self.operators.contains(&client_id)
     }
 
-    pub async fn add_ban_user(&self, client_id: ClientId) -> bool {
-        let modes = self.modes.write().await;
-        modes.ban_list.insert(client_id)
+    /// The role of `client_id`, Member for anyone neither op nor voiced.
+    pub fn role(&self, client_id: ClientId) -> Role {
+        if self.is_operator(client_id) {
+            Role::Operator
+        } else if self.voiced.contains(&client_id) {
+            Role::Voiced
+        } else {
+            Role::Member
+        }
+    }
+
+    /// The ERR_CHANOPRIVSNEEDED (482) to send `nick` when `client_id` isn't
+    /// an operator of this channel.
+    pub fn require_operator<'a>(
+        &'a self,
+        client_id: ClientId,
+        nick: &'a Nickname,
+    ) -> Result<(), IrcReply<'a>> {
+        if self.is_operator(client_id) {
+            Ok(())
+        } else {
+            Err(IrcReply::ErrChanOPrivsNeeded {
+                nick,
+                channel: &self.name,
+            })
+        }
+    }
+
+    pub async fn is_anonymous(&self) -> bool {
+        self.modes.read().await.anonymous
+    }
+
+    /// The `nick!user@host` other members see for a member's JOIN, PART and
+    /// PRIVMSG lines: in an anonymous (+a) channel, anonymous!anonymous@anonymous.
+    pub async fn displayed_source(
+        &self,
+        nick: &Nickname,
+        user: &Username,
+        host: &str,
+    ) -> (Nickname, Username, String) {
+        if self.is_anonymous().await {
+            (
+                Nickname(ANONYMOUS_NAME.to_owned()),
+                Username(ANONYMOUS_NAME.to_owned()),
+                ANONYMOUS_HOST.to_owned(),
+            )
+        } else {
+            (nick.clone(), user.clone(), host.to_owned())
+        }
+    }
+
+    pub async fn is_banned(&self, hostmask: &str) -> bool {
+        self.modes.read().await.is_banned(hostmask)
+    }
+
+    /// Whether `hostmask` (nick!user@host) matches a +q mask. Operators and
+    /// voiced members always keep their voice.
+    pub async fn is_quieted(&self, client_id: ClientId, hostmask: &str) -> bool {
+        if self.is_operator(client_id) || self.voiced.contains(&client_id) {
+            return false;
+        }
+        let modes = self.modes.read().await;
+        modes
+            .quiet_list
+            .iter()
+            .any(|mask| wildcard_match(&mask, hostmask))
     }
 }
 
@@ -162,33 +313,234 @@ pub enum IrcChannelOperationStatus {
 
 #[derive(Debug, Clone)]
 pub struct ChannelModes {
-    pub invite_only: bool,                    // +i
-    pub moderated: bool,                      // +m
-    pub no_external_msgs: bool,               // +n
-    pub private: bool,                        // +p
-    pub secret: bool,                         // +s
-    pub topic_lock: bool,                     // +t
-    pub key: Option<String>,                  // +k <key>
-    pub user_limit: Option<usize>,            // +l <count>
-    pub ban_list: DashSet<ClientId>,          // +b
-    pub except_list: DashSet<ClientId>,       // +e
-    pub invite_exceptions: DashSet<ClientId>, // +I
+    pub anonymous: bool,                    // +a
+    pub no_colors: bool,                    // +c
+    pub invite_only: bool,                  // +i
+    pub moderated: bool,                    // +m
+    pub no_external_msgs: bool,             // +n
+    pub private: bool,                      // +p
+    pub secret: bool,                       // +s
+    pub topic_lock: bool,                   // +t
+    pub op_moderated: bool,                 // +z
+    pub key: Option<String>,                // +k <key>
+    pub user_limit: Option<usize>,          // +l <count>
+    pub forward: Option<ChannelName>,       // +f <channel>
+    pub ban_list: DashSet<String>,          // +b <mask>
+    pub except_list: DashSet<String>,       // +e <mask>
+    pub invite_exceptions: DashSet<String>, // +I <mask>
+    pub quiet_list: DashSet<String>,        // +q <mask>
 }
-//TODO invite exceptions
+
+impl ChannelModes {
+    /// Whether `hostmask` (nick!user@host) matches a +b mask and no +e
+    /// mask: an exception always wins over a ban.
+    pub fn is_banned(&self, hostmask: &str) -> bool {
+        let matches =
+            |masks: &DashSet<String>| masks.iter().any(|mask| wildcard_match(&mask, hostmask));
+        matches(&self.ban_list) && !matches(&self.except_list)
+    }
+
+    /// Whether `hostmask` matches a +I mask, letting it into a +i channel
+    /// without an INVITE.
+    pub fn is_invite_excepted(&self, hostmask: &str) -> bool {
+        self.invite_exceptions
+            .iter()
+            .any(|mask| wildcard_match(&mask, hostmask))
+    }
+
+    /// Renders the flags for RPL_CHANNELMODEIS, e.g. `+ntl 10`.
+    /// The key is only revealed to channel members.
+    pub fn to_mode_string(&self, show_key: bool) -> String {
+        let mut flags = String::from("+");
+        let mut params = Vec::new();
+        for (is_set, flag) in [
+            (self.anonymous, 'a'),
+            (self.no_colors, 'c'),
+            (self.invite_only, 'i'),
+            (self.moderated, 'm'),
+            (self.no_external_msgs, 'n'),
+            (self.private, 'p'),
+            (self.secret, 's'),
+            (self.topic_lock, 't'),
+            (self.op_moderated, 'z'),
+        ] {
+            if is_set {
+                flags.push(flag);
+            }
+        }
+        if let Some(key) = &self.key {
+            flags.push('k');
+            params.push(if show_key {
+                key.clone()
+            } else {
+                "*".to_owned()
+            });
+        }
+        if let Some(limit) = self.user_limit {
+            flags.push('l');
+            params.push(limit.to_string());
+        }
+        if let Some(forward) = &self.forward {
+            flags.push('f');
+            params.push(forward.to_string());
+        }
+        params.insert(0, flags);
+        params.join(" ")
+    }
+}
+
 impl Default for ChannelModes {
     fn default() -> Self {
         Self {
+            anonymous: false,
+            no_colors: false,
             invite_only: false,
             moderated: false,
             no_external_msgs: false,
             private: false,
             secret: false,
             topic_lock: false,
+            op_moderated: false,
             key: None,
             user_limit: None,
+            forward: None,
             ban_list: DashSet::new(),
             except_list: DashSet::new(),
             invite_exceptions: DashSet::new(),
+            quiet_list: DashSet::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IrcChannel, IrcChannelOperationStatus};
+    use crate::message_models::IrcMessage;
+    use crate::{
+        server_state::ServerState,
+        types::{ChannelName, ClientId, Nickname},
+    };
+
+    #[tokio::test]
+    async fn test_creator_is_operator_member_is_not() {
+        let server_state = ServerState::default();
+        let channel_name = ChannelName("#ops".to_owned());
+        let (creator, member) = (ClientId(1), ClientId(2));
+        for client_id in [creator, member] {
+            server_state
+                .handle_join(channel_name.clone(), client_id, "", None, false, true)
+                .await
+                .unwrap();
         }
+        let channel = server_state.get_channel(&channel_name).unwrap();
+
+        assert!(channel.is_operator(creator));
+        assert!(!channel.is_operator(member));
+        let nick = Nickname("bob".to_owned());
+        assert!(channel.require_operator(creator, &nick).is_ok());
+        let err = channel.require_operator(member, &nick).unwrap_err();
+        assert!(err.format().contains(" 482 bob #ops "));
+    }
+
+    #[tokio::test]
+    async fn test_message_never_rebroadcast_into_its_origin() {
+        let channel = IrcChannel::new(ChannelName("#loop".to_owned()));
+        let mut rx = channel.subscribe();
+
+        channel.broadcast_message(IrcMessage::new("hello".to_owned()));
+        let received = rx.try_recv().unwrap();
+        assert_eq!(received.origin, Some(ChannelName("#loop".to_owned())));
+
+        // A relay handing it back to its source channel is a no-op
+        channel.broadcast_message(received.clone());
+        assert!(rx.try_recv().is_err());
+        let other = IrcChannel::new(ChannelName("#other".to_owned()));
+        let mut other_rx = other.subscribe();
+        other.broadcast_message(received);
+        assert!(other_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_joins_respect_user_limit() {
+        let server_state = ServerState::default();
+        let channel_name = ChannelName("#small".to_owned());
+        server_state
+            .handle_join(channel_name.clone(), ClientId(0), "", None, false, true)
+            .await
+            .unwrap();
+        let channel = server_state.get_channel(&channel_name).unwrap();
+        channel.modes.write().await.user_limit = Some(5);
+
+        let joins: Vec<_> = (1..=50)
+            .map(|id| {
+                let server_state = server_state.clone();
+                let channel_name = channel_name.clone();
+                tokio::spawn(async move {
+                    server_state
+                        .handle_join(channel_name, ClientId(id), "", None, false, false)
+                        .await
+                })
+            })
+            .collect();
+        let mut joined = 0;
+        for join in joins {
+            if let Ok((IrcChannelOperationStatus::NewJoin, _)) = join.await.unwrap() {
+                joined += 1;
+            }
+        }
+
+        // The creator already holds one of the 5 seats
+        assert_eq!(joined, 4);
+        assert_eq!(channel.members.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_except_mask_overrides_ban_mask() {
+        let server_state = ServerState::default();
+        let channel_name = ChannelName("#guarded".to_owned());
+        server_state
+            .handle_join(
+                channel_name.clone(),
+                ClientId(0),
+                "op!o@good.org",
+                None,
+                false,
+                true,
+            )
+            .await
+            .unwrap();
+        let channel = server_state.get_channel(&channel_name).unwrap();
+        {
+            let modes = channel.modes.write().await;
+            modes.ban_list.insert("*!*@evil.net".to_owned());
+            modes.except_list.insert("good!*@evil.net".to_owned());
+        }
+
+        let (status, _) = server_state
+            .handle_join(
+                channel_name.clone(),
+                ClientId(1),
+                "bad!b@evil.net",
+                None,
+                false,
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(status, IrcChannelOperationStatus::BannedFromChan));
+        let (status, _) = server_state
+            .handle_join(
+                channel_name,
+                ClientId(2),
+                "good!g@evil.net",
+                None,
+                false,
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(status, IrcChannelOperationStatus::NewJoin));
+        assert!(channel.is_banned("bad!b@evil.net").await);
+        assert!(!channel.is_banned("good!g@evil.net").await);
     }
 }
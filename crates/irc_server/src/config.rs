@@ -1,12 +1,31 @@
+use log::warn;
+
+use crate::crypto::constant_time_eq;
+use crate::ops::parsers::NICKNAME_MAX_LENGTH;
+use crate::utils::{cloak_ip, ip_in_range};
 use serde::Deserialize;
 use std::fs;
-use std::path::Path;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub server: ServerConfig,
     pub network: NetworkConfig,
     pub limits: LimitsConfig,
+    // Optional section, older config files don't have it
+    pub channels: Option<ChannelsConfig>,
+    pub features: Option<FeaturesConfig>,
+    pub admin: Option<AdminConfig>,
+    pub capabilities: Option<CapabilitiesConfig>,
+    pub accounts: Option<AccountsConfig>,
+    pub security: Option<SecurityConfig>,
+    // OPER <name> <password> accounts
+    pub opers: Option<Vec<OperConfig>>,
+    // Where the config was loaded from, for REHASH
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -14,6 +33,16 @@ pub struct ServerConfig {
     pub name: String,
     pub version: String,
     pub motd: String,
+    // Takes precedence over `motd` when set
+    pub motd_file: Option<String>,
+    // NOTICE AUTH lines sent as soon as a client connects
+    pub connect_notices: Option<Vec<String>>,
+    // Seconds between two heartbeat log lines, see `heartbeat.rs`
+    pub stats_interval: Option<u64>,
+    // Seconds DIE or a signal waits for clients to be flushed before exiting
+    pub shutdown_drain_secs: Option<u64>,
+    // Lines that aren't valid UTF-8 are refused, advertised as UTF8ONLY
+    pub utf8only: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -21,6 +50,8 @@ pub struct NetworkConfig {
     pub bind_address: String,
     pub port: u16,
     pub max_connections: usize,
+    // RFC 1413 ident lookup on connect
+    pub ident_lookup: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -36,18 +67,512 @@ pub struct LimitsConfig {
     // We use Option so the parser doesn't fail if they are missing.
     pub max_channel_name_length: Option<usize>,
     pub max_topic_length: Option<usize>,
+
+    // USER realnames are cut to `max_realname` characters, and refused with
+    // control characters (colors included) when `realname_no_controls` is set
+    pub max_realname: Option<usize>,
+    pub realname_no_controls: Option<bool>,
+
+    // Caps on comma-separated lists (PRIVMSG targets, JOIN channels)
+    pub max_targets: Option<usize>,
+    pub max_join_list: Option<usize>,
+
+    // Cap on the number of messages a single CHATHISTORY request returns
+    pub max_chathistory: Option<usize>,
+
+    // Channels one LIST shows at most, past it ELIST filters are needed
+    pub list_max: Option<usize>,
+
+    // Nick masks nobody may take, e.g. services names
+    pub forbidden_nicks: Option<Vec<String>>,
+
+    // NICKLEN, lowering it on REHASH leaves existing nicks alone
+    pub max_nick_length: Option<usize>,
+
+    // NICK changes a registered user may make per minute
+    pub nick_changes_per_min: Option<usize>,
+
+    // Bytes waiting to be written to one client before it is dropped
+    pub sendq_bytes: Option<usize>,
+
+    // Seconds before a sender is told again that the same target is away
+    pub away_reply_interval: Option<u64>,
+
+    // Connections one IP may open per sliding window, however short-lived
+    pub ip_connect_window: Option<u64>,
+    pub ip_connects_per_window: Option<usize>,
+
+    // Seconds a quitting user's nick stays unavailable to others
+    pub resource_hold_secs: Option<u64>,
+
+    // Seconds a logged in user's lost connection stays in its channels,
+    // taken back by a reconnect giving the account password with PASS
+    pub reconnect_grace_secs: Option<u64>,
+
+    // Commands token bucket: `flood_burst` at once, refilled at
+    // `flood_per_sec`; past it the link closes with "Excess Flood"
+    pub flood_burst: Option<usize>,
+    pub flood_per_sec: Option<u64>,
+    // nick!user@host masks never throttled, operators never are either
+    pub flood_exempt_masks: Option<Vec<String>>,
+    // Whether a client falling behind a channel is told how many lines it missed
+    pub lag_notice: Option<bool>,
+    // Times a client may fall behind one channel before it is disconnected, 0 never
+    pub max_lag_events: Option<u32>,
+    // Seconds without data from a client before it is sent a PING, then
+    // seconds more before it is disconnected with "Ping timeout"
+    pub ping_frequency: Option<u64>,
+    pub ping_timeout: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChannelsConfig {
+    pub oper_only_create: Option<bool>,
+    // Channel prefixes that may be joined, advertised as CHANTYPES
+    pub chantypes: Option<String>,
+    // Channels every user is joined to after the welcome burst
+    pub auto_join: Option<Vec<String>>,
+}
+
+// RFC 2812 4.x optional commands, disabled unless switched on
+#[derive(Debug, Deserialize, Clone)]
+pub struct FeaturesConfig {
+    pub users: Option<bool>,
+    pub summon: Option<bool>,
+}
+
+// IRCv3 capabilities advertised by CAP LS, picked up again on REHASH.
+// sasl, echo-message and multi-prefix aren't implemented, so they have no
+// switch and are never advertised.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CapabilitiesConfig {
+    pub batch: Option<bool>,
+    pub server_time: Option<bool>,
+    pub chathistory: Option<bool>,
+    pub message_tags: Option<bool>,
+    pub message_redaction: Option<bool>,
+    pub away_notify: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SecurityConfig {
+    // CIDR blocks whose connections start with user mode +r
+    pub restrict_ranges: Option<Vec<String>>,
+    // Secret mixed into cloaked hosts, hosts are only cloaked when it is set
+    pub cloak_key: Option<String>,
+    // Registrations with one realname, or identical messages, from one IP
+    // within `bot_window_secs` that flag a suspected bot; off unless set
+    pub bot_threshold: Option<usize>,
+    pub bot_window_secs: Option<u64>,
+    // Disconnect suspected bots instead of only counting and logging them
+    pub autokill_bots: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OperConfig {
+    pub name: String,
+    pub password: String,
+}
+
+// REGISTER / IDENTIFY, see `accounts.rs`
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccountsConfig {
+    pub enabled: Option<bool>,
+    // Where accounts and channel owners are kept, in memory only when unset
+    pub file: Option<String>,
+}
+
+// Read-only HTTP status API, see `admin.rs`
+#[derive(Debug, Deserialize, Clone)]
+pub struct AdminConfig {
+    pub enabled: Option<bool>,
+    pub bind: Option<String>,
+    pub token: Option<String>,
 }
 
 impl Config {
     /// Loads and parses the TOML configuration file
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        let content = fs::read_to_string(&path)?;
+        let mut config: Config = toml::from_str(&content)?;
+        config.path = Some(path.as_ref().to_path_buf());
         Ok(config)
     }
 
+    /// The MOTD lines: from `server.motd_file` when set, else the inline
+    /// `server.motd`. `None` (ERR_NOMOTD) when the file can't be read.
+    pub fn load_motd(&self) -> Option<Vec<String>> {
+        let Some(motd_file) = &self.server.motd_file else {
+            return Some(self.server.motd.lines().map(str::to_owned).collect());
+        };
+        match fs::read_to_string(motd_file) {
+            Ok(motd) => Some(motd.lines().map(str::to_owned).collect()),
+            Err(e) => {
+                warn!("Failed to read MOTD file {motd_file}: {e}");
+                None
+            }
+        }
+    }
+
     /// Helper to get channel name length with a hard fallback to RFC 2812 standard (200)
     pub fn get_max_channel_name_length(&self) -> usize {
         self.limits.max_channel_name_length.unwrap_or(200)
     }
+
+    /// Helper to get the maximum topic length (TOPICLEN), falling back to 390
+    pub fn get_max_topic_length(&self) -> usize {
+        self.limits.max_topic_length.unwrap_or(390)
+    }
+
+    /// Helper to get the maximum realname length in characters, falling back to 128
+    pub fn get_max_realname(&self) -> usize {
+        self.limits.max_realname.unwrap_or(128)
+    }
+
+    /// Helper to know whether realnames with control characters are refused, off by default
+    pub fn get_realname_no_controls(&self) -> bool {
+        self.limits.realname_no_controls.unwrap_or(false)
+    }
+
+    /// Helper to get the maximum number of PRIVMSG targets, falling back to 4
+    pub fn get_max_targets(&self) -> usize {
+        self.limits.max_targets.unwrap_or(4)
+    }
+
+    /// Helper to get the maximum number of channels in a single JOIN, falling back to 10
+    pub fn get_max_join_list(&self) -> usize {
+        self.limits.max_join_list.unwrap_or(10)
+    }
+
+    /// Helper to get the maximum number of CHATHISTORY messages, falling back to 100
+    pub fn get_max_chathistory(&self) -> usize {
+        self.limits.max_chathistory.unwrap_or(100)
+    }
+
+    /// Helper to get the maximum number of channels in a LIST reply, falling back to 1000
+    pub fn get_list_max(&self) -> usize {
+        self.limits.list_max.unwrap_or(1000)
+    }
+
+    /// Helper to get the notices sent on connection, none by default
+    pub fn get_connect_notices(&self) -> &[String] {
+        self.server.connect_notices.as_deref().unwrap_or(&[])
+    }
+
+    /// Helper to get the seconds between heartbeat log lines, none (no heartbeat) by default
+    pub fn get_stats_interval(&self) -> Option<Duration> {
+        self.server
+            .stats_interval
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Helper to get how long shutdown waits for writers to flush, falling back to 5 seconds
+    pub fn get_shutdown_drain(&self) -> Duration {
+        Duration::from_secs(self.server.shutdown_drain_secs.unwrap_or(5))
+    }
+
+    /// Helper to know whether lines must be valid UTF-8, off by default
+    pub fn get_utf8only(&self) -> bool {
+        self.server.utf8only.unwrap_or(false)
+    }
+
+    /// Helper to get the forbidden nick masks, none by default
+    pub fn get_forbidden_nicks(&self) -> &[String] {
+        self.limits.forbidden_nicks.as_deref().unwrap_or(&[])
+    }
+
+    /// Helper to get the maximum nick length (NICKLEN), falling back to the RFC's 9.
+    /// Never above what the nickname parser accepts
+    pub fn get_max_nick_length(&self) -> usize {
+        self.limits
+            .max_nick_length
+            .unwrap_or(9)
+            .min(NICKNAME_MAX_LENGTH)
+    }
+
+    /// Helper to get the number of nick changes allowed per minute, falling back to 5
+    pub fn get_nick_changes_per_min(&self) -> usize {
+        self.limits.nick_changes_per_min.unwrap_or(5)
+    }
+
+    /// Helper to get the per-connection SENDQ in bytes, falling back to 256 KiB
+    pub fn get_sendq_bytes(&self) -> usize {
+        self.limits.sendq_bytes.unwrap_or(262_144)
+    }
+
+    /// Helper to get the seconds between two RPL_AWAY for one target, falling back to 60
+    pub fn get_away_reply_interval(&self) -> u64 {
+        self.limits.away_reply_interval.unwrap_or(60)
+    }
+
+    /// Helper to get the length in seconds of the per-IP connection window, falling back to 60
+    pub fn get_ip_connect_window(&self) -> u64 {
+        self.limits.ip_connect_window.unwrap_or(60)
+    }
+
+    /// Helper to get the connections one IP may open per window, falling back to 10
+    pub fn get_ip_connects_per_window(&self) -> usize {
+        self.limits.ip_connects_per_window.unwrap_or(10)
+    }
+
+    /// Helper to get how long a quit user's nick is held, falling back to 30 seconds
+    pub fn get_resource_hold_secs(&self) -> u64 {
+        self.limits.resource_hold_secs.unwrap_or(30)
+    }
+
+    /// Helper to get how long a lost connection's channels are kept, falling back to 0 (off)
+    pub fn get_reconnect_grace_secs(&self) -> u64 {
+        self.limits.reconnect_grace_secs.unwrap_or(0)
+    }
+
+    /// Helper to get the flood token bucket (burst, refill per second), none
+    /// by default; the refill falls back to 1 when only the burst is set
+    pub fn get_flood_limit(&self) -> Option<(usize, u64)> {
+        let burst = self.limits.flood_burst?;
+        Some((burst, self.limits.flood_per_sec.unwrap_or(1)))
+    }
+
+    /// Helper to get the nick!user@host masks exempt from the flood limit, none by default
+    pub fn get_flood_exempt_masks(&self) -> &[String] {
+        self.limits.flood_exempt_masks.as_deref().unwrap_or(&[])
+    }
+
+    /// Helper to know whether a lagging client gets a NOTICE about the lines it missed, on by default
+    pub fn get_lag_notice(&self) -> bool {
+        self.limits.lag_notice.unwrap_or(true)
+    }
+
+    /// Helper to get how often a client may lag behind one channel before
+    /// it is disconnected, falling back to 3 (0 never disconnects)
+    pub fn get_max_lag_events(&self) -> u32 {
+        self.limits.max_lag_events.unwrap_or(3)
+    }
+
+    /// Helper to get how long a silent client waits for a PING, falling back to 120 seconds
+    pub fn get_ping_frequency(&self) -> Duration {
+        Duration::from_secs(self.limits.ping_frequency.unwrap_or(120))
+    }
+
+    /// Helper to get how long a PING waits for an answer, falling back to 60 seconds
+    pub fn get_ping_timeout(&self) -> Duration {
+        Duration::from_secs(self.limits.ping_timeout.unwrap_or(60))
+    }
+
+    /// Helper to know whether clients are looked up with ident on connect, off by default
+    pub fn get_ident_lookup(&self) -> bool {
+        self.network.ident_lookup.unwrap_or(false)
+    }
+
+    /// Helper to know whether the USERS command is enabled, off by default
+    pub fn get_users_enabled(&self) -> bool {
+        self.features
+            .as_ref()
+            .and_then(|features| features.users)
+            .unwrap_or(false)
+    }
+
+    /// Helper to know whether the SUMMON command is enabled, off by default
+    pub fn get_summon_enabled(&self) -> bool {
+        self.features
+            .as_ref()
+            .and_then(|features| features.summon)
+            .unwrap_or(false)
+    }
+
+    /// Helper to get the admin API address and bearer token, only when it is
+    /// enabled and both are set: the API is never served without a token
+    pub fn get_admin_api(&self) -> Option<(&str, &str)> {
+        let admin = self
+            .admin
+            .as_ref()
+            .filter(|admin| admin.enabled == Some(true))?;
+        Some((admin.bind.as_deref()?, admin.token.as_deref()?))
+    }
+
+    /// Helper to know whether REGISTER and IDENTIFY are available, off by default
+    pub fn get_accounts_enabled(&self) -> bool {
+        self.accounts
+            .as_ref()
+            .and_then(|accounts| accounts.enabled)
+            .unwrap_or(false)
+    }
+
+    /// Helper to get the accounts file, none (accounts kept in memory) by default
+    pub fn get_accounts_file(&self) -> Option<&str> {
+        self.accounts
+            .as_ref()
+            .and_then(|accounts| accounts.file.as_deref())
+    }
+
+    /// Helper to know whether connections from `ip` start restricted (+r), none by default
+    pub fn is_restricted_ip(&self, ip: IpAddr) -> bool {
+        self.security
+            .as_ref()
+            .and_then(|security| security.restrict_ranges.as_deref())
+            .unwrap_or(&[])
+            .iter()
+            .any(|range| ip_in_range(ip, range))
+    }
+
+    /// Helper to get the cloaked host shown for `ip`, none by default: the
+    /// bare IP is shown unless `security.cloak_key` is set
+    pub fn get_cloaked_host(&self, ip: IpAddr) -> Option<String> {
+        let key = self
+            .security
+            .as_ref()
+            .and_then(|security| security.cloak_key.as_deref())?;
+        Some(cloak_ip(ip, key))
+    }
+
+    /// Helper to get the bot heuristics threshold and window in seconds,
+    /// none by default; the window falls back to 60 when only the threshold is set
+    pub fn get_bot_detection(&self) -> Option<(usize, u64)> {
+        let security = self.security.as_ref()?;
+        Some((
+            security.bot_threshold?,
+            security.bot_window_secs.unwrap_or(60),
+        ))
+    }
+
+    /// Helper to know whether suspected bots are disconnected, off by default
+    pub fn get_autokill_bots(&self) -> bool {
+        self.security
+            .as_ref()
+            .and_then(|security| security.autokill_bots)
+            .unwrap_or(false)
+    }
+
+    /// Helper to check OPER credentials, no operator accounts by default
+    pub fn is_valid_oper(&self, name: &str, password: &str) -> bool {
+        self.opers.as_deref().unwrap_or(&[]).iter().any(|oper| {
+            oper.name == name && constant_time_eq(oper.password.as_bytes(), password.as_bytes())
+        })
+    }
+
+    /// Helper to know whether only operators may create channels, off by default
+    pub fn get_oper_only_create(&self) -> bool {
+        self.channels
+            .as_ref()
+            .and_then(|channels| channels.oper_only_create)
+            .unwrap_or(false)
+    }
+
+    /// Helper to get the channels joined on registration, none by default
+    pub fn get_auto_join(&self) -> &[String] {
+        self.channels
+            .as_ref()
+            .and_then(|channels| channels.auto_join.as_deref())
+            .unwrap_or(&[])
+    }
+
+    /// Helper to get the channel prefixes users may join, falling back to all of "#&+!"
+    pub fn get_chantypes(&self) -> &str {
+        self.channels
+            .as_ref()
+            .and_then(|channels| channels.chantypes.as_deref())
+            .unwrap_or("#&+!")
+    }
+
+    /// Helper to get the enabled capability names in CAP LS order.
+    /// draft/message-redaction and away-notify are off by default, the
+    /// others on. cap-notify is always advertised
+    pub fn get_capabilities(&self) -> Vec<&'static str> {
+        let capabilities = self.capabilities.as_ref();
+        [
+            (capabilities.and_then(|c| c.batch), true, "batch"),
+            (
+                capabilities.and_then(|c| c.server_time),
+                true,
+                "server-time",
+            ),
+            (
+                capabilities.and_then(|c| c.chathistory),
+                true,
+                "draft/chathistory",
+            ),
+            (
+                capabilities.and_then(|c| c.message_tags),
+                true,
+                "message-tags",
+            ),
+            (
+                capabilities.and_then(|c| c.message_redaction),
+                false,
+                "draft/message-redaction",
+            ),
+            (
+                capabilities.and_then(|c| c.away_notify),
+                false,
+                "away-notify",
+            ),
+        ]
+        .into_iter()
+        .filter(|(flag, default, _)| flag.unwrap_or(*default))
+        .map(|(_, _, name)| name)
+        .chain(["cap-notify"])
+        .collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            server: ServerConfig {
+                name: "irc.rust-server.io".to_owned(),
+                version: "0.1.0".to_owned(),
+                motd: "Welcome to a basic Rust IRC server!".to_owned(),
+                motd_file: None,
+                connect_notices: None,
+                stats_interval: None,
+                shutdown_drain_secs: None,
+                utf8only: None,
+            },
+            network: NetworkConfig {
+                bind_address: "127.0.0.1".to_owned(),
+                port: 6667,
+                max_connections: 10000,
+                ident_lookup: None,
+            },
+            limits: LimitsConfig {
+                max_channels_per_user: 10,
+                max_message_length: 512,
+                max_connections_per_ip: 5,
+                unregistered_timeout: 20,
+                max_channel_name_length: None,
+                max_topic_length: None,
+                max_realname: None,
+                realname_no_controls: None,
+                max_targets: None,
+                max_join_list: None,
+                max_chathistory: None,
+                list_max: None,
+                forbidden_nicks: None,
+                max_nick_length: None,
+                nick_changes_per_min: None,
+                sendq_bytes: None,
+                away_reply_interval: None,
+                ip_connect_window: None,
+                ip_connects_per_window: None,
+                resource_hold_secs: None,
+                reconnect_grace_secs: None,
+                flood_burst: None,
+                flood_per_sec: None,
+                flood_exempt_masks: None,
+                lag_notice: None,
+                max_lag_events: None,
+                ping_frequency: None,
+                ping_timeout: None,
+            },
+            channels: None,
+            features: None,
+            admin: None,
+            capabilities: None,
+            accounts: None,
+            security: None,
+            opers: None,
+            path: None,
+        }
+    }
 }
@@ -9,9 +9,172 @@ pub static SERVER_NAME: OnceLock<String> = OnceLock::new();
 pub const RPL_WELCOME_NB: u16 = 1;
 pub const RPL_WELCOME_STR: &str = "Welcome to the Internet Relay Network";
 
+// 002    RPL_YOURHOST
+//               "Your host is <servername>, running version <ver>"
+pub const RPL_YOURHOST_NB: u16 = 2;
+pub const RPL_YOURHOST_STR: &str = "Your host is";
+
+// 003    RPL_CREATED
+//               "This server was created <date>"
+pub const RPL_CREATED_NB: u16 = 3;
+pub const RPL_CREATED_STR: &str = "This server was created";
+
+// 004    RPL_MYINFO
+//               "<servername> <version> <available user modes>
+//                <available channel modes>"
+pub const RPL_MYINFO_NB: u16 = 4;
+
+// 005    RPL_ISUPPORT
+//        "<nick> <token>[=<value>] *( " " <token>[=<value>] ) :are supported by this server"
+//   - Advertises the features and limits of this server (de facto standard,
+//     see draft-brocklesby-irc-isupport).
+pub const RPL_ISUPPORT_NB: u16 = 5;
+pub const RPL_ISUPPORT_STR: &str = "are supported by this server";
+
+// 203    RPL_TRACEUNKNOWN
+//        "???? <class> [<client IP address in dot form>]"
+pub const RPL_TRACEUNKNOWN_NB: u16 = 203;
+
+// 204    RPL_TRACEOPERATOR
+//        "Oper <class> <nick>"
+pub const RPL_TRACEOPERATOR_NB: u16 = 204;
+
+// 205    RPL_TRACEUSER
+//        "User <class> <nick>"
+//   - The nick is sent as nick[user@host] for connection diagnostics.
+pub const RPL_TRACEUSER_NB: u16 = 205;
+
+// 211    RPL_STATSLINKINFO
+//        "<linkname> <sendq> <sent messages>
+//         <sent Kbytes> <received messages>
+//         <received Kbytes> <time open>"
+pub const RPL_STATSLINKINFO_NB: u16 = 211;
+
+// 212    RPL_STATSCOMMANDS
+//        "<command> <count> <byte count> <remote count>"
+pub const RPL_STATSCOMMANDS_NB: u16 = 212;
+
+// 219    RPL_ENDOFSTATS
+//        "<stats letter> :End of STATS report"
+pub const RPL_ENDOFSTATS_NB: u16 = 219;
+pub const RPL_ENDOFSTATS_STR: &str = "End of STATS report";
+
 // for Query User MODE
 pub const RPL_UMODEIS_NB: u16 = 221;
 
+// 251    RPL_LUSERCLIENT
+//        ":There are <integer> users and <integer>
+//         services on <integer> servers"
+//   - Sent in the RFC 1459 form, "<integer> users and <integer> invisible",
+//     so invisible users are counted separately.
+pub const RPL_LUSERCLIENT_NB: u16 = 251;
+
+// 252    RPL_LUSEROP
+//        "<integer> :operator(s) online"
+pub const RPL_LUSEROP_NB: u16 = 252;
+pub const RPL_LUSEROP_STR: &str = "operator(s) online";
+
+// 253    RPL_LUSERUNKNOWN
+//        "<integer> :unknown connection(s)"
+pub const RPL_LUSERUNKNOWN_NB: u16 = 253;
+pub const RPL_LUSERUNKNOWN_STR: &str = "unknown connection(s)";
+
+// 254    RPL_LUSERCHANNELS
+//        "<integer> :channels formed"
+pub const RPL_LUSERCHANNELS_NB: u16 = 254;
+pub const RPL_LUSERCHANNELS_STR: &str = "channels formed";
+
+// 255    RPL_LUSERME
+//        ":I have <integer> clients and <integer> servers"
+pub const RPL_LUSERME_NB: u16 = 255;
+
+// 262    RPL_TRACEEND
+//        "<server name> <version & debug level> :End of TRACE"
+//   - The RPL_TRACE* are all returned by the server in
+//     response to the TRACE message.
+pub const RPL_TRACEEND_NB: u16 = 262;
+pub const RPL_TRACEEND_STR: &str = "End of TRACE";
+
+// 265    RPL_LOCALUSERS
+//        "<u> <m> :Current local users <u>, max <m>"
+//   - Not in RFC 2812, sent by most servers after RPL_LUSERME.
+pub const RPL_LOCALUSERS_NB: u16 = 265;
+
+// 266    RPL_GLOBALUSERS
+//        "<u> <m> :Current global users <u>, max <m>"
+//   - Without server links the global counts are the local ones.
+pub const RPL_GLOBALUSERS_NB: u16 = 266;
+
+// 301    RPL_AWAY
+//        "<nick> :<away message>"
+pub const RPL_AWAY_NB: u16 = 301;
+
+// 305    RPL_UNAWAY
+//        ":You are no longer marked as being away"
+pub const RPL_UNAWAY_NB: u16 = 305;
+pub const RPL_UNAWAY_STR: &str = "You are no longer marked as being away";
+
+// 306    RPL_NOWAWAY
+//        ":You have been marked as being away"
+//   - These replies are used with the AWAY command (if
+//     allowed).  RPL_AWAY is sent to any client sending a
+//     PRIVMSG to a client which is away.  RPL_AWAY is only
+//     sent by the server to which the client is connected.
+//     Replies RPL_UNAWAY and RPL_NOWAWAY are sent when the
+//     client removes and sets an AWAY message.
+pub const RPL_NOWAWAY_NB: u16 = 306;
+pub const RPL_NOWAWAY_STR: &str = "You have been marked as being away";
+
+// 311    RPL_WHOISUSER
+//        "<nick> <user> <host> * :<real name>"
+pub const RPL_WHOISUSER_NB: u16 = 311;
+
+// 312    RPL_WHOISSERVER
+//        "<nick> <server> :<server info>"
+pub const RPL_WHOISSERVER_NB: u16 = 312;
+pub const SERVER_INFO: &str = "A basic Rust IRC server";
+
+// 315    RPL_ENDOFWHO
+//        "<name> :End of WHO list"
+pub const RPL_ENDOFWHO_NB: u16 = 315;
+pub const RPL_ENDOFWHO_STR: &str = "End of WHO list";
+
+// 317    RPL_WHOISIDLE
+//        "<nick> <integer> :seconds idle"
+//   - Followed by the signon time, as most servers do.
+pub const RPL_WHOISIDLE_NB: u16 = 317;
+pub const RPL_WHOISIDLE_STR: &str = "seconds idle, signon time";
+
+// 318    RPL_ENDOFWHOIS
+//        "<nick> :End of WHOIS list"
+pub const RPL_ENDOFWHOIS_NB: u16 = 318;
+pub const RPL_ENDOFWHOIS_STR: &str = "End of WHOIS list";
+
+// 319    RPL_WHOISCHANNELS
+//        "<nick> :*( ( "@" / "+" ) <channel> " " )"
+//   - Secret and private channels are only listed for requesters
+//     who are members too.
+pub const RPL_WHOISCHANNELS_NB: u16 = 319;
+
+// 322    RPL_LIST
+//        "<channel> <# visible> :<topic>"
+pub const RPL_LIST_NB: u16 = 322;
+
+// 323    RPL_LISTEND
+//        ":End of LIST"
+pub const RPL_LISTEND_NB: u16 = 323;
+pub const RPL_LISTEND_STR: &str = "End of LIST";
+
+// 324    RPL_CHANNELMODEIS
+//        "<channel> <mode> <mode params>"
+pub const RPL_CHANNELMODEIS_NB: u16 = 324;
+
+// 329    RPL_CREATIONTIME
+//        "<channel> <creation time>"
+//   - Not in RFC 2812, sent after RPL_CHANNELMODEIS by most servers so
+//     clients can resolve channel takeover races.
+pub const RPL_CREATIONTIME_NB: u16 = 329;
+
 // 331    RPL_NOTOPIC
 //        "<channel> :No topic is set"
 pub const RPL_NOTOPIC_NB: u16 = 331;
@@ -21,6 +184,51 @@ pub const RPL_NOTOPIC_STR: &str = "No topic is set";
 //        "<channel> :<topic>"
 pub const RPL_TOPIC_NB: u16 = 332;
 
+// 338    RPL_WHOISACTUALLY
+//        "<nick> <ip> :Actually using host"
+pub const RPL_WHOISACTUALLY_NB: u16 = 338;
+pub const RPL_WHOISACTUALLY_STR: &str = "Actually using host";
+
+// 336    RPL_INVITELIST (user)
+//        "<channel>"
+// 337    RPL_ENDOFINVITELIST (user)
+//        ":End of /INVITE list"
+//   - Not in RFC 2812, the ircdocs replies to INVITE without parameters,
+//     listing the channels the user has pending invitations to.
+pub const RPL_INVITEDLIST_NB: u16 = 336;
+pub const RPL_ENDOFINVITEDLIST_NB: u16 = 337;
+pub const RPL_ENDOFINVITEDLIST_STR: &str = "End of /INVITE list";
+
+// 341    RPL_INVITING
+//        "<channel> <nick>"
+//   - Returned by the server to indicate that the
+//     attempted INVITE message was successful and is
+//     being passed onto the end client.
+pub const RPL_INVITING_NB: u16 = 341;
+
+// 342    RPL_SUMMONING
+//        "<user> :Summoning user to IRC"
+pub const RPL_SUMMONING_NB: u16 = 342;
+pub const RPL_SUMMONING_STR: &str = "Summoning user to IRC";
+
+// 351    RPL_VERSION
+//        "<version>.<debuglevel> <server> :<comments>"
+pub const RPL_VERSION_NB: u16 = 351;
+
+// 346    RPL_INVITELIST
+//        "<channel> <invitemask>"
+// 347    RPL_ENDOFINVITELIST
+//        "<channel> :End of channel invite list"
+pub const RPL_INVITELIST_NB: u16 = 346;
+pub const RPL_ENDOFINVITELIST_NB: u16 = 347;
+pub const RPL_ENDOFINVITELIST_STR: &str = "End of channel invite list";
+
+// 352    RPL_WHOREPLY
+//        "<channel> <user> <host> <server> <nick>
+//        ( "H" / "G" > ["*"] [ ( "@" / "+" ) ]
+//        :<hopcount> <real name>"
+pub const RPL_WHOREPLY_NB: u16 = 352;
+
 // 353    RPL_NAMREPLY
 //        "( "=" / "*" / "@" ) <channel>
 //         :[ "@" / "+" ] <nick> *( " " [ "@" / "+" ] <nick> )
@@ -28,22 +236,143 @@ pub const RPL_TOPIC_NB: u16 = 332;
 //     channels, and "=" for others (public channels).
 pub const RPL_NAMREPLY_NB: u16 = 353;
 
+// 364    RPL_LINKS
+//        "<mask> <server> :<hopcount> <server info>"
+// 365    RPL_ENDOFLINKS
+//        "<mask> :End of LINKS list"
+//   - In replying to the LINKS message, a server MUST send
+//     replies back using the RPL_LINKS numeric and mark the
+//     end of the list using an RPL_ENDOFLINKS reply.
+pub const RPL_LINKS_NB: u16 = 364;
+pub const RPL_ENDOFLINKS_NB: u16 = 365;
+pub const RPL_ENDOFLINKS_STR: &str = "End of LINKS list";
+
 // 366    RPL_ENDOFNAMES
 //        "<channel> :End of NAMES list"
-pub const RPL_ENDOFNAMES_NB: u16 = 353;
+pub const RPL_ENDOFNAMES_NB: u16 = 366;
 pub const RPL_ENDOFNAMES_STR: &str = "End of NAMES list";
 
+// 375    RPL_MOTDSTART
+//        ":- <server> Message of the day - "
+pub const RPL_MOTDSTART_NB: u16 = 375;
+
+// 372    RPL_MOTD
+//        ":- <text>"
+pub const RPL_MOTD_NB: u16 = 372;
+
+// 376    RPL_ENDOFMOTD
+//        ":End of MOTD command"
+pub const RPL_ENDOFMOTD_NB: u16 = 376;
+pub const RPL_ENDOFMOTD_STR: &str = "End of MOTD command";
+
+// 381    RPL_YOUREOPER
+//        ":You are now an IRC operator"
+//   - RPL_YOUREOPER is sent back to a client which has
+//     just successfully issued an OPER message and gained
+//     operator status.
+pub const RPL_YOUREOPER_NB: u16 = 381;
+pub const RPL_YOUREOPER_STR: &str = "You are now an IRC operator";
+
+// 382    RPL_REHASHING
+//        "<config file> :Rehashing"
+pub const RPL_REHASHING_NB: u16 = 382;
+pub const RPL_REHASHING_STR: &str = "Rehashing";
+
+// 392    RPL_USERSSTART
+//        ":UserID   Terminal  Host"
+pub const RPL_USERSSTART_NB: u16 = 392;
+pub const RPL_USERSSTART_STR: &str = "UserID   Terminal  Host";
+
+// 393    RPL_USERS
+//        ":<username> <ttyline> <hostname>"
+pub const RPL_USERS_NB: u16 = 393;
+
+// 394    RPL_ENDOFUSERS
+//        ":End of users"
+pub const RPL_ENDOFUSERS_NB: u16 = 394;
+pub const RPL_ENDOFUSERS_STR: &str = "End of users";
+
+// 395    RPL_NOUSERS
+//        ":Nobody logged in"
+pub const RPL_NOUSERS_NB: u16 = 395;
+pub const RPL_NOUSERS_STR: &str = "Nobody logged in";
+
+// 401    ERR_NOSUCHNICK
+//        "<nickname> :No such nick/channel"
+//   - Used to indicate the nickname parameter supplied to a
+//     command is currently unused.
+pub const ERR_NOSUCHNICK_NB: u16 = 401;
+pub const ERR_NOSUCHNICK_STR: &str = "No such nick/channel";
+
+// 402    ERR_NOSUCHSERVER
+//        "<server name> :No such server"
+//   - Used to indicate the server name given currently
+//     does not exist.
+pub const ERR_NOSUCHSERVER_NB: u16 = 402;
+pub const ERR_NOSUCHSERVER_STR: &str = "No such server";
+
 // 403    ERR_NOSUCHCHANNEL
 //        "<channel name> :No such channel"
 //   - Used to indicate the given channel name is invalid.
 pub const ERR_NOSUCHCHANNEL_NB: u16 = 403;
 pub const ERR_NOSUCHCHANNEL_STR: &str = "No such channel";
 
+// 404    ERR_CANNOTSENDTOCHAN
+//        "<channel name> :Cannot send to channel"
+//   - Sent to a user who is either (a) not on a channel
+//     which is mode +n or (b) not a chanop (or mode +v) on
+//     a channel which has mode +m set or where the user is
+//     banned and is trying to send a PRIVMSG message to
+//     that channel.
+pub const ERR_CANNOTSENDTOCHAN_NB: u16 = 404;
+pub const ERR_CANNOTSENDTOCHAN_STR: &str = "Cannot send to channel";
+
+// 405    ERR_TOOMANYCHANNELS
+//        "<channel name> :You have joined too many channels"
+//   - Sent to a user when they have joined the maximum
+//     number of allowed channels and they try to join
+//     another channel.
+pub const ERR_TOOMANYCHANNELS_NB: u16 = 405;
+pub const ERR_TOOMANYCHANNELS_STR: &str = "You have joined too many channels";
+
+// 407    ERR_TOOMANYTARGETS
+//        "<target> :<error code> recipients. <abort message>"
+//   - Returned to a client which is attempting to send a
+//     PRIVMSG/NOTICE using the user@host destination format
+//     and for a user@host which has several occurrences, or
+//     to a client which is trying to use too many targets
+//     at once.
+pub const ERR_TOOMANYTARGETS_NB: u16 = 407;
+pub const ERR_TOOMANYTARGETS_STR: &str = "Too many recipients. No message delivered";
+
+// 417    ERR_INPUTTOOLONG
+//        ":Input line was too long"
+//   - Sent when a client's line is over the length limit,
+//     the line is dropped.
+pub const ERR_INPUTTOOLONG_NB: u16 = 417;
+pub const ERR_INPUTTOOLONG_STR: &str = "Input line was too long";
+
 // 421    ERR_UNKNOWNCOMMAND
 //           "<command> :Unknown command"
 pub const ERR_UNKNOWNCOMMAND_NB: u16 = 421;
 pub const ERR_UNKNOWNCOMMAND_STR: &str = "Unknown command";
 
+// 422    ERR_NOMOTD
+//        ":MOTD File is missing"
+//   - Server's MOTD file could not be opened by the server.
+pub const ERR_NOMOTD_NB: u16 = 422;
+pub const ERR_NOMOTD_STR: &str = "MOTD File is missing";
+
+// 432    ERR_ERRONEUSNICKNAME
+//               "<nick> :Erroneous nickname"
+
+//          - Returned after receiving a NICK message which contains
+//            characters which do not fall in the defined set.  See
+//            section 2.3.1 for details on valid nicknames.
+//   - Also returned for nicks matching `limits.forbidden_nicks`.
+pub const ERR_ERRONEUSNICKNAME_NB: u16 = 432;
+pub const ERR_ERRONEUSNICKNAME_STR: &str = "Erroneous nickname";
+
 // 433    ERR_NICKNAMEINUSE
 //               "<nick> :Nickname is already in use"
 
@@ -53,14 +382,71 @@ pub const ERR_UNKNOWNCOMMAND_STR: &str = "Unknown command";
 pub const ERR_NICKNAMEINUSE_NB: u16 = 433;
 pub const ERR_NICKNAMEINUSE_STR: &str = "Nickname is already in use";
 
+// 436    ERR_NICKCOLLISION
+//               "<nick> :Nickname collision KILL from <user>@<host>"
+
+//          - Returned by a server to a client when it detects a
+//            nickname collision (registered of a NICK that
+//            already exists by another server).
+//   - Also sent locally to the client losing a registration race.
+pub const ERR_NICKCOLLISION_NB: u16 = 436;
+pub const ERR_NICKCOLLISION_STR: &str = "Nickname collision KILL";
+
+// 437    ERR_UNAVAILRESOURCE
+//        "<nick/channel> :Nick/channel is temporarily unavailable"
+//   - Returned by a server to a user trying to join a channel
+//     currently blocked by the channel delay mechanism.
+//   - Returned by a server to a user trying to change nickname
+//     when the desired nickname is blocked by the nick delay
+//     mechanism.
+//   - Also returned when `limits.nick_changes_per_min` is exceeded.
+pub const ERR_UNAVAILRESOURCE_NB: u16 = 437;
+pub const ERR_UNAVAILRESOURCE_STR: &str = "Nick/channel is temporarily unavailable";
+
+// 441    ERR_USERNOTINCHANNEL
+//        "<nick> <channel> :They aren't on that channel"
+//   - Returned by the server to indicate that the target
+//     user of the command is not on the given channel.
+pub const ERR_USERNOTINCHANNEL_NB: u16 = 441;
+pub const ERR_USERNOTINCHANNEL_STR: &str = "They aren't on that channel";
+
 // 442    ERR_NOTONCHANNEL
 //        "<channel> :You're not on that channel"
 //        - Returned by the server whenever a client tries to
 //          perform a channel affecting command for which the
 //          client isn't a member.
-pub const ERR_NOTONCHANNEL_NB: u16 = 433;
+pub const ERR_NOTONCHANNEL_NB: u16 = 442;
 pub const ERR_NOTONCHANNEL_STR: &str = "You're not on that channel";
 
+// 443    ERR_USERONCHANNEL
+//        "<user> <channel> :is already on channel"
+//   - Returned when a client tries to invite a user to a
+//     channel they are already on.
+pub const ERR_USERONCHANNEL_NB: u16 = 443;
+pub const ERR_USERONCHANNEL_STR: &str = "is already on channel";
+
+// 444    ERR_NOLOGIN
+//        "<user> :User not logged in"
+//   - Returned by the summon after a SUMMON command for a
+//     user was unable to be performed since they were not
+//     logged in.
+pub const ERR_NOLOGIN_NB: u16 = 444;
+pub const ERR_NOLOGIN_STR: &str = "User not logged in";
+
+// 445    ERR_SUMMONDISABLED
+//        ":SUMMON has been disabled"
+//   - Returned as a response to the SUMMON command.  MUST be
+//     returned by any server which doesn't implement it.
+pub const ERR_SUMMONDISABLED_NB: u16 = 445;
+pub const ERR_SUMMONDISABLED_STR: &str = "SUMMON has been disabled";
+
+// 446    ERR_USERSDISABLED
+//        ":USERS has been disabled"
+//   - Returned as a response to the USERS command.  MUST be
+//     returned by any server which does not implement it.
+pub const ERR_USERSDISABLED_NB: u16 = 446;
+pub const ERR_USERSDISABLED_STR: &str = "USERS has been disabled";
+
 // 451    ERR_NOTREGISTERED
 //               ":You have not registered"
 
@@ -68,7 +454,7 @@ pub const ERR_NOTONCHANNEL_STR: &str = "You're not on that channel";
 //            MUST be registered before the server will allow it
 //            to be parsed in detail.
 pub const ERR_NOTREGISTERED_NB: u16 = 451;
-pub const ERR_NOTREGISTERED_STR: &str = ":You have not registered";
+pub const ERR_NOTREGISTERED_STR: &str = "You have not registered";
 
 // 461    ERR_NEEDMOREPARAMS
 //               "<command> :Not enough parameters"
@@ -79,6 +465,23 @@ pub const ERR_NOTREGISTERED_STR: &str = ":You have not registered";
 pub const ERR_NEEDMOREPARAMS_NB: u16 = 461;
 pub const ERR_NEEDMOREPARAMS_STR: &str = "Not enough parameters";
 
+// 462    ERR_ALREADYREGISTRED
+//               ":Unauthorized command (already registered)"
+
+//          - Returned by the server to any link which tries to
+//            change part of the registered details (such as
+//            password or user details from second USER message).
+pub const ERR_ALREADYREGISTRED_NB: u16 = 462;
+pub const ERR_ALREADYREGISTRED_STR: &str = "Unauthorized command (already registered)";
+
+// 464    ERR_PASSWDMISMATCH
+//        ":Password incorrect"
+//   - Returned to indicate a failed attempt at registering
+//     a connection for which a password was required and
+//     was either not given or incorrect.
+pub const ERR_PASSWDMISMATCH_NB: u16 = 464;
+pub const ERR_PASSWDMISMATCH_STR: &str = "Password incorrect";
+
 // 471    ERR_CHANNELISFULL
 //        "<channel> :Cannot join channel (+l)"
 pub const ERR_CHANNELISFULL_NB: u16 = 471;
@@ -99,12 +502,55 @@ pub const ERR_BANNEDFROMCHAN_STR: &str = "Cannot join channel (+b)";
 pub const ERR_BADCHANNELKEY_NB: u16 = 475;
 pub const ERR_BADCHANNELKEY_STR: &str = "Cannot join channel (+k)";
 
+// 476    ERR_BADCHANMASK
+//        "<channel> :Bad Channel Mask"
+pub const ERR_BADCHANMASK_NB: u16 = 476;
+pub const ERR_BADCHANMASK_STR: &str = "Bad Channel Mask";
+
+// 481    ERR_NOPRIVILEGES
+//        ":Permission Denied- You're not an IRC operator"
+//   - Any command requiring operator privileges to operate
+//     MUST return this error to indicate the attempt was
+//     unsuccessful.
+pub const ERR_NOPRIVILEGES_NB: u16 = 481;
+pub const ERR_NOPRIVILEGES_STR: &str = "Permission Denied- You're not an IRC operator";
+
+// 484    ERR_RESTRICTED
+//        ":Your connection is restricted!"
+//   - Sent by the server to a user upon connection to indicate
+//     the restricted nature of the connection (user mode "+r").
+pub const ERR_RESTRICTED_NB: u16 = 484;
+pub const ERR_RESTRICTED_STR: &str = "Your connection is restricted!";
+
+// 482    ERR_CHANOPRIVSNEEDED
+//        "<channel> :You're not channel operator"
+//   - Any command requiring 'chanop' privileges (such as
+//     MODE messages) MUST return this error if the client
+//     making the attempt is not a chanop on the specified
+//     channel.
+pub const ERR_CHANOPRIVSNEEDED_NB: u16 = 482;
+pub const ERR_CHANOPRIVSNEEDED_STR: &str = "You're not channel operator";
+
 pub const ERR_UMODEUNKNOWNFLAG_NB: u16 = 501;
 pub const ERR_UMODEUNKNOWNFLAG_STR: &str = "Unknown MODE flag";
 
 pub const ERR_USERSDONTMATCH_NB: u16 = 502;
 pub const ERR_USERSDONTMATCH_STR: &str = "Cannot change mode for other users";
 
+// 728    RPL_QUIETLIST
+//        "<channel> q <mask>"
+// 729    RPL_ENDOFQUIETLIST
+//        "<channel> q :End of channel quiet list"
+//   - Not in RFC 2812, the charybdis numerics for listing +q masks.
+pub const RPL_QUIETLIST_NB: u16 = 728;
+pub const RPL_ENDOFQUIETLIST_NB: u16 = 729;
+pub const RPL_ENDOFQUIETLIST_STR: &str = "End of channel quiet list";
+
+// 900    RPL_LOGGEDIN
+//        "<nick> <nick>!<user>@<host> <account> :You are now logged in as <account>"
+//   - IRCv3 SASL numeric, also sent after REGISTER and IDENTIFY.
+pub const RPL_LOGGEDIN_NB: u16 = 900;
+
 // ERR_NEEDMOREPARAMS
 //                ERR_BADCHANMASK
 // ERR_NOSUCHCHANNEL               ERR_TOOMANYCHANNELS
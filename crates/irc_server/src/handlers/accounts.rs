@@ -0,0 +1,366 @@
+use log::info;
+
+use crate::{
+    channels_models::IrcChannel,
+    errors::InternalIrcError,
+    message_models::IrcMessage,
+    replies::IrcReply,
+    server_state::ServerState,
+    types::{ChannelName, ClientId, Nickname},
+    user_state::{UserSnapshot, UserState, UserStatus},
+};
+
+pub async fn handle_register(
+    password: Option<String>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Replies:
+
+    //            ERR_UNKNOWNCOMMAND ✅           ERR_NEEDMOREPARAMS ✅
+    //            RPL_LOGGEDIN ✅                 FAIL ALREADY_AUTHENTICATED ✅
+    //            FAIL ACCOUNT_EXISTS ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    let Some(password) =
+        require_accounts("REGISTER", password, &nick, server_state, user_state).await
+    else {
+        return Ok(UserStatus::Active);
+    };
+    if let Some(account) = &caracs.account {
+        let irc_reply = IrcReply::Fail {
+            command: "REGISTER",
+            code: "ALREADY_AUTHENTICATED",
+            context: account,
+            description: "You are already logged in",
+        };
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(irc_reply.format()))
+            .await;
+        return Ok(UserStatus::Active);
+    }
+    if !server_state
+        .account_store
+        .register(&nick.0, &password)
+        .await
+    {
+        let irc_reply = IrcReply::Fail {
+            command: "REGISTER",
+            code: "ACCOUNT_EXISTS",
+            context: &nick.0,
+            description: "Account already exists",
+        };
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(irc_reply.format()))
+            .await;
+        return Ok(UserStatus::Active);
+    }
+    info!("[{client_id}] registered account {nick}");
+    log_in(&nick.0, &caracs, client_id, server_state, user_state).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_identify(
+    credentials: Option<(Option<String>, String)>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Replies:
+
+    //            ERR_UNKNOWNCOMMAND ✅           ERR_NEEDMOREPARAMS ✅
+    //            RPL_LOGGEDIN ✅                 FAIL INVALID_CREDENTIALS ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    let Some((account, password)) =
+        require_accounts("IDENTIFY", credentials, &nick, server_state, user_state).await
+    else {
+        return Ok(UserStatus::Active);
+    };
+    let account = account.unwrap_or(nick.0.clone());
+    let Some(account) = server_state.account_store.verify(&account, &password).await else {
+        let irc_reply = IrcReply::Fail {
+            command: "IDENTIFY",
+            code: "INVALID_CREDENTIALS",
+            context: &account,
+            description: "Invalid account or password",
+        };
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(irc_reply.format()))
+            .await;
+        return Ok(UserStatus::Active);
+    };
+    info!("[{client_id}] identified as {account}");
+    log_in(&account, &caracs, client_id, server_state, user_state).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_register_channel(
+    channel_name: ChannelName,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Replies:
+
+    //            ERR_UNKNOWNCOMMAND ✅           ERR_NOSUCHCHANNEL ✅
+    //            ERR_CHANOPRIVSNEEDED ✅         FAIL ACCOUNT_REQUIRED ✅
+    //            FAIL CHANNEL_REGISTERED ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    if require_accounts("REGISTER", Some(()), &nick, server_state, user_state)
+        .await
+        .is_none()
+    {
+        return Ok(UserStatus::Active);
+    }
+    let channel = server_state
+        .get_channel(&channel_name)
+        .filter(|channel| channel.members.contains(&client_id));
+    let irc_reply = match (&caracs.account, channel) {
+        (_, None) => IrcReply::ErrNoSuchChannel {
+            nick: &nick,
+            channel: &channel_name,
+        },
+        (_, Some(channel)) if !channel.is_operator(client_id) => IrcReply::ErrChanOPrivsNeeded {
+            nick: &nick,
+            channel: &channel_name,
+        },
+        (None, Some(_)) => IrcReply::Fail {
+            command: "REGISTER",
+            code: "ACCOUNT_REQUIRED",
+            context: &channel_name.0,
+            description: "You must be logged in to register a channel",
+        },
+        (Some(account), Some(_)) => {
+            if server_state
+                .account_store
+                .claim_channel(&channel_name, account)
+                .await
+            {
+                info!("[{client_id}] registered {channel_name} to {account}");
+                IrcReply::ServerNotice {
+                    nick: &nick,
+                    text: &format!("*** {channel_name} is now registered to {account}"),
+                }
+            } else {
+                IrcReply::Fail {
+                    command: "REGISTER",
+                    code: "CHANNEL_REGISTERED",
+                    context: &channel_name.0,
+                    description: "Channel is already registered",
+                }
+            }
+        }
+    };
+    let _ = user_state
+        .tx_outbound
+        .send(IrcMessage::new(irc_reply.format()))
+        .await;
+    Ok(UserStatus::Active)
+}
+
+/// The parameters when `accounts.enabled` is set and they were given, else
+/// sends ERR_UNKNOWNCOMMAND or ERR_NEEDMOREPARAMS.
+async fn require_accounts<T>(
+    command: &str,
+    params: Option<T>,
+    nick: &Nickname,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Option<T> {
+    let irc_reply = if !server_state.config.read().await.get_accounts_enabled() {
+        IrcReply::ErrUnknownCommand { nick, command }
+    } else if params.is_none() {
+        IrcReply::ErrNeedMoreParams { nick, command }
+    } else {
+        return params;
+    };
+    let _ = user_state
+        .tx_outbound
+        .send(IrcMessage::new(irc_reply.format()))
+        .await;
+    None
+}
+
+/// Sets `User.account`, confirms with RPL_LOGGEDIN and applies the
+/// account's privileges in the channels the user is already on.
+pub async fn log_in(
+    account: &str,
+    caracs: &UserSnapshot,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) {
+    user_state.user.write().await.account = Some(account.to_owned());
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    let hostmask = format!(
+        "{nick}!{}@{}",
+        caracs.user.as_ref().map_or("*", |user| user.0.as_str()),
+        caracs.displayed_host()
+    );
+    let irc_reply = IrcReply::LoggedIn {
+        nick: &nick,
+        hostmask: &hostmask,
+        account,
+    };
+    let _ = user_state
+        .tx_outbound
+        .send(IrcMessage::new(irc_reply.format()))
+        .await;
+    for channel_name in &caracs.member_of {
+        if let Some(channel) = server_state.get_channel(channel_name) {
+            apply_account_privileges(&channel, client_id, account, &nick, server_state).await;
+        }
+    }
+}
+
+/// Ops `account` in the channels it owns, see `handle_register_channel`.
+pub async fn apply_account_privileges(
+    channel: &IrcChannel,
+    client_id: ClientId,
+    account: &str,
+    nick: &Nickname,
+    server_state: &ServerState,
+) {
+    let owner = server_state
+        .account_store
+        .channel_owner(&channel.name)
+        .await;
+    if owner.is_some_and(|owner| owner.eq_ignore_ascii_case(account))
+        && channel.add_operator(client_id)
+    {
+        let modes = format!("+o {nick}");
+        let irc_reply = IrcReply::ServerChannelMode {
+            channel: &channel.name,
+            modes: &modes,
+        };
+        channel.broadcast_message(IrcMessage::new(irc_reply.format()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{AccountsConfig, Config},
+        server_state::ServerState,
+        test_utils::{TestClient, has_numeric},
+        types::ChannelName,
+    };
+
+    #[tokio::test]
+    async fn test_identify_on_a_new_server_restores_the_account() {
+        let path = std::env::temp_dir().join(format!("irc_accounts_{}.toml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = Config {
+            accounts: Some(AccountsConfig {
+                enabled: Some(true),
+                file: Some(path.display().to_string()),
+            }),
+            ..Config::default()
+        };
+
+        let server_state = ServerState::new(config.clone());
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "REGISTER").await.unwrap();
+        assert!(has_numeric(&alice.drain(), "461"));
+        alice.send(&server_state, "REGISTER s3cret").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server 900 alice alice!alice@127.0.0.1 alice :You are now logged in as alice"
+            ]
+        );
+        // Creating a channel doesn't claim it, registering it does
+        alice.send(&server_state, "JOIN #rust").await.unwrap();
+        alice.drain();
+        assert_eq!(
+            server_state
+                .account_store
+                .channel_owner(&ChannelName("#rust".to_owned()))
+                .await,
+            None
+        );
+        alice.send(&server_state, "REGISTER #rust").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server NOTICE alice :*** #rust is now registered to alice"]
+        );
+
+        // Same accounts file, as after a restart
+        let server_state = ServerState::new(config);
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        bob.send(&server_state, "JOIN #rust").await.unwrap();
+        alice.send(&server_state, "JOIN #rust").await.unwrap();
+        bob.drain();
+        alice.drain();
+        alice.send(&server_state, "IDENTIFY wrong").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server FAIL IDENTIFY INVALID_CREDENTIALS alice :Invalid account or password"
+            ]
+        );
+        alice
+            .send(&server_state, "IDENTIFY alice s3cret")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.user_state.get_caracs().await.account.as_deref(),
+            Some("alice")
+        );
+        assert_eq!(bob.drain(), vec![":unknown.server MODE #rust +o alice"]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_only_an_identified_operator_registers_a_channel() {
+        let config = Config {
+            accounts: Some(AccountsConfig {
+                enabled: Some(true),
+                file: None,
+            }),
+            ..Config::default()
+        };
+        let server_state = ServerState::new(config);
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #rust").await.unwrap();
+        bob.send(&server_state, "JOIN #rust").await.unwrap();
+        bob.send(&server_state, "REGISTER s3cret").await.unwrap();
+        alice.drain();
+        bob.drain();
+
+        bob.send(&server_state, "REGISTER #rust").await.unwrap();
+        assert!(has_numeric(&bob.drain(), "482"));
+        bob.send(&server_state, "REGISTER #elsewhere")
+            .await
+            .unwrap();
+        assert!(has_numeric(&bob.drain(), "403"));
+        alice.send(&server_state, "REGISTER #rust").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server FAIL REGISTER ACCOUNT_REQUIRED #rust :You must be logged in to register a channel"
+            ]
+        );
+
+        alice.send(&server_state, "REGISTER pa55").await.unwrap();
+        alice.send(&server_state, "REGISTER #rust").await.unwrap();
+        alice.drain();
+        alice
+            .send(&server_state, "MODE #rust +o bob")
+            .await
+            .unwrap();
+        bob.send(&server_state, "REGISTER #rust").await.unwrap();
+        assert!(bob.drain().contains(
+            &":unknown.server FAIL REGISTER CHANNEL_REGISTERED #rust :Channel is already registered"
+                .to_owned()
+        ));
+    }
+}
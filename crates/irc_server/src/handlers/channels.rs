@@ -2,17 +2,24 @@ use std::sync::Arc;
 
 use log::info;
 
-use crate::replies::MessageReply;
+use crate::ops::channel::{ChannelModeChange, ListFilter};
+use crate::ops::parsers::validate_channel_name;
+use crate::replies::{Batch, MessageReply};
 use crate::types::*;
+use crate::utils::{normalize_hostmask, unix_timestamp, wildcard_match};
 use crate::{
     channels_models::{IrcChannel, IrcChannelOperationStatus, SubscriptionControl},
     errors::InternalIrcError,
-    message_models::{BroadcastIrcMessage, DirectIrcMessage},
+    handlers::{accounts::apply_account_privileges, server_queries::require_local_target},
+    message_models::IrcMessage,
     replies::IrcReply,
     server_state::ServerState,
-    user_state::{UserState, UserStatus},
+    user_state::{UserSnapshot, UserState, UserStatus},
 };
 
+// How many +f forwards one JOIN may follow, loops between channels end there
+const MAX_FORWARD_HOPS: usize = 3;
+
 pub async fn handle_join_channel(
     channels_keys: Vec<(ChannelName, Option<String>)>,
     client_id: ClientId,
@@ -50,9 +57,9 @@ pub async fn handle_join_channel(
 
     //         ERR_NEEDMOREPARAMS              ERR_BANNEDFROMCHAN ✅
     //         ERR_INVITEONLYCHAN ✅             ERR_BADCHANNELKEY ✅
-    //         ERR_CHANNELISFULL ✅              ERR_BADCHANMASK
-    //         ERR_NOSUCHCHANNEL               ERR_TOOMANYCHANNELS
-    //         ERR_TOOMANYTARGETS              ERR_UNAVAILRESOURCE
+    //         ERR_CHANNELISFULL ✅              ERR_BADCHANMASK ✅
+    //         ERR_NOSUCHCHANNEL               ERR_TOOMANYCHANNELS ✅
+    //         ERR_TOOMANYTARGETS ✅             ERR_UNAVAILRESOURCE
     //         RPL_TOPIC ✅
     // User sends JOIN #test
     // │
@@ -81,108 +88,208 @@ pub async fn handle_join_channel(
     let caracs = user_state.get_caracs().await;
     let nick = caracs.clone().nick.unwrap_or(Nickname("*".to_owned()));
     let user = caracs.clone().user.unwrap_or(Username("*".to_owned()));
-    let host = &format!("{}", caracs.addr);
+    let host = &caracs.displayed_host();
     if !caracs.registered {
         let nick = match caracs.nick {
             Some(nick) => nick,
             None => Nickname("*".to_owned()),
         };
         let irc_reply = IrcReply::ErrNotRegistered { nick: &nick };
-        let not_registered_message = DirectIrcMessage::new(irc_reply.format());
+        let not_registered_message = IrcMessage::new(irc_reply.format());
         let _ = user_state.tx_outbound.send(not_registered_message).await;
         return Ok(UserStatus::Active);
     }
-    for (channel_name, key) in channels_keys {
-        match server_state
-            .handle_join(channel_name.clone(), client_id, key, false)
-            .await
-        {
-            Ok((IrcChannelOperationStatus::NewJoin, Some(channel))) => {
-                let irc_reply = MessageReply::BroadcastJoinMsg {
+    let (max_channels, max_join_list, oper_only_create, chantypes) = {
+        let config = server_state.config.read().await;
+        (
+            config.limits.max_channels_per_user,
+            config.get_max_join_list(),
+            config.get_oper_only_create(),
+            config.get_chantypes().to_owned(),
+        )
+    };
+    let can_create = !oper_only_create || caracs.modes.contains(&'o');
+    let hostmask = format!("{nick}!{user}@{host}");
+    for (i, (channel_name, key)) in channels_keys.into_iter().enumerate() {
+        if i >= max_join_list {
+            // 407 ERR_TOOMANYTARGETS, channels past the cap are not joined
+            let irc_reply = IrcReply::ErrTooManyTargets {
+                nick: &nick,
+                target: &channel_name.to_string(),
+            };
+            let err_too_many_targets = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(err_too_many_targets).await;
+            break;
+        }
+        // A JOIN refused by a +f channel is retried in its forward target,
+        // which has to pass the same checks
+        let (mut target, mut key) = (channel_name, key);
+        for hop in 0..=MAX_FORWARD_HOPS {
+            if !may_join(&target, &nick, &chantypes, max_channels, user_state).await {
+                break;
+            }
+            let jupe_reason = server_state.juped_channels.get(&target).map(|r| r.clone());
+            if let Some(reason) = jupe_reason {
+                // 437 ERR_UNAVAILRESOURCE: reserved by an operator
+                let irc_reply = IrcReply::ErrJupedChannel {
                     nick: &nick,
-                    user: &user,
-                    host,
-                    channel: &channel_name,
+                    channel: &target,
+                    reason: &reason,
                 };
-                let rx = channel.subscribe();
-                let _ = user_state
-                    .tx_control
-                    .send(SubscriptionControl::Subscribe {
-                        channel_name: channel_name.clone(),
-                        receiver: rx,
-                    })
-                    .await;
-                let welcome_channel_message = BroadcastIrcMessage::new(irc_reply.format());
-                channel.broadcast_message(welcome_channel_message);
-                let potential_topic = channel.topic.read().await;
-                if let Some(topic) = potential_topic.clone() {
-                    let irc_reply = IrcReply::Topic {
+                let err_unavail_resource = IrcMessage::new(irc_reply.format());
+                let _ = user_state.tx_outbound.send(err_unavail_resource).await;
+                break;
+            }
+            if server_state.is_held(&target.0) {
+                // 437 ERR_UNAVAILRESOURCE: a channel name held back
+                let irc_reply = IrcReply::ErrUnavailResource {
+                    nick: &nick,
+                    target: &target.0,
+                };
+                let err_unavail_resource = IrcMessage::new(irc_reply.format());
+                let _ = user_state.tx_outbound.send(err_unavail_resource).await;
+                break;
+            }
+            let (channel_name, can_create) = match server_state.resolve_safe_channel(&target) {
+                Ok((safe_name, may_create)) => (safe_name, can_create && may_create),
+                Err(IrcChannelOperationStatus::UnavailableResource) => {
+                    // !!short while a safe channel already uses that short name
+                    let irc_reply = IrcReply::ErrUnavailResource {
                         nick: &nick,
+                        target: &target.0,
+                    };
+                    let err_unavail_resource = IrcMessage::new(irc_reply.format());
+                    let _ = user_state.tx_outbound.send(err_unavail_resource).await;
+                    break;
+                }
+                Err(_) => {
+                    let irc_reply = IrcReply::ErrNoSuchChannel {
+                        nick: &nick,
+                        channel: &target,
+                    };
+                    let err_no_such_channel = IrcMessage::new(irc_reply.format());
+                    let _ = user_state.tx_outbound.send(err_no_such_channel).await;
+                    break;
+                }
+            };
+            let refused = match server_state
+                .handle_join(
+                    channel_name.clone(),
+                    client_id,
+                    &hostmask,
+                    key,
+                    false,
+                    can_create,
+                )
+                .await
+            {
+                Ok((IrcChannelOperationStatus::NewJoin, Some(channel))) => {
+                    let (src_nick, src_user, src_host) =
+                        channel.displayed_source(&nick, &user, host).await;
+                    let irc_reply = MessageReply::BroadcastJoinMsg {
+                        nick: &src_nick,
+                        user: &src_user,
+                        host: &src_host,
                         channel: &channel_name,
-                        topic: &topic,
                     };
-                    let topic_message = DirectIrcMessage::new(irc_reply.format());
-                    let _ = user_state.tx_outbound.send(topic_message).await;
-                } else {
-                    let irc_reply = IrcReply::NoTopic {
+                    let rx = channel.subscribe();
+                    let _ = user_state
+                        .tx_control
+                        .send(SubscriptionControl::Subscribe {
+                            channel_name: channel_name.clone(),
+                            receiver: rx,
+                        })
+                        .await;
+                    let welcome_channel_message = if channel.is_anonymous().await {
+                        // Only the joiner sees its real JOIN
+                        let own_join = MessageReply::BroadcastJoinMsg {
+                            nick: &nick,
+                            user: &user,
+                            host,
+                            channel: &channel_name,
+                        };
+                        let own_join_message = IrcMessage::new(own_join.format());
+                        let _ = user_state.tx_outbound.send(own_join_message).await;
+                        IrcMessage::new_with_sender(irc_reply.format(), client_id)
+                    } else {
+                        IrcMessage::new(irc_reply.format())
+                    };
+                    channel.broadcast_message(welcome_channel_message);
+                    if let Some(account) = &caracs.account {
+                        apply_account_privileges(&channel, client_id, account, &nick, server_state)
+                            .await;
+                    }
+                    send_topic_and_names(&channel, &caracs, server_state, user_state).await;
+                    user_state.join_channel(&channel_name).await;
+                    false
+                }
+                Ok((IrcChannelOperationStatus::AlreadyMember, Some(channel))) => {
+                    // No JOIN line for the others, the client just gets its view back
+                    send_topic_and_names(&channel, &caracs, server_state, user_state).await;
+                    false
+                }
+                Ok((IrcChannelOperationStatus::ChannelIsFull, None)) => {
+                    let irc_reply = IrcReply::ErrChannelIsFull {
+                        channel: &channel_name,
+                    };
+                    let err_channel_is_full = IrcMessage::new(irc_reply.format());
+                    let _ = user_state.tx_outbound.send(err_channel_is_full).await;
+                    true
+                }
+                Ok((IrcChannelOperationStatus::BannedFromChan, None)) => {
+                    let irc_reply = IrcReply::ErrBannedFromChan {
+                        channel: &channel_name,
+                    };
+                    let err_banned_from_chan = IrcMessage::new(irc_reply.format());
+                    let _ = user_state.tx_outbound.send(err_banned_from_chan).await;
+                    true
+                }
+                Ok((IrcChannelOperationStatus::InviteOnlyChan, None)) => {
+                    let irc_reply = IrcReply::ErrInviteOnlyChan {
+                        channel: &channel_name,
+                    };
+                    let err_invite_only_chan = IrcMessage::new(irc_reply.format());
+                    let _ = user_state.tx_outbound.send(err_invite_only_chan).await;
+                    true
+                }
+                Ok((IrcChannelOperationStatus::BadChannelKey, None)) => {
+                    let irc_reply = IrcReply::ErrBadChannelKey {
+                        channel: &channel_name,
+                    };
+                    let err_bad_channel_key = IrcMessage::new(irc_reply.format());
+                    let _ = user_state.tx_outbound.send(err_bad_channel_key).await;
+                    false
+                }
+                Ok((IrcChannelOperationStatus::NoSuchChannel, None)) => {
+                    // channels.oper_only_create: only operators create channels
+                    let irc_reply = IrcReply::ErrNoSuchChannel {
                         nick: &nick,
                         channel: &channel_name,
                     };
-                    let no_topic_message = DirectIrcMessage::new(irc_reply.format());
-                    let _ = user_state.tx_outbound.send(no_topic_message).await;
+                    let err_no_such_channel = IrcMessage::new(irc_reply.format());
+                    let _ = user_state.tx_outbound.send(err_no_such_channel).await;
+                    false
                 }
-
-                let (visibility, member_list) = handle_names_reply(&channel, server_state).await;
-                // ├─ send names list
-                // │    RPL_NAMREPLY (353)
-                // │    RPL_ENDOFNAMES (366)
-                let irc_reply = IrcReply::Names {
-                    nick: &nick,
-                    channel: &channel_name,
-                    visibility: &visibility,
-                    names: &member_list,
-                };
-                let channel_names = DirectIrcMessage::new(irc_reply.format());
-                let _ = user_state.tx_outbound.send(channel_names).await;
-                let irc_reply = IrcReply::EndOfName {
-                    nick: &nick,
-                    channel: &channel_name,
-                };
-                let channel_end_of_names = DirectIrcMessage::new(irc_reply.format());
-                let _ = user_state.tx_outbound.send(channel_end_of_names).await;
-                user_state.join_channel(&channel_name).await
-            }
-            Ok((IrcChannelOperationStatus::ChannelIsFull, None)) => {
-                let irc_reply = IrcReply::ErrChannelIsFull {
-                    channel: &channel_name,
-                };
-                let err_channel_is_full = DirectIrcMessage::new(irc_reply.format());
-                let _ = user_state.tx_outbound.send(err_channel_is_full).await;
-            }
-            Ok((IrcChannelOperationStatus::BannedFromChan, None)) => {
-                let irc_reply = IrcReply::ErrBannedFromChan {
-                    channel: &channel_name,
-                };
-                let err_banned_from_chan = DirectIrcMessage::new(irc_reply.format());
-                let _ = user_state.tx_outbound.send(err_banned_from_chan).await;
-            }
-            Ok((IrcChannelOperationStatus::InviteOnlyChan, None)) => {
-                let irc_reply = IrcReply::ErrInviteOnlyChan {
-                    channel: &channel_name,
-                };
-                let err_invite_only_chan = DirectIrcMessage::new(irc_reply.format());
-                let _ = user_state.tx_outbound.send(err_invite_only_chan).await;
-            }
-            Ok((IrcChannelOperationStatus::BadChannelKey, None)) => {
-                let irc_reply = IrcReply::ErrBadChannelKey {
-                    channel: &channel_name,
-                };
-                let err_bad_channel_key = DirectIrcMessage::new(irc_reply.format());
-                let _ = user_state.tx_outbound.send(err_bad_channel_key).await;
+                Ok(_) => false,
+                Err(e) => return Err(e),
+            };
+            if !refused || hop == MAX_FORWARD_HOPS {
+                break;
             }
-            Ok((IrcChannelOperationStatus::AlreadyMember, None)) => (),
-            Ok(_) => (),
-            Err(_e) => (),
+            let Some(channel) = server_state.get_channel(&channel_name) else {
+                break;
+            };
+            let Some(forward) = channel.modes.read().await.forward.clone() else {
+                break;
+            };
+            let text = format!("*** Cannot join {channel_name}, forwarding you to {forward}");
+            let irc_reply = IrcReply::ServerNotice {
+                nick: &nick,
+                text: &text,
+            };
+            let forward_notice = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(forward_notice).await;
+            (target, key) = (forward, None);
         }
         //
         // broadcast
@@ -191,8 +298,173 @@ pub async fn handle_join_channel(
     Ok(UserStatus::Active)
 }
 
+/// Whether the user may try `channel` at all: a well-formed name with a
+/// prefix in `channels.chantypes`, and a free place under
+/// `limits.max_channels_per_user`. The refusal is answered here.
+async fn may_join(
+    channel: &ChannelName,
+    nick: &Nickname,
+    chantypes: &str,
+    max_channels: usize,
+    user_state: &UserState,
+) -> bool {
+    if !require_valid_channel(channel, user_state).await {
+        return false;
+    }
+    let irc_reply = if !channel.0.starts_with(|prefix| chantypes.contains(prefix)) {
+        // 403 ERR_NOSUCHCHANNEL, a prefix this server doesn't offer
+        IrcReply::ErrNoSuchChannel { nick, channel }
+    } else {
+        // Counted again for every channel: the earlier ones of the list
+        // may have taken the last free places
+        let member_of = user_state.get_caracs().await.member_of;
+        if member_of.len() < max_channels || member_of.contains(channel) {
+            return true;
+        }
+        // 405 ERR_TOOMANYCHANNELS, only this channel is refused
+        IrcReply::ErrTooManyChannels { nick, channel }
+    };
+    let _ = user_state
+        .tx_outbound
+        .send(IrcMessage::new(irc_reply.format()))
+        .await;
+    false
+}
+
+/// Answers ERR_BADCHANMASK (476) and returns false for a malformed channel
+/// name, so junk never reaches the channel map.
+pub async fn require_valid_channel(channel: &ChannelName, user_state: &UserState) -> bool {
+    if validate_channel_name(&channel.0) {
+        return true;
+    }
+    let nick = user_state
+        .get_caracs()
+        .await
+        .nick
+        .unwrap_or(Nickname("*".to_owned()));
+    let irc_reply = IrcReply::ErrBadChanMask {
+        nick: &nick,
+        channel,
+    };
+    let err_bad_chan_mask = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(err_bad_chan_mask).await;
+    false
+}
+
+/// Answers ERR_RESTRICTED (484) and returns false for a +r user, who may
+/// not use channel operator privileges even when holding them.
+pub async fn require_unrestricted(user_state: &UserState) -> bool {
+    let caracs = user_state.get_caracs().await;
+    if !caracs.modes.contains(&'r') {
+        return true;
+    }
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    let irc_reply = IrcReply::ErrRestricted { nick: &nick };
+    let err_restricted = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(err_restricted).await;
+    false
+}
+
+/// Sends RPL_TOPIC (or RPL_NOTOPIC) then the names list, as after a JOIN.
+async fn send_topic_and_names(
+    channel: &Arc<IrcChannel>,
+    caracs: &UserSnapshot,
+    server_state: &ServerState,
+    user_state: &UserState,
+) {
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    let channel_name = &channel.name;
+    let potential_topic = channel.topic.read().await.clone();
+    if let Some(topic) = potential_topic {
+        let irc_reply = IrcReply::Topic {
+            nick: &nick,
+            channel: channel_name,
+            topic: &topic,
+        };
+        let topic_message = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(topic_message).await;
+    } else {
+        let irc_reply = IrcReply::NoTopic {
+            nick: &nick,
+            channel: channel_name,
+        };
+        let no_topic_message = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(no_topic_message).await;
+    }
+
+    let (visibility, member_list) = handle_names_reply(channel, caracs, server_state).await;
+    // ├─ send names list
+    // │    RPL_NAMREPLY (353)
+    // │    RPL_ENDOFNAMES (366)
+    let irc_reply = IrcReply::Names {
+        nick: &nick,
+        channel: channel_name,
+        visibility: &visibility,
+        names: &member_list,
+    };
+    let channel_names = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(channel_names).await;
+    let irc_reply = IrcReply::EndOfName {
+        nick: &nick,
+        channel: channel_name,
+    };
+    let channel_end_of_names = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(channel_end_of_names).await;
+}
+
+/// Hands the channels of the parked `lost_id` over to `client_id`, with
+/// its channel modes. Only the new connection gets JOIN, topic and names,
+/// the other members never saw the user leave.
+pub async fn resume_channels(
+    lost_id: ClientId,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) {
+    let Some((_, lost_state)) = server_state.users.remove(&lost_id) else {
+        return;
+    };
+    let lost = lost_state.get_caracs().await;
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.clone().nick.unwrap_or(Nickname("*".to_owned()));
+    let user = caracs.clone().user.unwrap_or(Username("*".to_owned()));
+    let host = &caracs.displayed_host();
+    for channel_name in lost.member_of {
+        let Some(channel) = server_state.get_channel(&channel_name) else {
+            continue;
+        };
+        channel.remove_member(&lost_id);
+        channel.add_member(client_id);
+        if channel.operators.remove(&lost_id).is_some() {
+            channel.add_operator(client_id);
+        }
+        if channel.voiced.remove(&lost_id).is_some() {
+            channel.voiced.insert(client_id);
+        }
+        let _ = user_state
+            .tx_control
+            .send(SubscriptionControl::Subscribe {
+                channel_name: channel_name.clone(),
+                receiver: channel.subscribe(),
+            })
+            .await;
+        user_state.join_channel(&channel_name).await;
+        let own_join = MessageReply::BroadcastJoinMsg {
+            nick: &nick,
+            user: &user,
+            host,
+            channel: &channel_name,
+        };
+        let own_join_message = IrcMessage::new(own_join.format());
+        let _ = user_state.tx_outbound.send(own_join_message).await;
+        let caracs = user_state.get_caracs().await;
+        send_topic_and_names(&channel, &caracs, server_state, user_state).await;
+    }
+}
+
 async fn handle_names_reply(
     channel: &Arc<IrcChannel>,
+    requester: &UserSnapshot,
     server_state: &ServerState,
 ) -> (String, String) {
     // The RPL_NAMREPLY (353) is one of the most important numeric replies in IRC. It tells the client exactly who is currently in a channel and what their "status" is.
@@ -243,11 +515,14 @@ async fn handle_names_reply(
         }
     };
 
+    // Members see everyone; outsiders don't see invisible (+i) users they
+    // share no channel with.
+    let requester_is_member = channel.members.contains(&requester.user_id);
     let mut member_list = String::new();
     let channel_members = channel
         .members
         .iter()
-        .map(|m| m.clone())
+        .map(|m| *m)
         .collect::<Vec<ClientId>>();
 
     for client_id in channel_members {
@@ -255,15 +530,15 @@ async fn handle_names_reply(
         let user_state_opt = server_state.users.get(&client_id).map(|r| r.clone());
 
         if let Some(user_state) = user_state_opt {
-            let user_caracs = user_state.user.read().await;
-            let prefix = if channel.operators.contains(&client_id) {
-                "@"
-            } else if channel.voiced.contains(&client_id) {
-                "+"
-            } else {
-                ""
-            };
-
+            let user_caracs = user_state.get_caracs().await;
+            if !requester_is_member && !user_caracs.is_visible_to(requester) {
+                continue;
+            }
+            // +a: nobody learns who else is in the channel
+            if modes.anonymous && client_id != requester.user_id {
+                continue;
+            }
+            let prefix = channel.role(client_id).prefix();
             if let Some(ref nick) = user_caracs.nick {
                 member_list.push_str(&format!("{prefix}{nick} "));
             }
@@ -272,7 +547,140 @@ async fn handle_names_reply(
     (visibility_symbol.to_owned(), member_list.trim().to_string())
 }
 
-pub async fn handle_invalid_join_channel(
+pub async fn handle_names_channel(
+    channels: Option<Vec<ChannelName>>,
+    target: Option<String>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.2.5 Names message
+    //    Numeric Replies:
+
+    //            ERR_TOOMANYMATCHES              ERR_NOSUCHSERVER ✅
+    //            RPL_NAMREPLY ✅                 RPL_ENDOFNAMES ✅
+    if !require_local_target(target.as_deref(), user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.clone().nick.unwrap_or(Nickname("*".to_owned()));
+    let requested = channels.clone().unwrap_or_else(|| {
+        server_state
+            .channels
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    });
+    let batch = Batch::new(caracs.capabilities.contains("batch"));
+    if let Some(batch_start) = batch.start("names", "") {
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(batch_start))
+            .await;
+    }
+    for channel_name in requested {
+        if let Some(channel) = server_state.get_channel(&channel_name) {
+            let is_member = channel.members.contains(&client_id);
+            let is_hidden = {
+                let modes = channel.modes.read().await;
+                modes.secret || modes.private
+            };
+            if is_member || !is_hidden {
+                let (visibility, member_list) =
+                    handle_names_reply(&channel, &caracs, server_state).await;
+                let irc_reply = IrcReply::Names {
+                    nick: &nick,
+                    channel: &channel_name,
+                    visibility: &visibility,
+                    names: &member_list,
+                };
+                let channel_names = IrcMessage::new(batch.tag(irc_reply.format()));
+                let _ = user_state.tx_outbound.send(channel_names).await;
+            }
+        }
+        // There is no error reply for bad channel names, each requested
+        // channel gets its RPL_ENDOFNAMES
+        if channels.is_some() {
+            let irc_reply = IrcReply::EndOfName {
+                nick: &nick,
+                channel: &channel_name,
+            };
+            let channel_end_of_names = IrcMessage::new(batch.tag(irc_reply.format()));
+            let _ = user_state.tx_outbound.send(channel_end_of_names).await;
+        }
+    }
+    if channels.is_none() {
+        // Everyone else visible shows up under the "*" pseudo-channel
+        let no_channel = ChannelName("*".to_owned());
+        let names = names_on_no_channel(client_id, &caracs, server_state).await;
+        if !names.is_empty() {
+            let irc_reply = IrcReply::Names {
+                nick: &nick,
+                channel: &no_channel,
+                visibility: "*",
+                names: &names.join(" "),
+            };
+            let no_channel_names = IrcMessage::new(batch.tag(irc_reply.format()));
+            let _ = user_state.tx_outbound.send(no_channel_names).await;
+        }
+        let irc_reply = IrcReply::EndOfName {
+            nick: &nick,
+            channel: &no_channel,
+        };
+        let end_of_names = IrcMessage::new(batch.tag(irc_reply.format()));
+        let _ = user_state.tx_outbound.send(end_of_names).await;
+    }
+    if let Some(batch_end) = batch.end() {
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(batch_end))
+            .await;
+    }
+    Ok(UserStatus::Active)
+}
+
+/// Nicks of the registered users the requester may see that are on no
+/// channel the requester can see either, sorted.
+async fn names_on_no_channel(
+    client_id: ClientId,
+    requester: &UserSnapshot,
+    server_state: &ServerState,
+) -> Vec<String> {
+    let users: Vec<UserState> = server_state
+        .users
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect();
+    let mut names = Vec::new();
+    for user_state in users {
+        let caracs = user_state.get_caracs().await;
+        let Some(nick) = caracs.nick.as_ref().filter(|_| caracs.registered) else {
+            continue;
+        };
+        if !caracs.is_visible_to(requester) {
+            continue;
+        }
+        let mut on_visible_channel = false;
+        for channel_name in &caracs.member_of {
+            if let Some(channel) = server_state.get_channel(channel_name) {
+                let modes = channel.modes.read().await;
+                if !(modes.secret || modes.private) || channel.members.contains(&client_id) {
+                    on_visible_channel = true;
+                    break;
+                }
+            }
+        }
+        if !on_visible_channel {
+            names.push(nick.to_string());
+        }
+    }
+    names.sort();
+    names
+}
+
+/// ERR_NEEDMOREPARAMS for a command recognised by its verb whose
+/// parameters are missing or didn't parse.
+pub async fn handle_missing_params(
     command: String,
     user_state: &UserState,
 ) -> Result<UserStatus, InternalIrcError> {
@@ -286,8 +694,8 @@ pub async fn handle_invalid_join_channel(
         nick: &nick,
         command: &command,
     };
-    let invalid_join_message = DirectIrcMessage::new(irc_reply.format());
-    let _ = user_state.tx_outbound.send(invalid_join_message).await;
+    let err_need_more_params = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(err_need_more_params).await;
     Ok(UserStatus::Active)
 }
 
@@ -320,23 +728,29 @@ pub async fn handle_part_channel(
     let caracs = user_state.get_caracs().await;
     let nick_from = caracs.clone().nick.unwrap_or(Nickname("*".to_owned()));
     let user_from = caracs.clone().user.unwrap_or(Username("*".to_owned()));
-    let host_from = &format!("{}", caracs.addr);
+    let host_from = &caracs.displayed_host();
     let leave_message = &match message {
         Some(message) => format!(":{message}"),
-        None => format!(""),
+        None => String::new(),
     };
     for channel in channels {
-        let irc_channel_opt = server_state.get_channel(&channel).map(|r| r.clone());
+        if !require_valid_channel(&channel, user_state).await {
+            continue;
+        }
+        let irc_channel_opt = server_state.get_channel(&channel);
         if let Some(irc_channel) = irc_channel_opt {
+            let (src_nick, src_user, src_host) = irc_channel
+                .displayed_source(&nick_from, &user_from, host_from)
+                .await;
             let part_msg = MessageReply::PartMsg {
-                nick_from: &nick_from,
-                user_from: &user_from,
-                host_from: host_from,
+                nick_from: &src_nick,
+                user_from: &src_user,
+                host_from: &src_host,
                 channel: &channel,
-                message: &leave_message,
+                message: leave_message,
             };
             if irc_channel.remove_member(&client_id).is_some() {
-                let bm = BroadcastIrcMessage::new_with_sender(part_msg.format(), client_id);
+                let bm = IrcMessage::new_with_sender(part_msg.format(), client_id);
                 irc_channel.broadcast_message(bm);
                 // irc_channel.broadcast_message(message);
                 user_state.leave_channel(&channel).await;
@@ -347,7 +761,7 @@ pub async fn handle_part_channel(
                     nick: &nick_from,
                     channel: &channel,
                 };
-                let dm = DirectIrcMessage::new(err_msg.format());
+                let dm = IrcMessage::new(err_msg.format());
                 let _ = user_state.tx_outbound.send(dm).await;
             }
         } else {
@@ -355,9 +769,1571 @@ pub async fn handle_part_channel(
                 nick: &nick_from,
                 channel: &channel,
             };
-            let dm = DirectIrcMessage::new(err_msg.format());
+            let dm = IrcMessage::new(err_msg.format());
             let _ = user_state.tx_outbound.send(dm).await;
         }
     }
     Ok(UserStatus::Active)
 }
+
+pub async fn handle_topic_channel(
+    channel_name: ChannelName,
+    topic: Option<Topic>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.2.4 Topic message
+    //    Numeric Replies:
+
+    //            ERR_NEEDMOREPARAMS              ERR_NOTONCHANNEL ✅
+    //            RPL_NOTOPIC ✅                  RPL_TOPIC ✅
+    //            ERR_CHANOPRIVSNEEDED ✅         ERR_NOCHANMODES
+    //            ERR_RESTRICTED ✅
+    if !require_valid_channel(&channel_name, user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let caracs = user_state.get_caracs().await;
+    let host_from = &caracs.displayed_host();
+    let nick_from = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    let user_from = caracs.user.clone().unwrap_or(Username("*".to_owned()));
+    let Some(channel) = server_state.get_channel(&channel_name) else {
+        let irc_reply = IrcReply::ErrNoSuchChannel {
+            nick: &nick_from,
+            channel: &channel_name,
+        };
+        let err_no_such_channel = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_such_channel).await;
+        return Ok(UserStatus::Active);
+    };
+    let is_member = channel.members.contains(&client_id);
+    let hidden = {
+        let modes = channel.modes.read().await;
+        modes.secret || modes.private
+    };
+    if !is_member && (topic.is_some() || hidden) {
+        // Secret and private channels keep their topic from outsiders, as LIST and NAMES do
+        let irc_reply = IrcReply::ErrNotOnChannel {
+            nick: &nick_from,
+            channel: &channel.name,
+        };
+        let err_not_on_channel = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_not_on_channel).await;
+        return Ok(UserStatus::Active);
+    }
+    let Some(topic) = topic else {
+        // Viewing the topic
+        let potential_topic = channel.topic.read().await.clone();
+        let irc_reply = match &potential_topic {
+            Some(topic) => IrcReply::Topic {
+                nick: &nick_from,
+                channel: &channel.name,
+                topic,
+            },
+            None => IrcReply::NoTopic {
+                nick: &nick_from,
+                channel: &channel.name,
+            },
+        };
+        let topic_message = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(topic_message).await;
+        return Ok(UserStatus::Active);
+    };
+    let topic_lock = channel.modes.read().await.topic_lock;
+    if topic_lock && !require_unrestricted(user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    if topic_lock && let Err(irc_reply) = channel.require_operator(client_id, &nick_from) {
+        let err_chanop_privs_needed = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_chanop_privs_needed).await;
+        return Ok(UserStatus::Active);
+    }
+    // Over-long topics are refused rather than cut at an arbitrary byte
+    let max_topic_length = server_state.config.read().await.get_max_topic_length();
+    let topic_length = topic.0.chars().count();
+    if topic_length > max_topic_length {
+        let text = format!(
+            "*** Topic for {} not changed: {topic_length} characters, TOPICLEN is {max_topic_length}",
+            channel.name
+        );
+        let irc_reply = IrcReply::ServerNotice {
+            nick: &nick_from,
+            text: &text,
+        };
+        let topic_too_long = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(topic_too_long).await;
+        return Ok(UserStatus::Active);
+    }
+
+    let mrep = MessageReply::Topic {
+        nick_from: &nick_from,
+        user_from: &user_from,
+        host_from,
+        channel: &channel.name,
+        topic: &topic.0,
+    };
+    // An empty topic removes it
+    *channel.topic.write().await = Some(topic.clone()).filter(|topic| !topic.0.is_empty());
+    *channel.topic_set_by.write().await = Some(client_id.0);
+    *channel.topic_set_at.write().await = Some(unix_timestamp());
+    channel.broadcast_message(IrcMessage::new(mrep.format()));
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_invite_channel(
+    target_nick: Nickname,
+    channel_name: ChannelName,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.2.7 Invite message
+    //    Numeric Replies:
+
+    //            ERR_NEEDMOREPARAMS              ERR_NOSUCHNICK ✅
+    //            ERR_NOTONCHANNEL ✅             ERR_USERONCHANNEL ✅
+    //            ERR_CHANOPRIVSNEEDED ✅
+    //            RPL_INVITING ✅                 RPL_AWAY
+    if !require_valid_channel(&channel_name, user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let caracs = user_state.get_caracs().await;
+    let host_from = &caracs.displayed_host();
+    let nick_from = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    let user_from = caracs.user.unwrap_or(Username("*".to_owned()));
+
+    let target_opt = server_state
+        .nick_holder(&target_nick)
+        .and_then(|target_id| server_state.get_user_state_from_client_id(&target_id));
+    let Some(target_state) = target_opt else {
+        let irc_reply = IrcReply::ErrNoSuchNick {
+            nick: &nick_from,
+            target: &target_nick.0,
+        };
+        let err_no_such_nick = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_such_nick).await;
+        return Ok(UserStatus::Active);
+    };
+    let target_id = target_state.get_user_id().await;
+
+    // There is no requirement that the channel exists, but if it does only
+    // members may invite, and only operators on an invite-only channel.
+    let channel_opt = server_state.get_channel(&channel_name);
+    if let Some(channel) = &channel_opt {
+        let error = if !channel.members.contains(&client_id) {
+            Some(IrcReply::ErrNotOnChannel {
+                nick: &nick_from,
+                channel: &channel_name,
+            })
+        } else if channel.members.contains(&target_id) {
+            Some(IrcReply::ErrUserOnChannel {
+                nick: &nick_from,
+                target: &target_nick,
+                channel: &channel_name,
+            })
+        } else if channel.modes.read().await.invite_only
+            && let Err(err_chan_o_privs_needed) = channel.require_operator(client_id, &nick_from)
+        {
+            Some(err_chan_o_privs_needed)
+        } else {
+            None
+        };
+        if let Some(irc_reply) = error {
+            let err_message = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(err_message).await;
+            return Ok(UserStatus::Active);
+        }
+        channel.invited.insert(target_id);
+    }
+
+    let irc_reply = IrcReply::Inviting {
+        nick: &nick_from,
+        channel: &channel_name,
+        target: &target_nick,
+    };
+    let inviting_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(inviting_message).await;
+    let invite = MessageReply::Invite {
+        nick_from: &nick_from,
+        user_from: &user_from,
+        host_from,
+        nick_to: &target_nick,
+        channel: &channel_name,
+    };
+    let invite_message = IrcMessage::new(invite.format());
+    let _ = target_state.tx_outbound.send(invite_message).await;
+    info!("[{client_id}] invited {target_nick} to {channel_name}");
+    Ok(UserStatus::Active)
+}
+
+/// INVITE without parameters: the channels `client_id` has a pending
+/// invitation to, until its next JOIN of them consumes it.
+pub async fn handle_invite_list(
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    let nick = user_state
+        .get_caracs()
+        .await
+        .nick
+        .unwrap_or(Nickname("*".to_owned()));
+    let mut channels = server_state
+        .channels
+        .iter()
+        .filter(|channel| channel.invited.contains(&client_id))
+        .map(|channel| channel.key().clone())
+        .collect::<Vec<_>>();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+    for channel in &channels {
+        let irc_reply = IrcReply::InvitedList {
+            nick: &nick,
+            channel,
+        };
+        let invited_list = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(invited_list).await;
+    }
+    let irc_reply = IrcReply::EndOfInvitedList { nick: &nick };
+    let end_of_invited_list = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(end_of_invited_list).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_kick_channel(
+    channels: Vec<ChannelName>,
+    users: Vec<Username>,
+    comment: Option<String>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.2.8 Kick command
+    //    Numeric Replies:
+
+    //            ERR_NEEDMOREPARAMS ✅           ERR_NOSUCHCHANNEL ✅
+    //            ERR_BADCHANMASK ✅              ERR_CHANOPRIVSNEEDED ✅
+    //            ERR_USERNOTINCHANNEL ✅         ERR_NOTONCHANNEL ✅
+    //            ERR_RESTRICTED ✅
+    if !require_unrestricted(user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let caracs = user_state.get_caracs().await;
+    let host_from = &caracs.displayed_host();
+    let nick_from = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    let user_from = caracs.user.unwrap_or(Username("*".to_owned()));
+    let comment = comment.unwrap_or(nick_from.0.clone());
+
+    // Either one channel and many users, or as many channels as users
+    let kicks = if channels.len() == 1 {
+        users
+            .into_iter()
+            .map(|user| (channels[0].clone(), user))
+            .collect::<Vec<_>>()
+    } else if channels.len() == users.len() {
+        channels.into_iter().zip(users).collect()
+    } else {
+        let irc_reply = IrcReply::ErrNeedMoreParams {
+            nick: &nick_from,
+            command: "KICK",
+        };
+        let err_need_more_params = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_need_more_params).await;
+        return Ok(UserStatus::Active);
+    };
+
+    for (channel_name, target) in kicks {
+        if !require_valid_channel(&channel_name, user_state).await {
+            continue;
+        }
+        let Some(channel) = server_state.get_channel(&channel_name) else {
+            let irc_reply = IrcReply::ErrNoSuchChannel {
+                nick: &nick_from,
+                channel: &channel_name,
+            };
+            let err_no_such_channel = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(err_no_such_channel).await;
+            continue;
+        };
+        if !channel.members.contains(&client_id) {
+            let irc_reply = IrcReply::ErrNotOnChannel {
+                nick: &nick_from,
+                channel: &channel_name,
+            };
+            let err_not_on_channel = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(err_not_on_channel).await;
+            continue;
+        }
+        if let Err(irc_reply) = channel.require_operator(client_id, &nick_from) {
+            let err_chan_o_privs_needed = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(err_chan_o_privs_needed).await;
+            continue;
+        }
+        let target_nick = Nickname(target.0);
+        let target_opt = server_state
+            .nick_holder(&target_nick)
+            .filter(|target_id| channel.members.contains(target_id))
+            .and_then(|target_id| server_state.get_user_state_from_client_id(&target_id));
+        let Some(target_state) = target_opt else {
+            let irc_reply = IrcReply::ErrUserNotInChannel {
+                nick: &nick_from,
+                target: &target_nick.0,
+                channel: &channel_name,
+            };
+            let err_user_not_in_channel = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(err_user_not_in_channel).await;
+            continue;
+        };
+        let target_id = target_state.get_user_id().await;
+        let kick = MessageReply::Kick {
+            nick_from: &nick_from,
+            user_from: &user_from,
+            host_from,
+            channel: &channel_name,
+            nick_to: &target_nick,
+            comment: &comment,
+        };
+        // The kicked user gets its copy directly: it is unsubscribed right after
+        let kick_line = kick.format();
+        let _ = target_state
+            .tx_outbound
+            .send(IrcMessage::new(kick_line.clone()))
+            .await;
+        channel.broadcast_message(IrcMessage::new_with_sender(kick_line, target_id));
+        channel.operators.remove(&target_id);
+        channel.voiced.remove(&target_id);
+        target_state.leave_channel(&channel_name).await;
+        let _ = target_state
+            .tx_control
+            .send(SubscriptionControl::Unsubscribe(channel_name.clone()))
+            .await;
+        server_state.quit_channel(&target_id, &channel_name).await;
+        info!("[{client_id}] kicked {target_nick} from {channel_name}");
+    }
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_channel_mode_query(
+    channel_name: ChannelName,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.2.3 Channel mode message, without mode changes: the server replies
+    // with the current modes (RPL_CHANNELMODEIS) followed by the channel
+    // creation time (RPL_CREATIONTIME).
+    if !require_valid_channel(&channel_name, user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    let Some(channel) = server_state.get_channel(&channel_name) else {
+        let irc_reply = IrcReply::ErrNoSuchChannel {
+            nick: &nick,
+            channel: &channel_name,
+        };
+        let err_no_such_channel = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_such_channel).await;
+        return Ok(UserStatus::Active);
+    };
+    let is_member = channel.members.contains(&client_id);
+    let modes = channel.modes.read().await.to_mode_string(is_member);
+    let irc_reply = IrcReply::ChannelModeIs {
+        nick: &nick,
+        channel: &channel_name,
+        modes: &modes,
+    };
+    let channel_mode_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(channel_mode_message).await;
+    let irc_reply = IrcReply::CreationTime {
+        nick: &nick,
+        channel: &channel_name,
+        created_at: channel.created_at,
+    };
+    let creation_time_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(creation_time_message).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_channel_mode_change(
+    channel_name: ChannelName,
+    changes: Vec<ChannelModeChange>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.2.3 Channel mode message
+    //    Numeric Replies:
+
+    //            ERR_NEEDMOREPARAMS              ERR_KEYSET
+    //            ERR_NOCHANMODES                 ERR_CHANOPRIVSNEEDED ✅
+    //            ERR_USERNOTINCHANNEL            ERR_UNKNOWNMODE
+    //            RPL_CHANNELMODEIS
+    //            RPL_BANLIST                     RPL_ENDOFBANLIST
+    //            RPL_EXCEPTLIST                  RPL_ENDOFEXCEPTLIST
+    //            RPL_INVITELIST ✅               RPL_ENDOFINVITELIST ✅
+    //            RPL_UNIQOPIS
+    //            RPL_QUIETLIST ✅                RPL_ENDOFQUIETLIST ✅
+    //            ERR_RESTRICTED ✅
+    if !require_valid_channel(&channel_name, user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.clone().nick.unwrap_or(Nickname("*".to_owned()));
+    let user = caracs.clone().user.unwrap_or(Username("*".to_owned()));
+    let Some(channel) = server_state.get_channel(&channel_name) else {
+        let irc_reply = IrcReply::ErrNoSuchChannel {
+            nick: &nick,
+            channel: &channel_name,
+        };
+        let err_no_such_channel = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_such_channel).await;
+        return Ok(UserStatus::Active);
+    };
+
+    // A list mode without a mask lists it, which needs no privileges
+    let (queries, changes): (Vec<_>, Vec<_>) = changes
+        .into_iter()
+        .partition(|change| change.param.is_none() && matches!(change.mode, 'q' | 'I'));
+    for query in queries {
+        let mut masks = {
+            let modes = channel.modes.read().await;
+            let list = match query.mode {
+                'I' => &modes.invite_exceptions,
+                _ => &modes.quiet_list,
+            };
+            list.iter().map(|mask| mask.clone()).collect::<Vec<_>>()
+        };
+        masks.sort();
+        let mut replies = masks
+            .iter()
+            .map(|mask| match query.mode {
+                'I' => IrcReply::InviteList {
+                    nick: &nick,
+                    channel: &channel_name,
+                    mask,
+                },
+                _ => IrcReply::QuietList {
+                    nick: &nick,
+                    channel: &channel_name,
+                    mask,
+                },
+            })
+            .collect::<Vec<_>>();
+        replies.push(match query.mode {
+            'I' => IrcReply::EndOfInviteList {
+                nick: &nick,
+                channel: &channel_name,
+            },
+            _ => IrcReply::EndOfQuietList {
+                nick: &nick,
+                channel: &channel_name,
+            },
+        });
+        for irc_reply in replies {
+            let list_message = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(list_message).await;
+        }
+    }
+    if changes.is_empty() || !require_unrestricted(user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    if let Err(irc_reply) = channel.require_operator(client_id, &nick) {
+        let err_chanop_privs_needed = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_chanop_privs_needed).await;
+        return Ok(UserStatus::Active);
+    }
+
+    // Apply the changes, keeping the ones that took effect for the broadcast
+    let mut applied: Vec<(bool, char, Option<String>)> = Vec::new();
+    for ChannelModeChange {
+        adding,
+        mode,
+        param,
+    } in changes
+    {
+        let mut modes = channel.modes.write().await;
+        let flag = match mode {
+            'a' => Some(&mut modes.anonymous),
+            'c' => Some(&mut modes.no_colors),
+            'i' => Some(&mut modes.invite_only),
+            'm' => Some(&mut modes.moderated),
+            'n' => Some(&mut modes.no_external_msgs),
+            'p' => Some(&mut modes.private),
+            's' => Some(&mut modes.secret),
+            't' => Some(&mut modes.topic_lock),
+            'z' => Some(&mut modes.op_moderated),
+            _ => None,
+        };
+        if let Some(flag) = flag {
+            if *flag != adding {
+                *flag = adding;
+                applied.push((adding, mode, None));
+            }
+            continue;
+        }
+        match (mode, param) {
+            ('k', Some(key)) if adding => {
+                modes.key = Some(key.clone());
+                applied.push((true, 'k', Some(key)));
+            }
+            ('k', _) if !adding && modes.key.is_some() => {
+                modes.key = None;
+                applied.push((false, 'k', Some("*".to_owned())));
+            }
+            ('l', Some(limit)) if adding => {
+                if let Ok(limit) = limit.parse::<usize>() {
+                    modes.user_limit = Some(limit);
+                    applied.push((true, 'l', Some(limit.to_string())));
+                }
+            }
+            ('l', None) if !adding && modes.user_limit.is_some() => {
+                modes.user_limit = None;
+                applied.push((false, 'l', None));
+            }
+            // Forwarding to itself would only use up the hops
+            ('f', Some(forward))
+                if adding && validate_channel_name(&forward) && forward != channel_name.0 =>
+            {
+                modes.forward = Some(ChannelName(forward.clone()));
+                applied.push((true, 'f', Some(forward)));
+            }
+            ('f', None) if !adding && modes.forward.is_some() => {
+                modes.forward = None;
+                applied.push((false, 'f', None));
+            }
+            ('b' | 'e' | 'I' | 'q', Some(mask)) => {
+                let mask = normalize_hostmask(&mask);
+                let masks = match mode {
+                    'b' => &modes.ban_list,
+                    'e' => &modes.except_list,
+                    'I' => &modes.invite_exceptions,
+                    _ => &modes.quiet_list,
+                };
+                let changed = if adding {
+                    masks.insert(mask.clone())
+                } else {
+                    masks.remove(&mask).is_some()
+                };
+                if changed {
+                    applied.push((adding, mode, Some(mask)));
+                }
+            }
+            ('o' | 'v', Some(target)) => {
+                drop(modes);
+                let target_nick = Nickname(target.clone());
+                let Some(target_id) = server_state.get_cliend_id_from_nick(&target_nick) else {
+                    let irc_reply = IrcReply::ErrNoSuchNick {
+                        nick: &nick,
+                        target: &target,
+                    };
+                    let err_no_such_nick = IrcMessage::new(irc_reply.format());
+                    let _ = user_state.tx_outbound.send(err_no_such_nick).await;
+                    continue;
+                };
+                if !channel.members.contains(&target_id) {
+                    continue;
+                }
+                let status = if mode == 'o' {
+                    &channel.operators
+                } else {
+                    &channel.voiced
+                };
+                let changed = if adding {
+                    status.insert(target_id)
+                } else {
+                    status.remove(&target_id).is_some()
+                };
+                if changed {
+                    applied.push((adding, mode, Some(target)));
+                }
+            }
+            _ => (),
+        }
+    }
+    if applied.is_empty() {
+        return Ok(UserStatus::Active);
+    }
+
+    // e.g. "+qo-t bob!*@* alice"
+    let mut mode_string = String::new();
+    let mut params = Vec::new();
+    let mut current_sign = None;
+    for (adding, mode, param) in applied {
+        let sign = if adding { '+' } else { '-' };
+        if current_sign != Some(sign) {
+            mode_string.push(sign);
+            current_sign = Some(sign);
+        }
+        mode_string.push(mode);
+        params.extend(param);
+    }
+    params.insert(0, mode_string);
+    let message_reply = MessageReply::ChannelMode {
+        nick_from: &nick,
+        user_from: &user,
+        host_from: &caracs.displayed_host(),
+        channel: &channel_name,
+        modes: &params.join(" "),
+    };
+    channel.broadcast_message(IrcMessage::new(message_reply.format()));
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_list_channel(
+    filters: Vec<ListFilter>,
+    target: Option<String>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.2.6 List message
+
+    //       Command: LIST
+    //    Parameters: [ <channel> *( "," <channel> ) [ <target> ] ]
+
+    //    The list command is used to list channels and their topics.  If the
+    //    <channel> parameter is used, only the status of that channel is
+    //    displayed.
+
+    //    Numeric Replies:
+
+    //            ERR_TOOMANYMATCHES              ERR_NOSUCHSERVER ✅
+    //            RPL_LIST ✅                       RPL_LISTEND ✅
+    if !require_local_target(target.as_deref(), user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    let now = unix_timestamp();
+    let list_max = server_state.config.read().await.get_list_max();
+    let channels = server_state
+        .channels
+        .iter()
+        .map(|entry| Arc::clone(entry.value()))
+        .collect::<Vec<Arc<IrcChannel>>>();
+
+    let mut listed = 0;
+    for channel in channels {
+        {
+            // secret and private channels are only listed to their members
+            let modes = channel.modes.read().await;
+            if (modes.secret || modes.private) && !channel.members.contains(&client_id) {
+                continue;
+            }
+        }
+        if !list_filters_match(&filters, &channel, now).await {
+            continue;
+        }
+        if listed == list_max {
+            let irc_reply = IrcReply::ServerNotice {
+                nick: &nick,
+                text: &format!(
+                    "*** LIST stopped after {list_max} channels, narrow it down with a mask or ELIST filters"
+                ),
+            };
+            let _ = user_state
+                .tx_outbound
+                .send(IrcMessage::new(irc_reply.format()))
+                .await;
+            break;
+        }
+        listed += 1;
+        let topic = channel.topic.read().await.clone();
+        let topic = topic.map(|t| t.0).unwrap_or_default();
+        let irc_reply = IrcReply::List {
+            nick: &nick,
+            channel: &channel.name,
+            visible: channel.members.len(),
+            topic: &topic,
+        };
+        let list_message = IrcMessage::new(irc_reply.format());
+        // Waits while the client's outbound queue is full, one line at a time
+        let _ = user_state.tx_outbound.send(list_message).await;
+    }
+    let irc_reply = IrcReply::ListEnd { nick: &nick };
+    let list_end_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(list_end_message).await;
+    Ok(UserStatus::Active)
+}
+
+async fn list_filters_match(filters: &[ListFilter], channel: &IrcChannel, now: u64) -> bool {
+    // Name masks are alternatives (`LIST #a,#b` lists both channels),
+    // every other ELIST filter must hold (AND semantics).
+    let mut masks = filters.iter().filter_map(|filter| match filter {
+        ListFilter::Mask(mask) => Some(mask),
+        _ => None,
+    });
+    let has_masks = masks.clone().next().is_some();
+    if has_masks && !masks.any(|mask| wildcard_match(mask, &channel.name.0)) {
+        return false;
+    }
+    let member_count = channel.members.len();
+    let topic_set_at = *channel.topic_set_at.read().await;
+    let age_minutes = |since: u64| now.saturating_sub(since) / 60;
+    filters.iter().all(|filter| match filter {
+        ListFilter::Mask(_) => true,
+        ListFilter::NotMask(mask) => !wildcard_match(mask, &channel.name.0),
+        ListFilter::MoreUsersThan(count) => member_count > *count,
+        ListFilter::FewerUsersThan(count) => member_count < *count,
+        ListFilter::CreatedWithin(minutes) => age_minutes(channel.created_at) < *minutes,
+        ListFilter::CreatedBefore(minutes) => age_minutes(channel.created_at) > *minutes,
+        ListFilter::TopicWithin(minutes) => {
+            topic_set_at.is_some_and(|at| age_minutes(at) < *minutes)
+        }
+        ListFilter::TopicBefore(minutes) => {
+            topic_set_at.is_some_and(|at| age_minutes(at) > *minutes)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        channels_models::IrcChannel,
+        config::ChannelsConfig,
+        server_state::ServerState,
+        test_utils::{TestClient, has_numeric, numeric},
+        types::{ChannelName, Topic},
+        utils::{self, unix_timestamp},
+    };
+
+    #[tokio::test]
+    async fn test_join_over_list_limit_returns_407() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.limits.max_join_list = Some(2);
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice.send(&server_state, "JOIN #a,#b,#c").await.unwrap();
+
+        let replies = alice.drain();
+        assert!(has_numeric(&replies, "407"), "{replies:?}");
+        assert!(server_state.channels_exists(&ChannelName("#a".to_owned())));
+        assert!(server_state.channels_exists(&ChannelName("#b".to_owned())));
+        assert!(!server_state.channels_exists(&ChannelName("#c".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn test_mode_query_returns_creation_time() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let before = unix_timestamp();
+        alice.send(&server_state, "JOIN #fresh").await.unwrap();
+        alice.drain();
+
+        alice.send(&server_state, "MODE #fresh").await.unwrap();
+
+        let replies = alice.drain();
+        assert!(has_numeric(&replies, "324"), "{replies:?}");
+        let creation_time = replies
+            .iter()
+            .find(|l| numeric(l) == Some("329"))
+            .expect("RPL_CREATIONTIME");
+        assert!(creation_time.contains(" 329 alice #fresh "));
+        let created_at: u64 = creation_time.rsplit(' ').next().unwrap().parse().unwrap();
+        assert!(created_at >= before && created_at <= unix_timestamp());
+    }
+
+    #[tokio::test]
+    async fn test_mode_query_unknown_channel() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "MODE #nowhere").await.unwrap();
+        assert!(has_numeric(&alice.drain(), "403"));
+    }
+
+    #[tokio::test]
+    async fn test_names_hides_invisible_members_from_outsiders() {
+        let server_state = ServerState::default();
+        let mut ghost = TestClient::registered(&server_state, "ghost").await;
+        let mut friend = TestClient::registered(&server_state, "friend").await;
+        let mut stranger = TestClient::registered(&server_state, "stranger").await;
+        ghost.user_state.user.write().await.modes.insert('i');
+        ghost.send(&server_state, "JOIN #haunt").await.unwrap();
+        friend.send(&server_state, "JOIN #haunt").await.unwrap();
+        friend.drain();
+
+        stranger.send(&server_state, "NAMES #haunt").await.unwrap();
+        let replies = stranger.drain();
+        let names = replies.iter().find(|l| numeric(l) == Some("353")).unwrap();
+        assert!(names.ends_with(" #haunt :friend"), "{names}");
+
+        friend.send(&server_state, "NAMES #haunt").await.unwrap();
+        let replies = friend.drain();
+        let names = replies.iter().find(|l| numeric(l) == Some("353")).unwrap();
+        assert!(names.contains("@ghost"), "{names}");
+    }
+
+    #[tokio::test]
+    async fn test_oper_only_create_refuses_regular_users() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.channels = Some(ChannelsConfig {
+            oper_only_create: Some(true),
+            chantypes: None,
+            auto_join: None,
+        });
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut oper = TestClient::registered(&server_state, "oper").await;
+        oper.user_state.user.write().await.modes.insert('o');
+
+        alice.send(&server_state, "JOIN #new").await.unwrap();
+        assert!(has_numeric(&alice.drain(), "403"));
+        assert!(!server_state.channels_exists(&ChannelName("#new".to_owned())));
+
+        oper.send(&server_state, "JOIN #new").await.unwrap();
+        assert!(server_state.channels_exists(&ChannelName("#new".to_owned())));
+
+        // existing channels stay open to everyone
+        alice.send(&server_state, "JOIN #new").await.unwrap();
+        assert!(!has_numeric(&alice.drain(), "403"));
+        assert_eq!(
+            server_state
+                .get_channel(&ChannelName("#new".to_owned()))
+                .unwrap()
+                .members
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_names_is_batched_for_capable_clients() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.send(&server_state, "NAMES #chan").await.unwrap();
+        let unbatched = alice.drain();
+        assert!(unbatched.iter().all(|l| !l.contains("BATCH")));
+
+        alice.send(&server_state, "CAP REQ :batch").await.unwrap();
+        alice.drain();
+        alice.send(&server_state, "NAMES #chan").await.unwrap();
+
+        let replies = alice.drain();
+        assert_eq!(replies.len(), 4, "{replies:?}");
+        let reference = replies[0]
+            .strip_prefix(":unknown.server BATCH +")
+            .and_then(|rest| rest.strip_suffix(" names"))
+            .expect("batch start");
+        assert_eq!(
+            replies[1],
+            format!("@batch={reference} :unknown.server 353 alice = #chan :@alice")
+        );
+        assert!(replies[2].starts_with(&format!("@batch={reference} ")));
+        assert!(replies[2].ends_with(" #chan :End of NAMES list"));
+        assert_eq!(replies[3], format!(":unknown.server BATCH -{reference}"));
+    }
+
+    fn listed_channels(replies: &[String]) -> Vec<String> {
+        let mut channels = replies
+            .iter()
+            .filter(|l| numeric(l) == Some("322"))
+            .map(|l| l.split(' ').nth(3).unwrap().to_owned())
+            .collect::<Vec<_>>();
+        channels.sort();
+        channels
+    }
+
+    #[tokio::test]
+    async fn test_list_stops_at_list_max() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.limits.list_max = Some(5);
+        for i in 0..50 {
+            let name = ChannelName(format!("#chan{i}"));
+            let channel = Arc::new(IrcChannel::new(name.clone()));
+            server_state.channels.insert(name, channel);
+        }
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice.send(&server_state, "LIST").await.unwrap();
+        let replies = alice.drain();
+        assert_eq!(listed_channels(&replies).len(), 5);
+        assert_eq!(replies.len(), 7, "{replies:?}");
+        assert!(replies[5].contains(" NOTICE alice :*** LIST stopped after 5 channels"));
+        assert_eq!(numeric(&replies[6]), Some("323"));
+
+        // Narrowed down, nothing is cut
+        alice
+            .send(&server_state, "LIST #chan1,#chan2")
+            .await
+            .unwrap();
+        let replies = alice.drain();
+        assert_eq!(listed_channels(&replies), vec!["#chan1", "#chan2"]);
+        assert_eq!(replies.len(), 3);
+    }
+
+    async fn list_fixture() -> (ServerState, TestClient) {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        alice.send(&server_state, "JOIN #big,#solo").await.unwrap();
+        bob.send(&server_state, "JOIN #big,#small").await.unwrap();
+        carol.send(&server_state, "JOIN #big").await.unwrap();
+        alice.drain();
+        (server_state, alice)
+    }
+
+    #[tokio::test]
+    async fn test_list_user_count_filter() {
+        let (server_state, mut alice) = list_fixture().await;
+
+        alice.send(&server_state, "LIST >2").await.unwrap();
+        let replies = alice.drain();
+        assert_eq!(listed_channels(&replies), vec!["#big"]);
+        assert!(has_numeric(&replies, "323"));
+
+        alice.send(&server_state, "LIST <2").await.unwrap();
+        assert_eq!(listed_channels(&alice.drain()), vec!["#small", "#solo"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_name_mask_filter() {
+        let (server_state, mut alice) = list_fixture().await;
+
+        alice.send(&server_state, "LIST #s*").await.unwrap();
+        assert_eq!(listed_channels(&alice.drain()), vec!["#small", "#solo"]);
+
+        alice
+            .send(&server_state, "LIST #s*,<2,!*ma*")
+            .await
+            .unwrap();
+        assert_eq!(listed_channels(&alice.drain()), vec!["#solo"]);
+
+        alice.send(&server_state, "LIST").await.unwrap();
+        assert_eq!(
+            listed_channels(&alice.drain()),
+            vec!["#big", "#small", "#solo"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quieted_user_cannot_talk_in_channel() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        bob.send(&server_state, "JOIN #chan").await.unwrap();
+        carol.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.drain();
+        bob.drain();
+        carol.drain();
+
+        alice
+            .send(&server_state, "MODE #chan +q bob")
+            .await
+            .unwrap();
+        let mode_line = ":alice!alice@127.0.0.1 MODE #chan +q bob!*@*";
+        assert_eq!(carol.drain(), vec![mode_line]);
+        bob.drain();
+        alice.send(&server_state, "MODE #chan +q").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server 728 alice #chan q bob!*@*",
+                ":unknown.server 729 alice #chan q :End of channel quiet list",
+                mode_line,
+            ]
+        );
+
+        bob.send(&server_state, "PRIVMSG #chan :can you hear me?")
+            .await
+            .unwrap();
+        assert!(has_numeric(&bob.drain(), "404"));
+        assert!(carol.drain().is_empty());
+
+        carol
+            .send(&server_state, "PRIVMSG #chan :loud and clear")
+            .await
+            .unwrap();
+        let received = bob.drain();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].ends_with(" PRIVMSG #chan :loud and clear"));
+
+        bob.send(&server_state, "MODE #chan -q bob").await.unwrap();
+        assert!(has_numeric(&bob.drain(), "482"));
+    }
+
+    #[tokio::test]
+    async fn test_invite_current_member_returns_443() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        bob.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.drain();
+        bob.drain();
+
+        alice.send(&server_state, "INVITE bob #chan").await.unwrap();
+
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 443 alice bob #chan :is already on channel"]
+        );
+        assert!(bob.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invite_lets_user_into_invite_only_channel() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.send(&server_state, "MODE #chan +i").await.unwrap();
+        bob.send(&server_state, "JOIN #chan").await.unwrap();
+        assert!(has_numeric(&bob.drain(), "473"));
+        alice.drain();
+
+        alice.send(&server_state, "INVITE bob #chan").await.unwrap();
+
+        assert_eq!(alice.drain(), vec![":unknown.server 341 alice #chan bob"]);
+        assert_eq!(
+            bob.drain(),
+            vec![":alice!alice@127.0.0.1 INVITE bob :#chan"]
+        );
+        bob.send(&server_state, "INVITE").await.unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![
+                ":unknown.server 336 bob #chan",
+                ":unknown.server 337 bob :End of /INVITE list",
+            ]
+        );
+        bob.send(&server_state, "JOIN #chan").await.unwrap();
+        assert!(!has_numeric(&bob.drain(), "473"));
+        // The JOIN used the invitation up
+        bob.send(&server_state, "INVITE").await.unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![":unknown.server 337 bob :End of /INVITE list"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invite_exception_masks_are_listed_and_let_in() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut good = TestClient::registered(&server_state, "good").await;
+        let mut bad = TestClient::registered(&server_state, "bad").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        alice
+            .send(&server_state, "MODE #chan +iI good")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain().last().unwrap(),
+            ":alice!alice@127.0.0.1 MODE #chan +iI good!*@*"
+        );
+
+        alice.send(&server_state, "MODE #chan +I").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server 346 alice #chan good!*@*",
+                ":unknown.server 347 alice #chan :End of channel invite list",
+            ]
+        );
+
+        good.send(&server_state, "JOIN #chan").await.unwrap();
+        assert!(!has_numeric(&good.drain(), "473"));
+        bad.send(&server_state, "JOIN #chan").await.unwrap();
+        assert!(has_numeric(&bad.drain(), "473"));
+    }
+
+    #[tokio::test]
+    async fn test_kick_non_member_returns_441() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        carol.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.drain();
+        carol.drain();
+
+        alice.send(&server_state, "KICK #chan bob").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 441 alice bob #chan :They aren't on that channel"]
+        );
+        assert!(bob.drain().is_empty());
+
+        alice
+            .send(&server_state, "KICK #chan carol :bye")
+            .await
+            .unwrap();
+        let kick_line = ":alice!alice@127.0.0.1 KICK #chan carol :bye";
+        assert_eq!(alice.drain(), vec![kick_line]);
+        assert_eq!(carol.drain(), vec![kick_line]);
+        let channel = server_state.get_channel(&ChannelName("#chan".to_owned()));
+        assert_eq!(channel.unwrap().members.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_safe_channel_gets_server_assigned_id() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+
+        alice.send(&server_state, "JOIN !!rust").await.unwrap();
+        let join = alice
+            .drain()
+            .into_iter()
+            .find(|l| l.contains(" JOIN "))
+            .unwrap();
+        let full_name = join.rsplit(':').next().unwrap().to_owned();
+        assert_eq!(full_name.len(), "!ABCDErust".len());
+        assert!(utils::is_safe_channel_id(&full_name));
+        assert!(full_name.ends_with("rust"));
+
+        // The short name resolves to the same channel, but can't be created twice
+        bob.send(&server_state, "JOIN !rust").await.unwrap();
+        assert!(
+            bob.drain()
+                .contains(&format!(":bob!bob@127.0.0.1 JOIN :{full_name}"))
+        );
+        bob.send(&server_state, "JOIN !!rust").await.unwrap();
+        assert!(has_numeric(&bob.drain(), "437"));
+        let channel = server_state.get_channel(&ChannelName(full_name)).unwrap();
+        assert_eq!(channel.members.len(), 2);
+
+        bob.send(&server_state, "JOIN !nope").await.unwrap();
+        assert!(has_numeric(&bob.drain(), "403"));
+    }
+
+    #[tokio::test]
+    async fn test_rejoin_resends_names_without_broadcast() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #again").await.unwrap();
+        bob.send(&server_state, "JOIN #again").await.unwrap();
+        alice.drain();
+        bob.drain();
+
+        bob.send(&server_state, "JOIN #again").await.unwrap();
+
+        let replies = bob.drain();
+        assert!(has_numeric(&replies, "331"), "{replies:?}");
+        assert!(
+            replies
+                .iter()
+                .any(|l| l.contains(" #again :") && l.contains("@alice")),
+            "{replies:?}"
+        );
+        assert!(!replies.iter().any(|l| l.contains(" JOIN ")), "{replies:?}");
+        let channel = server_state
+            .get_channel(&ChannelName("#again".to_owned()))
+            .unwrap();
+        assert_eq!(channel.members.len(), 2);
+        // Nothing reached the channel broadcast either
+        assert!(alice.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_names_for_another_server_returns_402() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.drain();
+
+        alice
+            .send(&server_state, "NAMES #chan other.server")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 402 alice other.server :No such server"]
+        );
+
+        // Naming this server (or a mask of it) is the same as no target
+        alice
+            .send(&server_state, "NAMES #chan unknown.*")
+            .await
+            .unwrap();
+        assert!(has_numeric(&alice.drain(), "353"));
+    }
+
+    #[tokio::test]
+    async fn test_topic_over_topiclen_is_refused() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.limits.max_topic_length = Some(10);
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #topics").await.unwrap();
+        alice.drain();
+
+        alice
+            .send(&server_state, "TOPIC #topics :far too long for this")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server NOTICE alice :*** Topic for #topics not changed: 21 characters, TOPICLEN is 10"
+            ]
+        );
+        alice.send(&server_state, "TOPIC #topics").await.unwrap();
+        assert!(has_numeric(&alice.drain(), "331"));
+
+        alice
+            .send(&server_state, "TOPIC #topics :just fits")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":alice!alice@127.0.0.1 TOPIC #topics :just fits"]
+        );
+        alice.send(&server_state, "TOPIC #topics").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 332 alice #topics :just fits"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_topic_with_embedded_newline_stays_one_line() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #topics").await.unwrap();
+        alice.drain();
+        // As if a topic had slipped past the parser with a CRLF in it
+        let channel = server_state
+            .get_channel(&ChannelName("#topics".to_owned()))
+            .unwrap();
+        *channel.topic.write().await = Some(Topic("evil\r\nQUIT :injected\n".to_owned()));
+
+        alice.send(&server_state, "TOPIC #topics").await.unwrap();
+        let replies = alice.drain();
+        assert_eq!(
+            replies,
+            vec![":unknown.server 332 alice #topics :evil  QUIT :injected"]
+        );
+        assert!(!replies[0].contains(['\r', '\n']));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_channel_names_return_476() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        // The well-formed ones in a list are still joined
+        alice.send(&server_state, "JOIN ##ok,name,#").await.unwrap();
+        let replies = alice.drain();
+        assert!(replies.contains(&":unknown.server 476 alice name :Bad Channel Mask".to_owned()));
+        assert!(replies.contains(&":unknown.server 476 alice # :Bad Channel Mask".to_owned()));
+        assert!(server_state.channels_exists(&ChannelName("##ok".to_owned())));
+        assert!(!server_state.channels_exists(&ChannelName("name".to_owned())));
+        assert_eq!(server_state.channels.len(), 1);
+
+        let too_long = format!("#{}", "x".repeat(50));
+        for command in [
+            format!("JOIN {too_long}"),
+            "PART name".to_owned(),
+            "TOPIC name :hello".to_owned(),
+            "MODE # +t".to_owned(),
+            "KICK name bob".to_owned(),
+            "INVITE alice name".to_owned(),
+        ] {
+            alice.send(&server_state, &command).await.unwrap();
+            let replies = alice.drain();
+            assert_eq!(replies.len(), 1, "{command}: {replies:?}");
+            assert_eq!(numeric(&replies[0]), Some("476"), "{command}");
+        }
+        assert_eq!(server_state.channels.len(), 1);
+
+        // Well-formed but unknown stays 403
+        alice.send(&server_state, "PART #nowhere").await.unwrap();
+        assert!(has_numeric(&alice.drain(), "403"));
+    }
+
+    #[tokio::test]
+    async fn test_join_outside_chantypes_is_refused() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.channels = Some(ChannelsConfig {
+            oper_only_create: None,
+            chantypes: Some("#".to_owned()),
+            auto_join: None,
+        });
+        let mut alice = TestClient::connect(&server_state).await;
+        alice.register(&server_state, "alice").await;
+
+        alice
+            .send(&server_state, "JOIN &local,#chan")
+            .await
+            .unwrap();
+
+        let replies = alice.drain();
+        assert_eq!(
+            replies[0],
+            ":unknown.server 403 alice &local :No such channel"
+        );
+        assert!(
+            server_state
+                .get_channel(&ChannelName("&local".to_owned()))
+                .is_none()
+        );
+        assert!(
+            server_state
+                .get_channel(&ChannelName("#chan".to_owned()))
+                .is_some()
+        );
+
+        let mut bob = TestClient::connect(&server_state).await;
+        bob.send(&server_state, "NICK bob").await.unwrap();
+        bob.send(&server_state, "USER bob 0 * :bob").await.unwrap();
+        assert!(bob.drain().iter().any(|l| l.contains(" CHANTYPES=# ")));
+    }
+
+    #[tokio::test]
+    async fn test_names_lists_users_on_no_channel_under_star() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        let mut dave = TestClient::registered(&server_state, "dave").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        // Only in a secret channel alice isn't on
+        carol.send(&server_state, "JOIN #hidden").await.unwrap();
+        carol.send(&server_state, "MODE #hidden +s").await.unwrap();
+        // Invisible and sharing nothing with alice
+        dave.send(&server_state, "MODE dave +i").await.unwrap();
+        bob.drain();
+        alice.drain();
+
+        alice.send(&server_state, "NAMES").await.unwrap();
+
+        let replies = alice.drain();
+        assert_eq!(
+            replies[..2],
+            [
+                ":unknown.server 353 alice = #chan :@alice",
+                ":unknown.server 353 alice * * :bob carol",
+            ]
+        );
+        assert!(replies[2].ends_with(" alice * :End of NAMES list"));
+    }
+
+    #[tokio::test]
+    async fn test_full_channel_forwards_to_its_plus_f_target() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #full").await.unwrap();
+        alice
+            .send(&server_state, "MODE #full +lf 1 #overflow")
+            .await
+            .unwrap();
+        alice.drain();
+
+        bob.send(&server_state, "JOIN #full").await.unwrap();
+
+        let replies = bob.drain();
+        assert!(has_numeric(&replies, "471"), "{replies:?}");
+        assert!(
+            replies.contains(
+                &":unknown.server NOTICE bob :*** Cannot join #full, forwarding you to #overflow"
+                    .to_owned()
+            )
+        );
+        assert!(replies.contains(&":bob!bob@127.0.0.1 JOIN :#overflow".to_owned()));
+        let overflow = server_state
+            .get_channel(&ChannelName("#overflow".to_owned()))
+            .unwrap();
+        assert!(overflow.members.contains(&bob.client_id));
+
+        // Two invite-only channels forwarding to each other stop after the hop cap
+        for (channel, forward) in [("#a", "#b"), ("#b", "#a")] {
+            alice
+                .send(&server_state, &format!("JOIN {channel}"))
+                .await
+                .unwrap();
+            alice
+                .send(&server_state, &format!("MODE {channel} +if {forward}"))
+                .await
+                .unwrap();
+        }
+        bob.send(&server_state, "JOIN #a").await.unwrap();
+        let replies = bob.drain();
+        let refusals = replies.iter().filter(|l| numeric(l) == Some("473"));
+        assert_eq!(refusals.count(), 4, "{replies:?}");
+    }
+
+    #[tokio::test]
+    async fn test_forward_target_passes_the_join_checks() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.channels = Some(ChannelsConfig {
+            oper_only_create: None,
+            chantypes: Some("#".to_owned()),
+            auto_join: None,
+        });
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #full").await.unwrap();
+        alice
+            .send(&server_state, "MODE #full +lf 1 &overflow")
+            .await
+            .unwrap();
+        alice.drain();
+
+        bob.send(&server_state, "JOIN #full").await.unwrap();
+        let replies = bob.drain();
+        assert!(has_numeric(&replies, "471"), "{replies:?}");
+        assert!(
+            replies.contains(&":unknown.server 403 bob &overflow :No such channel".to_owned()),
+            "{replies:?}"
+        );
+        assert!(!server_state.channels_exists(&ChannelName("&overflow".to_owned())));
+        assert!(bob.user_state.get_caracs().await.member_of.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restricted_range_refuses_nick_and_kick() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.security =
+            Some(toml::from_str(r#"restrict_ranges = ["10.0.0.0/8"]"#).unwrap());
+        let mut alice =
+            TestClient::connect_from(&server_state, "10.1.2.3:50000".parse().unwrap()).await;
+        alice.register(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        assert!(alice.user_state.get_caracs().await.modes.contains(&'r'));
+        assert!(!bob.user_state.get_caracs().await.modes.contains(&'r'));
+
+        // alice created the channel, being chanop doesn't help
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        bob.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.drain();
+        bob.drain();
+        let err_restricted = ":unknown.server 484 alice :Your connection is restricted!";
+        alice.send(&server_state, "KICK #chan bob").await.unwrap();
+        assert_eq!(alice.drain(), vec![err_restricted]);
+        alice.send(&server_state, "NICK alicia").await.unwrap();
+        assert_eq!(alice.drain(), vec![err_restricted]);
+
+        alice
+            .send(&server_state, "PRIVMSG #chan :still here")
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![":alice!alice@10.1.2.3 PRIVMSG #chan :still here"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_names_right_after_nick_change_shows_new_nick() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        bob.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.drain();
+        bob.drain();
+
+        // with_nick commits before NICK returns, so nothing sees the old nick
+        alice.send(&server_state, "NICK alicia").await.unwrap();
+        bob.send(&server_state, "NAMES #chan").await.unwrap();
+        let replies = bob.drain();
+        assert_eq!(replies[0], ":alice!alice@127.0.0.1 NICK :alicia");
+        let names = replies[1]
+            .strip_prefix(":unknown.server 353 bob = #chan :")
+            .unwrap();
+        let mut names = names.split(' ').collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["@alicia", "bob"]);
+    }
+
+    #[tokio::test]
+    async fn test_join_past_channel_limit_refuses_only_the_rest() {
+        let server_state = ServerState::default();
+        server_state
+            .config
+            .write()
+            .await
+            .limits
+            .max_channels_per_user = 2;
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice.send(&server_state, "JOIN #a,#b,#c,#d").await.unwrap();
+        let replies = alice.drain();
+        // Broadcasts of different channels come in no particular order
+        let mut joins = replies
+            .iter()
+            .filter(|line| line.starts_with(":alice!alice@127.0.0.1 JOIN "))
+            .collect::<Vec<_>>();
+        joins.sort();
+        assert_eq!(
+            joins,
+            [
+                ":alice!alice@127.0.0.1 JOIN :#a",
+                ":alice!alice@127.0.0.1 JOIN :#b"
+            ]
+        );
+        let refused = replies
+            .iter()
+            .filter(|line| numeric(line) == Some("405"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            refused,
+            [
+                ":unknown.server 405 alice #c :You have joined too many channels",
+                ":unknown.server 405 alice #d :You have joined too many channels"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_join_ends_names_with_366() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        let replies = alice.drain();
+        let codes = replies
+            .iter()
+            .filter_map(|l| numeric(l))
+            .filter(|code| code.starts_with(|c: char| c.is_ascii_digit()))
+            .collect::<Vec<_>>();
+        assert_eq!(codes, vec!["331", "353", "366"], "{replies:?}");
+        assert!(replies.contains(&":unknown.server 366 alice #chan :End of NAMES list".to_owned()));
+
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        bob.send(&server_state, "PART #chan").await.unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![":unknown.server 442 bob #chan :You're not on that channel"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_topic_of_secret_channel_is_hidden_from_outsiders() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #public").await.unwrap();
+        alice
+            .send(&server_state, "TOPIC #public :open")
+            .await
+            .unwrap();
+        alice.send(&server_state, "JOIN #hidden").await.unwrap();
+        alice
+            .send(&server_state, "TOPIC #hidden :plans")
+            .await
+            .unwrap();
+        alice.send(&server_state, "MODE #hidden +s").await.unwrap();
+        alice.drain();
+
+        bob.send(&server_state, "TOPIC #hidden").await.unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![":unknown.server 442 bob #hidden :You're not on that channel"]
+        );
+        bob.send(&server_state, "TOPIC #public").await.unwrap();
+        assert_eq!(bob.drain(), vec![":unknown.server 332 bob #public :open"]);
+        alice.send(&server_state, "TOPIC #hidden").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 332 alice #hidden :plans"]
+        );
+    }
+}
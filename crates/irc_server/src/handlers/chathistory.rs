@@ -0,0 +1,174 @@
+use crate::{
+    channels_models::HistoryEntry,
+    errors::InternalIrcError,
+    message_models::{IrcMessage, MessageTags},
+    ops::other_commands::ChatHistoryQuery,
+    replies::{Batch, IrcReply},
+    server_state::ServerState,
+    types::{ChannelName, ClientId},
+    user_state::{UserState, UserStatus},
+    utils::format_server_time,
+};
+
+pub async fn handle_chathistory(
+    query: Option<(String, ChatHistoryQuery, usize)>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Replies:
+
+    //            BATCH chathistory ✅            FAIL INVALID_PARAMS ✅
+    //            FAIL INVALID_TARGET ✅          FAIL MESSAGE_ERROR
+    let Some((target, query, limit)) = query else {
+        let irc_reply = IrcReply::Fail {
+            command: "CHATHISTORY",
+            code: "INVALID_PARAMS",
+            context: "*",
+            description: "Invalid parameters",
+        };
+        let invalid_params = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(invalid_params).await;
+        return Ok(UserStatus::Active);
+    };
+    // Only members may read a channel's history
+    let channel = server_state
+        .get_channel(&ChannelName(target.clone()))
+        .filter(|channel| channel.members.contains(&client_id));
+    let Some(channel) = channel else {
+        let irc_reply = IrcReply::Fail {
+            command: "CHATHISTORY",
+            code: "INVALID_TARGET",
+            context: &target,
+            description: "Messages could not be retrieved",
+        };
+        let invalid_target = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(invalid_target).await;
+        return Ok(UserStatus::Active);
+    };
+    let limit = limit.min(server_state.config.read().await.get_max_chathistory());
+    let entries = {
+        let history = channel.history.read().await;
+        select_history(history.iter(), &query, limit)
+    };
+
+    // chathistory requires batch, replies are always batched
+    let batch = Batch::new(true);
+    if let Some(batch_start) = batch.start("chathistory", &target) {
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(batch_start))
+            .await;
+    }
+    for entry in entries {
+        let line = MessageTags::new()
+            .with("time", &format_server_time(entry.time))
+            .with("msgid", &entry.msgid)
+            .render(&entry.line);
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(batch.tag(line)))
+            .await;
+    }
+    if let Some(batch_end) = batch.end() {
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(batch_end))
+            .await;
+    }
+    Ok(UserStatus::Active)
+}
+
+// Picks the requested window, always returned oldest first. LATEST and
+// BEFORE keep the newest `limit` messages, AFTER keeps the oldest ones and
+// BETWEEN walks from its first timestamp towards the second.
+fn select_history<'a>(
+    history: impl Iterator<Item = &'a HistoryEntry>,
+    query: &ChatHistoryQuery,
+    limit: usize,
+) -> Vec<HistoryEntry> {
+    let (matching, keep_newest): (Vec<&HistoryEntry>, bool) = match *query {
+        ChatHistoryQuery::Latest(after) => (
+            history
+                .filter(|e| after.is_none_or(|after| e.time > after))
+                .collect(),
+            true,
+        ),
+        ChatHistoryQuery::Before(before) => (history.filter(|e| e.time < before).collect(), true),
+        ChatHistoryQuery::After(after) => (history.filter(|e| e.time > after).collect(), false),
+        ChatHistoryQuery::Between(start, end) => (
+            history
+                .filter(|e| e.time > start.min(end) && e.time < start.max(end))
+                .collect(),
+            start > end,
+        ),
+    };
+    let skip = if keep_newest {
+        matching.len().saturating_sub(limit)
+    } else {
+        0
+    };
+    matching
+        .into_iter()
+        .skip(skip)
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{server_state::ServerState, test_utils::TestClient};
+
+    #[tokio::test]
+    async fn test_chathistory_latest_replays_batched_messages_in_order() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        bob.send(&server_state, "JOIN #chan").await.unwrap();
+        for text in ["one", "two", "three"] {
+            alice
+                .send(&server_state, &format!("PRIVMSG #chan :{text}"))
+                .await
+                .unwrap();
+        }
+        bob.drain();
+
+        bob.send(&server_state, "CHATHISTORY LATEST #chan * 2")
+            .await
+            .unwrap();
+
+        let replies = bob.drain();
+        assert_eq!(replies.len(), 4, "{replies:?}");
+        let reference = replies[0]
+            .strip_prefix(":unknown.server BATCH +")
+            .and_then(|rest| rest.strip_suffix(" chathistory #chan"))
+            .expect("batch start");
+        for (line, text) in replies[1..3].iter().zip(["two", "three"]) {
+            assert!(line.starts_with(&format!("@batch={reference};time=")));
+            assert!(line.ends_with(&format!(" PRIVMSG #chan :{text}")));
+        }
+        assert_eq!(replies[3], format!(":unknown.server BATCH -{reference}"));
+    }
+
+    #[tokio::test]
+    async fn test_chathistory_requires_membership() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut stranger = TestClient::registered(&server_state, "stranger").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+
+        stranger
+            .send(&server_state, "CHATHISTORY LATEST #chan * 10")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stranger.drain(),
+            vec![
+                ":unknown.server FAIL CHATHISTORY INVALID_TARGET #chan :Messages could not be retrieved"
+            ]
+        );
+    }
+}
@@ -1,28 +1,44 @@
 use log::{debug, error, info};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
-use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{RwLock, broadcast, mpsc};
+use tokio::time::timeout;
 
 use super::request::handle_request;
 use crate::channels_models::SubscriptionControl;
 use crate::errors::InternalIrcError;
-use crate::message_models::DirectIrcMessage;
-use crate::types::{ChannelName, ClientId};
-use crate::user_state::UserStatus;
+use crate::ident::{IdentStatus, lookup_ident};
+use crate::message_models::IrcMessage;
+use crate::replies::{IrcReply, MessageReply};
+use crate::shutdown::SHUTDOWN_REASON;
+use crate::types::{ChannelName, ClientId, Nickname};
+use crate::user_state::{ConnectionStats, User, UserStatus};
 use crate::{server_state::ServerState, user_state::UserState};
 
 // Define the size of the personal outbound channel
 const OUTBOUND_CHANNEL_SIZE: usize = 32;
 const CONTROL_CHANNEL_SIZE: usize = 4;
+// A client over its SENDQ isn't reading, don't wait long on it for the ERROR
+const ERROR_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+const PING_TIMEOUT_REASON: &str = "Ping timeout";
+// IRCv3 message-tags allows this many bytes of tags ahead of a message
+const MAX_TAGS_LENGTH: usize = 8191;
 
 /// Refactored entry point for a new client connection
 pub async fn handle_client(socket: TcpStream, addr: SocketAddr, server_state: &ServerState) {
+    // Dropping the socket refuses the connection
+    let Some(slot) = server_state.admit_connection(addr.ip()).await else {
+        return;
+    };
     info!("Client connected: {:?}", addr);
     info!("Client number connected: {}", server_state.users.len());
 
-    let (tx_outbound, rx_outbound) = mpsc::channel::<DirectIrcMessage>(OUTBOUND_CHANNEL_SIZE);
+    let (tx_outbound, rx_outbound) = mpsc::channel::<IrcMessage>(OUTBOUND_CHANNEL_SIZE);
     let (tx_control, rx_control) = mpsc::channel::<SubscriptionControl>(CONTROL_CHANNEL_SIZE);
     let (tx_status, rx_status) = mpsc::channel::<UserStatus>(CONTROL_CHANNEL_SIZE);
 
@@ -35,44 +51,171 @@ pub async fn handle_client(socket: TcpStream, addr: SocketAddr, server_state: &S
         }
     };
 
+    // RFC 1413: done before reading anything, so USER sees the result
+    if server_state.config.read().await.get_ident_lookup()
+        && let Ok(local_addr) = socket.local_addr()
+    {
+        let ident = lookup_ident(addr, local_addr).await;
+        info!("[{client_id}] ident lookup: {ident:?}");
+        user_state.user.write().await.ident = ident;
+    }
+    send_connect_notices(server_state, &user_state).await;
+
     let (read_half, write_half) = io::split(socket);
 
+    let (limits, silence_limit, max_line) = {
+        let config = server_state.config.read().await;
+        let limits = WriterLimits {
+            sendq_bytes: config.get_sendq_bytes(),
+            lag_notice: config.get_lag_notice(),
+            max_lag_events: config.get_max_lag_events(),
+            ping_frequency: config.get_ping_frequency(),
+        };
+        // The writer pings after `ping_frequency`, the answer has `ping_timeout`
+        (
+            limits,
+            config.get_ping_frequency() + config.get_ping_timeout(),
+            config.limits.max_message_length + MAX_TAGS_LENGTH,
+        )
+    };
+
     // 4. Spawn two new, independent tasks
-    tokio::spawn(client_reader_task(
-        read_half,
-        client_id,
-        server_state.clone(),
-        user_state.clone(),
-    ));
-    tokio::spawn(client_writer_task(
-        write_half,
-        client_id,
-        rx_outbound,
-        rx_control,
-        rx_status,
-    ));
+    let reader_task = tokio::spawn({
+        let (server_state, user_state) = (server_state.clone(), user_state.clone());
+        async move {
+            // Released when the reader ends, or is aborted on SendQ exceeded
+            let _slot = slot;
+            client_reader_task(
+                read_half,
+                client_id,
+                silence_limit,
+                max_line,
+                server_state,
+                user_state,
+            )
+            .await
+        }
+    });
+    let server_state = server_state.clone();
+    tokio::spawn(async move {
+        let exit = client_writer_task(
+            write_half,
+            client_id,
+            user_state.user.clone(),
+            user_state.stats.clone(),
+            limits,
+            WriterInbox {
+                rx_outbound,
+                rx_control,
+                rx_status,
+            },
+        )
+        .await;
+        if let Some(reason) = exit.error_reason() {
+            // Dropping the read half too closes the socket
+            reader_task.abort();
+            server_state
+                .handle_quit(client_id, Some(reason.to_owned()))
+                .await;
+        } else if server_state.shutting_down.load(Ordering::Relaxed) {
+            // Flushed, `shutdown` counts the users still waiting on theirs
+            server_state
+                .handle_quit(client_id, Some(SHUTDOWN_REASON.to_owned()))
+                .await;
+        }
+    });
+}
+
+/// Queues the configured `server.connect_notices`, so they reach the client
+/// before the reply to anything it sends.
+async fn send_connect_notices(server_state: &ServerState, user_state: &UserState) {
+    let notices = server_state
+        .config
+        .read()
+        .await
+        .get_connect_notices()
+        .to_vec();
+    if notices.is_empty() {
+        return;
+    }
+    let (host, ident) = {
+        let user = user_state.user.read().await;
+        let ident = match &user.ident {
+            IdentStatus::NotChecked => "Ident check disabled".to_owned(),
+            IdentStatus::Failed => "No Ident response".to_owned(),
+            IdentStatus::Resolved(username) => format!("Got Ident response: {username}"),
+        };
+        (user.addr.ip().to_string(), ident)
+    };
+    let auth = Nickname("AUTH".to_owned());
+    for notice in notices {
+        let text = notice.replace("{host}", &host).replace("{ident}", &ident);
+        let irc_reply = IrcReply::ServerNotice {
+            nick: &auth,
+            text: &text,
+        };
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(irc_reply.format()))
+            .await;
+    }
 }
 
 async fn client_reader_task(
     reader: tokio::io::ReadHalf<TcpStream>,
     client_id: ClientId,
+    silence_limit: Duration,
+    max_line: usize,
     server_state: ServerState,
     user_state: UserState,
 ) -> Result<(), InternalIrcError> {
     // Wrap the reader for line-based (IRC) protocol handling
     let mut buffered_reader = tokio::io::BufReader::new(reader);
-    let mut line = String::new();
+    let mut buffer = Vec::new();
 
     loop {
         // Asynchronously read one line (ending in \r\n)
-        let _bytes_read = match buffered_reader.read_line(&mut line).await {
-            Ok(0) | Err(_) => {
-                // TODO: Handle QUIT/cleanup in ServerState
+        let read = timeout(
+            silence_limit,
+            read_request_line(&mut buffered_reader, &mut buffer, max_line),
+        );
+        let line = match read.await {
+            Ok(Ok(RequestLine::Line(line))) => line,
+            Ok(Ok(RequestLine::TooLong)) => {
+                info!("[{client_id}] Input line too long");
+                let nick = user_state.user.read().await.nick.clone();
+                // 417 ERR_INPUTTOOLONG
+                let irc_reply = IrcReply::ErrInputTooLong {
+                    nick: &nick.unwrap_or(Nickname("*".to_owned())),
+                };
+                let _ = user_state
+                    .tx_outbound
+                    .send(IrcMessage::new(irc_reply.format()))
+                    .await;
+                continue;
+            }
+            Ok(Ok(RequestLine::Closed) | Err(_)) => {
+                info!("[{client_id}] Connection lost");
+                server_state.handle_lost_connection(client_id).await;
+                let _ = user_state.tx_status.send(UserStatus::Leaving(None)).await;
+                break;
+            }
+            Err(_) => {
+                info!("[{client_id}] {PING_TIMEOUT_REASON}");
+                user_state.send_error_and_close(PING_TIMEOUT_REASON).await;
+                server_state
+                    .handle_quit(client_id, Some(PING_TIMEOUT_REASON.to_owned()))
+                    .await;
                 break;
             }
-            Ok(n) => n,
         };
 
+        user_state.stats.record_received(buffer.len());
+
+        if !utf8_accepted(&buffer, &line, &server_state, &user_state).await {
+            continue;
+        }
+
         // Process the request line
         let request = line.trim();
         info!(">> incoming [{}] # {}", client_id, request);
@@ -80,8 +223,8 @@ async fn client_reader_task(
         // This call is now handled inside the Reader task:
         match handle_request(request, client_id, &server_state, &user_state).await {
             Ok(UserStatus::Leaving(reason)) => {
+                // The handler already sent ERROR and told the writer to close
                 info!("[{client_id}] Client Quit with message :{reason:?}");
-                let _ = user_state.tx_status.send(UserStatus::Leaving(reason)).await;
                 info!("[{}] Client disconnected.", client_id);
                 // debug!("{server_state:?}");
                 break;
@@ -91,44 +234,201 @@ async fn client_reader_task(
         }
         // The handler's response logic (writing to the socket) must change!
         // Instead of writing to the socket, it must use the outbound channel.
-
-        line.clear(); // Clear the buffer for the next line
     }
 
     Ok(())
 }
 
-async fn client_writer_task(
-    mut writer: tokio::io::WriteHalf<TcpStream>,
+/// What `read_request_line` got from the client.
+#[derive(Debug, PartialEq)]
+enum RequestLine {
+    Line(String),
+    /// Longer than the limit, discarded up to its `\n`.
+    TooLong,
+    Closed,
+}
+
+/// Reads one raw line (up to `\n`) of at most `max_line` bytes and decodes
+/// it lossily, so a client sending Latin-1 or a stray invalid byte gets
+/// U+FFFD in that line instead of losing its connection. A longer line is
+/// never buffered: the rest of it is skipped as it arrives.
+async fn read_request_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    max_line: usize,
+) -> io::Result<RequestLine> {
+    buffer.clear(); // Clear the buffer for the next line
+    let mut too_long = false;
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            break;
+        }
+        let (chunk, complete) = match available.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (&available[..=end], true),
+            None => (available, false),
+        };
+        let used = chunk.len();
+        if !too_long && buffer.len() + used > max_line {
+            too_long = true;
+            buffer.clear();
+        }
+        if !too_long {
+            buffer.extend_from_slice(chunk);
+        }
+        reader.consume(used);
+        if complete {
+            break;
+        }
+    }
+    Ok(if too_long {
+        RequestLine::TooLong
+    } else if buffer.is_empty() {
+        RequestLine::Closed
+    } else {
+        RequestLine::Line(String::from_utf8_lossy(buffer).into_owned())
+    })
+}
+
+/// False when `server.utf8only` is set and the raw line isn't valid UTF-8,
+/// after telling the client with FAIL INVALID_UTF8.
+async fn utf8_accepted(
+    raw: &[u8],
+    line: &str,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> bool {
+    if std::str::from_utf8(raw).is_ok() || !server_state.config.read().await.get_utf8only() {
+        return true;
+    }
+    // The command, past any prefix
+    let mut words = line.split_whitespace();
+    let first = words.next().unwrap_or("*");
+    let command = if first.starts_with(':') {
+        words.next().unwrap_or("*")
+    } else {
+        first
+    };
+    let irc_reply = IrcReply::Fail {
+        command: &command.to_uppercase(),
+        code: "INVALID_UTF8",
+        context: "",
+        description: "Message rejected, this server only accepts UTF-8",
+    };
+    let _ = user_state
+        .tx_outbound
+        .send(IrcMessage::new(irc_reply.format()))
+        .await;
+    false
+}
+
+/// Why the writer task stopped.
+#[derive(Debug, PartialEq)]
+enum WriterExit {
+    /// The socket failed or the user is leaving.
+    Closed,
+    /// More than `limits.sendq_bytes` were waiting to be written.
+    SendQExceeded,
+    /// The client fell behind a channel more than `limits.max_lag_events` times.
+    LagExceeded,
+}
+
+impl WriterExit {
+    /// The reason given in the closing ERROR, None when no ERROR is owed.
+    fn error_reason(&self) -> Option<&'static str> {
+        match self {
+            WriterExit::Closed => None,
+            WriterExit::SendQExceeded => Some("SendQ exceeded"),
+            WriterExit::LagExceeded => Some("Max SendQ exceeded"),
+        }
+    }
+}
+
+/// What the writer task tolerates from a slow client.
+struct WriterLimits {
+    sendq_bytes: usize,
+    lag_notice: bool,
+    max_lag_events: u32,
+    ping_frequency: Duration,
+}
+
+/// The receiving ends of the channels a client's writer task drains.
+struct WriterInbox {
+    rx_outbound: mpsc::Receiver<IrcMessage>,
+    rx_control: mpsc::Receiver<SubscriptionControl>,
+    rx_status: mpsc::Receiver<UserStatus>,
+}
+
+async fn client_writer_task<W: AsyncWrite + Unpin>(
+    mut writer: W,
     client_id: ClientId,
-    mut rx_outbound: mpsc::Receiver<DirectIrcMessage>,
-    mut rx_control: mpsc::Receiver<SubscriptionControl>,
-    mut rx_status: mpsc::Receiver<UserStatus>,
-) -> Result<(), std::io::Error> {
+    user: Arc<RwLock<User>>,
+    stats: Arc<ConnectionStats>,
+    limits: WriterLimits,
+    inbox: WriterInbox,
+) -> WriterExit {
+    let WriterInbox {
+        mut rx_outbound,
+        mut rx_control,
+        mut rx_status,
+    } = inbox;
     // Single aggregated channel for ALL outgoing messages (broadcast + direct)
-    let (tx_aggregated, mut rx_aggregated) = mpsc::channel::<DirectIrcMessage>(100);
+    let (tx_aggregated, mut rx_aggregated) = mpsc::channel::<IrcMessage>(100);
+    // Forwarders report here the channel a client lagged behind too often
+    let (tx_lag_exceeded, mut rx_lag_exceeded) = mpsc::channel::<ChannelName>(1);
 
     // Track spawned tasks for cleanup
     let mut subscription_tasks: HashMap<ChannelName, tokio::task::JoinHandle<()>> = HashMap::new();
 
-    loop {
+    // Bytes accepted for this client but not yet written to its socket. A
+    // client that stops reading makes it grow until SENDQ is exceeded.
+    let mut sendq: VecDeque<u8> = VecDeque::new();
+
+    // `last_received_at` when the last PING went out, one per silence
+    let mut pinged_at = None;
+
+    let exit = loop {
+        if sendq.len() > limits.sendq_bytes {
+            error!("[{client_id}] SendQ exceeded ({} bytes)", sendq.len());
+            break WriterExit::SendQExceeded;
+        }
+        let (queued, _) = sendq.as_slices();
+        let last_received_at = stats.last_received_at.load(Ordering::Relaxed);
+        let ping_in = limits.ping_frequency.saturating_sub(stats.silent_for());
         tokio::select! {
+            // `write` is cancel safe: nothing is written if another branch wins
+            written = writer.write(queued), if !queued.is_empty() => match written {
+                Ok(0) | Err(_) => {
+                    error!("[{client_id}] Failed to write: {written:?}");
+                    break WriterExit::Closed;
+                }
+                Ok(n) => {
+                    sendq.drain(..n);
+                }
+            },
+
             Some(msg) = rx_outbound.recv() => {
                 info!(">> out [{client_id}] direct # {}", &msg.raw_line);
-                if let Err(e) = writer.write_all(msg.raw_line.as_bytes()).await {
-                    error!("[{}] Failed to write: {:?}", client_id, e);
-                    break;
-                }
+                sendq.extend(msg.raw_line.as_bytes());
+                stats.record_sent(msg.raw_line.len());
             }
 
             Some(msg) = rx_aggregated.recv() => {
                 info!(">> out [{client_id}] broadcast # {}", &msg.raw_line);
-                if let Err(e) = writer.write_all(msg.raw_line.as_bytes()).await {
-                    error!("[{}] Failed to write: {:?}", client_id, e);
-                    break;
-                }
+                sendq.extend(msg.raw_line.as_bytes());
+                stats.record_sent(msg.raw_line.len());
+            }
+            _ = tokio::time::sleep(ping_in), if pinged_at != Some(last_received_at) => {
+                pinged_at = Some(last_received_at);
+                let ping = IrcMessage::new(IrcReply::Ping.format());
+                info!(">> out [{client_id}] direct # {}", &ping.raw_line);
+                sendq.extend(ping.raw_line.as_bytes());
+                stats.record_sent(ping.raw_line.len());
+            }
+            Some(name) = rx_lag_exceeded.recv() => {
+                error!("[{client_id}] Lagged behind {name} too often");
+                break WriterExit::LagExceeded;
             }
-
             Some(control) = rx_control.recv() => {
                 match control {
                     SubscriptionControl::Subscribe { channel_name, receiver } => {
@@ -138,15 +438,21 @@ async fn client_writer_task(
                         let tx = tx_aggregated.clone();
                         let name = channel_name.clone();
                         let client_id_copy = client_id;
+                        let user = user.clone();
+                        let tx_lag_exceeded = tx_lag_exceeded.clone();
+                        let (lag_notice, max_lag_events) = (limits.lag_notice, limits.max_lag_events);
 
                         let handle = tokio::spawn(async move {
                             let mut rx = receiver;
+                            // Times this subscription fell behind the channel
+                            let mut lag_events = 0;
                             loop {
                                 match rx.recv().await {
                                     Ok(channel_msg) => {
-                                        // Convert ChannelMessage to IrcMessage if needed
-                                        if channel_msg.sender != Some(client_id) {
-                                            let irc_msg = DirectIrcMessage {sender: None, raw_line: channel_msg.raw_line };
+                                        if channel_msg.is_for(client_id) {
+                                            // Tagged lines only reach clients that negotiated the tags
+                                            let raw_line = channel_msg.line_for(&user.read().await.capabilities);
+                                            let irc_msg = IrcMessage { sender: channel_msg.sender, raw_line, ..Default::default() };
                                             if tx.send(irc_msg).await.is_err() {
                                                 debug!("[{client_id_copy}] Aggregated channel closed for {name}");
                                                 break;
@@ -155,6 +461,21 @@ async fn client_writer_task(
                                     }
                                     Err(broadcast::error::RecvError::Lagged(n)) => {
                                         error!("[{client_id_copy}] Lagged on {name} by {n}");
+                                        lag_events += 1;
+                                        if max_lag_events > 0 && lag_events > max_lag_events {
+                                            let _ = tx_lag_exceeded.send(name.clone()).await;
+                                            break;
+                                        }
+                                        if lag_notice {
+                                            let nick = user.read().await.nick.clone();
+                                            let irc_reply = IrcReply::ServerNotice {
+                                                nick: &nick.unwrap_or(Nickname("*".to_owned())),
+                                                text: &format!("*** {n} messages to {name} were dropped, you are reading too slowly"),
+                                            };
+                                            if tx.send(IrcMessage::new(irc_reply.format())).await.is_err() {
+                                                break;
+                                            }
+                                        }
                                     }
                                     Err(broadcast::error::RecvError::Closed) => {
                                         info!("[{client_id_copy}] Channel {name} closed");
@@ -176,21 +497,333 @@ async fn client_writer_task(
             }
 
             Some(status) = rx_status.recv() => {
-                match status {
-                    UserStatus::Leaving(_reason) => break,
-                    _ => ()
+                if let UserStatus::Leaving(_reason) = status {
+                    // Flush what the user is owed before closing, the ERROR
+                    // may still be waiting in the outbound queue
+                    while let Ok(msg) = rx_outbound.try_recv() {
+                        sendq.extend(msg.raw_line.as_bytes());
+                    }
+                    let (front, back) = sendq.as_slices();
+                    let _ = writer.write_all(front).await;
+                    let _ = writer.write_all(back).await;
+                    break WriterExit::Closed;
                 }
             }
         }
-    }
+    };
 
     // Cleanup: abort all subscription tasks
     for (_name, handle) in subscription_tasks {
         handle.abort();
     }
 
-    Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "Writer task terminated",
-    ))
+    if let Some(reason) = exit.error_reason() {
+        // The backlog is dropped, only the ERROR is still worth a try
+        rx_outbound.close();
+        let nick = user.read().await.nick.clone();
+        let mrep = MessageReply::Error {
+            nick: &nick.unwrap_or(Nickname("*".to_owned())),
+            reason,
+        };
+        let error_line = IrcMessage::new(mrep.format()).raw_line;
+        let _ = timeout(ERROR_WRITE_TIMEOUT, writer.write_all(error_line.as_bytes())).await;
+    }
+    let _ = writer.shutdown().await;
+    exit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_models::IrcMessage;
+
+    #[tokio::test]
+    async fn test_invalid_utf8_byte_does_not_end_the_stream() {
+        let input: &[u8] = b"PRIVMSG #chan :caf\xe9\r\nPING :still here\r\n";
+        let mut reader = tokio::io::BufReader::new(input);
+        let mut buffer = Vec::new();
+
+        let first = read_request_line(&mut reader, &mut buffer, 512)
+            .await
+            .unwrap();
+        assert_eq!(
+            first,
+            RequestLine::Line("PRIVMSG #chan :caf\u{FFFD}\r\n".to_owned())
+        );
+        let second = read_request_line(&mut reader, &mut buffer, 512)
+            .await
+            .unwrap();
+        assert_eq!(second, RequestLine::Line("PING :still here\r\n".to_owned()));
+        let eof = read_request_line(&mut reader, &mut buffer, 512)
+            .await
+            .unwrap();
+        assert_eq!(eof, RequestLine::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_sendq_limit_disconnects_slow_reader() {
+        // A 64 byte pipe nobody reads from stands in for a stalled client
+        let (mut client_end, server_end) = tokio::io::duplex(64);
+        let (tx_outbound, rx_outbound) = mpsc::channel(OUTBOUND_CHANNEL_SIZE);
+        let (_tx_control, rx_control) = mpsc::channel(CONTROL_CHANNEL_SIZE);
+        let (_tx_status, rx_status) = mpsc::channel(CONTROL_CHANNEL_SIZE);
+        let writer = tokio::spawn(client_writer_task(
+            server_end,
+            ClientId(1),
+            Arc::new(RwLock::new(User::new("127.0.0.1:50000".parse().unwrap()))),
+            Arc::new(ConnectionStats::new()),
+            WriterLimits {
+                sendq_bytes: 1024,
+                lag_notice: true,
+                max_lag_events: 3,
+                ping_frequency: Duration::from_secs(120),
+            },
+            WriterInbox {
+                rx_outbound,
+                rx_control,
+                rx_status,
+            },
+        ));
+
+        let line = format!(":alice!alice@127.0.0.1 PRIVMSG #chan :{}", "x".repeat(80));
+        let mut sent = 0;
+        while sent < 1000
+            && tx_outbound
+                .send(IrcMessage::new(line.clone()))
+                .await
+                .is_ok()
+        {
+            sent += 1;
+        }
+        assert!(sent < 1000, "the writer never gave up on the slow reader");
+
+        let mut received = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut client_end, &mut received)
+            .await
+            .unwrap();
+        assert_eq!(writer.await.unwrap(), WriterExit::SendQExceeded);
+        assert!(received.ends_with(b"ERROR :Closing Link: * (SendQ exceeded)\r\n"));
+        assert!(received.len() < 64 + 1024 + 100);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_lag_is_noticed_then_disconnects() {
+        let (mut client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let (_tx_outbound, rx_outbound) = mpsc::channel(OUTBOUND_CHANNEL_SIZE);
+        let (tx_control, rx_control) = mpsc::channel(CONTROL_CHANNEL_SIZE);
+        let (_tx_status, rx_status) = mpsc::channel(CONTROL_CHANNEL_SIZE);
+        let writer = tokio::spawn(client_writer_task(
+            server_end,
+            ClientId(1),
+            Arc::new(RwLock::new(User::new("127.0.0.1:50000".parse().unwrap()))),
+            Arc::new(ConnectionStats::new()),
+            WriterLimits {
+                sendq_bytes: 64 * 1024,
+                lag_notice: true,
+                max_lag_events: 2,
+                ping_frequency: Duration::from_secs(120),
+            },
+            WriterInbox {
+                rx_outbound,
+                rx_control,
+                rx_status,
+            },
+        ));
+        // A 4 line channel, flooded faster than the forwarder gets to run
+        let (tx_channel, receiver) = broadcast::channel(4);
+        tx_control
+            .send(SubscriptionControl::Subscribe {
+                channel_name: ChannelName("#chan".to_owned()),
+                receiver,
+            })
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            for i in 0..10 {
+                let line = format!(":alice!alice@127.0.0.1 PRIVMSG #chan :{i}");
+                let _ = tx_channel.send(IrcMessage::new(line));
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let mut received = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut client_end, &mut received)
+            .await
+            .unwrap();
+        assert_eq!(writer.await.unwrap(), WriterExit::LagExceeded);
+        let received = String::from_utf8(received).unwrap();
+        let notice = ":unknown.server NOTICE * :*** 6 messages to #chan were dropped, you are reading too slowly\r\n";
+        assert_eq!(received.matches(notice).count(), 2, "{received}");
+        assert!(received.ends_with("ERROR :Closing Link: * (Max SendQ exceeded)\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_notices_are_sent_first() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.server.connect_notices = Some(vec![
+            "*** Looking up your hostname...".to_owned(),
+            "*** Using your IP address: {host}".to_owned(),
+            "*** {ident}".to_owned(),
+        ]);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept_state = server_state.clone();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            handle_client(socket, peer, &accept_state).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"PING early\r\n").await.unwrap();
+        let mut lines = io::BufReader::new(stream).lines();
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            let line = timeout(Duration::from_secs(5), lines.next_line())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            received.push(line);
+        }
+        assert_eq!(
+            received[..3],
+            [
+                ":unknown.server NOTICE AUTH :*** Looking up your hostname...",
+                ":unknown.server NOTICE AUTH :*** Using your IP address: 127.0.0.1",
+                ":unknown.server NOTICE AUTH :*** Ident check disabled",
+            ]
+        );
+        assert!(received[3].contains("PONG"), "{received:?}");
+    }
+
+    #[tokio::test]
+    async fn test_quit_gets_error_before_the_socket_closes() {
+        let server_state = ServerState::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept_state = server_state.clone();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            handle_client(socket, peer, &accept_state).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"QUIT :gone fishing\r\n").await.unwrap();
+        let mut received = String::new();
+        timeout(
+            Duration::from_secs(5),
+            tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut received),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(received, "ERROR :Closing Link: * (Quit: gone fishing)\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_silent_client_is_pinged_then_disconnected() {
+        let server_state = ServerState::default();
+        {
+            let mut config = server_state.config.write().await;
+            config.limits.ping_frequency = Some(1);
+            config.limits.ping_timeout = Some(1);
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept_state = server_state.clone();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            handle_client(socket, peer, &accept_state).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut received = String::new();
+        timeout(
+            Duration::from_secs(5),
+            tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut received),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            received,
+            "PING :unknown.server\r\nERROR :Closing Link: * (Ping timeout)\r\n"
+        );
+        assert!(server_state.users.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_utf8only_rejects_an_invalid_channel_name() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.server.utf8only = Some(true);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept_state = server_state.clone();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            handle_client(socket, peer, &accept_state).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #caf\xe9\r\nJOIN #caf\xc3\xa9\r\n",
+            )
+            .await
+            .unwrap();
+        let mut lines = io::BufReader::new(stream).lines();
+        let mut received = Vec::new();
+        while !received.iter().any(|l: &String| l.starts_with(":alice!")) {
+            let line = timeout(Duration::from_secs(5), lines.next_line())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            received.push(line);
+        }
+        assert!(received.iter().any(|l| l.contains(" UTF8ONLY")));
+        assert!(received.contains(
+            &":unknown.server FAIL JOIN INVALID_UTF8 :Message rejected, this server only accepts UTF-8"
+                .to_owned()
+        ));
+        assert_eq!(
+            received.last().unwrap(),
+            ":alice!alice@127.0.0.1 JOIN :#café"
+        );
+        assert!(
+            server_state
+                .get_channel(&ChannelName("#caf\u{FFFD}".to_owned()))
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_over_long_line_is_dropped_with_input_too_long() {
+        let server_state = ServerState::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept_state = server_state.clone();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            handle_client(socket, peer, &accept_state).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let long_line = format!("PRIVMSG bob :{}\r\n", "a".repeat(64 * 1024));
+        stream.write_all(long_line.as_bytes()).await.unwrap();
+        stream.write_all(b"QUIT :done\r\n").await.unwrap();
+        let mut received = String::new();
+        timeout(
+            Duration::from_secs(5),
+            tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut received),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            received,
+            ":unknown.server 417 * :Input line was too long\r\n\
+             ERROR :Closing Link: * (Quit: done)\r\n"
+        );
+    }
 }
@@ -1,12 +1,40 @@
 use crate::{
     errors::InternalIrcError,
-    message_models::{BroadcastIrcMessage, DirectIrcMessage},
-    replies::MessageReply,
+    handlers::server_queries::require_local_target,
+    message_models::{IrcMessage, MessageTags},
+    replies::{IrcReply, MessageReply},
     server_state::ServerState,
-    types::{ClientId, MessageTo},
+    types::{ChannelName, ClientId, MessageTo, Nickname, Username},
     user_state::{UserState, UserStatus},
+    utils::strip_formatting,
 };
 use log::error;
+use std::collections::HashSet;
+
+/// PRIVMSG or NOTICE, delivered alike. A NOTICE never gets an automatic
+/// reply, errors and RPL_AWAY included (RFC 2812 3.3.2).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageKind {
+    Privmsg,
+    Notice,
+}
+
+impl MessageKind {
+    pub fn command(self) -> &'static str {
+        match self {
+            MessageKind::Privmsg => "PRIVMSG",
+            MessageKind::Notice => "NOTICE",
+        }
+    }
+
+    /// Sends `irc_reply` to the sender, unless this is a NOTICE.
+    async fn reply(self, user_state: &UserState, irc_reply: IrcReply<'_>) {
+        if self == MessageKind::Privmsg {
+            let dm = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(dm).await;
+        }
+    }
+}
 // 3.3.1 Private messages
 
 //       Command: PRIVMSG
@@ -28,12 +56,13 @@ use log::error;
 //    Numeric Replies:
 
 //            ERR_NORECIPIENT                 ERR_NOTEXTTOSEND
-//            ERR_CANNOTSENDTOCHAN            ERR_NOTOPLEVEL
+//            ERR_CANNOTSENDTOCHAN ✅         ERR_NOTOPLEVEL
 //            ERR_WILDTOPLEVEL                ERR_TOOMANYTARGETS
-//            ERR_NOSUCHNICK
-//            RPL_AWAY
+//            ERR_NOSUCHNICK ✅               ERR_NOSUCHSERVER ✅
+//            RPL_AWAY ✅
 
-pub async fn handle_privmsg(
+pub async fn handle_message(
+    kind: MessageKind,
     msgtarget: Vec<MessageTo>,
     message: String,
     client_id: ClientId,
@@ -41,49 +70,809 @@ pub async fn handle_privmsg(
     user_state: &UserState,
 ) -> Result<UserStatus, InternalIrcError> {
     let caracs = user_state.get_caracs().await;
+    let host_from = caracs.displayed_host();
     let nick_from = caracs.nick.unwrap();
     let user_from = caracs.user.unwrap();
-    let host_from = format!("{}", caracs.addr);
+    let (max_targets, away_reply_interval) = {
+        let config = server_state.config.read().await;
+        (config.get_max_targets(), config.get_away_reply_interval())
+    };
+
+    if server_state
+        .audit_bot_signal(caracs.addr.ip(), &nick_from, &format!("message {message}"))
+        .await
+    {
+        let reason = "Suspected bot".to_owned();
+        user_state.send_error_and_close(&reason).await;
+        server_state
+            .handle_quit(client_id, Some(reason.clone()))
+            .await;
+        return Ok(user_state
+            .transition_status(UserStatus::Leaving(Some(reason)))
+            .await);
+    }
+
+    user_state.stats.record_message();
+    let sender = (&nick_from, &user_from, host_from.as_str());
 
-    for target in msgtarget {
+    // A member of both #a and #b gets `PRIVMSG #a,#b` once per channel, but
+    // a target repeated in the list is only delivered (and counted) once
+    let mut seen = HashSet::new();
+    let targets = msgtarget
+        .into_iter()
+        .filter(|target| seen.insert(target.to_string()));
+    for (i, target) in targets.enumerate() {
+        let target_name = target.to_string();
+        // Every member of a channel gets the same id for one message
+        let msgid = server_state.new_msgid();
+        if i >= max_targets {
+            // 407 ERR_TOOMANYTARGETS, targets past the cap are dropped
+            let irc_reply = IrcReply::ErrTooManyTargets {
+                nick: &nick_from,
+                target: &target_name,
+            };
+            kind.reply(user_state, irc_reply).await;
+            break;
+        }
         match target {
             MessageTo::ChannelName(channel) => {
-                let irc_channel_opt = server_state.get_channel(&channel).map(|r| r.clone());
+                let irc_channel_opt = server_state.get_channel(&channel);
                 if let Some(irc_channel) = irc_channel_opt {
+                    let (no_external_msgs, no_colors, moderated, op_moderated) = {
+                        let modes = irc_channel.modes.read().await;
+                        (
+                            modes.no_external_msgs,
+                            modes.no_colors,
+                            modes.moderated,
+                            modes.op_moderated,
+                        )
+                    };
+                    if no_external_msgs && !irc_channel.members.contains(&client_id) {
+                        // 404 ERR_CANNOTSENDTOCHAN, +n keeps outsiders out
+                        let irc_reply = IrcReply::ErrCannotSendToChan {
+                            nick: &nick_from,
+                            channel: &channel,
+                        };
+                        kind.reply(user_state, irc_reply).await;
+                        continue;
+                    }
+                    let hostmask = format!("{nick_from}!{user_from}@{host_from}");
+                    if irc_channel.is_quieted(client_id, &hostmask).await {
+                        // 404 ERR_CANNOTSENDTOCHAN, +q lets them read but not talk
+                        let irc_reply = IrcReply::ErrCannotSendToChan {
+                            nick: &nick_from,
+                            channel: &channel,
+                        };
+                        kind.reply(user_state, irc_reply).await;
+                        continue;
+                    }
+                    let (src_nick, src_user, src_host) = irc_channel
+                        .displayed_source(&nick_from, &user_from, &host_from)
+                        .await;
+                    // +c: members get the text without colors or formatting
+                    let message = if no_colors {
+                        strip_formatting(&message)
+                    } else {
+                        message.clone()
+                    };
+                    let can_speak = irc_channel.is_operator(client_id)
+                        || irc_channel.voiced.contains(&client_id);
+                    if moderated && !can_speak {
+                        if op_moderated {
+                            // +z: the channel operators still see it, sent to @#channel
+                            let mrep = MessageReply::ChannelPrivMsg {
+                                command: kind.command(),
+                                nick_from: &src_nick,
+                                user_from: &src_user,
+                                host_from: &src_host,
+                                channel: &ChannelName(format!("@{channel}")),
+                                message: &message,
+                            };
+                            let line = mrep.format();
+                            let operators = irc_channel
+                                .operators
+                                .iter()
+                                .filter_map(|op| server_state.users.get(&op).map(|e| e.clone()))
+                                .collect::<Vec<_>>();
+                            for op_state in operators {
+                                let dm = IrcMessage::new_with_sender(line.clone(), client_id);
+                                let _ = op_state.tx_outbound.send(dm).await;
+                            }
+                        } else {
+                            // 404 ERR_CANNOTSENDTOCHAN, +m needs voice or ops
+                            let irc_reply = IrcReply::ErrCannotSendToChan {
+                                nick: &nick_from,
+                                channel: &channel,
+                            };
+                            kind.reply(user_state, irc_reply).await;
+                        }
+                        continue;
+                    }
                     let mrep = MessageReply::ChannelPrivMsg {
-                        nick_from: &nick_from,
-                        user_from: &user_from,
-                        host_from: &host_from,
+                        command: kind.command(),
+                        nick_from: &src_nick,
+                        user_from: &src_user,
+                        host_from: &src_host,
                         channel: &channel,
                         message: &message,
                     };
+                    let line = mrep.format();
+                    let tags = MessageTags::new().with("msgid", &msgid);
                     let broadcast_irc_message =
-                        BroadcastIrcMessage::new_with_sender(mrep.format(), client_id);
-                    let _ = irc_channel.broadcast_message(broadcast_irc_message);
+                        IrcMessage::new_with_sender(line.clone(), client_id).with_tags(tags);
+                    irc_channel.broadcast_message(broadcast_irc_message);
+                    irc_channel.record_history(msgid, line).await;
+                } else {
+                    // 403 ERR_NOSUCHCHANNEL
+                    let irc_reply = IrcReply::ErrNoSuchChannel {
+                        nick: &nick_from,
+                        channel: &channel,
+                    };
+                    kind.reply(user_state, irc_reply).await;
                 }
-                //todo faire le else :)
             }
-            MessageTo::NickUserHost(_nuh) => error!("PRIVMSG to NickUserHost not implemented yet"),
             MessageTo::Nickname(nick_to) => {
+                let dests = server_state
+                    .get_user_state_from_nick(&nick_to)
+                    .into_iter()
+                    .collect();
+                deliver_to_nick(
+                    kind,
+                    dests,
+                    &target_name,
+                    sender,
+                    (&msgid, &message),
+                    user_state,
+                    away_reply_interval,
+                )
+                .await;
+            }
+            MessageTo::NickUserHost((nick_to, user_to, host_to)) => {
+                // nick!user@host only reaches the nick while user and host match
+                let mut dests = Vec::new();
                 if let Some(user_state_dest) = server_state.get_user_state_from_nick(&nick_to) {
-                    let mrep = MessageReply::NicknamePrivMsg {
-                        nick_from: &nick_from,
-                        user_from: &user_from,
-                        host_from: &host_from,
-                        nick_to: &nick_to,
-                        message: &message,
-                    };
-                    let direct_irc_message = DirectIrcMessage::new(mrep.format());
-                    let _ = user_state_dest.tx_outbound.send(direct_irc_message).await;
+                    let dest = user_state_dest.get_caracs().await;
+                    if dest.user.as_ref() == Some(&user_to)
+                        && dest
+                            .displayed_host()
+                            .eq_ignore_ascii_case(&host_to.to_string())
+                    {
+                        dests.push(user_state_dest);
+                    }
+                }
+                deliver_to_nick(
+                    kind,
+                    dests,
+                    &target_name,
+                    sender,
+                    (&msgid, &message),
+                    user_state,
+                    away_reply_interval,
+                )
+                .await;
+            }
+            MessageTo::UserHostServer((user_to, host_to, server)) => {
+                // user[%host]@server, and we are the only server
+                if !require_local_target(Some(&server.to_string()), user_state).await {
+                    continue;
                 }
-                //todo faire le else :)
+                let host_to = host_to.map(|host| host.to_string());
+                let dests = find_users(server_state, &user_to, host_to.as_deref()).await;
+                deliver_to_nick(
+                    kind,
+                    dests,
+                    &target_name,
+                    sender,
+                    (&msgid, &message),
+                    user_state,
+                    away_reply_interval,
+                )
+                .await;
             }
-            MessageTo::UserHostServer(_uhs) => {
-                error!("PRIVMSG to UserHostServer not implemented yet")
+            MessageTo::UserHost((user_to, host_to)) => {
+                let dests = find_users(server_state, &user_to, Some(&host_to.to_string())).await;
+                deliver_to_nick(
+                    kind,
+                    dests,
+                    &target_name,
+                    sender,
+                    (&msgid, &message),
+                    user_state,
+                    away_reply_interval,
+                )
+                .await;
             }
-            MessageTo::UserHost(_uh) => error!("PRIVMSG to UserHost not implemented yet"),
             MessageTo::TargetMask(_tm) => error!("PRIVMSG to TargetMask not implemented yet"),
         }
     }
     Ok(UserStatus::Active)
 }
+
+/// The prefix of the sending user: nick, user and displayed host
+type MessageFrom<'a> = (&'a Nickname, &'a Username, &'a str);
+
+/// A message being delivered: its msgid and its text
+type MessageText<'a> = (&'a str, &'a str);
+
+/// Registered users with the given username, and host when one is given.
+async fn find_users(
+    server_state: &ServerState,
+    user_to: &Username,
+    host_to: Option<&str>,
+) -> Vec<UserState> {
+    let candidates: Vec<UserState> = server_state
+        .users
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect();
+    let mut found = Vec::new();
+    for candidate in candidates {
+        let caracs = candidate.get_caracs().await;
+        if caracs.registered
+            && caracs.user.as_ref() == Some(user_to)
+            && host_to.is_none_or(|host| caracs.displayed_host().eq_ignore_ascii_case(host))
+        {
+            found.push(candidate);
+        }
+    }
+    found
+}
+
+/// Delivers to the one user a `target` resolved to: ERR_NOSUCHNICK when
+/// nobody matches, ERR_TOOMANYTARGETS when the target is ambiguous. Every
+/// nick target goes through here, so PRIVMSG and NOTICE can't drift apart:
+///
+/// | target  | PRIVMSG         | NOTICE    |
+/// |---------|-----------------|-----------|
+/// | unknown | 401             | nothing   |
+/// | away    | delivered + 301 | delivered |
+async fn deliver_to_nick(
+    kind: MessageKind,
+    dests: Vec<UserState>,
+    target: &str,
+    sender: MessageFrom<'_>,
+    message: MessageText<'_>,
+    user_state: &UserState,
+    away_reply_interval: u64,
+) {
+    let nick_from = sender.0;
+    match dests.as_slice() {
+        [user_state_dest] => {
+            deliver_to_user(
+                kind,
+                user_state_dest,
+                sender,
+                message,
+                user_state,
+                away_reply_interval,
+            )
+            .await
+        }
+        [] => {
+            let irc_reply = IrcReply::ErrNoSuchNick {
+                nick: nick_from,
+                target,
+            };
+            kind.reply(user_state, irc_reply).await;
+        }
+        _ => {
+            let irc_reply = IrcReply::ErrTooManyTargets {
+                nick: nick_from,
+                target,
+            };
+            kind.reply(user_state, irc_reply).await;
+        }
+    }
+}
+
+/// Sends the message to a resolved user, answering a PRIVMSG with its
+/// AWAY message.
+async fn deliver_to_user(
+    kind: MessageKind,
+    user_state_dest: &UserState,
+    (nick_from, user_from, host_from): MessageFrom<'_>,
+    (msgid, message): MessageText<'_>,
+    user_state: &UserState,
+    away_reply_interval: u64,
+) {
+    let dest = user_state_dest.get_caracs().await;
+    let Some(nick_to) = &dest.nick else {
+        return;
+    };
+    let mrep = MessageReply::NicknamePrivMsg {
+        command: kind.command(),
+        nick_from,
+        user_from,
+        host_from,
+        nick_to,
+        message,
+    };
+    let message = IrcMessage::new(mrep.format()).with_tags(MessageTags::new().with("msgid", msgid));
+    let direct_irc_message = IrcMessage::new(message.line_for(&dest.capabilities));
+    let _ = user_state_dest.tx_outbound.send(direct_irc_message).await;
+    if let Some(away) = dest.away_message()
+        && kind == MessageKind::Privmsg
+        && user_state
+            .try_away_reply(dest.user_id, away_reply_interval)
+            .await
+    {
+        // 301 RPL_AWAY, at most once per interval per target
+        let irc_reply = IrcReply::Away {
+            nick: nick_from,
+            target: nick_to,
+            message: away,
+        };
+        kind.reply(user_state, irc_reply).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        server_state::ServerState,
+        test_utils::{TestClient, has_numeric, numeric},
+        types::ChannelName,
+        utils::parse_server_time,
+    };
+
+    #[tokio::test]
+    async fn test_privmsg_over_target_limit_returns_407() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.limits.max_targets = Some(2);
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        let mut dave = TestClient::registered(&server_state, "dave").await;
+
+        alice
+            .send(&server_state, "PRIVMSG bob,carol,dave :hello")
+            .await
+            .unwrap();
+
+        let replies = alice.drain();
+        assert!(has_numeric(&replies, "407"), "{replies:?}");
+        assert!(replies.iter().any(|l| l.contains(" 407 alice dave ")));
+        assert_eq!(bob.drain().len(), 1);
+        assert_eq!(carol.drain().len(), 1);
+        assert!(dave.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_privmsg_within_target_limit() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.limits.max_targets = Some(2);
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+
+        alice
+            .send(&server_state, "PRIVMSG bob,carol :hello")
+            .await
+            .unwrap();
+
+        assert!(!has_numeric(&alice.drain(), "407"));
+        let received = bob.drain();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].ends_with("PRIVMSG bob :hello"));
+        assert_eq!(carol.drain().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_privmsg_to_missing_channel_returns_403() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice
+            .send(&server_state, "PRIVMSG #nowhere :hello?")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 403 alice #nowhere :No such channel"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_privmsg_from_outside_no_external_channel_returns_404() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #closed").await.unwrap();
+        let closed = server_state
+            .get_channel(&ChannelName("#closed".to_owned()))
+            .unwrap();
+        closed.modes.write().await.no_external_msgs = true;
+        alice.drain();
+
+        bob.send(&server_state, "PRIVMSG #closed :let me in")
+            .await
+            .unwrap();
+
+        assert!(has_numeric(&bob.drain(), "404"));
+        assert!(alice.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_away_reply_sent_once_per_interval() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        bob.send(&server_state, "AWAY :gone fishing").await.unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![":unknown.server 306 bob :You have been marked as being away"]
+        );
+
+        alice.send(&server_state, "PRIVMSG bob :hi").await.unwrap();
+        alice
+            .send(&server_state, "PRIVMSG bob :still there?")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 301 alice bob :gone fishing"]
+        );
+        assert_eq!(bob.drain().len(), 2);
+
+        bob.send(&server_state, "AWAY").await.unwrap();
+        assert!(has_numeric(&bob.drain(), "305"));
+    }
+
+    #[tokio::test]
+    async fn test_privmsg_to_nick_user_host() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+
+        alice
+            .send(&server_state, "PRIVMSG bob!bob@127.0.0.1 :found you")
+            .await
+            .unwrap();
+        assert!(alice.drain().is_empty());
+        assert_eq!(
+            bob.drain(),
+            vec![":alice!alice@127.0.0.1 PRIVMSG bob :found you"]
+        );
+
+        // Right nick, stale host
+        alice
+            .send(&server_state, "PRIVMSG bob!bob@10.0.0.1 :are you there?")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 401 alice bob!bob@10.0.0.1 :No such nick/channel"]
+        );
+        assert!(bob.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_privmsg_to_user_at_server() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+
+        alice
+            .send(&server_state, "PRIVMSG bob@unknown.server :hi")
+            .await
+            .unwrap();
+        assert_eq!(bob.drain().len(), 1);
+
+        alice
+            .send(&server_state, "PRIVMSG bob@other.server :hi")
+            .await
+            .unwrap();
+        assert!(has_numeric(&alice.drain(), "402"));
+        assert!(bob.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_channel_message_reaches_each_other_member_once() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client.send(&server_state, "JOIN #once").await.unwrap();
+        }
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client.drain();
+        }
+
+        alice
+            .send(&server_state, "PRIVMSG #once :just once")
+            .await
+            .unwrap();
+
+        let expected = vec![":alice!alice@127.0.0.1 PRIVMSG #once :just once".to_owned()];
+        assert_eq!(bob.drain(), expected);
+        assert_eq!(carol.drain(), expected);
+        assert!(alice.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_channel_hides_the_sender() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #masks").await.unwrap();
+        alice.send(&server_state, "MODE #masks +a").await.unwrap();
+        alice.drain();
+
+        bob.send(&server_state, "JOIN #masks").await.unwrap();
+        assert!(
+            bob.drain()
+                .contains(&":bob!bob@127.0.0.1 JOIN :#masks".to_owned())
+        );
+        bob.send(&server_state, "PRIVMSG #masks :guess who")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":anonymous!anonymous@anonymous. JOIN :#masks",
+                ":anonymous!anonymous@anonymous. PRIVMSG #masks :guess who",
+            ]
+        );
+
+        alice.send(&server_state, "NAMES #masks").await.unwrap();
+        let names = alice.drain();
+        assert!(names[0].ends_with(" #masks :@alice"), "{names:?}");
+    }
+
+    #[tokio::test]
+    async fn test_channel_members_share_one_msgid() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.capabilities =
+            Some(toml::from_str("message-tags = true").unwrap());
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client
+                .send(&server_state, "CAP REQ :message-tags")
+                .await
+                .unwrap();
+            client.send(&server_state, "JOIN #chan").await.unwrap();
+        }
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client.drain();
+        }
+
+        alice
+            .send(&server_state, "PRIVMSG #chan :hi")
+            .await
+            .unwrap();
+        let (bob_line, carol_line) = (bob.drain(), carol.drain());
+        assert_eq!(bob_line, carol_line);
+        assert!(bob_line[0].starts_with("@msgid="), "{bob_line:?}");
+        assert!(bob_line[0].ends_with(" :alice!alice@127.0.0.1 PRIVMSG #chan :hi"));
+
+        // A private message gets its own id, only for clients with the tags
+        alice
+            .send(&server_state, "PRIVMSG bob :psst")
+            .await
+            .unwrap();
+        let private = bob.drain();
+        assert!(private[0].starts_with("@msgid="), "{private:?}");
+        assert_ne!(private[0].split(' ').next(), bob_line[0].split(' ').next());
+        bob.send(&server_state, "CAP REQ :-message-tags")
+            .await
+            .unwrap();
+        bob.drain();
+        alice
+            .send(&server_state, "PRIVMSG bob :psst")
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![":alice!alice@127.0.0.1 PRIVMSG bob :psst"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_colors_channel_strips_formatting() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        for client in [&mut alice, &mut bob] {
+            client
+                .send(&server_state, "JOIN #plain,#clean")
+                .await
+                .unwrap();
+        }
+        alice.send(&server_state, "MODE #clean +c").await.unwrap();
+        alice.drain();
+        bob.drain();
+
+        let colored = "\x0304,12red\x03 and \x02bold\x02 \x1funder\x0f, 3,5";
+        alice
+            .send(&server_state, &format!("PRIVMSG #plain :{colored}"))
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![format!(":alice!alice@127.0.0.1 PRIVMSG #plain :{colored}")]
+        );
+        alice
+            .send(&server_state, &format!("PRIVMSG #clean :{colored}"))
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![":alice!alice@127.0.0.1 PRIVMSG #clean :red and bold under, 3,5"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_op_moderated_channel_relays_blocked_messages_to_ops() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client.send(&server_state, "JOIN #chan").await.unwrap();
+        }
+        alice
+            .send(&server_state, "MODE #chan +mzv bob")
+            .await
+            .unwrap();
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client.drain();
+        }
+
+        carol
+            .send(&server_state, "PRIVMSG #chan :can anyone hear me")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":carol!carol@127.0.0.1 PRIVMSG @#chan :can anyone hear me"]
+        );
+        assert!(bob.drain().is_empty());
+        assert!(carol.drain().is_empty());
+
+        // Voiced members still talk to everyone
+        bob.send(&server_state, "PRIVMSG #chan :I can")
+            .await
+            .unwrap();
+        assert_eq!(
+            carol.drain(),
+            vec![":bob!bob@127.0.0.1 PRIVMSG #chan :I can"]
+        );
+
+        // Without +z, +m alone refuses the message
+        alice.send(&server_state, "MODE #chan -z").await.unwrap();
+        alice.drain();
+        carol.drain();
+        carol
+            .send(&server_state, "PRIVMSG #chan :hello?")
+            .await
+            .unwrap();
+        assert!(has_numeric(&carol.drain(), "404"));
+        assert!(alice.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_privmsg_and_notice_reply_matrix() {
+        // (command, target, what the sender gets back, whether bob gets it)
+        let cases = [
+            (
+                "PRIVMSG",
+                "nobody",
+                vec![":unknown.server 401 alice nobody :No such nick/channel"],
+                false,
+            ),
+            (
+                "PRIVMSG",
+                "bob",
+                vec![":unknown.server 301 alice bob :gone fishing"],
+                true,
+            ),
+            ("NOTICE", "nobody", vec![], false),
+            ("NOTICE", "bob", vec![], true),
+        ];
+        for (command, target, replies, delivered) in cases {
+            let server_state = ServerState::default();
+            let mut alice = TestClient::registered(&server_state, "alice").await;
+            let mut bob = TestClient::registered(&server_state, "bob").await;
+            bob.send(&server_state, "AWAY :gone fishing").await.unwrap();
+            bob.drain();
+
+            alice
+                .send(&server_state, &format!("{command} {target} :hi"))
+                .await
+                .unwrap();
+            assert_eq!(alice.drain(), replies, "{command} {target}");
+            let expected = if delivered {
+                vec![format!(":alice!alice@127.0.0.1 {command} bob :hi")]
+            } else {
+                vec![]
+            };
+            assert_eq!(bob.drain(), expected, "{command} {target}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_privmsg_to_ten_thousand_targets_is_bounded() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+
+        let targets = (0..10_000)
+            .map(|i| format!("bob{i}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        alice
+            .send(&server_state, &format!("PRIVMSG bob,{targets} :hi"))
+            .await
+            .unwrap();
+
+        // Delivered up to limits.max_targets (one 401 per missing nick), then a single 407
+        let replies = alice.drain();
+        assert_eq!(replies.len(), 4, "{replies:?}");
+        assert_eq!(numeric(&replies[3]), Some("407"));
+        assert_eq!(bob.drain().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_member_of_two_targeted_channels_gets_one_line_each() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        for client in [&mut alice, &mut bob] {
+            client.send(&server_state, "JOIN #a,#b").await.unwrap();
+        }
+        alice.drain();
+        bob.drain();
+
+        alice
+            .send(&server_state, "PRIVMSG #a,#b,#a,bob,bob :hi")
+            .await
+            .unwrap();
+
+        assert!(alice.drain().is_empty());
+        // Direct and channel lines arrive on separate queues
+        let mut received = bob.drain();
+        received.sort();
+        assert_eq!(
+            received,
+            vec![
+                ":alice!alice@127.0.0.1 PRIVMSG #a :hi",
+                ":alice!alice@127.0.0.1 PRIVMSG #b :hi",
+                ":alice!alice@127.0.0.1 PRIVMSG bob :hi",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_live_messages_carry_server_time() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        bob.send(&server_state, "CAP REQ :server-time")
+            .await
+            .unwrap();
+        for client in [&mut alice, &mut bob] {
+            client.send(&server_state, "JOIN #chan").await.unwrap();
+        }
+        alice.drain();
+        bob.drain();
+
+        alice
+            .send(&server_state, "PRIVMSG #chan :hi")
+            .await
+            .unwrap();
+        alice.send(&server_state, "NOTICE bob :psst").await.unwrap();
+        let replies = bob.drain();
+        assert_eq!(replies.len(), 2, "{replies:?}");
+        for line in &replies {
+            let (tags, rest) = line.split_once(' ').unwrap();
+            let time = tags.strip_prefix("@time=").expect(line);
+            assert!(parse_server_time(time).is_some(), "{line}");
+            assert!(rest.starts_with(":alice!alice@127.0.0.1 "), "{line}");
+        }
+    }
+}
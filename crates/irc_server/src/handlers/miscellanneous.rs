@@ -1,9 +1,12 @@
 use crate::{
     errors::InternalIrcError,
-    message_models::DirectIrcMessage,
-    replies::IrcReply,
+    handlers::{registration::update_nick, server_queries::require_local_target},
+    message_models::IrcMessage,
+    replies::{IrcReply, MessageReply},
+    server_state::ServerState,
     types::{Host, Nickname},
     user_state::{UserState, UserStatus},
+    utils::wildcard_match,
 };
 use nom::{IResult, Parser, bytes::complete::take_till};
 
@@ -44,14 +47,146 @@ pub async fn handle_ping(
     server: Vec<Host>,
     user_state: &UserState,
 ) -> Result<UserStatus, InternalIrcError> {
+    // PING <server1> <server2> is to be forwarded to server2
+    let forward_to = server.get(1).map(|server2| server2.to_string());
+    if !require_local_target(forward_to.as_deref(), user_state).await {
+        return Ok(UserStatus::Active);
+    }
     let irc_reply = IrcReply::Pong {
         destination: &format!("{}", server[0]),
     };
-    let pong_message = DirectIrcMessage::new(irc_reply.format());
+    let pong_message = IrcMessage::new(irc_reply.format());
     let _ = user_state.tx_outbound.send(pong_message).await;
     Ok(UserStatus::Active)
 }
 
+// 3.7.1 Kill message
+
+//       Command: KILL
+//    Parameters: <nickname> <comment>
+
+//    The KILL command is used to cause a client-server connection to be
+//    closed by the server which has the actual connection.
+
+//    Numeric Replies:
+
+//            ERR_NOPRIVILEGES ✅             ERR_NEEDMOREPARAMS
+//            ERR_NOSUCHNICK ✅               ERR_CANTKILLSERVER
+
+pub async fn handle_kill(
+    target: Nickname,
+    comment: Option<String>,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    if !caracs.modes.contains(&'o') {
+        let irc_reply = IrcReply::ErrNoPrivileges { nick: &nick };
+        let err_no_privileges = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_privileges).await;
+        return Ok(UserStatus::Active);
+    }
+    let target_id = server_state.nick.get(&target).map(|r| *r);
+    let target_state = target_id.and_then(|id| server_state.users.get(&id).map(|r| r.clone()));
+    let (Some(target_id), Some(target_state)) = (target_id, target_state) else {
+        let irc_reply = IrcReply::ErrNoSuchNick {
+            nick: &nick,
+            target: &target.0,
+        };
+        let err_no_such_nick = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_such_nick).await;
+        return Ok(UserStatus::Active);
+    };
+    let comment = comment.unwrap_or_else(|| nick.0.clone());
+    server_state
+        .record_oper_action(&nick, "KILL", &format!("{target} :{comment}"))
+        .await;
+
+    let reason = format!("Killed ({nick} ({comment}))");
+    target_state.send_error_and_close(&reason).await;
+    server_state.handle_quit(target_id, Some(reason)).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_sanick(
+    target: Nickname,
+    new_nick: Nickname,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Replies:
+
+    //            ERR_NOPRIVILEGES ✅             ERR_NOSUCHNICK ✅
+    //            ERR_ERRONEUSNICKNAME ✅         ERR_NICKNAMEINUSE ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    if !caracs.modes.contains(&'o') {
+        let irc_reply = IrcReply::ErrNoPrivileges { nick: &nick };
+        let err_no_privileges = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_privileges).await;
+        return Ok(UserStatus::Active);
+    }
+    let target_id = server_state.nick.get(&target).map(|r| *r);
+    let target_state = target_id.and_then(|id| server_state.users.get(&id).map(|r| r.clone()));
+    let (Some(target_id), Some(target_state)) = (target_id, target_state) else {
+        let irc_reply = IrcReply::ErrNoSuchNick {
+            nick: &nick,
+            target: &target.0,
+        };
+        let err_no_such_nick = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_such_nick).await;
+        return Ok(UserStatus::Active);
+    };
+    let is_erroneous = {
+        let config = server_state.config.read().await;
+        new_nick.0.len() > config.get_max_nick_length()
+            || config
+                .get_forbidden_nicks()
+                .iter()
+                .any(|mask| wildcard_match(mask, &new_nick.0))
+    };
+    if is_erroneous {
+        let irc_reply = IrcReply::ErrErroneusNickname { nick: &new_nick };
+        let err_erroneus_nickname = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_erroneus_nickname).await;
+        return Ok(UserStatus::Active);
+    }
+    let in_use = server_state
+        .nick_holder(&new_nick)
+        .is_some_and(|holder| holder != target_id);
+    if in_use || !server_state.claim_nick(&new_nick, target_id) {
+        let irc_reply = IrcReply::ErrNicknameInUse { nick: &new_nick };
+        let err_nick_in_use = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_nick_in_use).await;
+        return Ok(UserStatus::Active);
+    }
+    server_state
+        .record_oper_action(&nick, "SANICK", &format!("{target} {new_nick}"))
+        .await;
+
+    // Same path as a NICK of their own, shared channels hear of it
+    let Some(old_nick) = target_state.with_nick(new_nick.clone()).await else {
+        return Ok(UserStatus::Active);
+    };
+    update_nick(&old_nick, &new_nick, target_id, server_state, &target_state).await?;
+    let target_caracs = target_state.get_caracs().await;
+    if target_caracs.member_of.is_empty() {
+        // Nobody relayed it, the renamed user still has to learn their nick
+        let update = MessageReply::UpdateNick {
+            old_nick: &old_nick,
+            new_nick: &new_nick,
+            user: target_caracs.user.as_ref().expect("registered"),
+            host: &target_caracs.displayed_host(),
+        };
+        let _ = target_state
+            .tx_outbound
+            .send(IrcMessage::new(update.format()))
+            .await;
+    }
+    Ok(UserStatus::Active)
+}
+
 pub struct IrcUnknownCommand(String);
 impl IrcUnknownCommand {
     pub fn irc_command_parser(input: &str) -> IResult<&str, Self> {
@@ -73,7 +208,7 @@ impl IrcUnknownCommand {
                     nick: &nick,
                     command: &parsed_command,
                 };
-                let unknown_command_message = DirectIrcMessage::new(irc_reply.format());
+                let unknown_command_message = IrcMessage::new(irc_reply.format());
                 let _ = user_state.tx_outbound.send(unknown_command_message).await;
                 if &nick != &Nickname("*".to_owned()) {
                     Ok(UserStatus::Handshaking)
@@ -1,6 +1,12 @@
+pub mod accounts;
 pub mod channels;
+pub mod chathistory;
 pub mod client;
 pub mod messages;
 pub mod miscellanneous;
+pub mod optional_features;
+pub mod redaction;
 pub mod registration;
 pub mod request;
+pub mod server_queries;
+pub mod user_queries;
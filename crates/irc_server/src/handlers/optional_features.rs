@@ -0,0 +1,677 @@
+use log::{error, info};
+
+use crate::{
+    channels_models::SubscriptionControl,
+    config::Config,
+    errors::InternalIrcError,
+    handlers::channels::require_valid_channel,
+    message_models::IrcMessage,
+    replies::{IrcReply, MessageReply},
+    server_state::ServerState,
+    types::{ChannelName, Nickname, Username},
+    user_state::{UserState, UserStatus},
+};
+
+pub async fn handle_globops(
+    text: String,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Numeric Replies:
+
+    //            ERR_NEEDMOREPARAMS              ERR_NOPRIVILEGES ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    if !caracs.modes.contains(&'o') {
+        let irc_reply = IrcReply::ErrNoPrivileges { nick: &nick };
+        let err_no_privileges = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_privileges).await;
+        return Ok(UserStatus::Active);
+    }
+    let text = format!("*** Global -- {text}");
+    for operator in server_state.users_with_mode('o').await {
+        let op_nick = operator.get_caracs().await.nick;
+        if let Some(op_nick) = op_nick {
+            let irc_reply = IrcReply::ServerNotice {
+                nick: &op_nick,
+                text: &text,
+            };
+            let globops_message = IrcMessage::new(irc_reply.format());
+            let _ = operator.tx_outbound.send(globops_message).await;
+        }
+    }
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_away(
+    text: Option<String>,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 4.1 Away
+    //    Numeric Replies:
+
+    //            RPL_UNAWAY ✅                   RPL_NOWAWAY ✅
+    user_state.with_away(text).await;
+    // Read back, so the replies show what WHO and WHOIS will show
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    let irc_reply = if caracs.is_away() {
+        IrcReply::NowAway { nick: &nick }
+    } else {
+        IrcReply::UnAway { nick: &nick }
+    };
+    let away_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(away_message).await;
+    if let Some(user) = &caracs.user {
+        let mrep = MessageReply::Away {
+            nick_from: &nick,
+            user_from: user,
+            host_from: &caracs.displayed_host(),
+            message: caracs.away_message(),
+        };
+        server_state
+            .broadcast_to_capable_neighbors(
+                &caracs.member_of,
+                "away-notify",
+                IrcMessage::new(mrep.format()),
+                Some(caracs.user_id),
+            )
+            .await;
+    }
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_rehash(
+    motd_only: bool,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 4.2 Rehash message
+    //    Numeric Replies:
+
+    //            RPL_REHASHING ✅                ERR_NOPRIVILEGES ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    if !caracs.modes.contains(&'o') {
+        let irc_reply = IrcReply::ErrNoPrivileges { nick: &nick };
+        let err_no_privileges = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_privileges).await;
+        return Ok(UserStatus::Active);
+    }
+    if motd_only {
+        // Limits and everything else stay as they are
+        server_state.reload_motd().await;
+        server_state
+            .record_oper_action(&nick, "REHASH", "MOTD")
+            .await;
+        let irc_reply = IrcReply::Rehashing {
+            nick: &nick,
+            config_file: "MOTD",
+        };
+        let rehashing_message = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(rehashing_message).await;
+        return Ok(UserStatus::Active);
+    }
+    let config_path = server_state.config.read().await.path.clone();
+    // A config that no longer parses keeps the running one
+    let loaded = match &config_path {
+        // The boxed error isn't Send, so it can't be held across the await
+        Some(path) => Config::load(path).map_err(|e| format!("{}: {e}", path.display())),
+        None => Err("no configuration file was given".to_owned()),
+    };
+    let config = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            error!("[{nick}] REHASH failed: {e}");
+            let text = format!("*** REHASH failed, keeping the running configuration: {e}");
+            let irc_reply = IrcReply::ServerNotice {
+                nick: &nick,
+                text: &text,
+            };
+            let rehash_failed = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(rehash_failed).await;
+            return Ok(UserStatus::Active);
+        }
+    };
+    *server_state.config.write().await = config;
+    server_state.reload_motd().await;
+    server_state.notify_capability_changes().await;
+    server_state.record_oper_action(&nick, "REHASH", "").await;
+    let config_file = config_path
+        .map(|path| path.display().to_string())
+        .unwrap_or("*".to_owned());
+    info!("[{nick}] rehashed {config_file}");
+    let irc_reply = IrcReply::Rehashing {
+        nick: &nick,
+        config_file: &config_file,
+    };
+    let rehashing_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(rehashing_message).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_die(
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 4.3 Die message
+    //    Numeric Replies:
+
+    //            ERR_NOPRIVILEGES ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    if !caracs.modes.contains(&'o') {
+        let irc_reply = IrcReply::ErrNoPrivileges { nick: &nick };
+        let err_no_privileges = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_privileges).await;
+        return Ok(UserStatus::Active);
+    }
+    server_state.record_oper_action(&nick, "DIE", "").await;
+    info!("{nick} asked the server to shut down");
+    // The accept loop drains every connection, this one included
+    server_state.shutdown_requested.notify_one();
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_summon(
+    user: Option<String>,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 4.5 Summon message
+    //    Numeric Replies:
+
+    //            ERR_NORECIPIENT               ERR_FILEERROR
+    //            ERR_NOLOGIN ✅                ERR_NOSUCHSERVER
+    //            ERR_SUMMONDISABLED ✅         RPL_SUMMONING ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    let irc_reply = if !server_state.config.read().await.get_summon_enabled() {
+        IrcReply::ErrSummonDisabled { nick: &nick }.format()
+    } else if let Some(user) = user {
+        let users = server_state
+            .users
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<UserState>>();
+        let mut summoned = false;
+        for other_state in users {
+            let other = other_state.get_caracs().await;
+            if other.registered
+                && let Some(other_user) = &other.user
+                && other_user.0.trim_start_matches('~') == user
+                && let Some(other_nick) = &other.nick
+            {
+                let text = format!("*** {nick} summons you to IRC");
+                let irc_reply = IrcReply::ServerNotice {
+                    nick: other_nick,
+                    text: &text,
+                };
+                let summon_message = IrcMessage::new(irc_reply.format());
+                let _ = other_state.tx_outbound.send(summon_message).await;
+                summoned = true;
+            }
+        }
+        if summoned {
+            IrcReply::Summoning {
+                nick: &nick,
+                user: &user,
+            }
+            .format()
+        } else {
+            IrcReply::ErrNoLogin {
+                nick: &nick,
+                user: &user,
+            }
+            .format()
+        }
+    } else {
+        IrcReply::ErrNeedMoreParams {
+            nick: &nick,
+            command: "SUMMON",
+        }
+        .format()
+    };
+    let summon_reply = IrcMessage::new(irc_reply);
+    let _ = user_state.tx_outbound.send(summon_reply).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_users(
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 4.6 Users
+    //    Numeric Replies:
+
+    //            ERR_NOSUCHSERVER              ERR_FILEERROR
+    //            RPL_USERSSTART ✅             RPL_USERS ✅
+    //            RPL_NOUSERS ✅                RPL_ENDOFUSERS ✅
+    //            ERR_USERSDISABLED ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    if !server_state.config.read().await.get_users_enabled() {
+        let irc_reply = IrcReply::ErrUsersDisabled { nick: &nick };
+        let err_users_disabled = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_users_disabled).await;
+        return Ok(UserStatus::Active);
+    }
+
+    let users = server_state
+        .users
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect::<Vec<UserState>>();
+    let mut listed = Vec::new();
+    for other_state in users {
+        let other = other_state.get_caracs().await;
+        if other.registered {
+            let host = other.displayed_host();
+            let user = other.user.unwrap_or(Username("*".to_owned()));
+            listed.push((user, host));
+        }
+    }
+    listed.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+
+    let mut replies = vec![IrcReply::UsersStart { nick: &nick }.format()];
+    for (user, host) in &listed {
+        let irc_reply = IrcReply::Users {
+            nick: &nick,
+            user,
+            host,
+        };
+        replies.push(irc_reply.format());
+    }
+    if listed.is_empty() {
+        replies.push(IrcReply::NoUsers { nick: &nick }.format());
+    }
+    replies.push(IrcReply::EndOfUsers { nick: &nick }.format());
+    for reply in replies {
+        let _ = user_state.tx_outbound.send(IrcMessage::new(reply)).await;
+    }
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_jupe(
+    channel_name: ChannelName,
+    reason: Option<String>,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Numeric Replies:
+
+    //            ERR_NOPRIVILEGES ✅             ERR_BADCHANMASK ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    if !caracs.modes.contains(&'o') {
+        let irc_reply = IrcReply::ErrNoPrivileges { nick: &nick };
+        let err_no_privileges = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_privileges).await;
+        return Ok(UserStatus::Active);
+    }
+    if !require_valid_channel(&channel_name, user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let reason = reason.unwrap_or_else(|| "Channel is juped".to_owned());
+    server_state
+        .juped_channels
+        .insert(channel_name.clone(), reason.clone());
+    server_state
+        .record_oper_action(&nick, "JUPE", &format!("{channel_name} :{reason}"))
+        .await;
+
+    // Everyone is kicked out, each member gets its own copy of the KICK
+    if let Some(channel) = server_state.get_channel(&channel_name) {
+        let user = caracs.user.clone().unwrap_or(Username("*".to_owned()));
+        let host = caracs.displayed_host();
+        let members = channel.members.iter().map(|id| *id).collect::<Vec<_>>();
+        for member_id in members {
+            let Some(member_state) = server_state.get_user_state_from_client_id(&member_id) else {
+                continue;
+            };
+            let member_nick = member_state.get_caracs().await.nick;
+            let kick = MessageReply::Kick {
+                nick_from: &nick,
+                user_from: &user,
+                host_from: &host,
+                channel: &channel_name,
+                nick_to: &member_nick.unwrap_or(Nickname("*".to_owned())),
+                comment: &reason,
+            };
+            let _ = member_state
+                .tx_outbound
+                .send(IrcMessage::new(kick.format()))
+                .await;
+            member_state.leave_channel(&channel_name).await;
+            let _ = member_state
+                .tx_control
+                .send(SubscriptionControl::Unsubscribe(channel_name.clone()))
+                .await;
+            server_state.quit_channel(&member_id, &channel_name).await;
+        }
+    }
+    info!("{nick} juped {channel_name}: {reason}");
+    let text = format!("*** {channel_name} is now juped: {reason}");
+    let irc_reply = IrcReply::ServerNotice {
+        nick: &nick,
+        text: &text,
+    };
+    let _ = user_state
+        .tx_outbound
+        .send(IrcMessage::new(irc_reply.format()))
+        .await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_unjupe(
+    channel_name: ChannelName,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Numeric Replies:
+
+    //            ERR_NOPRIVILEGES ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    if !caracs.modes.contains(&'o') {
+        let irc_reply = IrcReply::ErrNoPrivileges { nick: &nick };
+        let err_no_privileges = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_no_privileges).await;
+        return Ok(UserStatus::Active);
+    }
+    let text = if server_state.juped_channels.remove(&channel_name).is_some() {
+        server_state
+            .record_oper_action(&nick, "UNJUPE", &channel_name.0)
+            .await;
+        format!("*** {channel_name} is no longer juped")
+    } else {
+        format!("*** {channel_name} is not juped")
+    };
+    let irc_reply = IrcReply::ServerNotice {
+        nick: &nick,
+        text: &text,
+    };
+    let _ = user_state
+        .tx_outbound
+        .send(IrcMessage::new(irc_reply.format()))
+        .await;
+    Ok(UserStatus::Active)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::FeaturesConfig,
+        server_state::ServerState,
+        test_utils::{TestClient, has_numeric, numeric},
+    };
+
+    #[tokio::test]
+    async fn test_globops_reaches_operators_only() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        alice.user_state.user.write().await.modes.insert('o');
+        bob.user_state.user.write().await.modes.insert('o');
+
+        alice
+            .send(&server_state, "GLOBOPS :netsplit incoming")
+            .await
+            .unwrap();
+
+        let expected = ":unknown.server NOTICE bob :*** Global -- netsplit incoming";
+        assert_eq!(bob.drain(), vec![expected]);
+        assert_eq!(alice.drain().len(), 1);
+        assert!(carol.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_globops_requires_operator() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        alice.user_state.user.write().await.modes.insert('o');
+
+        carol.send(&server_state, "GLOBOPS :hi").await.unwrap();
+
+        assert!(has_numeric(&carol.drain(), "481"));
+        assert!(alice.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_users_and_summon_disabled_by_default() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice.send(&server_state, "USERS").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 446 alice :USERS has been disabled"]
+        );
+        alice.send(&server_state, "SUMMON bob").await.unwrap();
+        assert!(has_numeric(&alice.drain(), "445"));
+    }
+
+    #[tokio::test]
+    async fn test_users_lists_connected_users_when_enabled() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.features = Some(FeaturesConfig {
+            users: Some(true),
+            summon: None,
+        });
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let _bob = TestClient::registered(&server_state, "bob").await;
+
+        alice.send(&server_state, "USERS").await.unwrap();
+
+        let replies = alice.drain();
+        let numerics = replies
+            .iter()
+            .filter_map(|l| numeric(l))
+            .collect::<Vec<_>>();
+        assert_eq!(numerics, vec!["392", "393", "393", "394"]);
+        assert!(replies[1].starts_with(":unknown.server 393 alice :alice    -         127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn test_rehash_motd_leaves_limits_alone() {
+        let tmp = std::env::temp_dir();
+        let pid = std::process::id();
+        let motd_path = tmp.join(format!("irc_rehash_motd_{pid}.txt"));
+        let config_path = tmp.join(format!("irc_rehash_config_{pid}.toml"));
+        std::fs::write(&motd_path, "before").unwrap();
+        // A full REHASH would pick this up and lift max_targets to 7
+        std::fs::write(
+            &config_path,
+            "[server]\nname = \"x\"\nversion = \"0\"\nmotd = \"inline\"\n\
+             [network]\nbind_address = \"127.0.0.1\"\nport = 6667\nmax_connections = 1\n\
+             [limits]\nmax_channels_per_user = 1\nmax_message_length = 512\n\
+             max_connections_per_ip = 1\nunregistered_timeout = 1\nmax_targets = 7\n",
+        )
+        .unwrap();
+        let server_state = ServerState::default();
+        {
+            let mut config = server_state.config.write().await;
+            config.server.motd_file = Some(motd_path.display().to_string());
+            config.limits.max_targets = Some(2);
+            config.path = Some(config_path.clone());
+        }
+        server_state.reload_motd().await;
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.user_state.user.write().await.modes.insert('o');
+        std::fs::write(&motd_path, "after").unwrap();
+
+        alice.send(&server_state, "REHASH MOTD").await.unwrap();
+        alice.send(&server_state, "MOTD").await.unwrap();
+        std::fs::remove_file(&motd_path).unwrap();
+        std::fs::remove_file(&config_path).unwrap();
+
+        let replies = alice.drain();
+        assert_eq!(replies[0], ":unknown.server 382 alice MOTD :Rehashing");
+        assert!(replies.contains(&":unknown.server 372 alice :- after".to_owned()));
+        assert!(!replies.iter().any(|l| l.ends_with(":- before")));
+        let config = server_state.config.read().await;
+        assert_eq!(config.get_max_targets(), 2);
+        assert_eq!(config.server.name, "irc.rust-server.io");
+    }
+
+    #[tokio::test]
+    async fn test_rehash_sends_cap_new_to_cap_notify_clients() {
+        let config_path =
+            std::env::temp_dir().join(format!("irc_rehash_caps_{}.toml", std::process::id()));
+        std::fs::write(
+            &config_path,
+            "[server]\nname = \"x\"\nversion = \"0\"\nmotd = \"inline\"\n\
+             [network]\nbind_address = \"127.0.0.1\"\nport = 6667\nmax_connections = 1\n\
+             [limits]\nmax_channels_per_user = 1\nmax_message_length = 512\n\
+             max_connections_per_ip = 1\nunregistered_timeout = 1\n\
+             [capabilities]\naway-notify = true\nbatch = false\n",
+        )
+        .unwrap();
+        let server_state = ServerState::default();
+        server_state.config.write().await.path = Some(config_path.clone());
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.user_state.user.write().await.modes.insert('o');
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        bob.send(&server_state, "CAP REQ :cap-notify batch")
+            .await
+            .unwrap();
+        carol.send(&server_state, "CAP REQ :batch").await.unwrap();
+        bob.drain();
+        carol.drain();
+
+        alice.send(&server_state, "REHASH").await.unwrap();
+        std::fs::remove_file(&config_path).unwrap();
+
+        assert_eq!(
+            bob.drain(),
+            vec![
+                ":unknown.server CAP bob NEW :away-notify",
+                ":unknown.server CAP bob DEL :batch"
+            ]
+        );
+        // Not told, but batch is off for them too
+        assert!(carol.drain().is_empty());
+        assert!(!carol.user_state.has_capability("batch").await);
+    }
+
+    #[tokio::test]
+    async fn test_juped_channel_refuses_joins_until_unjuped() {
+        let server_state = ServerState::default();
+        let mut oper = TestClient::registered(&server_state, "oper").await;
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #warez").await.unwrap();
+        alice.drain();
+
+        alice.send(&server_state, "JUPE #warez").await.unwrap();
+        assert!(has_numeric(&alice.drain(), "481"));
+
+        oper.user_state.user.write().await.modes.insert('o');
+        oper.send(&server_state, "JUPE #warez :Closed by staff")
+            .await
+            .unwrap();
+        assert_eq!(
+            oper.drain(),
+            vec![":unknown.server NOTICE oper :*** #warez is now juped: Closed by staff"]
+        );
+        assert_eq!(
+            alice.drain(),
+            vec![":oper!oper@127.0.0.1 KICK #warez alice :Closed by staff"]
+        );
+        assert!(server_state.channels.is_empty());
+
+        alice.send(&server_state, "JOIN #warez").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 437 alice #warez :Closed by staff"]
+        );
+
+        oper.send(&server_state, "UNJUPE #warez").await.unwrap();
+        oper.drain();
+        alice.send(&server_state, "JOIN #warez").await.unwrap();
+        let replies = alice.drain();
+        assert!(replies.iter().any(|line| line.ends_with(" JOIN :#warez")));
+        assert!(!has_numeric(&replies, "437"));
+    }
+
+    #[tokio::test]
+    async fn test_away_flips_mode_who_whois_privmsg_and_notify() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.capabilities =
+            Some(toml::from_str("away-notify = true").unwrap());
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        bob.send(&server_state, "CAP REQ :away-notify")
+            .await
+            .unwrap();
+        for client in [&mut alice, &mut bob] {
+            client.send(&server_state, "JOIN #chan").await.unwrap();
+        }
+        alice.drain();
+        bob.drain();
+
+        alice.send(&server_state, "AWAY :lunch").await.unwrap();
+        assert_eq!(bob.drain(), vec![":alice!alice@127.0.0.1 AWAY :lunch"]);
+        assert_eq!(alice.user_state.get_caracs().await.mode_string(), "+a");
+        alice.send(&server_state, "WHO #chan").await.unwrap();
+        let replies = alice.drain();
+        assert!(
+            replies
+                .iter()
+                .any(|line| numeric(line) == Some("352") && line.contains(" alice G@ :"))
+        );
+        bob.send(&server_state, "WHOIS alice").await.unwrap();
+        bob.send(&server_state, "PRIVMSG alice :hi").await.unwrap();
+        let replies = bob.drain();
+        let away = ":unknown.server 301 bob alice :lunch".to_owned();
+        assert_eq!(replies.iter().filter(|line| **line == away).count(), 2);
+        assert!(has_numeric(&replies, "317"));
+
+        alice.send(&server_state, "AWAY").await.unwrap();
+        assert_eq!(bob.drain(), vec![":alice!alice@127.0.0.1 AWAY"]);
+        assert_eq!(alice.user_state.get_caracs().await.mode_string(), "+");
+        alice.send(&server_state, "WHO #chan").await.unwrap();
+        let replies = alice.drain();
+        assert!(replies.iter().any(|line| line.contains(" alice H@ :")));
+        bob.send(&server_state, "WHOIS alice").await.unwrap();
+        bob.send(&server_state, "PRIVMSG alice :back?")
+            .await
+            .unwrap();
+        assert!(!has_numeric(&bob.drain(), "301"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_rehash_is_reported_and_not_logged() {
+        let config_path =
+            std::env::temp_dir().join(format!("irc_rehash_broken_{}.toml", std::process::id()));
+        std::fs::write(&config_path, "[server\n").unwrap();
+        let server_state = ServerState::default();
+        server_state.config.write().await.limits.max_targets = Some(2);
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.user_state.user.write().await.modes.insert('o');
+
+        alice.send(&server_state, "REHASH").await.unwrap();
+        let replies = alice.drain();
+        assert_eq!(
+            replies,
+            vec![
+                ":unknown.server NOTICE alice :*** REHASH failed, keeping the running configuration: no configuration file was given"
+            ]
+        );
+
+        server_state.config.write().await.path = Some(config_path.clone());
+        alice.send(&server_state, "REHASH").await.unwrap();
+        std::fs::remove_file(&config_path).unwrap();
+        let replies = alice.drain();
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].starts_with(&format!(
+            ":unknown.server NOTICE alice :*** REHASH failed, keeping the running configuration: {}: ",
+            config_path.display()
+        )));
+        assert!(server_state.oper_audit.read().await.is_empty());
+        assert_eq!(server_state.config.read().await.get_max_targets(), 2);
+    }
+}
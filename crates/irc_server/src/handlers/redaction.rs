@@ -0,0 +1,182 @@
+use crate::{
+    errors::InternalIrcError,
+    message_models::IrcMessage,
+    replies::{IrcReply, MessageReply},
+    server_state::ServerState,
+    types::{ChannelName, ClientId, Nickname},
+    user_state::{UserState, UserStatus},
+};
+
+pub async fn handle_redact(
+    params: Option<(String, String, Option<String>)>,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //    Replies:
+
+    //            ERR_NEEDMOREPARAMS ✅           FAIL INVALID_TARGET ✅
+    //            FAIL REDACT_FORBIDDEN ✅        FAIL UNKNOWN_MSGID ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    let Some((target, msgid, reason)) = params else {
+        let irc_reply = IrcReply::ErrNeedMoreParams {
+            nick: &nick,
+            command: "REDACT",
+        };
+        let err_need_more_params = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_need_more_params).await;
+        return Ok(UserStatus::Active);
+    };
+    // Only channel messages are kept, so only they can be redacted
+    let channel = server_state
+        .get_channel(&ChannelName(target.clone()))
+        .filter(|channel| channel.members.contains(&client_id));
+    let Some(channel) = channel else {
+        let irc_reply = IrcReply::Fail {
+            command: "REDACT",
+            code: "INVALID_TARGET",
+            context: &target,
+            description: "You cannot delete messages from this target",
+        };
+        let invalid_target = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(invalid_target).await;
+        return Ok(UserStatus::Active);
+    };
+    let context = format!("{target} {msgid}");
+    if !channel.is_operator(client_id) && !caracs.modes.contains(&'o') {
+        let irc_reply = IrcReply::Fail {
+            command: "REDACT",
+            code: "REDACT_FORBIDDEN",
+            context: &context,
+            description: "You are not a channel operator",
+        };
+        let forbidden = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(forbidden).await;
+        return Ok(UserStatus::Active);
+    }
+    if !channel.redact_history(&msgid).await {
+        let irc_reply = IrcReply::Fail {
+            command: "REDACT",
+            code: "UNKNOWN_MSGID",
+            context: &context,
+            description: "This message does not exist or is too old",
+        };
+        let unknown_msgid = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(unknown_msgid).await;
+        return Ok(UserStatus::Active);
+    }
+
+    let (src_nick, src_user, src_host) = channel
+        .displayed_source(
+            &nick,
+            caracs.user.as_ref().expect("registered"),
+            &caracs.displayed_host(),
+        )
+        .await;
+    let redact = MessageReply::Redact {
+        nick_from: &src_nick,
+        user_from: &src_user,
+        host_from: &src_host,
+        channel: &channel.name,
+        msgid: &msgid,
+        reason: reason.as_deref(),
+    };
+    let text = match &reason {
+        Some(reason) => format!("A message was deleted by {src_nick}: {reason}"),
+        None => format!("A message was deleted by {src_nick}"),
+    };
+    let notice = MessageReply::ChannelNotice {
+        nick_from: &src_nick,
+        user_from: &src_user,
+        host_from: &src_host,
+        channel: &channel.name,
+        message: &text,
+    };
+    // Everyone who got the message hears of it, the redacting member included
+    let broadcast_irc_message = IrcMessage::new(notice.format())
+        .with_capability_line("draft/message-redaction", redact.format());
+    channel.broadcast_message(broadcast_irc_message);
+    Ok(UserStatus::Active)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{server_state::ServerState, test_utils::TestClient};
+
+    #[tokio::test]
+    async fn test_op_redact_reaches_capable_members() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.capabilities =
+            Some(toml::from_str("message-tags = true\nmessage-redaction = true").unwrap());
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        bob.send(
+            &server_state,
+            "CAP REQ :message-tags draft/message-redaction",
+        )
+        .await
+        .unwrap();
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client.send(&server_state, "JOIN #chan").await.unwrap();
+        }
+        carol
+            .send(&server_state, "PRIVMSG #chan :spam")
+            .await
+            .unwrap();
+        alice.drain();
+        carol.drain();
+        let replies = bob.drain();
+        let msgid = replies
+            .last()
+            .and_then(|line| line.strip_prefix("@msgid="))
+            .and_then(|rest| rest.split_once(' '))
+            .map(|(msgid, _)| msgid.to_owned())
+            .expect("tagged PRIVMSG");
+
+        // Only channel operators may redact
+        bob.send(&server_state, &format!("REDACT #chan {msgid}"))
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![format!(
+                ":unknown.server FAIL REDACT REDACT_FORBIDDEN #chan {msgid} :You are not a channel operator"
+            )]
+        );
+
+        alice
+            .send(&server_state, &format!("REDACT #chan {msgid} :no spam"))
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.drain(),
+            vec![format!(
+                ":alice!alice@127.0.0.1 REDACT #chan {msgid} :no spam"
+            )]
+        );
+        assert_eq!(
+            carol.drain(),
+            vec![":alice!alice@127.0.0.1 NOTICE #chan :A message was deleted by alice: no spam"]
+        );
+        let channel = server_state.channels.iter().next().unwrap().clone();
+        assert!(channel.history.read().await.is_empty());
+
+        // The message is gone, a second REDACT doesn't find it
+        alice
+            .send(&server_state, &format!("REDACT #chan {msgid}"))
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                format!(
+                    ":unknown.server FAIL REDACT UNKNOWN_MSGID #chan {msgid} :This message does not exist or is too old"
+                ),
+                ":alice!alice@127.0.0.1 NOTICE #chan :A message was deleted by alice: no spam"
+                    .to_owned(),
+            ]
+        );
+    }
+}
@@ -1,18 +1,22 @@
 use log::error;
 
 use crate::{
+    auth::AuthResult,
+    config::Config,
     errors::InternalIrcError,
-    message_models::DirectIrcMessage,
+    handlers::{
+        accounts::log_in,
+        channels::{handle_join_channel, require_unrestricted, resume_channels},
+        server_queries::{send_local_global_users, send_motd},
+    },
+    message_models::IrcMessage,
     replies::{IrcReply, MessageReply},
     server_state::ServerState,
-    types::{ClientId, Nickname, Realname, Username},
+    types::{ChannelName, ClientId, Nickname, Realname, Username},
     user_state::{UserState, UserStatus},
+    utils::wildcard_match,
 };
 
-pub const IRC_SERVER_CAP_MULTI_PREFIX: bool = false;
-pub const IRC_SERVER_CAP_SASL: bool = false;
-pub const IRC_SERVER_CAP_ECHO_MESSAGE: bool = false;
-
 // 3.1 CAP LS [version]
 
 // Client → server OR server → client.
@@ -23,7 +27,7 @@ pub const IRC_SERVER_CAP_ECHO_MESSAGE: bool = false;
 
 pub async fn handle_cap_ls_response(
     _client_id: ClientId,
-    _server: &ServerState,
+    server_state: &ServerState,
     user_state: &UserState,
 ) -> Result<UserStatus, InternalIrcError> {
     let user_caracs = user_state.get_caracs().await;
@@ -34,16 +38,16 @@ pub async fn handle_cap_ls_response(
     };
     let irc_reply = IrcReply::CapLs {
         nick: &nick,
-        capabilities: &get_capabilities(),
+        capabilities: &get_capabilities(server_state).await,
     };
-    let cap_list_message = DirectIrcMessage::new(irc_reply.format());
+    let cap_list_message = IrcMessage::new(irc_reply.format());
     let _ = user_state.tx_outbound.send(cap_list_message).await;
     // :server CAP * LS :chghost echo-message extended-join invite-notify
     // :server CAP * LS :message-tags multi-prefix sasl
-    if &nick != &Nickname("*".to_owned()) {
-        Ok(UserStatus::Handshaking)
-    } else {
+    if user_caracs.registered {
         Ok(UserStatus::Active)
+    } else {
+        Ok(UserStatus::Handshaking)
     }
     // :server CAP * LS :chghost echo-message extended-join invite-notify
     // :server CAP * LS :message-tags multi-prefix sasl
@@ -54,7 +58,7 @@ pub async fn handle_cap_ls_response(
 
 pub async fn handle_cap_list_response(
     _client_id: ClientId,
-    _server: &ServerState,
+    server_state: &ServerState,
     user_state: &UserState,
 ) -> Result<UserStatus, InternalIrcError> {
     let user_caracs = user_state.get_caracs().await;
@@ -65,31 +69,74 @@ pub async fn handle_cap_list_response(
     };
     let irc_reply = IrcReply::CapList {
         nick: &nick,
-        capabilities: &get_capabilities(),
+        capabilities: &get_capabilities(server_state).await,
     };
-    let cap_list_message = DirectIrcMessage::new(irc_reply.format());
+    let cap_list_message = IrcMessage::new(irc_reply.format());
     let _ = user_state.tx_outbound.send(cap_list_message).await;
     // :server CAP * LS :chghost echo-message extended-join invite-notify
     // :server CAP * LS :message-tags multi-prefix sasl
-    if &nick != &Nickname("*".to_owned()) {
-        Ok(UserStatus::Handshaking)
-    } else {
+    if user_caracs.registered {
         Ok(UserStatus::Active)
+    } else {
+        Ok(UserStatus::Handshaking)
     }
 }
 
-fn get_capabilities() -> String {
-    let mut capabilities_string = String::new();
-    if IRC_SERVER_CAP_SASL {
-        capabilities_string.push_str("sasl ");
+async fn get_capabilities(server_state: &ServerState) -> String {
+    server_state
+        .config
+        .read()
+        .await
+        .get_capabilities()
+        .join(" ")
+}
+
+// 3.3 CAP REQ <capabilities>
+// Client → server.
+// The request is atomic: every capability is ACKed, or the whole
+// list is NAKed and nothing changes. A "-" prefix disables a capability.
+
+pub async fn handle_cap_req_response(
+    requested: String,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    let user_caracs = user_state.get_caracs().await;
+    let nick = if user_caracs.registered {
+        user_caracs.nick.unwrap().clone()
+    } else {
+        Nickname("*".to_string())
     };
-    if IRC_SERVER_CAP_ECHO_MESSAGE {
-        capabilities_string.push_str("echo-message ");
-    }
-    if IRC_SERVER_CAP_MULTI_PREFIX {
-        capabilities_string.push_str("multi-prefix ");
+    let available = get_capabilities(server_state).await;
+    let all_available = requested.split_whitespace().all(|capability| {
+        let capability = capability.strip_prefix('-').unwrap_or(capability);
+        available.split(' ').any(|a| a == capability)
+    });
+    let irc_reply = if all_available && !requested.is_empty() {
+        let mut user_data = user_state.user.write().await;
+        for capability in requested.split_whitespace() {
+            match capability.strip_prefix('-') {
+                Some(disabled) => user_data.capabilities.remove(disabled),
+                None => user_data.capabilities.insert(capability.to_owned()),
+            };
+        }
+        IrcReply::CapAck {
+            nick: &nick,
+            capabilities: &requested,
+        }
+    } else {
+        IrcReply::CapNak {
+            nick: &nick,
+            capabilities: &requested,
+        }
+    };
+    let cap_req_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(cap_req_message).await;
+    if user_caracs.registered {
+        Ok(UserStatus::Active)
+    } else {
+        Ok(UserStatus::Handshaking)
     }
-    capabilities_string.trim().to_string()
 }
 
 // 3.7 CAP END
@@ -97,7 +144,41 @@ fn get_capabilities() -> String {
 // Ends negotiation.
 // After this, client typically expects start of normal IRC registration.
 
-pub fn handle_cap_end_response() -> Result<UserStatus, InternalIrcError> {
+pub async fn handle_cap_end_response(
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    if user_state.is_registered().await {
+        Ok(UserStatus::Active)
+    } else {
+        Ok(UserStatus::Handshaking)
+    }
+}
+
+pub async fn handle_pass_registration(
+    password: String,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    //     3.1.1 Password message
+    //       Command: PASS
+    //    Parameters: <password>
+    // Numeric Replies:
+    //         ERR_NEEDMOREPARAMS              ERR_ALREADYREGISTRED ✅
+    // There is no server password, PASS is the account password a parked
+    // session is taken back with, see `ServerState::handle_lost_connection`
+    if user_state.is_registered().await {
+        let nick = user_state
+            .get_caracs()
+            .await
+            .nick
+            .unwrap_or(Nickname("*".to_owned()));
+        let irc_reply = IrcReply::ErrAlreadyRegistred { nick: &nick };
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(irc_reply.format()))
+            .await;
+        return Ok(UserStatus::Active);
+    }
+    user_state.user.write().await.password = Some(password);
     Ok(UserStatus::Handshaking)
 }
 
@@ -113,33 +194,120 @@ pub async fn handle_nick_registration(
     //    NICK command is used to give user a nickname or change the existing
     //    one.
     // Numeric Replies:
-    //         ERR_NONICKNAMEGIVEN             ERR_ERRONEUSNICKNAME
-    //         ERR_NICKNAMEINUSE ✅              ERR_NICKCOLLISION
-    //         ERR_UNAVAILRESOURCE
-    //         ERR_RESTRICTED
-    let nick_already_exists = server_state.nick.contains_key(&nick);
+    //         ERR_NONICKNAMEGIVEN             ERR_ERRONEUSNICKNAME ✅
+    //         ERR_NICKNAMEINUSE ✅              ERR_NICKCOLLISION ✅
+    //         ERR_UNAVAILRESOURCE ✅
+    //         ERR_RESTRICTED ✅
+    let caracs = user_state.get_caracs().await;
+    if caracs.registered
+        && let Some(current_nick) = &caracs.nick
+        && *current_nick != nick
+    {
+        // 484 ERR_RESTRICTED: +r users keep the nick they registered with
+        if !require_unrestricted(user_state).await {
+            return Ok(UserStatus::Active);
+        }
+        let per_min = server_state.config.read().await.get_nick_changes_per_min();
+        if !user_state.try_nick_change(per_min).await {
+            // 437 ERR_UNAVAILRESOURCE: nick-flood throttle
+            error!("[{client_id}] too many nick changes, '{nick}' refused");
+            let err_unavail_resource = IrcReply::ErrUnavailResource {
+                nick: current_nick,
+                target: &nick.0,
+            };
+            let dm = IrcMessage::new(err_unavail_resource.format());
+            let _ = user_state.tx_outbound.send(dm).await;
+            return Ok(UserStatus::Active);
+        }
+    }
+    let (is_forbidden, is_too_long) = {
+        let config = server_state.config.read().await;
+        let is_forbidden = config
+            .get_forbidden_nicks()
+            .iter()
+            .any(|mask| wildcard_match(mask, &nick.0));
+        (is_forbidden, nick.0.len() > config.get_max_nick_length())
+    };
+    if is_too_long {
+        // 432 ERR_ERRONEUSNICKNAME: longer than NICKLEN
+        error!("[{client_id}] nick '{nick}' is too long");
+        let err_erroneus_nickname = IrcReply::ErrErroneusNickname { nick: &nick };
+        let dm = IrcMessage::new(err_erroneus_nickname.format());
+        let _ = user_state.tx_outbound.send(dm).await;
+        return Ok(UserStatus::Active);
+    }
+    if is_forbidden {
+        // 432 ERR_ERRONEUSNICKNAME: reserved for services and staff
+        error!("[{client_id}] nick '{nick}' is forbidden");
+        let err_erroneus_nickname = IrcReply::ErrErroneusNickname { nick: &nick };
+        let dm = IrcMessage::new(err_erroneus_nickname.format());
+        let _ = user_state.tx_outbound.send(dm).await;
+        return Ok(UserStatus::Active);
+    }
+    // A parked nick goes to whoever proves its account with PASS
+    if !caracs.registered
+        && let Some(account) = server_state.parked_account(&nick)
+    {
+        let password = user_state.user.read().await.password.clone();
+        if let Some(password) = password
+            && server_state
+                .account_store
+                .verify(&account, &password)
+                .await
+                .is_some()
+        {
+            user_state.user.write().await.account = Some(account);
+            server_state.release_parked_nick(&nick);
+        }
+    }
+    if server_state.is_held(&nick.0) {
+        // 437 ERR_UNAVAILRESOURCE: its last user quit moments ago
+        error!("[{client_id}] nick '{nick}' is held");
+        let current_nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+        let err_unavail_resource = IrcReply::ErrUnavailResource {
+            nick: &current_nick,
+            target: &nick.0,
+        };
+        let dm = IrcMessage::new(err_unavail_resource.format());
+        let _ = user_state.tx_outbound.send(dm).await;
+        return Ok(UserStatus::Active);
+    }
+    let nick_already_exists = server_state
+        .nick_holder(&nick)
+        .is_some_and(|holder| holder != client_id);
     if nick_already_exists {
         // 433 ERR_NICKNAMEINUSE
         error!("[{client_id}] nick '{nick}' already exists");
         let err_nick_in_use = IrcReply::ErrNicknameInUse { nick: &nick };
-        let dm = DirectIrcMessage::new(err_nick_in_use.format());
+        let dm = IrcMessage::new(err_nick_in_use.format());
+        let _ = user_state.tx_outbound.send(dm).await;
+        Ok(UserStatus::Active)
+    } else if !server_state.claim_nick(&nick, client_id) {
+        // 436 ERR_NICKCOLLISION: another client took the nick between the
+        // check above and the claim
+        error!("[{client_id}] nick '{nick}' collided with a concurrent registration");
+        let err_nick_collision = IrcReply::ErrNickCollision { nick: &nick };
+        let dm = IrcMessage::new(err_nick_collision.format());
         let _ = user_state.tx_outbound.send(dm).await;
         Ok(UserStatus::Active)
     } else {
         let old_nick_opt = user_state.with_nick(nick.clone()).await;
-        if old_nick_opt.is_some() && user_state.is_registered().await {
-            update_nick(
-                &old_nick_opt.unwrap(),
-                &nick,
-                client_id,
-                server_state,
-                user_state,
-            )
-            .await
-        } else if user_state.is_registered().await {
-            when_registered(user_state, server_state).await
+        if let Some(old_nick) = &old_nick_opt
+            && user_state.is_registered().await
+        {
+            update_nick(old_nick, &nick, client_id, server_state, user_state).await
         } else {
-            Ok(UserStatus::Handshaking)
+            // A nick chosen earlier in the handshake is no longer ours
+            if let Some(old_nick) = old_nick_opt
+                && old_nick != nick
+            {
+                server_state.release_nick(&old_nick, client_id);
+            }
+            if user_state.complete_registration().await {
+                when_registered(user_state, server_state).await
+            } else {
+                Ok(UserStatus::Handshaking)
+            }
         }
     }
 }
@@ -153,9 +321,9 @@ pub async fn update_nick(
 ) -> Result<UserStatus, InternalIrcError> {
     let _ = server_state.handle_nick_change(client_id, new_nick, old_nick);
     let user_caracs = user_state.get_caracs().await;
+    let host = &user_caracs.displayed_host();
     let user = &user_caracs.user.unwrap();
-    let host = &format!("{}", user_caracs.addr);
-    let message = DirectIrcMessage::new(
+    let message = IrcMessage::new(
         MessageReply::UpdateNick {
             old_nick,
             new_nick,
@@ -178,8 +346,42 @@ pub async fn handle_user_registration(
     user_state: &UserState,
     server_state: &ServerState,
 ) -> Result<UserStatus, InternalIrcError> {
-    user_state.with_user(user_name, real_name, mode).await;
     if user_state.is_registered().await {
+        // 462 ERR_ALREADYREGISTRED, the user details are set once
+        let nick = user_state
+            .get_caracs()
+            .await
+            .nick
+            .unwrap_or(Nickname("*".to_owned()));
+        let irc_reply = IrcReply::ErrAlreadyRegistred { nick: &nick };
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(irc_reply.format()))
+            .await;
+        return Ok(UserStatus::Active);
+    }
+    let (max_realname, no_controls) = {
+        let config = server_state.config.read().await;
+        (config.get_max_realname(), config.get_realname_no_controls())
+    };
+    if no_controls && real_name.0.chars().any(char::is_control) {
+        // The parameters are all there, only the realname is refused
+        let irc_reply = IrcReply::Fail {
+            command: "USER",
+            code: "INVALID_REALNAME",
+            context: "",
+            description: "Realname must not contain control characters",
+        };
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(irc_reply.format()))
+            .await;
+        return Ok(UserStatus::Handshaking);
+    }
+    // Long realnames would bloat every WHOIS and extended-join line
+    let real_name = Realname(real_name.0.chars().take(max_realname).collect());
+    user_state.with_user(user_name, real_name, mode).await;
+    if user_state.complete_registration().await {
         when_registered(user_state, server_state).await
     } else {
         Ok(UserStatus::Handshaking)
@@ -191,22 +393,195 @@ pub async fn when_registered(
     server_state: &ServerState,
 ) -> Result<UserStatus, InternalIrcError> {
     let user_data = user_state.get_caracs().await;
+    let host = user_data.displayed_host();
+    let modes = user_data.mode_string();
     let nick = user_data.nick.unwrap();
     let user = user_data.user.unwrap();
-    let host = user_data.addr;
-    server_state.add_connecting_user(user_state).await?;
-    let welcome_message = DirectIrcMessage::new(
+    let real_name = user_data.real_name.map(|real_name| real_name.0);
+    if let Some(real_name) = real_name
+        && server_state
+            .audit_bot_signal(user_data.addr.ip(), &nick, &format!("realname {real_name}"))
+            .await
+    {
+        let reason = "Suspected bot".to_owned();
+        user_state.send_error_and_close(&reason).await;
+        server_state
+            .handle_quit(user_data.user_id, Some(reason.clone()))
+            .await;
+        return Ok(user_state
+            .transition_status(UserStatus::Leaving(Some(reason)))
+            .await);
+    }
+    let welcome_message = IrcMessage::new(
         IrcReply::Welcome {
             nick: &nick,
             user: &user,
-            host: &format!("{host:?}"),
+            host: &host,
         }
         .format(),
     );
     let _ = user_state.tx_outbound.send(welcome_message).await;
+    let version = server_state.config.read().await.server.version.clone();
+    let your_host_message = IrcMessage::new(
+        IrcReply::YourHost {
+            nick: &nick,
+            version: &version,
+        }
+        .format(),
+    );
+    let _ = user_state.tx_outbound.send(your_host_message).await;
+    let created_message = IrcMessage::new(
+        IrcReply::Created {
+            nick: &nick,
+            date: &server_state.created,
+        }
+        .format(),
+    );
+    let _ = user_state.tx_outbound.send(created_message).await;
+    let (channel_modes, param_modes) = myinfo_channel_modes();
+    let my_info_message = IrcMessage::new(
+        IrcReply::MyInfo {
+            nick: &nick,
+            version: &version,
+            user_modes: USER_MODES,
+            channel_modes: &channel_modes,
+            param_modes: &param_modes,
+        }
+        .format(),
+    );
+    let _ = user_state.tx_outbound.send(my_info_message).await;
+    let tokens = isupport_tokens(&*server_state.config.read().await);
+    let isupport_message = IrcMessage::new(
+        IrcReply::ISupport {
+            nick: &nick,
+            tokens: &tokens,
+        }
+        .format(),
+    );
+    let _ = user_state.tx_outbound.send(isupport_message).await;
+    send_local_global_users(&nick, server_state, user_state).await;
+    send_motd(&nick, server_state, user_state).await;
+    // Modes from the USER bitmask, e.g. +i for a mode of 8
+    if modes != "+" {
+        send_umode_is(&nick, &modes, user_state).await;
+    }
+    // Back within the reconnection grace, the lost session's channels are
+    // taken over without its peers seeing it leave
+    if let Some(account) = &user_data.account
+        && let Some(lost_id) = server_state.resume_session(&nick, account)
+    {
+        let caracs = user_state.get_caracs().await;
+        log_in(
+            account,
+            &caracs,
+            user_data.user_id,
+            server_state,
+            user_state,
+        )
+        .await;
+        resume_channels(lost_id, user_data.user_id, server_state, user_state).await;
+    }
+    // channels.auto_join, one JOIN each as if the user had sent it
+    let (auto_join, max_channels) = {
+        let config = server_state.config.read().await;
+        (
+            config.get_auto_join().to_vec(),
+            config.limits.max_channels_per_user,
+        )
+    };
+    let hostmask = format!("{nick}!{user}@{host}");
+    for channel_name in auto_join.into_iter().map(ChannelName) {
+        if user_state.get_caracs().await.member_of.len() >= max_channels {
+            break;
+        }
+        if server_state.is_joinable(&channel_name, &hostmask).await {
+            let channels_keys = vec![(channel_name, None)];
+            handle_join_channel(channels_keys, user_data.user_id, server_state, user_state).await?;
+        }
+    }
+    Ok(UserStatus::Active)
+}
+
+async fn send_umode_is(nick: &Nickname, modes: &str, user_state: &UserState) {
+    let irc_reply = IrcReply::UModeIs { nick, modes };
+    let umode_is_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(umode_is_message).await;
+}
+
+pub async fn handle_oper_registration(
+    name: String,
+    password: String,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.1.4 Oper message
+    //    Numeric Replies:
+
+    //            ERR_NEEDMOREPARAMS              RPL_YOUREOPER ✅
+    //            ERR_NOOPERHOST                  ERR_PASSWDMISMATCH ✅
+    let nick = user_state
+        .get_caracs()
+        .await
+        .nick
+        .unwrap_or(Nickname("*".to_owned()));
+    if server_state.auth_provider.verify(&name, &password).await != AuthResult::Accepted {
+        // Same answer for an unknown name, so names can't be probed
+        let irc_reply = IrcReply::ErrPasswdMismatch { nick: &nick };
+        let err_passwd_mismatch = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(err_passwd_mismatch).await;
+        return Ok(UserStatus::Active);
+    }
+    user_state.user.write().await.modes.insert('o');
+    server_state.record_oper_action(&nick, "OPER", &name).await;
+    let irc_reply = IrcReply::YoureOper { nick: &nick };
+    let youre_oper_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(youre_oper_message).await;
+    let modes = user_state.get_caracs().await.mode_string();
+    send_umode_is(&nick, &modes, user_state).await;
     Ok(UserStatus::Active)
 }
 
+// Channel modes by CHANMODES type: lists, always a parameter, a parameter
+// when set, never one. RPL_MYINFO is built from it too
+const CHANMODES: [&str; 4] = ["Ibeq", "k", "fl", "acimnpstz"];
+// Channel modes giving a member status, they take a nick
+const STATUS_MODES: &str = "ov";
+// `a` is not set with MODE but follows AWAY
+const USER_MODES: &str = "aiorwO";
+
+// RPL_ISUPPORT tokens advertised in the welcome burst
+pub fn isupport_tokens(config: &Config) -> String {
+    let tokens = [
+        format!("CHANMODES={}", CHANMODES.join(",")),
+        format!("CHANTYPES={}", config.get_chantypes()),
+        "ELIST=CMNTU".to_owned(),
+        format!("NICKLEN={}", config.get_max_nick_length()),
+        format!("TOPICLEN={}", config.get_max_topic_length()),
+    ];
+    let mut tokens = tokens.join(" ");
+    if config.get_utf8only() {
+        tokens.push_str(" UTF8ONLY");
+    }
+    tokens
+}
+
+// RPL_MYINFO channel modes: all of them, then those taking a parameter
+fn myinfo_channel_modes() -> (String, String) {
+    let sorted = |modes: String| {
+        let mut modes = modes.chars().collect::<Vec<_>>();
+        modes.sort_unstable();
+        modes.into_iter().collect::<String>()
+    };
+    let with_param = format!(
+        "{}{}{}{STATUS_MODES}",
+        CHANMODES[0], CHANMODES[1], CHANMODES[2]
+    );
+    (
+        sorted(format!("{with_param}{}", CHANMODES[3])),
+        sorted(with_param),
+    )
+}
+
 pub async fn handle_mode_registration(
     nick: Nickname,
     modes: Vec<(char, Vec<char>)>,
@@ -214,8 +589,8 @@ pub async fn handle_mode_registration(
 ) -> Result<UserStatus, InternalIrcError> {
     match user_state.with_modes(&nick, modes).await {
         Ok(Some(status)) => {
-            let status_message = DirectIrcMessage::new(status.format());
-            let _ = user_state.tx_outbound.send(status_message);
+            let status_message = IrcMessage::new(status.format());
+            let _ = user_state.tx_outbound.send(status_message).await;
         }
         Ok(_) => (),
         Err(e) => return Err(e),
@@ -226,9 +601,704 @@ pub async fn handle_mode_registration(
 pub async fn handle_quit_registration(
     reason: Option<String>,
     client_id: ClientId,
-    _user_state: &UserState,
+    user_state: &UserState,
     server_state: &ServerState,
 ) -> Result<UserStatus, InternalIrcError> {
+    let error_reason = match &reason {
+        Some(reason) => format!("Quit: {reason}"),
+        None => "Client Quit".to_owned(),
+    };
+    user_state.send_error_and_close(&error_reason).await;
     server_state.handle_quit(client_id, reason.clone()).await;
     Ok(UserStatus::Leaving(reason))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::{
+        config::OperConfig,
+        server_state::ServerState,
+        test_utils::{TestClient, has_numeric, numeric},
+        types::{ChannelName, Nickname, Realname},
+        user_state::UserStatus,
+        utils::unix_timestamp,
+    };
+
+    #[tokio::test]
+    async fn test_cap_req_is_atomic() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+
+        client
+            .send(&server_state, "CAP REQ :batch bogus-cap")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![":unknown.server CAP * NAK :batch bogus-cap"]
+        );
+        assert!(!client.user_state.has_capability("batch").await);
+
+        client
+            .send(&server_state, "CAP REQ :batch draft/chathistory")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![":unknown.server CAP * ACK :batch draft/chathistory"]
+        );
+        assert!(client.user_state.has_capability("draft/chathistory").await);
+
+        client.send(&server_state, "CAP REQ :-batch").await.unwrap();
+        client.drain();
+        assert!(!client.user_state.has_capability("batch").await);
+    }
+
+    #[tokio::test]
+    async fn test_forbidden_nick_is_erroneous() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.limits.forbidden_nicks =
+            Some(vec!["*Serv".to_owned(), "admin".to_owned()]);
+        let mut client = TestClient::connect(&server_state).await;
+
+        client.send(&server_state, "NICK NickServ").await.unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![":unknown.server 432 NickServ :Erroneous nickname"]
+        );
+        client.send(&server_state, "NICK ADMIN").await.unwrap();
+        assert!(has_numeric(&client.drain(), "432"));
+
+        client.send(&server_state, "NICK alice").await.unwrap();
+        client
+            .send(&server_state, "USER alice 0 * :Alice")
+            .await
+            .unwrap();
+        let replies = client.drain();
+        assert!(!has_numeric(&replies, "432"));
+        assert!(has_numeric(&replies, "001"));
+    }
+
+    #[tokio::test]
+    async fn test_nick_changes_beyond_limit_are_refused() {
+        let server_state = ServerState::default();
+        server_state
+            .config
+            .write()
+            .await
+            .limits
+            .nick_changes_per_min = Some(2);
+        let mut client = TestClient::registered(&server_state, "alice").await;
+
+        client.send(&server_state, "NICK alice1").await.unwrap();
+        client.send(&server_state, "NICK alice2").await.unwrap();
+        assert!(!has_numeric(&client.drain(), "437"));
+        client.send(&server_state, "NICK alice3").await.unwrap();
+
+        assert_eq!(
+            client.drain(),
+            vec![":unknown.server 437 alice2 alice3 :Nick/channel is temporarily unavailable"]
+        );
+        let nick = client.user_state.get_caracs().await.nick.unwrap();
+        assert_eq!(nick.0, "alice2");
+        assert!(server_state.nick_holder(&nick).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_welcome_burst_says_when_the_server_started() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+        client.send(&server_state, "NICK alice").await.unwrap();
+        client
+            .send(&server_state, "USER alice 0 * :Alice")
+            .await
+            .unwrap();
+        let replies = client.drain();
+        assert_eq!(numeric(&replies[2]), Some("003"));
+        let date = replies[2]
+            .strip_prefix(":unknown.server 003 alice :This server was created ")
+            .unwrap();
+        // e.g. "Thu Oct 15 2026 at 09:12:45 UTC"
+        let words = date.split(' ').collect::<Vec<_>>();
+        assert_eq!(words.len(), 7, "{date}");
+        assert!(words[3].parse::<u32>().unwrap() >= 2024);
+        assert_eq!((words[4], words[6]), ("at", "UTC"));
+        assert_eq!(words[5].split(':').count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_welcome_host_matches_join_host() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+
+        client.send(&server_state, "NICK alice").await.unwrap();
+        client
+            .send(&server_state, "USER alice 0 * :Alice")
+            .await
+            .unwrap();
+        let welcome = client
+            .drain()
+            .into_iter()
+            .find(|l| numeric(l) == Some("001"))
+            .unwrap();
+        client.send(&server_state, "JOIN #rust").await.unwrap();
+        let join = client
+            .drain()
+            .into_iter()
+            .find(|l| l.contains(" JOIN "))
+            .unwrap();
+
+        let welcome_prefix = welcome.rsplit(' ').next().unwrap();
+        let join_prefix = join.split(' ').next().unwrap().trim_start_matches(':');
+        assert_eq!(welcome_prefix, "alice!alice@127.0.0.1");
+        assert_eq!(join_prefix, welcome_prefix);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_racing_nick_registrations_do_not_both_succeed() {
+        for round in 0..50 {
+            let server_state = ServerState::default();
+            let nick = format!("racer{round}");
+            let mut contenders = Vec::new();
+            for _ in 0..2 {
+                let mut client = TestClient::connect(&server_state).await;
+                client
+                    .send(&server_state, "USER racer 0 * :racer")
+                    .await
+                    .unwrap();
+                let (server_state, nick) = (server_state.clone(), nick.clone());
+                contenders.push(tokio::spawn(async move {
+                    client
+                        .send(&server_state, &format!("NICK {nick}"))
+                        .await
+                        .unwrap();
+                    client.drain()
+                }));
+            }
+            let mut welcomed = 0;
+            for contender in contenders {
+                let replies = contender.await.unwrap();
+                if has_numeric(&replies, "001") {
+                    welcomed += 1;
+                } else {
+                    assert!(has_numeric(&replies, "433") || has_numeric(&replies, "436"));
+                }
+            }
+            assert_eq!(welcomed, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cap_ls_follows_capabilities_config() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+
+        client.send(&server_state, "CAP LS 302").await.unwrap();
+        let replies = client.drain();
+        assert!(!replies[0].contains("sasl"), "{replies:?}");
+
+        server_state.config.write().await.capabilities =
+            Some(toml::from_str("away-notify = true\nbatch = false").unwrap());
+        client.send(&server_state, "CAP LS 302").await.unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![
+                ":unknown.server CAP * LS :server-time draft/chathistory message-tags away-notify cap-notify"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unimplemented_capabilities_are_refused() {
+        let server_state = ServerState::default();
+        // Left over in an old config, the switches are ignored
+        server_state.config.write().await.capabilities =
+            Some(toml::from_str("sasl = true\necho-message = true").unwrap());
+        let mut client = TestClient::connect(&server_state).await;
+
+        client.send(&server_state, "CAP LS 302").await.unwrap();
+        let replies = client.drain();
+        assert!(!replies[0].contains("sasl"), "{replies:?}");
+        assert!(!replies[0].contains("echo-message"), "{replies:?}");
+        for capability in ["echo-message", "sasl", "multi-prefix"] {
+            client
+                .send(&server_state, &format!("CAP REQ :{capability}"))
+                .await
+                .unwrap();
+            assert_eq!(
+                client.drain(),
+                vec![format!(":unknown.server CAP * NAK :{capability}")]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quit_reaches_peer_once_across_shared_channels() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        for client in [&mut alice, &mut bob] {
+            client.send(&server_state, "JOIN #one,#two").await.unwrap();
+        }
+        alice.drain();
+
+        bob.send(&server_state, "QUIT :bye").await.unwrap();
+
+        assert_eq!(
+            alice.drain(),
+            vec![":bob!bob@127.0.0.1 QUIT :bye".to_owned()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nick_over_nicklen_is_erroneous() {
+        let server_state = ServerState::default();
+        let alicia = TestClient::registered(&server_state, "alicia").await;
+        server_state.config.write().await.limits.max_nick_length = Some(5);
+        let mut client = TestClient::connect(&server_state).await;
+
+        client.send(&server_state, "NICK toolong").await.unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![":unknown.server 432 toolong :Erroneous nickname"]
+        );
+        client.send(&server_state, "NICK short").await.unwrap();
+        client
+            .send(&server_state, "USER short 0 * :Short")
+            .await
+            .unwrap();
+        let replies = client.drain();
+        assert!(
+            replies.iter().any(|l| l.contains(" NICKLEN=5 ")),
+            "{replies:?}"
+        );
+
+        // Already taken longer nicks survive the lower limit
+        let nick = alicia.user_state.get_caracs().await.nick.unwrap();
+        assert_eq!(nick.0, "alicia");
+    }
+
+    #[tokio::test]
+    async fn test_umode_is_sent_on_registration_and_oper() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.opers = Some(vec![OperConfig {
+            name: "root".to_owned(),
+            password: "hunter2".to_owned(),
+        }]);
+        let mut client = TestClient::connect(&server_state).await;
+        client.send(&server_state, "NICK x").await.unwrap();
+        client.send(&server_state, "USER x 8 * :y").await.unwrap();
+        let replies = client.drain();
+        assert_eq!(replies.last().unwrap(), ":unknown.server 221 x :+i");
+
+        client.send(&server_state, "OPER root wrong").await.unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![":unknown.server 464 x :Password incorrect"]
+        );
+        client
+            .send(&server_state, "OPER root hunter2")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![
+                ":unknown.server 381 x :You are now an IRC operator",
+                ":unknown.server 221 x :+io",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quit_during_cap_negotiation_frees_the_connection() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+        client.send(&server_state, "CAP LS 302").await.unwrap();
+        client.send(&server_state, "NICK alice").await.unwrap();
+
+        let status = client.send(&server_state, "QUIT :changed my mind").await;
+
+        assert_eq!(
+            status.unwrap(),
+            UserStatus::Leaving(Some("changed my mind".to_owned()))
+        );
+        assert!(!server_state.users.contains_key(&client.client_id));
+        assert!(
+            server_state
+                .nick_holder(&Nickname("alice".to_owned()))
+                .is_none()
+        );
+        // The nick it had picked is free again
+        let mut other = TestClient::connect(&server_state).await;
+        other.register(&server_state, "alice").await;
+        assert!(other.user_state.get_caracs().await.registered);
+    }
+
+    #[tokio::test]
+    async fn test_just_quit_nick_is_unavailable_until_the_hold_expires() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "QUIT :brb").await.unwrap();
+
+        let mut client = TestClient::connect(&server_state).await;
+        client.send(&server_state, "NICK alice").await.unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![":unknown.server 437 * alice :Nick/channel is temporarily unavailable"]
+        );
+
+        // Expired holds are forgotten
+        server_state
+            .held_resources
+            .insert("alice".to_owned(), unix_timestamp() - 1);
+        client.register(&server_state, "alice").await;
+        assert!(client.user_state.get_caracs().await.registered);
+    }
+
+    #[tokio::test]
+    async fn test_oper_sanick_renames_another_user() {
+        let server_state = ServerState::default();
+        let mut oper = TestClient::registered(&server_state, "oper").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        for client in [&mut bob, &mut carol] {
+            client.send(&server_state, "JOIN #chan").await.unwrap();
+        }
+        bob.drain();
+        carol.drain();
+
+        carol
+            .send(&server_state, "SANICK bob robert")
+            .await
+            .unwrap();
+        assert_eq!(
+            carol.drain(),
+            vec![":unknown.server 481 carol :Permission Denied- You're not an IRC operator"]
+        );
+
+        oper.user_state.user.write().await.modes.insert('o');
+        oper.send(&server_state, "SANICK bob carol").await.unwrap();
+        assert_eq!(numeric(&oper.drain()[0]), Some("433"));
+
+        oper.send(&server_state, "SANICK bob robert").await.unwrap();
+        assert!(oper.drain().is_empty());
+        let nick_line = ":bob!bob@127.0.0.1 NICK :robert".to_owned();
+        assert_eq!(bob.drain(), vec![nick_line.clone()]);
+        assert_eq!(carol.drain(), vec![nick_line]);
+        assert_eq!(
+            server_state.nick_holder(&Nickname("robert".to_owned())),
+            Some(bob.client_id)
+        );
+        assert!(
+            server_state
+                .nick_holder(&Nickname("bob".to_owned()))
+                .is_none()
+        );
+    }
+
+    async fn parked_alice(grace: u64) -> (ServerState, TestClient, TestClient) {
+        use crate::config::{AccountsConfig, Config};
+
+        let path =
+            std::env::temp_dir().join(format!("irc_parked_{}_{grace}.toml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server_state = ServerState::new(Config {
+            accounts: Some(AccountsConfig {
+                enabled: Some(true),
+                file: Some(path.display().to_string()),
+            }),
+            ..Config::default()
+        });
+        server_state
+            .config
+            .write()
+            .await
+            .limits
+            .reconnect_grace_secs = Some(grace);
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "REGISTER s3cret").await.unwrap();
+        for channel in ["#a", "#b"] {
+            alice
+                .send(&server_state, &format!("JOIN {channel}"))
+                .await
+                .unwrap();
+        }
+        bob.send(&server_state, "JOIN #a").await.unwrap();
+        alice.drain();
+        bob.drain();
+        (server_state, alice, bob)
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_within_grace_rejoins_channels() {
+        let (server_state, alice, mut bob) = parked_alice(60).await;
+
+        server_state.handle_lost_connection(alice.client_id).await;
+        // Still there as far as bob knows
+        assert!(bob.drain().is_empty());
+
+        // Neither the same IP nor a wrong password take the nick
+        for pass in [None, Some("PASS guess")] {
+            let mut mallory = TestClient::connect(&server_state).await;
+            if let Some(pass) = pass {
+                mallory.send(&server_state, pass).await.unwrap();
+            }
+            mallory.send(&server_state, "NICK alice").await.unwrap();
+            assert!(has_numeric(&mallory.drain(), "433"));
+        }
+
+        let mut back = TestClient::connect(&server_state).await;
+        back.send(&server_state, "PASS s3cret").await.unwrap();
+        back.send(&server_state, "NICK alice").await.unwrap();
+        back.send(&server_state, "USER alice 0 * :alice")
+            .await
+            .unwrap();
+        let replies = back.drain();
+        assert!(has_numeric(&replies, "900"));
+        assert!(replies.contains(&":alice!alice@127.0.0.1 JOIN :#a".to_owned()));
+        assert!(replies.contains(&":alice!alice@127.0.0.1 JOIN :#b".to_owned()));
+        // Still op in #a, names come in no particular order
+        let names_a = replies
+            .iter()
+            .find(|l| l.starts_with(":unknown.server 353 alice = #a :"))
+            .unwrap();
+        assert!(names_a.split([':', ' ']).any(|name| name == "@alice"));
+        // No QUIT nor JOIN for bob, alice never left
+        assert!(bob.drain().is_empty());
+        assert!(server_state.parked_sessions.is_empty());
+        assert!(!server_state.users.contains_key(&alice.client_id));
+        bob.send(&server_state, "PRIVMSG #a :welcome back")
+            .await
+            .unwrap();
+        assert_eq!(
+            back.drain(),
+            vec![":bob!bob@127.0.0.1 PRIVMSG #a :welcome back"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parked_session_quits_once_the_grace_is_over() {
+        let (server_state, alice, mut bob) = parked_alice(61).await;
+
+        server_state.handle_lost_connection(alice.client_id).await;
+        assert!(bob.drain().is_empty());
+        server_state
+            .expire_parked_session(&Nickname("alice".to_owned()), alice.client_id)
+            .await;
+        assert_eq!(
+            bob.drain(),
+            vec![":alice!alice@127.0.0.1 QUIT :Connection lost"]
+        );
+        assert!(server_state.parked_sessions.is_empty());
+
+        // Without an account there is nothing to resume with
+        let carol = TestClient::registered(&server_state, "carol").await;
+        server_state.handle_lost_connection(carol.client_id).await;
+        assert!(server_state.parked_sessions.is_empty());
+        assert!(!server_state.users.contains_key(&carol.client_id));
+    }
+
+    #[tokio::test]
+    async fn test_oper_goes_through_the_auth_provider() {
+        use crate::auth::{AuthFuture, AuthProvider, AuthResult};
+        use std::sync::Arc;
+
+        #[derive(Debug)]
+        struct OneAccount;
+        impl AuthProvider for OneAccount {
+            fn verify<'a>(&'a self, user: &'a str, password: &'a str) -> AuthFuture<'a> {
+                let accepted = (user, password) == ("ldap-admin", "s3cret");
+                Box::pin(async move {
+                    if accepted {
+                        AuthResult::Accepted
+                    } else {
+                        AuthResult::Rejected
+                    }
+                })
+            }
+        }
+
+        let server_state = ServerState::default().with_auth_provider(Arc::new(OneAccount));
+        // The config's accounts are no longer consulted
+        server_state.config.write().await.opers = Some(vec![OperConfig {
+            name: "root".to_owned(),
+            password: "hunter2".to_owned(),
+        }]);
+        let mut client = TestClient::registered(&server_state, "alice").await;
+
+        for rejected in ["OPER root hunter2", "OPER ldap-admin wrong"] {
+            client.send(&server_state, rejected).await.unwrap();
+            assert_eq!(
+                client.drain(),
+                vec![":unknown.server 464 alice :Password incorrect"]
+            );
+        }
+        client
+            .send(&server_state, "OPER ldap-admin s3cret")
+            .await
+            .unwrap();
+        assert!(has_numeric(&client.drain(), "381"));
+        assert!(client.user_state.get_caracs().await.modes.contains(&'o'));
+    }
+
+    #[tokio::test]
+    async fn test_identical_realnames_from_one_ip_flag_a_bot() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.security =
+            Some(toml::from_str("bot_threshold = 3\nautokill_bots = true").unwrap());
+        let register = async |addr: &str, nick: &str| {
+            let mut client = TestClient::connect_from(&server_state, addr.parse().unwrap()).await;
+            client
+                .send(&server_state, &format!("NICK {nick}"))
+                .await
+                .unwrap();
+            let status = client
+                .send(&server_state, "USER bot 0 * :Totally Human")
+                .await
+                .unwrap();
+            (client, status)
+        };
+
+        for nick in ["bot1", "bot2"] {
+            let (_client, status) = register("10.0.0.5:40000", nick).await;
+            assert_eq!(status, UserStatus::Active);
+        }
+        // Same realname from elsewhere doesn't count against 10.0.0.5
+        let (_client, status) = register("10.0.0.6:40000", "human").await;
+        assert_eq!(status, UserStatus::Active);
+        assert_eq!(server_state.suspected_bots.load(Ordering::Relaxed), 0);
+
+        let (mut bot3, status) = register("10.0.0.5:40000", "bot3").await;
+        assert!(matches!(status, UserStatus::Leaving(_)));
+        assert_eq!(
+            bot3.drain(),
+            vec!["ERROR :Closing Link: bot3 (Suspected bot)"]
+        );
+        assert!(!server_state.users.contains_key(&bot3.client_id));
+        assert_eq!(server_state.suspected_bots.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_auto_join_channels_on_registration() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.channels =
+            Some(toml::from_str("auto_join = [\"#lobby\", \"#locked\"]").unwrap());
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        assert_eq!(alice.user_state.get_caracs().await.member_of.len(), 2);
+        alice
+            .send(&server_state, "MODE #locked +k sesame")
+            .await
+            .unwrap();
+        alice.drain();
+
+        let mut bob = TestClient::connect(&server_state).await;
+        bob.send(&server_state, "NICK bob").await.unwrap();
+        bob.send(&server_state, "USER bob 0 * :bob").await.unwrap();
+        let replies = bob.drain();
+        assert!(replies.contains(&":bob!bob@127.0.0.1 JOIN :#lobby".to_owned()));
+        // The keyed channel is skipped without a 475
+        assert!(!has_numeric(&replies, "475"));
+        let member_of = bob.user_state.get_caracs().await.member_of;
+        assert_eq!(
+            member_of.into_iter().collect::<Vec<_>>(),
+            vec![ChannelName("#lobby".to_owned())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_my_info_lists_the_implemented_modes() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+        client.send(&server_state, "NICK alice").await.unwrap();
+        client
+            .send(&server_state, "USER alice 0 * :Alice")
+            .await
+            .unwrap();
+        let replies = client.drain();
+        assert_eq!(
+            replies[1],
+            ":unknown.server 002 alice :Your host is unknown.server, running version 0.1.0"
+        );
+        assert_eq!(
+            replies[3],
+            ":unknown.server 004 alice unknown.server 0.1.0 aiorwO Iabcefiklmnopqstvz Ibefkloqv"
+        );
+        // Every CHANMODES letter of RPL_ISUPPORT is in RPL_MYINFO
+        let chanmodes = replies[4]
+            .split(' ')
+            .find_map(|token| token.strip_prefix("CHANMODES="))
+            .unwrap();
+        let my_info_modes = replies[3].split(' ').nth(6).unwrap();
+        assert!(
+            chanmodes
+                .chars()
+                .filter(|&mode| mode != ',')
+                .all(|mode| my_info_modes.contains(mode))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_realname_is_truncated_or_refused() {
+        let server_state = ServerState::default();
+        {
+            let mut config = server_state.config.write().await;
+            config.limits.max_realname = Some(10);
+            config.limits.realname_no_controls = Some(true);
+        }
+        let mut client = TestClient::connect(&server_state).await;
+        client.send(&server_state, "NICK alice").await.unwrap();
+        client
+            .send(&server_state, "USER alice 0 * :\x0304Alice\x03")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![
+                ":unknown.server FAIL USER INVALID_REALNAME :Realname must not contain control characters"
+            ]
+        );
+        client
+            .send(&server_state, "USER alice 0 * :Alice Liddell of Wonderland")
+            .await
+            .unwrap();
+        assert!(has_numeric(&client.drain(), "001"));
+        assert_eq!(
+            client.user_state.get_caracs().await.real_name,
+            Some(Realname("Alice Lidd".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nick_and_user_in_either_order_register_once() {
+        for requests in [
+            ["NICK alice", "USER alice 0 * :Alice"],
+            ["USER alice 0 * :Alice", "NICK alice"],
+        ] {
+            let server_state = ServerState::default();
+            let mut client = TestClient::connect(&server_state).await;
+            for request in requests {
+                client.send(&server_state, request).await.unwrap();
+            }
+            client
+                .send(&server_state, "USER alice 0 * :Again")
+                .await
+                .unwrap();
+
+            let replies = client.drain();
+            let welcomes = replies.iter().filter(|l| numeric(l) == Some("001"));
+            assert_eq!(welcomes.count(), 1, "{requests:?}");
+            assert_eq!(
+                replies.last().unwrap(),
+                ":unknown.server 462 alice :Unauthorized command (already registered)"
+            );
+            assert_eq!(server_state.users.len(), 1);
+            assert_eq!(
+                server_state.nick_holder(&Nickname("alice".to_owned())),
+                Some(client.client_id)
+            );
+        }
+    }
+}
@@ -1,18 +1,29 @@
+use log::error;
+
 use crate::{
     errors::InternalIrcError,
     handlers::miscellanneous::IrcUnknownCommand,
+    message_models::IrcMessage,
     ops::{
         channel::{IrcChannelOperation, IrcInvalidChannelOperation},
-        message::IrcMessageSending,
+        message::{IrcInvalidMessageSending, IrcMessageSending},
         miscellanneous::IrcMiscellaneousMessages,
+        other_commands::{IrcOptionalFeatures, IrcServiceQueryCommands},
         pre_registration::IrcCapPreRegistration,
         registration::IrcConnectionRegistration,
     },
+    replies::IrcReply,
     server_state::ServerState,
-    types::ClientId,
+    types::{ClientId, Nickname},
     user_state::{UserState, UserStatus},
+    utils::wildcard_match,
 };
 
+// Everything else needs a completed NICK/USER registration. SASL isn't
+// supported, so AUTHENTICATE isn't let through either.
+const PRE_REGISTRATION_COMMANDS: [&str; 7] =
+    ["CAP", "PASS", "NICK", "USER", "QUIT", "PING", "PONG"];
+
 pub async fn handle_request(
     request: &str,
     client_id: ClientId,
@@ -21,51 +32,462 @@ pub async fn handle_request(
 ) -> Result<UserStatus, InternalIrcError> {
     log::info!("{request:?}");
 
-    // -1. Try Message-sending
-    match IrcMessageSending::handle_command(request, client_id, server_state, user_state).await {
-        Ok(status) => return Ok(status),
-        Err(InternalIrcError::InvalidCommand) => {}
-        Err(err) => return Err(err),
+    if !try_flood_token(server_state, user_state).await {
+        let reason = "Excess Flood".to_owned();
+        user_state.send_error_and_close(&reason).await;
+        server_state
+            .handle_quit(client_id, Some(reason.clone()))
+            .await;
+        return Ok(user_state
+            .transition_status(UserStatus::Leaving(Some(reason)))
+            .await);
     }
 
-    // 0. Try pre-registration
-    match IrcMiscellaneousMessages::handle_command(request, client_id, user_state).await {
-        Ok(status) => return Ok(status),
-        Err(InternalIrcError::InvalidCommand) => {}
-        Err(err) => return Err(err),
+    let command = request.split(' ').next().unwrap_or_default();
+    let needs_registration = !command.is_empty()
+        && !PRE_REGISTRATION_COMMANDS
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(command));
+    if needs_registration {
+        let caracs = user_state.get_caracs().await;
+        if !caracs.registered {
+            // 451 ERR_NOTREGISTERED
+            let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+            let irc_reply = IrcReply::ErrNotRegistered { nick: &nick };
+            let not_registered_message = IrcMessage::new(irc_reply.format());
+            let _ = user_state.tx_outbound.send(not_registered_message).await;
+            return Ok(UserStatus::Handshaking);
+        }
     }
 
-    // 1. Try pre-registration
-    match IrcCapPreRegistration::handle_command(request, client_id, server_state, user_state).await
-    {
-        Ok(status) => return Ok(status),
-        Err(InternalIrcError::InvalidCommand) => {}
-        Err(err) => return Err(err),
+    let result = match route_request(request, client_id, server_state, user_state).await {
+        // 5. Fallback to "unknown command"
+        Err(InternalIrcError::InvalidCommand) => {
+            IrcUnknownCommand::handle_command(request, user_state).await
+        }
+        result => {
+            // Only recognised commands are counted, for STATS m
+            server_state.count_command(request);
+            result
+        }
+    };
+    let status = match result {
+        Ok(status) => status,
+        Err(err) => reply_to_error(request, client_id, &err, server_state, user_state).await,
+    };
+    // Every handler's answer goes through the lifecycle check
+    Ok(user_state.transition_status(status).await)
+}
+
+/// Charges the command to the user's flood bucket. Operators and users
+/// matching `limits.flood_exempt_masks` are never throttled.
+async fn try_flood_token(server_state: &ServerState, user_state: &UserState) -> bool {
+    let (limit, exempt_masks) = {
+        let config = server_state.config.read().await;
+        (
+            config.get_flood_limit(),
+            config.get_flood_exempt_masks().to_vec(),
+        )
+    };
+    let Some((burst, per_sec)) = limit else {
+        return true;
+    };
+    let caracs = user_state.get_caracs().await;
+    let mask = format!(
+        "{}!{}@{}",
+        caracs.nick.as_ref().map_or("*", |nick| &nick.0),
+        caracs.user.as_ref().map_or("*", |user| &user.0),
+        caracs.displayed_host()
+    );
+    if caracs.modes.contains(&'o') || exempt_masks.iter().any(|m| wildcard_match(m, &mask)) {
+        return true;
     }
+    user_state.try_flood_token(burst, per_sec).await
+}
 
-    // 2. Try registration
-    match IrcConnectionRegistration::handle_command(request, client_id, server_state, user_state)
+/// A failed handler still answers, so the client never waits on a reply
+/// that won't come. Only a server state inconsistency ends the connection.
+async fn reply_to_error(
+    request: &str,
+    client_id: ClientId,
+    err: &InternalIrcError,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> UserStatus {
+    error!("Err occured while dealing with request {request} with error {err}");
+    let nick = user_state
+        .get_caracs()
         .await
-    {
-        Ok(status) => return Ok(status),
-        Err(InternalIrcError::InvalidCommand) => {}
-        Err(err) => return Err(err),
+        .nick
+        .unwrap_or(Nickname("*".to_owned()));
+    let command = request
+        .split(' ')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    let text = format!("*** {command} failed: {err}");
+    let irc_reply = match err {
+        InternalIrcError::InvalidCommand => IrcReply::ErrUnknownCommand {
+            nick: &nick,
+            command: &command,
+        },
+        InternalIrcError::ParsingError(_) => IrcReply::ErrNeedMoreParams {
+            nick: &nick,
+            command: &command,
+        },
+        InternalIrcError::ServerStateError(_) => {
+            let reason = err.to_string();
+            user_state.send_error_and_close(&reason).await;
+            server_state
+                .handle_quit(client_id, Some(reason.clone()))
+                .await;
+            return UserStatus::Leaving(Some(reason));
+        }
+        _ => IrcReply::ServerNotice {
+            nick: &nick,
+            text: &text,
+        },
+    };
+    let error_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(error_message).await;
+    UserStatus::Active
+}
+
+/// The handler family a command verb belongs to. Each command is parsed by
+/// exactly one family, picked from its verb alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CommandFamily {
+    MessageSending,
+    Miscellaneous,
+    CapPreRegistration,
+    ConnectionRegistration,
+    OptionalFeatures,
+    ServiceQueries,
+    ChannelOperation,
+}
+
+fn command_family(verb: &str, params: &str) -> Option<CommandFamily> {
+    let family = match verb.to_ascii_uppercase().as_str() {
+        "PRIVMSG" | "NOTICE" | "LUSERS" | "STATS" | "MOTD" | "VERSION" | "CONNECT" | "TRACE"
+        | "LINKS" => CommandFamily::MessageSending,
+        "KILL" | "SANICK" | "PING" | "PONG" => CommandFamily::Miscellaneous,
+        "CAP" => CommandFamily::CapPreRegistration,
+        // MODE <nickname> is a user mode, MODE <channel> a channel mode
+        "MODE" if params.starts_with(['#', '+', '!', '&']) => CommandFamily::ChannelOperation,
+        "PASS" | "NICK" | "USER" | "OPER" | "MODE" | "SERVICE" | "QUIT" | "SQUIT" => {
+            CommandFamily::ConnectionRegistration
+        }
+        "GLOBOPS" | "CHATHISTORY" | "REDACT" | "SUMMON" | "USERS" | "REHASH" | "AWAY" | "JUPE"
+        | "UNJUPE" | "DIE" | "REGISTER" | "IDENTIFY" => CommandFamily::OptionalFeatures,
+        "WHO" | "WHOIS" => CommandFamily::ServiceQueries,
+        "JOIN" | "PART" | "TOPIC" | "NAMES" | "LIST" | "INVITE" | "KICK" => {
+            CommandFamily::ChannelOperation
+        }
+        _ => return None,
+    };
+    Some(family)
+}
+
+async fn route_request(
+    request: &str,
+    client_id: ClientId,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    let (verb, params) = request.split_once(' ').unwrap_or((request, ""));
+    let Some(family) = command_family(verb, params) else {
+        return Err(InternalIrcError::InvalidCommand);
+    };
+    let result = match family {
+        CommandFamily::MessageSending => {
+            match IrcMessageSending::handle_command(request, client_id, server_state, user_state)
+                .await
+            {
+                // A PRIVMSG without a target or text gets ERR_NEEDMOREPARAMS
+                Err(InternalIrcError::InvalidCommand) => {
+                    IrcInvalidMessageSending::handle_command(request, user_state).await
+                }
+                result => result,
+            }
+        }
+        CommandFamily::Miscellaneous => {
+            IrcMiscellaneousMessages::handle_command(request, client_id, server_state, user_state)
+                .await
+        }
+        CommandFamily::CapPreRegistration => {
+            IrcCapPreRegistration::handle_command(request, client_id, server_state, user_state)
+                .await
+        }
+        CommandFamily::ConnectionRegistration => {
+            match IrcConnectionRegistration::handle_command(
+                request,
+                client_id,
+                server_state,
+                user_state,
+            )
+            .await
+            {
+                // MODE without a target is routed here, not as a channel MODE
+                Err(InternalIrcError::InvalidCommand) if verb.eq_ignore_ascii_case("MODE") => {
+                    IrcInvalidChannelOperation::handle_command(request, user_state).await
+                }
+                result => result,
+            }
+        }
+        CommandFamily::OptionalFeatures => {
+            IrcOptionalFeatures::handle_command(request, client_id, server_state, user_state).await
+        }
+        CommandFamily::ServiceQueries => {
+            IrcServiceQueryCommands::handle_command(request, client_id, server_state, user_state)
+                .await
+        }
+        CommandFamily::ChannelOperation => {
+            match IrcChannelOperation::handle_command(request, client_id, server_state, user_state)
+                .await
+            {
+                // A JOIN without channels, a KICK without users... still
+                // get their ERR_NEEDMOREPARAMS
+                Err(InternalIrcError::InvalidCommand) => {
+                    IrcInvalidChannelOperation::handle_command(request, user_state).await
+                }
+                result => result,
+            }
+        }
+    };
+    match result {
+        // The verb is known, so it is its parameters that didn't parse
+        Err(InternalIrcError::InvalidCommand) => {
+            Err(InternalIrcError::ParsingError(verb.to_ascii_uppercase()))
+        }
+        result => result,
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TestClient, has_numeric, numeric};
+
+    #[tokio::test]
+    async fn test_commands_before_registration_get_451() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
 
-    // 3. Try normal channel operations
-    match IrcChannelOperation::handle_command(request, client_id, server_state, user_state).await {
-        Ok(status) => return Ok(status),
-        Err(InternalIrcError::InvalidCommand) => {}
-        Err(err) => return Err(err),
+        client
+            .send(&server_state, "PRIVMSG #chan :too early")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.drain(),
+            vec![":unknown.server 451 * :You have not registered"]
+        );
+        client.send(&server_state, "JOIN #chan").await.unwrap();
+        assert!(has_numeric(&client.drain(), "451"));
+        client
+            .send(&server_state, "AUTHENTICATE PLAIN")
+            .await
+            .unwrap();
+        assert!(has_numeric(&client.drain(), "451"));
+
+        client.send(&server_state, "NICK alice").await.unwrap();
+        assert!(!has_numeric(&client.drain(), "451"));
+        client
+            .send(&server_state, "USER alice 0 * :Alice")
+            .await
+            .unwrap();
+        assert!(has_numeric(&client.drain(), "001"));
+
+        client.send(&server_state, "JOIN #chan").await.unwrap();
+        assert!(!has_numeric(&client.drain(), "451"));
+    }
+
+    #[tokio::test]
+    async fn test_handler_errors_are_answered() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::registered(&server_state, "alice").await;
+
+        let err = InternalIrcError::UserStateError("Cannot change of an unregistered user");
+        let status = reply_to_error(
+            "mode alice +i",
+            client.client_id,
+            &err,
+            &server_state,
+            &client.user_state,
+        )
+        .await;
+        assert_eq!(status, UserStatus::Active);
+        assert_eq!(
+            client.drain(),
+            vec![
+                ":unknown.server NOTICE alice :*** MODE failed: User State error: 'Cannot change of an unregistered user'"
+            ]
+        );
+
+        let err = InternalIrcError::ParsingError("KICK".to_owned());
+        reply_to_error(
+            "KICK",
+            client.client_id,
+            &err,
+            &server_state,
+            &client.user_state,
+        )
+        .await;
+        assert!(has_numeric(&client.drain(), "461"));
+
+        // Fatal: the client is told why, then dropped
+        let err = InternalIrcError::ServerStateError("nick collision");
+        let status = reply_to_error(
+            "NICK bob",
+            client.client_id,
+            &err,
+            &server_state,
+            &client.user_state,
+        )
+        .await;
+        assert!(matches!(status, UserStatus::Leaving(_)));
+        assert_eq!(
+            client.drain(),
+            vec!["ERROR :Closing Link: alice (Server State error: 'nick collision')"]
+        );
+        assert!(!server_state.users.contains_key(&client.client_id));
+    }
+
+    #[test]
+    fn test_each_verb_routes_to_one_family() {
+        use CommandFamily::*;
+        for (request, family) in [
+            ("PRIVMSG #chan :hi", MessageSending),
+            ("motd", MessageSending),
+            ("PING irc.example.org", Miscellaneous),
+            ("PONG :unknown.server", Miscellaneous),
+            ("CAP LS 302", CapPreRegistration),
+            ("NICK alice", ConnectionRegistration),
+            ("USER alice 0 * :Alice", ConnectionRegistration),
+            ("USERS", OptionalFeatures),
+            ("MODE alice +i", ConnectionRegistration),
+            ("MODE #chan +t", ChannelOperation),
+            ("AWAY :lunch", OptionalFeatures),
+            ("WHOIS alice", ServiceQueries),
+            ("JOIN #chan", ChannelOperation),
+            ("KICK #chan bob", ChannelOperation),
+        ] {
+            let (verb, params) = request.split_once(' ').unwrap_or((request, ""));
+            assert_eq!(command_family(verb, params), Some(family), "{request}");
+        }
+        assert_eq!(command_family("PRIVMSGX", "#chan :hi"), None);
+        assert_eq!(command_family("WHOWAS", "alice"), None);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_verbs_get_421() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::registered(&server_state, "alice").await;
+
+        for request in ["FROBNICATE now", "PRIVMSGX #chan :hi", "MOTDS"] {
+            client.send(&server_state, request).await.unwrap();
+            let replies = client.drain();
+            assert_eq!(replies.len(), 1, "{request}: {replies:?}");
+            assert_eq!(numeric(&replies[0]), Some("421"), "{request}");
+        }
+        // A known verb with bad parameters isn't reported as unknown
+        client.send(&server_state, "JOIN").await.unwrap();
+        assert!(has_numeric(&client.drain(), "461"));
+    }
+
+    #[tokio::test]
+    async fn test_known_verbs_with_bad_params_get_461() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::registered(&server_state, "alice").await;
+
+        for (request, command) in [
+            ("JOIN #a bad key", "JOIN"),
+            ("WHOIS", "WHOIS"),
+            ("oper", "OPER"),
+            ("LINKS a b c", "LINKS"),
+        ] {
+            client.send(&server_state, request).await.unwrap();
+            assert_eq!(
+                client.drain(),
+                vec![format!(
+                    ":unknown.server 461 alice {command} :Not enough parameters"
+                )],
+                "{request}"
+            );
+        }
+        assert!(client.user_state.get_caracs().await.member_of.is_empty());
     }
 
-    // 4. Try invalid-channel ops
-    match IrcInvalidChannelOperation::handle_command(request, user_state).await {
-        Ok(status) => return Ok(status),
-        Err(InternalIrcError::InvalidCommand) => {}
-        Err(err) => return Err(err),
+    #[tokio::test]
+    async fn test_under_specified_commands_get_461() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.drain();
+
+        for (request, command) in [
+            ("JOIN", "JOIN"),
+            ("PART", "PART"),
+            ("KICK #chan", "KICK"),
+            ("TOPIC", "TOPIC"),
+            ("INVITE bob", "INVITE"),
+            ("MODE", "MODE"),
+            ("PRIVMSG", "PRIVMSG"),
+            ("PRIVMSG #chan", "PRIVMSG"),
+        ] {
+            alice.send(&server_state, request).await.unwrap();
+            assert_eq!(
+                alice.drain(),
+                vec![format!(
+                    ":unknown.server 461 alice {command} :Not enough parameters"
+                )],
+                "{request}"
+            );
+        }
     }
 
-    // 5. Fallback to "unknown command"
-    IrcUnknownCommand::handle_command(request, user_state).await
+    #[tokio::test]
+    async fn test_flood_limit_spares_operators_and_exempt_masks() {
+        let server_state = ServerState::default();
+        let mut oper = TestClient::registered(&server_state, "oper").await;
+        let mut bot = TestClient::registered(&server_state, "bot").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        oper.user_state.user.write().await.modes.insert('o');
+        {
+            let mut config = server_state.config.write().await;
+            config.limits.flood_burst = Some(5);
+            config.limits.flood_per_sec = Some(1);
+            config.limits.flood_exempt_masks = Some(vec!["bot!*@127.0.0.1".to_owned()]);
+        }
+
+        for client in [&mut oper, &mut bot] {
+            for _ in 0..20 {
+                let status = client.send(&server_state, "PING :hi").await.unwrap();
+                assert_eq!(status, UserStatus::Active);
+            }
+            assert_eq!(client.drain().len(), 20);
+        }
+
+        for _ in 0..5 {
+            bob.send(&server_state, "PING :hi").await.unwrap();
+        }
+        assert_eq!(bob.drain().len(), 5);
+        let status = bob.send(&server_state, "PING :hi").await.unwrap();
+        assert!(matches!(status, UserStatus::Leaving(_)));
+        assert_eq!(bob.drain(), vec!["ERROR :Closing Link: bob (Excess Flood)"]);
+        assert!(!server_state.users.contains_key(&bob.client_id));
+    }
+
+    #[tokio::test]
+    async fn test_pong_is_accepted_silently() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+        let status = client.send(&server_state, "PONG :token").await.unwrap();
+        assert_eq!(status, UserStatus::Handshaking);
+        assert!(client.drain().is_empty());
+
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let status = alice.send(&server_state, "PONG :token").await.unwrap();
+        assert_eq!(status, UserStatus::Active);
+        assert!(alice.drain().is_empty());
+    }
 }
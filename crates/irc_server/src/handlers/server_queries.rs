@@ -0,0 +1,651 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    errors::InternalIrcError,
+    handlers::registration::isupport_tokens,
+    message_models::IrcMessage,
+    replies::IrcReply,
+    server_state::ServerState,
+    types::Nickname,
+    user_state::{UserState, UserStatus},
+    utils::{is_local_server, unix_timestamp},
+};
+
+/// Checks the optional `<target>` of NAMES, LIST, WHOIS, PING... There are
+/// no server links, so anything but this server gets ERR_NOSUCHSERVER and
+/// the command stops there.
+pub async fn require_local_target(target: Option<&str>, user_state: &UserState) -> bool {
+    let Some(target) = target.filter(|target| !is_local_server(target)) else {
+        return true;
+    };
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    let irc_reply = IrcReply::ErrNoSuchServer {
+        nick: &nick,
+        server: target,
+    };
+    let err_no_such_server = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(err_no_such_server).await;
+    false
+}
+
+pub async fn handle_motd(
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    let nick = user_state
+        .get_caracs()
+        .await
+        .nick
+        .unwrap_or(Nickname("*".to_owned()));
+    send_motd(&nick, server_state, user_state).await;
+    Ok(UserStatus::Active)
+}
+
+/// Sends the MOTD block (375/372.../376), or ERR_NOMOTD, as done for the
+/// MOTD command and at the end of the welcome burst.
+pub async fn send_motd(nick: &Nickname, server_state: &ServerState, user_state: &UserState) {
+    // 3.4.1 Motd message
+    //    Numeric Replies:
+    //            RPL_MOTDSTART ✅                RPL_MOTD ✅
+    //            RPL_ENDOFMOTD ✅                ERR_NOMOTD ✅
+    let replies = match &*server_state.motd.read().await {
+        Some(lines) => {
+            let mut replies = vec![IrcReply::MotdStart { nick }.format()];
+            for line in lines {
+                replies.push(IrcReply::Motd { nick, line }.format());
+            }
+            replies.push(IrcReply::EndOfMotd { nick }.format());
+            replies
+        }
+        None => vec![IrcReply::ErrNoMotd { nick }.format()],
+    };
+    for reply in replies {
+        let _ = user_state.tx_outbound.send(IrcMessage::new(reply)).await;
+    }
+}
+
+pub async fn handle_version(
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.4.3 Version message
+    //    Numeric Replies:
+    //            ERR_NOSUCHSERVER                RPL_VERSION ✅
+    let nick = user_state
+        .get_caracs()
+        .await
+        .nick
+        .unwrap_or(Nickname("*".to_owned()));
+    let config = server_state.config.read().await;
+    let capabilities = config.get_capabilities();
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    // No TLS listener yet, and the admin API is what serves the metrics
+    let modules = format!(
+        "TLS=off METRICS={} SASL={}",
+        on_off(config.get_admin_api().is_some()),
+        on_off(capabilities.contains(&"sasl")),
+    );
+    let version = format!("irc_server-{}", env!("CARGO_PKG_VERSION"));
+    let tokens = isupport_tokens(&config);
+    let replies = [
+        IrcReply::Version {
+            nick: &nick,
+            version: &version,
+            comments: &config.server.version,
+        }
+        .format(),
+        IrcReply::ISupport {
+            nick: &nick,
+            tokens: &tokens,
+        }
+        .format(),
+        IrcReply::ServerNotice {
+            nick: &nick,
+            text: &format!("*** Capabilities: {}", capabilities.join(" ")),
+        }
+        .format(),
+        IrcReply::ServerNotice {
+            nick: &nick,
+            text: &format!("*** Modules: {modules}"),
+        }
+        .format(),
+    ];
+    drop(config);
+    for reply in replies {
+        let _ = user_state.tx_outbound.send(IrcMessage::new(reply)).await;
+    }
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_connect(
+    target: String,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.4.7 Connect message
+    //    Numeric Replies:
+
+    //            ERR_NOSUCHSERVER                ERR_NOPRIVILEGES ✅
+    //            ERR_NEEDMOREPARAMS
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.unwrap_or(Nickname("*".to_owned()));
+    let irc_reply = if !caracs.modes.contains(&'o') {
+        IrcReply::ErrNoPrivileges { nick: &nick }
+    } else {
+        // Server links don't exist yet, there is nothing to connect with
+        IrcReply::ServerNotice {
+            nick: &nick,
+            text: &format!("*** Linking is not configured, cannot connect to {target}"),
+        }
+    };
+    let connect_message = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(connect_message).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_links(
+    remote: Option<String>,
+    mask: Option<String>,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.4.5 Links message
+    //    Numeric Replies:
+
+    //            ERR_NOSUCHSERVER ✅
+    //            RPL_LINKS ✅                    RPL_ENDOFLINKS ✅
+    if !require_local_target(remote.as_deref(), user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let nick = user_state
+        .get_caracs()
+        .await
+        .nick
+        .unwrap_or(Nickname("*".to_owned()));
+    let mask = mask.unwrap_or("*".to_owned());
+    // No server links: this server is the whole list, when the mask matches
+    if is_local_server(&mask) {
+        let irc_reply = IrcReply::Links {
+            nick: &nick,
+            mask: &mask,
+        };
+        let links = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(links).await;
+    }
+    let irc_reply = IrcReply::EndOfLinks {
+        nick: &nick,
+        mask: &mask,
+    };
+    let end_of_links = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(end_of_links).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_trace(
+    target: Option<String>,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.4.8 Trace message
+    //    Numeric Replies:
+
+    //            ERR_NOSUCHSERVER
+
+    //    If the TRACE message is destined for another server, all
+    //    intermediate servers must return a RPL_TRACELINK reply to indicate
+    //    that the TRACE passed through it and where it's going next.
+
+    //            RPL_TRACELINK
+    //    A TRACE reply may be composed of any number of the following
+    //    numeric replies.
+
+    //            RPL_TRACECONNECTING           RPL_TRACEHANDSHAKE
+    //            RPL_TRACEUNKNOWN ✅            RPL_TRACEOPERATOR ✅
+    //            RPL_TRACEUSER ✅               RPL_TRACESERVER
+    //            RPL_TRACESERVICE              RPL_TRACENEWTYPE
+    //            RPL_TRACECLASS                RPL_TRACELOG
+    //            RPL_TRACEEND ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    // A nick target narrows the trace to that user, anything else is us
+    let target_id = target.and_then(|target| server_state.nick_holder(&Nickname(target)));
+    // Only operators see other connections
+    let traced = if !caracs.modes.contains(&'o') {
+        match target_id {
+            Some(target_id) if target_id != caracs.user_id => Vec::new(),
+            _ => vec![user_state.clone()],
+        }
+    } else {
+        match target_id.and_then(|id| server_state.get_user_state_from_client_id(&id)) {
+            Some(target_state) => vec![target_state],
+            None => server_state
+                .users
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect(),
+        }
+    };
+    let mut replies = Vec::new();
+    for connection in traced {
+        let other = connection.get_caracs().await;
+        let host = other.displayed_host();
+        let reply = match (other.registered, &other.nick, &other.user) {
+            (true, Some(other_nick), Some(other_user)) => {
+                let link = format!("{other_nick}[{other_user}@{host}]");
+                if other.modes.contains(&'o') {
+                    IrcReply::TraceOperator {
+                        nick: &nick,
+                        link: &link,
+                    }
+                    .format()
+                } else {
+                    IrcReply::TraceUser {
+                        nick: &nick,
+                        link: &link,
+                    }
+                    .format()
+                }
+            }
+            _ => IrcReply::TraceUnknown {
+                nick: &nick,
+                ip: &host,
+            }
+            .format(),
+        };
+        replies.push(reply);
+    }
+    replies.sort();
+    let version = server_state.config.read().await.server.version.clone();
+    replies.push(
+        IrcReply::TraceEnd {
+            nick: &nick,
+            version: &version,
+        }
+        .format(),
+    );
+    for reply in replies {
+        let _ = user_state.tx_outbound.send(IrcMessage::new(reply)).await;
+    }
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_stats(
+    query: Option<char>,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.4.4 Stats message
+    //    Numeric Replies:
+
+    //            ERR_NOSUCHSERVER
+    //            RPL_STATSLINKINFO ✅             RPL_STATSUPTIME
+    //            RPL_STATSCOMMANDS ✅             RPL_STATSOLINE
+    //            RPL_ENDOFSTATS ✅
+    let caracs = user_state.get_caracs().await;
+    let nick = caracs.nick.clone().unwrap_or(Nickname("*".to_owned()));
+    match query {
+        Some('m') => {
+            let mut counts = server_state
+                .command_counts
+                .iter()
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect::<Vec<_>>();
+            counts.sort();
+            for (command, (count, bytes)) in counts {
+                let irc_reply = IrcReply::StatsCommands {
+                    nick: &nick,
+                    command: &command,
+                    count,
+                    bytes,
+                };
+                let stats_commands = IrcMessage::new(irc_reply.format());
+                let _ = user_state.tx_outbound.send(stats_commands).await;
+            }
+        }
+        Some('l') => {
+            // Operators see every connection, other users only their own
+            let connections = if caracs.modes.contains(&'o') {
+                server_state
+                    .users
+                    .iter()
+                    .map(|entry| entry.value().clone())
+                    .collect::<Vec<UserState>>()
+            } else {
+                vec![user_state.clone()]
+            };
+            let now = unix_timestamp();
+            for connection in connections {
+                let link = connection.get_caracs().await;
+                let host = link.displayed_host();
+                let link_name = format!(
+                    "{}[{}@{}]",
+                    link.nick.map(|n| n.0).unwrap_or("*".to_owned()),
+                    link.user.map(|u| u.0).unwrap_or("*".to_owned()),
+                    host
+                );
+                let stats = &connection.stats;
+                let irc_reply = IrcReply::StatsLinkInfo {
+                    nick: &nick,
+                    link: &link_name,
+                    sent_messages: stats.sent_messages.load(Ordering::Relaxed),
+                    sent_kbytes: stats.sent_bytes.load(Ordering::Relaxed) / 1024,
+                    received_messages: stats.received_messages.load(Ordering::Relaxed),
+                    received_kbytes: stats.received_bytes.load(Ordering::Relaxed) / 1024,
+                    time_open: now.saturating_sub(stats.connected_at),
+                };
+                let stats_link_info = IrcMessage::new(irc_reply.format());
+                let _ = user_state.tx_outbound.send(stats_link_info).await;
+            }
+        }
+        _ => (),
+    }
+    let irc_reply = IrcReply::EndOfStats {
+        nick: &nick,
+        query: query.unwrap_or('*'),
+    };
+    let end_of_stats = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(end_of_stats).await;
+    Ok(UserStatus::Active)
+}
+
+pub async fn handle_lusers(
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.4.2 Lusers message
+    //    Numeric Replies:
+
+    //            RPL_LUSERCLIENT ✅              RPL_LUSEROP ✅
+    //            RPL_LUSERUNKNOWN ✅             RPL_LUSERCHANNELS ✅
+    //            RPL_LUSERME ✅                  ERR_NOSUCHSERVER
+    let nick = user_state
+        .get_caracs()
+        .await
+        .nick
+        .unwrap_or(Nickname("*".to_owned()));
+    let users = server_state
+        .users
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect::<Vec<UserState>>();
+    let (mut visible, mut invisible, mut operators, mut unknown) = (0, 0, 0, 0);
+    for other_state in users {
+        let other = other_state.get_caracs().await;
+        if !other.registered {
+            unknown += 1;
+            continue;
+        }
+        if other.modes.contains(&'i') {
+            invisible += 1;
+        } else {
+            visible += 1;
+        }
+        if other.modes.contains(&'o') {
+            operators += 1;
+        }
+    }
+
+    let replies = [
+        IrcReply::LuserClient {
+            nick: &nick,
+            users: visible,
+            invisible,
+        },
+        IrcReply::LuserOp {
+            nick: &nick,
+            operators,
+        },
+        IrcReply::LuserUnknown {
+            nick: &nick,
+            unknown,
+        },
+        IrcReply::LuserChannels {
+            nick: &nick,
+            channels: server_state.channels.len(),
+        },
+        IrcReply::LuserMe {
+            nick: &nick,
+            clients: visible + invisible,
+        },
+    ];
+    for irc_reply in replies {
+        let lusers_message = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(lusers_message).await;
+    }
+    send_local_global_users(&nick, server_state, user_state).await;
+    Ok(UserStatus::Active)
+}
+
+/// Sends RPL_LOCALUSERS and RPL_GLOBALUSERS, part of LUSERS and of the
+/// welcome burst.
+pub async fn send_local_global_users(
+    nick: &Nickname,
+    server_state: &ServerState,
+    user_state: &UserState,
+) {
+    // No server links: the whole network is this server
+    let (current, max) = server_state.local_users();
+    let replies = [
+        IrcReply::LocalUsers { nick, current, max },
+        IrcReply::GlobalUsers { nick, current, max },
+    ];
+    for irc_reply in replies {
+        let users_message = IrcMessage::new(irc_reply.format());
+        let _ = user_state.tx_outbound.send(users_message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        server_state::ServerState,
+        test_utils::{TestClient, numeric},
+    };
+
+    #[tokio::test]
+    async fn test_lusers_counts_invisible_separately() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let ghost = TestClient::registered(&server_state, "ghost").await;
+        let _pending = TestClient::connect(&server_state).await;
+        ghost.user_state.user.write().await.modes.insert('i');
+
+        alice.send(&server_state, "LUSERS").await.unwrap();
+
+        let replies = alice.drain();
+        let line = |nb| replies.iter().find(|l| numeric(l) == Some(nb)).unwrap();
+        assert!(line("251").ends_with(":There are 1 users and 1 invisible on 1 servers"));
+        assert!(line("253").contains(" 1 :unknown connection(s)"));
+        assert!(line("255").ends_with(":I have 2 clients and 0 servers"));
+    }
+
+    #[tokio::test]
+    async fn test_local_users_follow_connections() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::connect(&server_state).await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        carol.send(&server_state, "QUIT").await.unwrap();
+
+        alice.send(&server_state, "LUSERS").await.unwrap();
+        let replies = alice.drain();
+        let line = |nb| replies.iter().find(|l| numeric(l) == Some(nb)).unwrap();
+        assert_eq!(
+            line("265"),
+            ":unknown.server 265 alice 2 3 :Current local users 2, max 3"
+        );
+        assert_eq!(
+            line("266"),
+            ":unknown.server 266 alice 2 3 :Current global users 2, max 3"
+        );
+
+        bob.send(&server_state, "NICK bob").await.unwrap();
+        bob.send(&server_state, "USER bob 0 * :Bob").await.unwrap();
+        assert!(
+            bob.drain()
+                .contains(&":unknown.server 265 bob 2 3 :Current local users 2, max 3".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_needs_operator() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice
+            .send(&server_state, "CONNECT irc.example.net 6667")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 481 alice :Permission Denied- You're not an IRC operator"]
+        );
+
+        alice.user_state.user.write().await.modes.insert('o');
+        alice
+            .send(&server_state, "CONNECT irc.example.net 6667")
+            .await
+            .unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server NOTICE alice :*** Linking is not configured, cannot connect to irc.example.net"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_operator_trace_lists_connections() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let _pending = TestClient::connect(&server_state).await;
+        alice.user_state.user.write().await.modes.insert('o');
+        let version = server_state.config.read().await.server.version.clone();
+
+        alice.send(&server_state, "TRACE").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server 203 alice ???? users 127.0.0.1".to_owned(),
+                ":unknown.server 204 alice Oper opers alice[alice@127.0.0.1]".to_owned(),
+                ":unknown.server 205 alice User users bob[bob@127.0.0.1]".to_owned(),
+                format!(":unknown.server 262 alice unknown.server {version} :End of TRACE"),
+            ]
+        );
+
+        // Without +o only the requester shows up
+        bob.send(&server_state, "TRACE").await.unwrap();
+        let replies = bob.drain();
+        assert_eq!(replies.len(), 2);
+        assert!(replies[0].ends_with(" 205 bob User users bob[bob@127.0.0.1]"));
+    }
+
+    #[tokio::test]
+    async fn test_stats_m_counts_commands() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        for _ in 0..3 {
+            alice
+                .send(&server_state, "PRIVMSG #chan :hello")
+                .await
+                .unwrap();
+        }
+        alice.send(&server_state, "BOGUS command").await.unwrap();
+        alice.drain();
+
+        alice.send(&server_state, "STATS m").await.unwrap();
+
+        let replies = alice.drain();
+        assert!(replies.contains(&":unknown.server 212 alice PRIVMSG 3 60 0".to_owned()));
+        assert!(!replies.iter().any(|l| l.contains(" BOGUS ")));
+        assert!(
+            replies
+                .last()
+                .unwrap()
+                .ends_with(" 219 alice m :End of STATS report")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_motd_file_lines_become_372() {
+        let motd_path = std::env::temp_dir().join(format!("irc_motd_{}.txt", std::process::id()));
+        std::fs::write(&motd_path, "Welcome!\nBe nice.\n\nHave fun").unwrap();
+        let server_state = ServerState::default();
+        server_state.config.write().await.server.motd_file = Some(motd_path.display().to_string());
+        server_state.reload_motd().await;
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice.send(&server_state, "MOTD").await.unwrap();
+        std::fs::remove_file(&motd_path).unwrap();
+
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server 375 alice :- unknown.server Message of the day -",
+                ":unknown.server 372 alice :- Welcome!",
+                ":unknown.server 372 alice :- Be nice.",
+                ":unknown.server 372 alice :-",
+                ":unknown.server 372 alice :- Have fun",
+                ":unknown.server 376 alice :End of MOTD command",
+            ]
+        );
+
+        // Gone on the next reload: ERR_NOMOTD rather than the inline MOTD
+        server_state.reload_motd().await;
+        alice.send(&server_state, "MOTD").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 422 alice :MOTD File is missing"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_version_reports_crate_version_and_modules() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice.send(&server_state, "VERSION").await.unwrap();
+        let replies = alice.drain();
+        assert_eq!(
+            replies[0],
+            format!(
+                ":unknown.server 351 alice irc_server-{}. unknown.server :0.1.0",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(numeric(&replies[1]), Some("005"));
+        assert!(replies[2].starts_with(":unknown.server NOTICE alice :*** Capabilities: "));
+        assert_eq!(
+            replies[3],
+            ":unknown.server NOTICE alice :*** Modules: TLS=off METRICS=off SASL=off"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_links_lists_this_server_when_the_mask_matches() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        alice.send(&server_state, "LINKS").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![
+                ":unknown.server 364 alice * unknown.server :0 A basic Rust IRC server",
+                ":unknown.server 365 alice * :End of LINKS list",
+            ]
+        );
+        alice.send(&server_state, "LINKS *.example").await.unwrap();
+        assert_eq!(
+            alice.drain(),
+            vec![":unknown.server 365 alice *.example :End of LINKS list"]
+        );
+        alice
+            .send(&server_state, "LINKS peer.example *")
+            .await
+            .unwrap();
+        let replies = alice.drain();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(numeric(&replies[0]), Some("402"));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::{
+    errors::InternalIrcError,
+    handlers::server_queries::require_local_target,
+    message_models::IrcMessage,
+    replies::{Batch, IrcReply},
+    server_state::ServerState,
+    types::{ChannelName, Nickname, Realname, Username},
+    user_state::{UserSnapshot, UserState, UserStatus},
+    utils::wildcard_match,
+};
+
+pub async fn handle_who(
+    mask: Option<String>,
+    operators_only: bool,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.6.1 Who query
+    //    Numeric Replies:
+
+    //            ERR_NOSUCHSERVER
+    //            RPL_WHOREPLY ✅                  RPL_ENDOFWHO ✅
+    let requester = user_state.get_caracs().await;
+    let nick = requester.clone().nick.unwrap_or(Nickname("*".to_owned()));
+    let mask = mask.unwrap_or_else(|| "*".to_owned());
+
+    if mask.starts_with(['#', '&', '+', '!']) {
+        if let Some(channel) = server_state.get_channel(&ChannelName(mask.clone())) {
+            let is_member = channel.members.contains(&requester.user_id);
+            let is_hidden = {
+                let modes = channel.modes.read().await;
+                modes.secret || modes.private
+            };
+            let members = channel.members.iter().map(|m| *m).collect::<Vec<_>>();
+            for member_id in members {
+                if !is_member && is_hidden {
+                    break;
+                }
+                let Some(member_state) = server_state.get_user_state_from_client_id(&member_id)
+                else {
+                    continue;
+                };
+                let member = member_state.get_caracs().await;
+                if (!is_member && !member.is_visible_to(&requester))
+                    || (operators_only && !member.modes.contains(&'o'))
+                {
+                    continue;
+                }
+                let channel_prefix = channel.role(member_id).prefix();
+                send_who_reply(&nick, &mask, &member, channel_prefix, user_state).await;
+            }
+        }
+    } else {
+        // "0" is the RFC alias for "every visible user"
+        let pattern = if mask == "0" { "*" } else { mask.as_str() };
+        let users = server_state
+            .users
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<UserState>>();
+        for other_state in users {
+            let other = other_state.get_caracs().await;
+            if !other.registered
+                || !other.is_visible_to(&requester)
+                || (operators_only && !other.modes.contains(&'o'))
+            {
+                continue;
+            }
+            let host = other.displayed_host();
+            let fields = [
+                other.nick.as_ref().map(|n| n.0.as_str()),
+                other.user.as_ref().map(|u| u.0.as_str()),
+                Some(host.as_str()),
+                other.real_name.as_ref().map(|r| r.0.as_str()),
+            ];
+            if fields
+                .into_iter()
+                .flatten()
+                .any(|field| wildcard_match(pattern, field))
+            {
+                send_who_reply(&nick, "*", &other, "", user_state).await;
+            }
+        }
+    }
+
+    let irc_reply = IrcReply::EndOfWho {
+        nick: &nick,
+        mask: &mask,
+    };
+    let end_of_who = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(end_of_who).await;
+    Ok(UserStatus::Active)
+}
+
+async fn send_who_reply(
+    nick: &Nickname,
+    channel: &str,
+    target: &UserSnapshot,
+    channel_prefix: &str,
+    user_state: &UserState,
+) {
+    let Some(target_nick) = &target.nick else {
+        return;
+    };
+    let flags = target.who_flags(channel_prefix);
+    let user = target.user.clone().unwrap_or(Username("*".to_owned()));
+    let real_name = target.real_name.clone().unwrap_or(Realname(String::new()));
+    let irc_reply = IrcReply::WhoReply {
+        nick,
+        channel,
+        user: &user,
+        host: &target.displayed_host(),
+        target: target_nick,
+        flags: &flags,
+        real_name: &real_name,
+    };
+    let who_reply = IrcMessage::new(irc_reply.format());
+    let _ = user_state.tx_outbound.send(who_reply).await;
+}
+
+pub async fn handle_whois(
+    server: Option<String>,
+    target: Nickname,
+    server_state: &ServerState,
+    user_state: &UserState,
+) -> Result<UserStatus, InternalIrcError> {
+    // 3.6.2 Whois query
+    //    Numeric Replies:
+
+    //            ERR_NOSUCHSERVER ✅           ERR_NONICKNAMEGIVEN
+    //            RPL_WHOISUSER ✅              RPL_WHOISCHANNELS ✅
+    //            RPL_WHOISCHANNELS ✅          RPL_WHOISSERVER ✅
+    //            RPL_AWAY ✅                   RPL_WHOISOPERATOR
+    //            RPL_WHOISIDLE ✅              ERR_NOSUCHNICK ✅
+    //            RPL_ENDOFWHOIS ✅
+    // "WHOIS nick nick" asks the server of that nick, which is us
+    let server = server.filter(|server| {
+        server_state
+            .nick_holder(&Nickname(server.clone()))
+            .is_none()
+    });
+    if !require_local_target(server.as_deref(), user_state).await {
+        return Ok(UserStatus::Active);
+    }
+    let requester = user_state.get_caracs().await;
+    let nick = requester.clone().nick.unwrap_or(Nickname("*".to_owned()));
+    let batch = Batch::new(requester.capabilities.contains("batch"));
+    if let Some(batch_start) = batch.start("whois", &target.0) {
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(batch_start))
+            .await;
+    }
+
+    let mut found = None;
+    if let Some(target_state) = server_state.get_user_state_from_nick(&target) {
+        let target_caracs = target_state.get_caracs().await;
+        if target_caracs.is_visible_to(&requester) {
+            found = Some((target_caracs, target_state));
+        }
+    }
+    match found {
+        Some((target_caracs, target_state)) => {
+            let user = target_caracs
+                .user
+                .clone()
+                .unwrap_or(Username("*".to_owned()));
+            let real_name = target_caracs
+                .real_name
+                .clone()
+                .unwrap_or(Realname(String::new()));
+            let irc_reply = IrcReply::WhoisUser {
+                nick: &nick,
+                target: &target,
+                user: &user,
+                host: &target_caracs.displayed_host(),
+                real_name: &real_name,
+            };
+            let whois_user = IrcMessage::new(batch.tag(irc_reply.format()));
+            let _ = user_state.tx_outbound.send(whois_user).await;
+            let channels = whois_channels(&target_caracs, &requester, server_state).await;
+            if !channels.is_empty() {
+                let irc_reply = IrcReply::WhoisChannels {
+                    nick: &nick,
+                    target: &target,
+                    channels: &channels,
+                };
+                let whois_channels = IrcMessage::new(batch.tag(irc_reply.format()));
+                let _ = user_state.tx_outbound.send(whois_channels).await;
+            }
+            let irc_reply = IrcReply::WhoisServer {
+                nick: &nick,
+                target: &target,
+            };
+            let whois_server = IrcMessage::new(batch.tag(irc_reply.format()));
+            let _ = user_state.tx_outbound.send(whois_server).await;
+            if let Some(message) = target_caracs.away_message() {
+                let irc_reply = IrcReply::Away {
+                    nick: &nick,
+                    target: &target,
+                    message,
+                };
+                let away = IrcMessage::new(batch.tag(irc_reply.format()));
+                let _ = user_state.tx_outbound.send(away).await;
+            }
+            // Only operators see through host cloaks
+            if requester.modes.contains(&'o') {
+                let irc_reply = IrcReply::WhoisActually {
+                    nick: &nick,
+                    target: &target,
+                    host: &target_caracs.real_host(),
+                };
+                let whois_actually = IrcMessage::new(batch.tag(irc_reply.format()));
+                let _ = user_state.tx_outbound.send(whois_actually).await;
+            }
+            let irc_reply = IrcReply::WhoisIdle {
+                nick: &nick,
+                target: &target,
+                idle: target_state.stats.idle_secs(),
+                signon: target_state.stats.connected_at,
+            };
+            let whois_idle = IrcMessage::new(batch.tag(irc_reply.format()));
+            let _ = user_state.tx_outbound.send(whois_idle).await;
+        }
+        None => {
+            let irc_reply = IrcReply::ErrNoSuchNick {
+                nick: &nick,
+                target: &target.0,
+            };
+            let err_no_such_nick = IrcMessage::new(batch.tag(irc_reply.format()));
+            let _ = user_state.tx_outbound.send(err_no_such_nick).await;
+        }
+    }
+    let irc_reply = IrcReply::EndOfWhois {
+        nick: &nick,
+        target: &target,
+    };
+    let end_of_whois = IrcMessage::new(batch.tag(irc_reply.format()));
+    let _ = user_state.tx_outbound.send(end_of_whois).await;
+    if let Some(batch_end) = batch.end() {
+        let _ = user_state
+            .tx_outbound
+            .send(IrcMessage::new(batch_end))
+            .await;
+    }
+    Ok(UserStatus::Active)
+}
+
+// The target's channels with its status prefix, leaving out secret and
+// private channels the requester isn't on
+async fn whois_channels(
+    target: &UserSnapshot,
+    requester: &UserSnapshot,
+    server_state: &ServerState,
+) -> String {
+    let mut channel_names = target.member_of.iter().cloned().collect::<Vec<_>>();
+    channel_names.sort_by(|a, b| a.0.cmp(&b.0));
+    // A self-WHOIS lists every channel of member_of, secret ones included
+    let is_self = target.user_id == requester.user_id;
+    let mut listed = Vec::new();
+    for channel_name in channel_names {
+        let Some(channel) = server_state.get_channel(&channel_name) else {
+            continue;
+        };
+        let is_hidden = {
+            let modes = channel.modes.read().await;
+            modes.secret || modes.private
+        };
+        if is_hidden && !is_self && !channel.members.contains(&requester.user_id) {
+            continue;
+        }
+        let prefix = channel.role(target.user_id).prefix();
+        listed.push(format!("{prefix}{channel_name}"));
+    }
+    listed.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        server_state::ServerState,
+        test_utils::{TestClient, has_numeric, numeric},
+        types::ChannelName,
+    };
+
+    fn who_nicks(replies: &[String]) -> Vec<String> {
+        replies
+            .iter()
+            .filter(|l| numeric(l) == Some("352"))
+            .map(|l| l.split(' ').nth(7).unwrap().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_who_hides_invisible_user_from_strangers() {
+        let server_state = ServerState::default();
+        let mut ghost = TestClient::registered(&server_state, "ghost").await;
+        let mut friend = TestClient::registered(&server_state, "friend").await;
+        let mut stranger = TestClient::registered(&server_state, "stranger").await;
+        ghost.user_state.user.write().await.modes.insert('i');
+        ghost.send(&server_state, "JOIN #haunt").await.unwrap();
+        friend.send(&server_state, "JOIN #haunt").await.unwrap();
+        ghost.drain();
+
+        stranger.send(&server_state, "WHO *").await.unwrap();
+        let replies = stranger.drain();
+        assert!(has_numeric(&replies, "315"));
+        assert!(!who_nicks(&replies).contains(&"ghost".to_owned()));
+
+        stranger.send(&server_state, "WHO #haunt").await.unwrap();
+        assert_eq!(who_nicks(&stranger.drain()), vec!["friend"]);
+
+        friend.drain();
+        friend.send(&server_state, "WHO gh*").await.unwrap();
+        assert_eq!(who_nicks(&friend.drain()), vec!["ghost"]);
+    }
+
+    #[tokio::test]
+    async fn test_whois_invisible_stranger_is_no_such_nick() {
+        let server_state = ServerState::default();
+        let ghost = TestClient::registered(&server_state, "ghost").await;
+        let mut stranger = TestClient::registered(&server_state, "stranger").await;
+        ghost.user_state.user.write().await.modes.insert('i');
+
+        stranger.send(&server_state, "WHOIS ghost").await.unwrap();
+
+        let replies = stranger.drain();
+        assert!(has_numeric(&replies, "401"));
+        assert!(!has_numeric(&replies, "311"));
+        assert!(has_numeric(&replies, "318"));
+    }
+
+    #[tokio::test]
+    async fn test_whois_channels_hide_secret_channels_from_outsiders() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut stranger = TestClient::registered(&server_state, "stranger").await;
+        alice
+            .send(&server_state, "JOIN #lobby,#hideout")
+            .await
+            .unwrap();
+        bob.send(&server_state, "JOIN #hideout").await.unwrap();
+        let hideout = server_state
+            .get_channel(&ChannelName("#hideout".to_owned()))
+            .unwrap();
+        hideout.modes.write().await.secret = true;
+        alice.drain();
+        bob.drain();
+
+        let whois_channels = |replies: Vec<String>| {
+            replies
+                .into_iter()
+                .find(|l| numeric(l) == Some("319"))
+                .unwrap()
+        };
+        bob.send(&server_state, "WHOIS alice").await.unwrap();
+        assert!(whois_channels(bob.drain()).ends_with(" alice :@#hideout @#lobby"));
+
+        stranger.send(&server_state, "WHOIS alice").await.unwrap();
+        assert!(whois_channels(stranger.drain()).ends_with(" alice :@#lobby"));
+    }
+
+    #[tokio::test]
+    async fn test_self_whois_lists_own_channels() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice
+            .send(&server_state, "JOIN #open,#hidden")
+            .await
+            .unwrap();
+        bob.send(&server_state, "JOIN #open,#kicked").await.unwrap();
+        alice.send(&server_state, "MODE #hidden +s").await.unwrap();
+        alice.drain();
+
+        alice.send(&server_state, "WHOIS alice").await.unwrap();
+        assert!(
+            alice
+                .drain()
+                .contains(&":unknown.server 319 alice alice :@#hidden @#open".to_owned())
+        );
+
+        // PART and KICK keep member_of in step with the channels
+        alice.send(&server_state, "KICK #open bob").await.unwrap();
+        bob.send(&server_state, "PART #kicked").await.unwrap();
+        bob.drain();
+        bob.send(&server_state, "WHOIS bob").await.unwrap();
+        let replies = bob.drain();
+        assert!(!has_numeric(&replies, "319"), "{replies:?}");
+        assert!(bob.user_state.get_caracs().await.member_of.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_who_flags_of_an_away_channel_operator() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+        bob.send(&server_state, "JOIN #chan").await.unwrap();
+        alice.user_state.user.write().await.modes.insert('o');
+        alice.send(&server_state, "AWAY :lunch").await.unwrap();
+        bob.drain();
+
+        bob.send(&server_state, "WHO #chan").await.unwrap();
+        let replies = bob.drain();
+        let flags = |nick: &str| {
+            replies
+                .iter()
+                .filter(|l| numeric(l) == Some("352"))
+                .find(|l| l.split(' ').nth(7) == Some(nick))
+                .and_then(|l| l.split(' ').nth(8))
+                .map(str::to_owned)
+        };
+        assert_eq!(flags("alice").as_deref(), Some("G*@"));
+        assert_eq!(flags("bob").as_deref(), Some("H"));
+    }
+
+    #[tokio::test]
+    async fn test_whois_real_host_only_for_operators() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.security =
+            Some(toml::from_str("cloak_key = \"s3cret\"").unwrap());
+        let alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut oper = TestClient::registered(&server_state, "oper").await;
+        oper.user_state.user.write().await.modes.insert('o');
+        let cloak = alice.user_state.get_caracs().await.displayed_host();
+        assert!(cloak.ends_with(".cloak"));
+
+        bob.send(&server_state, "WHOIS alice").await.unwrap();
+        let replies = bob.drain();
+        assert!(replies[0].ends_with(&format!(" 311 bob alice alice {cloak} * :alice")));
+        assert!(!has_numeric(&replies, "338"));
+        assert!(replies.iter().all(|line| !line.contains("127.0.0.1")));
+
+        oper.send(&server_state, "WHOIS alice").await.unwrap();
+        let replies = oper.drain();
+        assert!(replies[0].contains(&cloak));
+        assert!(
+            replies.contains(
+                &":unknown.server 338 oper alice 127.0.0.1 :Actually using host".to_owned()
+            )
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use log::info;
+
+use crate::server_state::ServerState;
+
+/// Logs `heartbeat_line` every `interval`, for `server.stats_interval`.
+pub async fn run_heartbeat(server_state: ServerState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes at once, nothing has happened yet
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        info!("{}", heartbeat_line(&server_state));
+    }
+}
+
+/// One line summing up the server's load. `ips` is the number of
+/// addresses holding a connection slot, it should follow `users`.
+pub fn heartbeat_line(server_state: &ServerState) -> String {
+    let messages: u64 = server_state
+        .command_counts
+        .iter()
+        .map(|counts| counts.value().0)
+        .sum();
+    format!(
+        "heartbeat: {} users, {} channels, {} messages processed, {} ips, {} suspected bots",
+        server_state.users.len(),
+        server_state.channels.len(),
+        messages,
+        server_state.ip_counts.len(),
+        server_state.suspected_bots.load(Ordering::Relaxed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use log::{Level, Log, Metadata, Record};
+
+    use super::run_heartbeat;
+    use crate::{server_state::ServerState, test_utils::TestClient};
+
+    // Keeps the lines logged from this module, other tests' logs are ignored
+    struct Capture(Mutex<Vec<String>>);
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Info
+        }
+
+        fn log(&self, record: &Record) {
+            if record.target() == "irc_server::heartbeat" {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+    #[tokio::test]
+    async fn test_heartbeat_logs_counts_every_interval() {
+        log::set_logger(&CAPTURE).expect("no other logger in tests");
+        log::set_max_level(log::LevelFilter::Info);
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        alice.send(&server_state, "JOIN #chan").await.unwrap();
+
+        let heartbeat = tokio::spawn(run_heartbeat(
+            server_state.clone(),
+            Duration::from_millis(20),
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        heartbeat.abort();
+
+        let lines = CAPTURE.0.lock().unwrap().clone();
+        assert!(!lines.is_empty());
+        // NICK, USER and JOIN
+        assert_eq!(
+            lines[0],
+            "heartbeat: 1 users, 1 channels, 3 messages processed, 0 ips, 0 suspected bots"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+// RFC 1413 - Identification Protocol
+// https://www.rfc-editor.org/rfc/rfc1413
+
+//    The Identification Protocol (a.k.a., "ident", a.k.a., "the Ident
+//    Protocol") provides a means to determine the identity of a user of a
+//    particular TCP connection.  Given a TCP port number pair, it returns
+//    a character string which identifies the owner of that connection on
+//    the server's system.
+
+//    The query is "<port-on-server> , <port-on-client>", where the
+//    "server" is the host running the ident daemon, i.e. our client.
+//    A successful reply looks like:
+//       6193, 23 : USERID : UNIX : stjohns
+
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::timeout,
+};
+
+use crate::types::Username;
+
+pub const IDENT_PORT: u16 = 113;
+// Registration waits on the lookup, so a silent firewall must not stall it
+const IDENT_TIMEOUT: Duration = Duration::from_secs(3);
+// An ident reply is one short line, anything longer is garbage
+const IDENT_MAX_REPLY: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdentStatus {
+    /// `network.ident_lookup` is off: the USER name is shown as given.
+    NotChecked,
+    /// No usable ident reply: the USER name is shown with a `~` prefix.
+    Failed,
+    /// The ident daemon vouched for this username, shown instead of USER's.
+    Resolved(Username),
+}
+
+impl IdentStatus {
+    /// The username to display for a client that sent `USER <user_name> ...`
+    pub fn displayed_username(&self, user_name: Username) -> Username {
+        match self {
+            IdentStatus::NotChecked => user_name,
+            IdentStatus::Failed => Username(format!("~{user_name}")),
+            IdentStatus::Resolved(ident) => ident.clone(),
+        }
+    }
+}
+
+/// Asks the ident daemon on the client's host who owns the connection
+/// `client` -> `server`.
+pub async fn lookup_ident(client: SocketAddr, server: SocketAddr) -> IdentStatus {
+    let ident_server = SocketAddr::new(client.ip(), IDENT_PORT);
+    query_ident(ident_server, client.port(), server.port()).await
+}
+
+pub async fn query_ident(
+    ident_server: SocketAddr,
+    client_port: u16,
+    server_port: u16,
+) -> IdentStatus {
+    let query = async {
+        let stream = TcpStream::connect(ident_server).await.ok()?;
+        let (read_half, mut write_half) = stream.into_split();
+        write_half
+            .write_all(format!("{client_port} , {server_port}\r\n").as_bytes())
+            .await
+            .ok()?;
+        let mut line = String::new();
+        BufReader::new(read_half.take(IDENT_MAX_REPLY))
+            .read_line(&mut line)
+            .await
+            .ok()?;
+        parse_ident_reply(&line, client_port, server_port)
+    };
+    match timeout(IDENT_TIMEOUT, query).await {
+        Ok(Some(ident)) => IdentStatus::Resolved(ident),
+        _ => IdentStatus::Failed,
+    }
+}
+
+fn parse_ident_reply(line: &str, client_port: u16, server_port: u16) -> Option<Username> {
+    let mut fields = line.trim_end().splitn(4, ':').map(str::trim);
+    let (ports, reply_type, _os, user_id) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    let (reply_client_port, reply_server_port) = ports.split_once(',')?;
+    if reply_client_port.trim().parse::<u16>().ok()? != client_port
+        || reply_server_port.trim().parse::<u16>().ok()? != server_port
+        || !reply_type.eq_ignore_ascii_case("USERID")
+    {
+        return None;
+    }
+    // The username ends up in nick!user@host prefixes
+    let is_valid = !user_id.is_empty()
+        && !user_id
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '@' | '!' | ':'));
+    is_valid.then(|| Username(user_id.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        server_state::ServerState,
+        test_utils::{TestClient, numeric},
+    };
+    use tokio::net::TcpListener;
+
+    // Answers a single ident query with `reply` for whatever ports were asked
+    async fn mock_ident_responder(reply: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read_half, mut write_half) = stream.into_split();
+            let mut query = String::new();
+            BufReader::new(read_half)
+                .read_line(&mut query)
+                .await
+                .unwrap();
+            let ports = query.trim_end();
+            let _ = write_half
+                .write_all(format!("{ports} : {reply}\r\n").as_bytes())
+                .await;
+        });
+        addr
+    }
+
+    async fn welcome_with(ident: IdentStatus) -> String {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+        client.user_state.user.write().await.ident = ident;
+        client.send(&server_state, "NICK alice").await.unwrap();
+        client
+            .send(&server_state, "USER alice 0 * :Alice")
+            .await
+            .unwrap();
+        client
+            .drain()
+            .into_iter()
+            .find(|l| numeric(l) == Some("001"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_resolved_ident_replaces_user_name() {
+        let ident_server = mock_ident_responder("USERID : UNIX : realalice").await;
+
+        let ident = query_ident(ident_server, 50000, 6667).await;
+
+        assert_eq!(
+            ident,
+            IdentStatus::Resolved(Username("realalice".to_owned()))
+        );
+        assert!(welcome_with(ident).await.contains(" alice!realalice@"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_ident_prefixes_user_name() {
+        let ident_server = mock_ident_responder("ERROR : NO-USER").await;
+
+        let ident = query_ident(ident_server, 50000, 6667).await;
+
+        assert_eq!(ident, IdentStatus::Failed);
+        assert!(welcome_with(ident).await.contains(" alice!~alice@"));
+    }
+}
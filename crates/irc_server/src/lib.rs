@@ -1,11 +1,21 @@
+pub mod accounts;
+pub mod admin;
+pub mod auth;
 pub mod channels_models;
 pub mod config;
 pub mod constants;
+pub mod crypto;
 pub mod errors;
 pub mod handlers;
+pub mod heartbeat;
+pub mod ident;
 pub mod message_models;
 pub mod ops;
 pub mod replies;
 pub mod server_state;
+pub mod shutdown;
+#[cfg(test)]
+mod test_utils;
 pub mod types;
 pub mod user_state;
+pub mod utils;
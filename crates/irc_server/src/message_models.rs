@@ -1,62 +1,215 @@
-use crate::{handlers::client, types::ClientId};
+use std::collections::HashSet;
 
-#[derive(Debug, Clone)]
-pub struct DirectIrcMessage {
-    pub sender: Option<ClientId>,
-    pub raw_line: String,
-}
-impl DirectIrcMessage {
-    pub fn new(line: String) -> Self {
-        let final_line = if line.ends_with("\r\n") {
-            line
-        } else {
-            format!("{line}\r\n")
-        };
-        DirectIrcMessage {
-            sender: None,
-            raw_line: final_line,
+use crate::{
+    types::{ChannelName, ClientId},
+    utils::{format_server_time, unix_timestamp_millis},
+};
+
+/// IRCv3 message tags, in the order they were added, rendered in front
+/// of a line as `@key=value;key2 :prefix COMMAND ...`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageTags(Vec<(String, Option<String>)>);
+
+impl MessageTags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `key=value`. An empty value is rendered as a bare `key`.
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        let value = (!value.is_empty()).then(|| value.to_owned());
+        self.0.push((key.to_owned(), value));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `line` with these tags in front, ahead of the tags it already has.
+    pub fn render(&self, line: &str) -> String {
+        if self.is_empty() {
+            return line.to_owned();
+        }
+        let tags = self
+            .0
+            .iter()
+            .map(|(key, value)| match value {
+                Some(value) => format!("{key}={}", escape_tag_value(value)),
+                None => key.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(";");
+        match line.strip_prefix('@') {
+            Some(tagged) => format!("@{tags};{tagged}"),
+            None => format!("@{tags} {line}"),
         }
     }
+}
 
-    pub fn new_with_sender(line: String, sender: ClientId) -> Self {
-        let final_line = if line.ends_with("\r\n") {
-            line
-        } else {
-            format!("{line}\r\n")
-        };
-        DirectIrcMessage {
-            sender: Some(sender),
-            raw_line: final_line,
+/// IRCv3 tag value escaping: `;`, space, `\`, CR and LF can't appear as is.
+fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
         }
     }
+    escaped
 }
 
-#[derive(Debug, Clone)]
-pub struct BroadcastIrcMessage {
+/// Ends `line` with exactly one CRLF. A CR, LF or NUL left inside it can
+/// only come from a user field the parsers let through, and becomes a space
+/// so that one outbound message never turns into two lines.
+fn terminate_line(line: String) -> String {
+    let body = line.strip_suffix("\r\n").unwrap_or(&line);
+    let mut final_line = body
+        .chars()
+        .map(|c| {
+            if matches!(c, '\r' | '\n' | '\0') {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+    final_line.push_str("\r\n");
+    final_line
+}
+
+/// A line on its way to a client, either queued directly to one user or
+/// broadcast to a channel's subscribers.
+#[derive(Debug, Clone, Default)]
+pub struct IrcMessage {
     pub sender: Option<ClientId>,
     pub raw_line: String,
+    // IRCv3 tags, only sent to recipients that negotiated message-tags
+    pub tags: MessageTags,
+    // When the message was built (ms), the `time` tag for server-time
+    pub sent_at: u64,
+    // Channel the message was first broadcast in, stamped by the channel
+    pub origin: Option<ChannelName>,
+    // What subscribers that negotiated the capability get instead of `raw_line`
+    pub capability_line: Option<(&'static str, String)>,
 }
-impl BroadcastIrcMessage {
+impl IrcMessage {
     pub fn new(line: String) -> Self {
-        let final_line = if line.ends_with("\r\n") {
-            line
-        } else {
-            format!("{line}\r\n")
-        };
-        BroadcastIrcMessage {
-            sender: None,
-            raw_line: final_line,
+        IrcMessage {
+            raw_line: terminate_line(line),
+            sent_at: unix_timestamp_millis(),
+            ..Default::default()
         }
     }
+
     pub fn new_with_sender(line: String, sender: ClientId) -> Self {
-        let final_line = if line.ends_with("\r\n") {
-            line
+        IrcMessage {
+            sender: Some(sender),
+            ..Self::new(line)
+        }
+    }
+
+    /// Tags the line for the recipients that negotiated message-tags.
+    pub fn with_tags(mut self, tags: MessageTags) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Sends `line` instead to the subscribers that negotiated `capability`.
+    pub fn with_capability_line(mut self, capability: &'static str, line: String) -> Self {
+        self.capability_line = Some((capability, terminate_line(line)));
+        self
+    }
+
+    /// The line a recipient with these capabilities is sent: the tags for
+    /// message-tags, then the `time` tag for server-time.
+    pub fn line_for(&self, capabilities: &HashSet<String>) -> String {
+        let line = match &self.capability_line {
+            Some((capability, line)) if capabilities.contains(*capability) => line,
+            _ => &self.raw_line,
+        };
+        let mut tags = if capabilities.contains("message-tags") {
+            self.tags.clone()
         } else {
-            format!("{line}\r\n")
+            MessageTags::new()
         };
-        BroadcastIrcMessage {
-            sender: Some(sender),
-            raw_line: final_line,
+        if capabilities.contains("server-time") {
+            tags = tags.with("time", &format_server_time(self.sent_at));
         }
+        tags.render(line)
+    }
+
+    /// Whether a subscriber gets this message: everyone but its sender.
+    pub fn is_for(&self, client_id: ClientId) -> bool {
+        self.sender != Some(client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_render_in_front_of_the_prefix() {
+        let tags = MessageTags::new()
+            .with("msgid", "42")
+            .with("time", "2024-01-01T00:00:00.000Z")
+            .with("+draft/reply", "");
+        assert_eq!(
+            tags.render(":alice!a@h PRIVMSG #chan :hi"),
+            "@msgid=42;time=2024-01-01T00:00:00.000Z;+draft/reply :alice!a@h PRIVMSG #chan :hi"
+        );
+        // Merged ahead of the tags already there, values escaped
+        let batch = MessageTags::new().with("batch", "1");
+        assert_eq!(
+            batch.render("@msgid=42 :alice!a@h PRIVMSG #chan :hi"),
+            "@batch=1;msgid=42 :alice!a@h PRIVMSG #chan :hi"
+        );
+        let escaped = MessageTags::new().with("k", "a b;c\\");
+        assert_eq!(escaped.render(":s CMD"), "@k=a\\sb\\:c\\\\ :s CMD");
+        assert_eq!(MessageTags::new().render(":s CMD"), ":s CMD");
+    }
+
+    #[test]
+    fn test_tagged_message_renders_for_message_tags_recipients() {
+        let message =
+            IrcMessage::new_with_sender(":alice!a@h PRIVMSG #chan :hi".to_owned(), ClientId(1))
+                .with_tags(MessageTags::new().with("msgid", "7"));
+        assert_eq!(message.sender, Some(ClientId(1)));
+        let capabilities = HashSet::from(["message-tags".to_owned()]);
+        assert_eq!(
+            message.line_for(&capabilities),
+            "@msgid=7 :alice!a@h PRIVMSG #chan :hi\r\n"
+        );
+        assert_eq!(
+            message.line_for(&HashSet::new()),
+            ":alice!a@h PRIVMSG #chan :hi\r\n"
+        );
+
+        // The capability line replaces the raw one, tags still go in front
+        let redaction = IrcMessage::new(":s CMD".to_owned())
+            .with_tags(MessageTags::new().with("msgid", "7"))
+            .with_capability_line("message-tags", ":s OTHER".to_owned());
+        assert_eq!(redaction.line_for(&capabilities), "@msgid=7 :s OTHER\r\n");
+
+        // server-time stamps the time the message was built
+        let timed = IrcMessage {
+            sent_at: 1_700_000_000_123,
+            ..message
+        };
+        let capabilities = HashSet::from(["message-tags".to_owned(), "server-time".to_owned()]);
+        assert_eq!(
+            timed.line_for(&capabilities),
+            "@msgid=7;time=2023-11-14T22:13:20.123Z :alice!a@h PRIVMSG #chan :hi\r\n"
+        );
+        let capabilities = HashSet::from(["server-time".to_owned()]);
+        assert_eq!(
+            timed.line_for(&capabilities),
+            "@time=2023-11-14T22:13:20.123Z :alice!a@h PRIVMSG #chan :hi\r\n"
+        );
     }
 }
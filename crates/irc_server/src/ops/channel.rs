@@ -1,9 +1,17 @@
-use crate::handlers::channels::handle_part_channel;
+use crate::handlers::channels::{
+    handle_channel_mode_change, handle_channel_mode_query, handle_invite_channel,
+    handle_invite_list, handle_kick_channel, handle_list_channel, handle_names_channel,
+    handle_part_channel, handle_topic_channel,
+};
 use crate::types::{ChannelName, ClientId, Topic, Username};
+use crate::utils::is_safe_channel_id;
 use crate::{
     errors::InternalIrcError,
-    handlers::channels::{handle_invalid_join_channel, handle_join_channel},
-    ops::parsers::{channel_parser, key_parser, nickname_parser, trailing_parser, user_parser},
+    handlers::channels::{handle_join_channel, handle_missing_params},
+    ops::parsers::{
+        MAX_PARAMS, bounded_list1, channel_parser, channel_target_parser, key_parser,
+        middle_parser, nickname_parser, trailing_parser, user_parser,
+    },
     server_state::ServerState,
     types::Nickname,
     user_state::{UserState, UserStatus},
@@ -11,35 +19,39 @@ use crate::{
 use nom::{
     IResult, Parser,
     branch::alt,
-    bytes::complete::{tag, tag_no_case},
-    character::complete::{char, satisfy},
-    combinator::{opt, recognize},
-    multi::{many1, separated_list1},
-    sequence::{pair, preceded},
+    bytes::complete::{tag, tag_no_case, take_while1},
+    character::complete::{char, digit1, satisfy},
+    combinator::{eof, map, map_res, opt, recognize, verify},
+    multi::{many_m_n, many1},
+    sequence::{pair, preceded, terminated},
 };
 
 pub enum IrcChannelOperation {
     LEAVE, // JOIN 0 - should be tested befoire JOIN Channel
     JOIN(Vec<(ChannelName, Option<String>)>),
     PART(Vec<ChannelName>, Option<String>),
-    MODE(ChannelName, Vec<(char, Vec<char>)>),
+    MODE(ChannelName, Vec<ChannelModeChange>),
     TOPIC(ChannelName, Option<Topic>),
-    NAMES(Option<Vec<String>>, Option<String>),
-    LIST(Option<Vec<String>>, Option<String>),
+    NAMES(Option<Vec<ChannelName>>, Option<String>),
+    LIST(Vec<ListFilter>, Option<String>),
     INVITE(Nickname, ChannelName),
+    // INVITE without parameters lists the user's pending invitations
+    INVITES,
     KICK(Vec<ChannelName>, Vec<Username>, Option<String>),
 }
 impl IrcChannelOperation {
     pub fn irc_command_parser(input: &str) -> IResult<&str, Self> {
         let mut parser = alt((
-            valid_join_channel_parser,
             valid_leave_channel_parser,
+            valid_join_channel_parser,
             valid_part_channel_parser,
             valid_mode_channel_parser,
+            valid_mode_query_channel_parser,
             valid_topic_channel_parser,
-            // valid_names_channel_parser,
-            // valid_list_channel_parser,
+            valid_names_channel_parser,
+            valid_list_channel_parser,
             valid_invite_channel_parser,
+            valid_invite_list_parser,
             valid_kick_channel_parser,
         ));
         parser.parse(input)
@@ -60,6 +72,46 @@ impl IrcChannelOperation {
                     handle_part_channel(channels, message, client_id, server_state, user_state)
                         .await
                 }
+                IrcChannelOperation::MODE(channel, modes) if modes.is_empty() => {
+                    handle_channel_mode_query(channel, client_id, server_state, user_state).await
+                }
+                IrcChannelOperation::MODE(channel, changes) => {
+                    handle_channel_mode_change(
+                        channel,
+                        changes,
+                        client_id,
+                        server_state,
+                        user_state,
+                    )
+                    .await
+                }
+                IrcChannelOperation::NAMES(channels, target) => {
+                    handle_names_channel(channels, target, client_id, server_state, user_state)
+                        .await
+                }
+                IrcChannelOperation::LIST(filters, target) => {
+                    handle_list_channel(filters, target, client_id, server_state, user_state).await
+                }
+                IrcChannelOperation::INVITE(nick, channel) => {
+                    handle_invite_channel(nick, channel, client_id, server_state, user_state).await
+                }
+                IrcChannelOperation::INVITES => {
+                    handle_invite_list(client_id, server_state, user_state).await
+                }
+                IrcChannelOperation::KICK(channels, users, comment) => {
+                    handle_kick_channel(
+                        channels,
+                        users,
+                        comment,
+                        client_id,
+                        server_state,
+                        user_state,
+                    )
+                    .await
+                }
+                IrcChannelOperation::TOPIC(channel, topic) => {
+                    handle_topic_channel(channel, topic, client_id, server_state, user_state).await
+                }
                 // Ir
                 _ => todo!(),
             },
@@ -99,17 +151,19 @@ impl IrcChannelOperation {
 pub fn valid_join_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
     let (rem, (channels, keys)) = preceded(
         tag_no_case("JOIN "),
-        (
-            (separated_list1(char(','), channel_parser)),
-            opt(preceded(tag(" "), separated_list1(char(','), key_parser))),
+        terminated(
+            (
+                bounded_list1(channel_target_parser),
+                opt(preceded(tag(" "), bounded_list1(key_parser))),
+            ),
+            eof,
         ),
     )
     .parse(input)?;
     let mut opt_keys = vec![None; channels.len()];
-    if let Some(keys) = keys {
-        keys.into_iter()
-            .enumerate()
-            .for_each(|(i, v)| opt_keys[i] = Some((*v).to_string()))
+    // Keys past the last channel are ignored
+    for (opt_key, key) in opt_keys.iter_mut().zip(keys.unwrap_or_default()) {
+        *opt_key = Some(key.to_string());
     }
     let channel_keys: Vec<(ChannelName, Option<String>)> =
         std::iter::zip(channels, opt_keys).collect::<Vec<_>>();
@@ -119,7 +173,7 @@ pub fn valid_join_channel_parser(input: &str) -> IResult<&str, IrcChannelOperati
 
 // LEAVE Message / JOIN 0
 pub fn valid_leave_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
-    let (rem, _join0) = recognize(tag_no_case("JOIN 0")).parse(input)?;
+    let (rem, _join0) = terminated(tag_no_case("JOIN 0"), eof).parse(input)?;
     Ok((rem, IrcChannelOperation::LEAVE))
 }
 
@@ -142,7 +196,7 @@ pub fn valid_part_channel_parser(input: &str) -> IResult<&str, IrcChannelOperati
     let (rem, (channels, optional_message)) = preceded(
         tag_no_case("PART "),
         (
-            separated_list1(tag(","), channel_parser),
+            bounded_list1(channel_target_parser),
             opt(preceded(tag(":"), trailing_parser)),
         ),
     )
@@ -169,6 +223,7 @@ pub fn valid_part_channel_parser(input: &str) -> IResult<&str, IrcChannelOperati
 //         v - give/take the voice privilege;
 
 //         a - toggle the anonymous channel flag;
+//         c - toggle stripping colors and formatting (extension);
 //         i - toggle the invite-only channel flag;
 //         m - toggle the moderated channel;
 //         n - toggle the no messages to channel from clients on the
@@ -178,6 +233,8 @@ pub fn valid_part_channel_parser(input: &str) -> IResult<&str, IrcChannelOperati
 //         s - toggle the secret channel flag;
 //         r - toggle the server reop channel flag;
 //         t - toggle the topic settable by channel operator only flag;
+//         z - toggle relaying messages +m blocks to the channel operators
+//             (extension);
 
 //         k - set/remove the channel key (password);
 //         l - set/remove the user limit to channel;
@@ -193,6 +250,7 @@ fn is_channel_mode(c: char) -> bool {
         'O' | 'o'
             | 'v'
             | 'a'
+            | 'c'
             | 'i'
             | 'm'
             | 'n'
@@ -201,17 +259,46 @@ fn is_channel_mode(c: char) -> bool {
             | 's'
             | 'r'
             | 't'
+            | 'z'
             | 'k'
             | 'l'
+            | 'f'
             | 'b'
             | 'e'
             | 'I'
     )
 }
 
+// Whether a mode consumes one of the <modeparams>, in order of appearance.
+// `-k` takes the key too, but a missing one is tolerated.
+fn channel_mode_takes_param(mode: char, adding: bool) -> bool {
+    match mode {
+        'O' | 'o' | 'v' | 'k' | 'b' | 'e' | 'I' | 'q' => true,
+        'l' | 'f' => adding,
+        _ => false,
+    }
+}
+
+/// One `+x [param]` / `-x [param]` item of a channel MODE command. A list
+/// mode without its mask (`MODE #chan +q`) is a query of that list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelModeChange {
+    pub adding: bool,
+    pub mode: char,
+    pub param: Option<String>,
+}
+
+// Anything else is a user MODE target
+fn mode_channel_target_parser(input: &str) -> IResult<&str, ChannelName> {
+    verify(channel_target_parser, |channel: &ChannelName| {
+        channel.0.starts_with(['#', '+', '!', '&'])
+    })
+    .parse(input)
+}
+
 fn valid_mode_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
-    let (rem, (channel, modes)) = (
-        preceded(tag_no_case("MODE "), channel_parser),
+    let (rem, (channel, modes, params)) = (
+        preceded(tag_no_case("MODE "), mode_channel_target_parser),
         preceded(
             tag(" "),
             many1(pair(
@@ -219,9 +306,37 @@ fn valid_mode_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation>
                 many1(satisfy(is_channel_mode)),
             )),
         ),
+        many_m_n(0, MAX_PARAMS, preceded(tag(" "), middle_parser)),
     )
         .parse(input)?;
-    Ok((rem, IrcChannelOperation::MODE(channel, modes)))
+    let mut params = params.into_iter();
+    let mut changes = Vec::new();
+    for (sign, letters) in modes {
+        let adding = sign == '+';
+        for mode in letters {
+            let param = if channel_mode_takes_param(mode, adding) {
+                params.next().map(str::to_owned)
+            } else {
+                None
+            };
+            changes.push(ChannelModeChange {
+                adding,
+                mode,
+                param,
+            });
+        }
+    }
+    Ok((rem, IrcChannelOperation::MODE(channel, changes)))
+}
+
+// MODE <channel> with no mode changes queries the current modes
+fn valid_mode_query_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
+    let (rem, channel) = terminated(
+        preceded(tag_no_case("MODE "), mode_channel_target_parser),
+        eof,
+    )
+    .parse(input)?;
+    Ok((rem, IrcChannelOperation::MODE(channel, Vec::new())))
 }
 
 // 3.2.4 Topic message
@@ -238,15 +353,11 @@ fn valid_mode_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation>
 
 fn valid_topic_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
     let (rem, (channel, topic)) = (
-        preceded(tag_no_case("TOPIC "), channel_parser),
-        opt(preceded(tag(" "), trailing_parser)),
+        preceded(tag_no_case("TOPIC "), channel_target_parser),
+        opt(preceded((tag(" "), opt(tag(":"))), trailing_parser)),
     )
         .parse(input)?;
-    let topic = if let Some(the_topic) = topic {
-        Some(Topic(the_topic.to_owned()))
-    } else {
-        None
-    };
+    let topic = topic.map(|the_topic| Topic(the_topic.to_owned()));
 
     Ok((rem, IrcChannelOperation::TOPIC(channel, topic)))
 }
@@ -272,25 +383,30 @@ fn valid_topic_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation>
 
 //    Wildcards are allowed in the <target> parameter.
 
-// fn valid_names_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
-//     let (rem, (_names, params)) = (
-//         tag_no_case("NAMES"),
-//         opt(preceded(
-//             tag(" "),
-//             (
-//                 separated_list1(tag(","), channel_parser),
-//                 opt(preceded(tag(" "), alt((target_parser, wildcards_parser)))),
-//             ),
-//         )),
-//     )
-//         .parse(input)?;
-//     let channels = params
-//         .clone()
-//         .map(|(ch, _)| ch.into_iter().map(str::to_owned).collect::<Vec<String>>());
-//     let target = params.and_then(|(_, targ)| targ.map(str::to_owned));
-//     // let topic = topic.map(str::to_owned);
-//     Ok((rem, IrcChannelOperation::NAMES(channels, target)))
-// }
+fn valid_names_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
+    let (rem, params) = preceded(
+        tag_no_case("NAMES"),
+        alt((
+            map(eof, |_| None),
+            map(
+                preceded(
+                    tag(" "),
+                    (
+                        bounded_list1(channel_parser),
+                        opt(preceded(tag(" "), middle_parser)),
+                    ),
+                ),
+                Some,
+            ),
+        )),
+    )
+    .parse(input)?;
+    let (channels, target) = match params {
+        Some((channels, target)) => (Some(channels), target.map(str::to_owned)),
+        None => (None, None),
+    };
+    Ok((rem, IrcChannelOperation::NAMES(channels, target)))
+}
 
 // 3.2.6 List message
 
@@ -306,24 +422,63 @@ fn valid_topic_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation>
 
 //    Wildcards are allowed in the <target> parameter.
 
-// fn valid_list_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
-//     let (rem, (_list, params)) = (
-//         tag_no_case("LIST"),
-//         opt(preceded(
-//             tag(" "),
-//             (
-//                 separated_list1(tag(","), channel_parser),
-//                 opt(preceded(tag(" "), alt((target_parser, wildcards_parser)))),
-//             ),
-//         )),
-//     )
-//         .parse(input)?;
-//     let channels = params
-//         .clone()
-//         .map(|(ch, _)| ch.into_iter().map(str::to_owned).collect::<Vec<String>>());
-//     let target = params.and_then(|(_, targ)| targ.map(str::to_owned));
-//     Ok((rem, IrcChannelOperation::LIST(channels, target)))
-// }
+// ELIST search extensions (advertised as ELIST=CMNTU in RPL_ISUPPORT)
+//   C<val / C>val : channel created less / more than <val> minutes ago
+//   M (mask)      : channel name matches the mask (plain or wildcard)
+//   N (!mask)     : channel name does not match the mask
+//   T<val / T>val : topic set less / more than <val> minutes ago
+//   U (<val/>val) : channel has less / more than <val> users
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListFilter {
+    Mask(String),
+    NotMask(String),
+    MoreUsersThan(usize),
+    FewerUsersThan(usize),
+    CreatedWithin(u64),
+    CreatedBefore(u64),
+    TopicWithin(u64),
+    TopicBefore(u64),
+}
+
+fn list_filter_parser(input: &str) -> IResult<&str, ListFilter> {
+    let number = || map_res(digit1, str::parse::<u64>);
+    let mut parser = alt((
+        preceded(tag("C<"), number()).map(ListFilter::CreatedWithin),
+        preceded(tag("C>"), number()).map(ListFilter::CreatedBefore),
+        preceded(tag("T<"), number()).map(ListFilter::TopicWithin),
+        preceded(tag("T>"), number()).map(ListFilter::TopicBefore),
+        preceded(char('>'), number()).map(|n| ListFilter::MoreUsersThan(n as usize)),
+        preceded(char('<'), number()).map(|n| ListFilter::FewerUsersThan(n as usize)),
+        // "!name" is the N filter unless it is a full safe channel name
+        verify(recognize(channel_parser), |c: &str| {
+            !c.starts_with('!') || is_safe_channel_id(c)
+        })
+        .map(|c: &str| ListFilter::Mask(c.to_owned())),
+        preceded(char('!'), take_while1(|c| c != ',' && c != ' '))
+            .map(|m: &str| ListFilter::NotMask(m.to_owned())),
+        take_while1(|c| c != ',' && c != ' ').map(|m: &str| ListFilter::Mask(m.to_owned())),
+    ));
+    parser.parse(input)
+}
+
+fn valid_list_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
+    let (rem, (_list, params)) = (
+        tag_no_case("LIST"),
+        opt(preceded(
+            tag(" "),
+            (
+                bounded_list1(list_filter_parser),
+                opt(preceded(tag(" "), trailing_parser)),
+            ),
+        )),
+    )
+        .parse(input)?;
+    let (filters, target) = match params {
+        Some((filters, target)) => (filters, target.map(str::to_owned)),
+        None => (Vec::new(), None),
+    };
+    Ok((rem, IrcChannelOperation::LIST(filters, target)))
+}
 
 // 3.2.7 Invite message
 
@@ -344,11 +499,19 @@ fn valid_topic_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation>
 //    source of trouble for users.)
 
 fn valid_invite_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
-    let (rem, (nickname, channel)) =
-        (preceded(tag_no_case("INVITE "), (nickname_parser, channel_parser))).parse(input)?;
+    let (rem, (nickname, channel)) = (preceded(
+        tag_no_case("INVITE "),
+        (nickname_parser, preceded(tag(" "), channel_target_parser)),
+    ))
+    .parse(input)?;
     Ok((rem, IrcChannelOperation::INVITE(nickname, channel)))
 }
 
+fn valid_invite_list_parser(input: &str) -> IResult<&str, IrcChannelOperation> {
+    let (rem, _) = terminated(tag_no_case("INVITE"), eof).parse(input)?;
+    Ok((rem, IrcChannelOperation::INVITES))
+}
+
 // 3.2.8 Kick command
 
 //       Command: KICK
@@ -370,8 +533,8 @@ fn valid_kick_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation>
     let (rem, (channels, users, comment)) = (preceded(
         tag_no_case("KICK "),
         (
-            separated_list1(tag(","), channel_parser),
-            (preceded(tag(" "), separated_list1(tag(","), user_parser))),
+            bounded_list1(channel_target_parser),
+            (preceded(tag(" "), bounded_list1(user_parser))),
             opt(preceded(tag(" :"), trailing_parser)),
         ),
     ))
@@ -380,13 +543,19 @@ fn valid_kick_channel_parser(input: &str) -> IResult<&str, IrcChannelOperation>
     Ok((rem, IrcChannelOperation::KICK(channels, users, comment)))
 }
 
+/// A channel command whose parameters didn't parse, tried after the valid
+/// parsers: it is answered with ERR_NEEDMOREPARAMS rather than 421.
 #[derive(Debug)]
 pub struct IrcInvalidChannelOperation(String);
 impl IrcInvalidChannelOperation {
     pub fn irc_command_parser(input: &str) -> IResult<&str, Self> {
         let mut parser = alt((
             invalid_join_channel_parser,
-            invalid_join_channel_parser, // valid_leave_channel_parser,
+            invalid_part_channel_parser,
+            invalid_kick_channel_parser,
+            invalid_topic_channel_parser,
+            invalid_invite_channel_parser,
+            invalid_mode_parser,
         ));
         parser.parse(input)
     }
@@ -396,7 +565,7 @@ impl IrcInvalidChannelOperation {
     ) -> Result<UserStatus, InternalIrcError> {
         match IrcInvalidChannelOperation::irc_command_parser(command) {
             Ok((_rem, IrcInvalidChannelOperation(valid_commmand))) => {
-                handle_invalid_join_channel(valid_commmand, user_state).await
+                handle_missing_params(valid_commmand, user_state).await
             }
             Err(_e) => Err(InternalIrcError::InvalidCommand),
         }
@@ -407,3 +576,83 @@ pub fn invalid_join_channel_parser(input: &str) -> IResult<&str, IrcInvalidChann
     let (rem, _) = tag_no_case("JOIN").parse(input)?;
     Ok((rem, IrcInvalidChannelOperation("JOIN".to_string())))
 }
+
+pub fn invalid_part_channel_parser(input: &str) -> IResult<&str, IrcInvalidChannelOperation> {
+    let (rem, _) = tag_no_case("PART").parse(input)?;
+    Ok((rem, IrcInvalidChannelOperation("PART".to_string())))
+}
+
+pub fn invalid_kick_channel_parser(input: &str) -> IResult<&str, IrcInvalidChannelOperation> {
+    let (rem, _) = tag_no_case("KICK").parse(input)?;
+    Ok((rem, IrcInvalidChannelOperation("KICK".to_string())))
+}
+
+pub fn invalid_topic_channel_parser(input: &str) -> IResult<&str, IrcInvalidChannelOperation> {
+    let (rem, _) = tag_no_case("TOPIC").parse(input)?;
+    Ok((rem, IrcInvalidChannelOperation("TOPIC".to_string())))
+}
+
+pub fn invalid_invite_channel_parser(input: &str) -> IResult<&str, IrcInvalidChannelOperation> {
+    let (rem, _) = tag_no_case("INVITE").parse(input)?;
+    Ok((rem, IrcInvalidChannelOperation("INVITE".to_string())))
+}
+
+// Channel or user MODE: without a target, it can't be told apart
+pub fn invalid_mode_parser(input: &str) -> IResult<&str, IrcInvalidChannelOperation> {
+    let (rem, _) = tag_no_case("MODE").parse(input)?;
+    Ok((rem, IrcInvalidChannelOperation("MODE".to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_list_channel_parser() {
+        let (_rem, list) = valid_list_channel_parser("LIST").unwrap();
+        assert!(matches!(list, IrcChannelOperation::LIST(f, None) if f.is_empty()));
+
+        let (_rem, list) = valid_list_channel_parser("LIST >10,<100,#r*,!*bot*,C<60").unwrap();
+        let IrcChannelOperation::LIST(filters, _) = list else {
+            panic!("expected LIST");
+        };
+        assert_eq!(
+            filters,
+            vec![
+                ListFilter::MoreUsersThan(10),
+                ListFilter::FewerUsersThan(100),
+                ListFilter::Mask("#r*".to_owned()),
+                ListFilter::NotMask("*bot*".to_owned()),
+                ListFilter::CreatedWithin(60),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_valid_mode_channel_parser_pairs_params() {
+        let (_rem, mode) = valid_mode_channel_parser("MODE #chan +ql-k bob 10 oldkey").unwrap();
+        let IrcChannelOperation::MODE(channel, changes) = mode else {
+            panic!("expected MODE");
+        };
+        assert_eq!(channel, ChannelName("#chan".to_owned()));
+        let change = |adding, mode, param: Option<&str>| ChannelModeChange {
+            adding,
+            mode,
+            param: param.map(str::to_owned),
+        };
+        assert_eq!(
+            changes,
+            vec![
+                change(true, 'q', Some("bob")),
+                change(true, 'l', Some("10")),
+                change(false, 'k', Some("oldkey")),
+            ]
+        );
+
+        let (_rem, mode) = valid_mode_channel_parser("MODE #chan +q").unwrap();
+        let IrcChannelOperation::MODE(_, changes) = mode else {
+            panic!("expected MODE");
+        };
+        assert_eq!(changes, vec![change(true, 'q', None)]);
+    }
+}
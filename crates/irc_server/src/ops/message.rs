@@ -2,13 +2,22 @@ use nom::{
     IResult, Parser,
     branch::alt,
     bytes::complete::{tag, tag_no_case},
-    sequence::preceded,
+    character::complete::{anychar, u16},
+    combinator::{eof, opt},
+    sequence::{preceded, terminated},
 };
 
 use crate::{
     errors::InternalIrcError,
-    handlers::messages::handle_privmsg,
-    ops::parsers::{msgtarget_parser, trailing_parser},
+    handlers::{
+        channels::handle_missing_params,
+        messages::{MessageKind, handle_message},
+        server_queries::{
+            handle_connect, handle_links, handle_lusers, handle_motd, handle_stats, handle_trace,
+            handle_version,
+        },
+    },
+    ops::parsers::{middle_parser, msgtarget_parser, trailing_parser},
     server_state::ServerState,
     types::{ClientId, MessageTo},
     user_state::{UserState, UserStatus},
@@ -84,21 +93,34 @@ impl FromStr for Message {
 
 pub enum IrcMessageSending {
     PRIVMSG(Vec<MessageTo>, String),
-    NOTICE,
+    NOTICE(Vec<MessageTo>, String),
     MOTD,
+    LUSERS,
     VERSION,
-    STATS,
-    LINKS,
+    STATS(Option<char>),
+    // [ [ <remote server> ] <server mask> ]
+    LINKS(Option<String>, Option<String>),
     TIME,
-    CONNECT,
-    TRACE,
+    // <target server> [ <port> [ <remote server> ] ]
+    CONNECT(String, Option<u16>, Option<String>),
+    TRACE(Option<String>),
     ADMIN,
     INFO,
 }
 
 impl IrcMessageSending {
     pub fn irc_command_parser(input: &str) -> IResult<&str, Self> {
-        let mut parser = alt((valid_privmsg_parser,));
+        let mut parser = alt((
+            valid_privmsg_parser,
+            valid_notice_parser,
+            valid_lusers_parser,
+            valid_stats_parser,
+            valid_motd_parser,
+            valid_version_parser,
+            valid_connect_parser,
+            valid_trace_parser,
+            valid_links_parser,
+        ));
         parser.parse(input)
     }
 
@@ -111,7 +133,27 @@ impl IrcMessageSending {
         match IrcMessageSending::irc_command_parser(command) {
             Ok((_rem, valid_commmand)) => match valid_commmand {
                 IrcMessageSending::PRIVMSG(msgtarget, msg) => {
-                    handle_privmsg(msgtarget, msg, client_id, server_state, user_state).await
+                    let kind = MessageKind::Privmsg;
+                    handle_message(kind, msgtarget, msg, client_id, server_state, user_state).await
+                }
+                IrcMessageSending::NOTICE(msgtarget, msg) => {
+                    let kind = MessageKind::Notice;
+                    handle_message(kind, msgtarget, msg, client_id, server_state, user_state).await
+                }
+                IrcMessageSending::LUSERS => handle_lusers(server_state, user_state).await,
+                IrcMessageSending::MOTD => handle_motd(server_state, user_state).await,
+                IrcMessageSending::VERSION => handle_version(server_state, user_state).await,
+                IrcMessageSending::LINKS(remote, mask) => {
+                    handle_links(remote, mask, user_state).await
+                }
+                IrcMessageSending::TRACE(target) => {
+                    handle_trace(target, server_state, user_state).await
+                }
+                IrcMessageSending::CONNECT(target, _port, _remote) => {
+                    handle_connect(target, user_state).await
+                }
+                IrcMessageSending::STATS(query) => {
+                    handle_stats(query, server_state, user_state).await
                 }
                 _ => todo!(),
             },
@@ -120,6 +162,42 @@ impl IrcMessageSending {
     }
 }
 
+/// A message command whose parameters didn't parse, tried after the valid
+/// parsers: it is answered with ERR_NEEDMOREPARAMS rather than 421.
+#[derive(Debug)]
+pub struct IrcInvalidMessageSending(String);
+impl IrcInvalidMessageSending {
+    pub fn irc_command_parser(input: &str) -> IResult<&str, Self> {
+        alt((invalid_privmsg_parser, invalid_notice_parser)).parse(input)
+    }
+
+    pub async fn handle_command(
+        command: &str,
+        user_state: &UserState,
+    ) -> Result<UserStatus, InternalIrcError> {
+        match IrcInvalidMessageSending::irc_command_parser(command) {
+            // No automatic reply to a NOTICE, not even an error
+            Ok((_rem, IrcInvalidMessageSending(command))) if command == "NOTICE" => {
+                Ok(UserStatus::Active)
+            }
+            Ok((_rem, IrcInvalidMessageSending(valid_commmand))) => {
+                handle_missing_params(valid_commmand, user_state).await
+            }
+            Err(_e) => Err(InternalIrcError::InvalidCommand),
+        }
+    }
+}
+
+pub fn invalid_privmsg_parser(input: &str) -> IResult<&str, IrcInvalidMessageSending> {
+    let (rem, _) = tag_no_case("PRIVMSG").parse(input)?;
+    Ok((rem, IrcInvalidMessageSending("PRIVMSG".to_string())))
+}
+
+pub fn invalid_notice_parser(input: &str) -> IResult<&str, IrcInvalidMessageSending> {
+    let (rem, _) = tag_no_case("NOTICE").parse(input)?;
+    Ok((rem, IrcInvalidMessageSending("NOTICE".to_string())))
+}
+
 // 3.3.1 Private messages
 
 //       Command: PRIVMSG
@@ -149,3 +227,172 @@ fn valid_privmsg_parser(input: &str) -> IResult<&str, IrcMessageSending> {
         IrcMessageSending::PRIVMSG(target_mask, text_to_be_sent.to_owned()),
     ))
 }
+
+// 3.3.2 Notice
+
+//       Command: NOTICE
+//    Parameters: <msgtarget> <text>
+
+//    The NOTICE command is used similarly to PRIVMSG.  The difference
+//    between NOTICE and PRIVMSG is that automatic replies MUST NEVER be
+//    sent in response to a NOTICE message.  This rule applies to servers
+//    too - they MUST NOT send any error reply back to the client on
+//    receipt of a notice.
+fn valid_notice_parser(input: &str) -> IResult<&str, IrcMessageSending> {
+    let (rem, (target_mask, text_to_be_sent)) = preceded(
+        tag_no_case("NOTICE "),
+        (msgtarget_parser, preceded(tag(" :"), trailing_parser)),
+    )
+    .parse(input)?;
+    Ok((
+        rem,
+        IrcMessageSending::NOTICE(target_mask, text_to_be_sent.to_owned()),
+    ))
+}
+
+// 3.4.2 Lusers message
+
+//       Command: LUSERS
+//    Parameters: [ <mask> [ <target> ] ]
+
+//    The LUSERS command is used to get statistics about the size of the
+//    IRC network.  If no parameter is given, the reply will be about the
+//    whole net.  If a <mask> is specified, then the reply will only
+//    concern the part of the network formed by the servers matching the
+//    mask.  Finally, if the <target> parameter is specified, the request
+//    is forwarded to that server which will generate the reply.
+fn valid_lusers_parser(input: &str) -> IResult<&str, IrcMessageSending> {
+    // Single server: <mask> and <target> don't change the reply
+    let (rem, _) = (
+        tag_no_case("LUSERS"),
+        opt(preceded(tag(" "), trailing_parser)),
+    )
+        .parse(input)?;
+    Ok((rem, IrcMessageSending::LUSERS))
+}
+
+// 3.4.4 Stats message
+
+//       Command: STATS
+//    Parameters: [ <query> [ <target> ] ]
+
+//    The stats command is used to query statistics of certain server.  If
+//    <query> parameter is omitted, only the end of stats reply is sent
+//    back.
+
+//    l - returns a list of the server's connections, showing how
+//        long each connection has been established and the
+//        traffic over that connection in Kbytes and messages for
+//        each direction;
+//    m - returns the usage count for each of commands supported
+//        by the server; commands for which the usage count is
+//        zero MAY be omitted;
+fn valid_stats_parser(input: &str) -> IResult<&str, IrcMessageSending> {
+    // Single server: <target> doesn't change the reply
+    let (rem, (_stats, query, _target)) = (
+        tag_no_case("STATS"),
+        opt(preceded(tag(" "), anychar)),
+        opt(preceded(tag(" "), trailing_parser)),
+    )
+        .parse(input)?;
+    Ok((rem, IrcMessageSending::STATS(query)))
+}
+
+// 3.4.1 Motd message
+
+//       Command: MOTD
+//    Parameters: [ <target> ]
+
+//    The MOTD command is used to get the "Message Of The Day" of the given
+//    server, or current server if <target> is omitted.
+fn valid_motd_parser(input: &str) -> IResult<&str, IrcMessageSending> {
+    // Single server: <target> doesn't change the reply
+    let (rem, _) = (
+        tag_no_case("MOTD"),
+        opt(preceded(tag(" "), trailing_parser)),
+    )
+        .parse(input)?;
+    Ok((rem, IrcMessageSending::MOTD))
+}
+
+// 3.4.3 Version message
+
+//       Command: VERSION
+//    Parameters: [ <target> ]
+
+//    The VERSION command is used to query the version of the server
+//    program.  An optional parameter <target> is used to query the version
+//    of the server program which a client is not directly connected to.
+fn valid_version_parser(input: &str) -> IResult<&str, IrcMessageSending> {
+    // Single server: <target> doesn't change the reply
+    let (rem, _) = (
+        tag_no_case("VERSION"),
+        opt(preceded(tag(" "), trailing_parser)),
+    )
+        .parse(input)?;
+    Ok((rem, IrcMessageSending::VERSION))
+}
+
+// 3.4.7 Connect message
+
+//       Command: CONNECT
+//    Parameters: <target server> <port> [ <remote server> ]
+
+//    The CONNECT command can be used to request a server to try to
+//    establish a new connection to another server immediately.  CONNECT is
+//    a privileged command and SHOULD be available only to IRC Operators.
+fn valid_connect_parser(input: &str) -> IResult<&str, IrcMessageSending> {
+    let (rem, (_connect, target, port, remote)) = (
+        tag_no_case("CONNECT "),
+        middle_parser,
+        opt(preceded(tag(" "), u16)),
+        opt(preceded(tag(" "), middle_parser)),
+    )
+        .parse(input)?;
+    Ok((
+        rem,
+        IrcMessageSending::CONNECT(target.to_owned(), port, remote.map(str::to_owned)),
+    ))
+}
+
+// 3.4.5 Links message
+
+//       Command: LINKS
+//    Parameters: [ [ <remote server> ] <server mask> ]
+
+//    With LINKS, a user can list all servernames, which are known by the
+//    server answering the query.  The returned list of servers MUST match
+//    the mask, or if no mask is given, the full list is returned.
+fn valid_links_parser(input: &str) -> IResult<&str, IrcMessageSending> {
+    let (rem, (_links, first, second)) = terminated(
+        (
+            tag_no_case("LINKS"),
+            opt(preceded(tag(" "), middle_parser)),
+            opt(preceded(tag(" "), middle_parser)),
+        ),
+        eof,
+    )
+    .parse(input)?;
+    let (remote, mask) = match second {
+        Some(mask) => (first, Some(mask)),
+        None => (None, first),
+    };
+    Ok((
+        rem,
+        IrcMessageSending::LINKS(remote.map(str::to_owned), mask.map(str::to_owned)),
+    ))
+}
+
+// 3.4.8 Trace message
+
+//       Command: TRACE
+//    Parameters: [ <target> ]
+
+//    TRACE command is used to find the route to specific server and
+//    information about its peers.  Each server that processes this message
+//    MUST report to the sender about it.
+fn valid_trace_parser(input: &str) -> IResult<&str, IrcMessageSending> {
+    let (rem, (_trace, target)) =
+        (tag_no_case("TRACE"), opt(preceded(tag(" "), middle_parser))).parse(input)?;
+    Ok((rem, IrcMessageSending::TRACE(target.map(str::to_owned))))
+}
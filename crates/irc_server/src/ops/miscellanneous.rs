@@ -2,37 +2,54 @@ use nom::{
     IResult, Parser,
     branch::alt,
     bytes::complete::{tag, tag_no_case},
+    combinator::{opt, rest},
     multi::many1,
     sequence::preceded,
 };
 
 use crate::{
     errors::InternalIrcError,
-    handlers::miscellanneous::handle_ping,
-    ops::parsers::host_parser,
-    types::{ClientId, Host},
+    handlers::miscellanneous::{handle_kill, handle_ping, handle_sanick},
+    ops::parsers::{host_parser, nickname_parser, trailing_parser},
+    server_state::ServerState,
+    types::{ClientId, Host, Nickname},
     user_state::{UserState, UserStatus},
 };
 pub enum IrcMiscellaneousMessages {
-    KILL,
+    KILL(Nickname, Option<String>),
+    SANICK(Nickname, Nickname),
     PING(Vec<Host>),
     PONG,
     ERROR,
 }
 impl IrcMiscellaneousMessages {
     pub fn irc_command_parser(input: &str) -> IResult<&str, Self> {
-        let mut parser = alt((valid_ping_parser,));
+        let mut parser = alt((
+            valid_kill_parser,
+            valid_sanick_parser,
+            valid_ping_parser,
+            valid_pong_parser,
+        ));
         parser.parse(input)
     }
 
     pub async fn handle_command(
         command: &str,
         _client_id: ClientId,
+        server_state: &ServerState,
         user_state: &UserState,
     ) -> Result<UserStatus, InternalIrcError> {
         match IrcMiscellaneousMessages::irc_command_parser(command) {
             Ok((_rem, valid_commmand)) => match valid_commmand {
+                IrcMiscellaneousMessages::KILL(target, comment) => {
+                    handle_kill(target, comment, server_state, user_state).await
+                }
+                IrcMiscellaneousMessages::SANICK(target, new_nick) => {
+                    handle_sanick(target, new_nick, server_state, user_state).await
+                }
                 IrcMiscellaneousMessages::PING(server) => handle_ping(server, user_state).await,
+                // The reader already counted the line as activity
+                IrcMiscellaneousMessages::PONG => Ok(UserStatus::Active),
                 _ => todo!(),
             },
             Err(_e) => Err(InternalIrcError::InvalidCommand),
@@ -45,3 +62,30 @@ pub fn valid_ping_parser(input: &str) -> IResult<&str, IrcMiscellaneousMessages>
         preceded(tag_no_case("PING"), many1(preceded(tag(" "), host_parser))).parse(input)?;
     Ok((rem, IrcMiscellaneousMessages::PING(servers)))
 }
+
+pub fn valid_pong_parser(input: &str) -> IResult<&str, IrcMiscellaneousMessages> {
+    let (rem, _) = preceded(tag_no_case("PONG"), opt(preceded(tag(" "), rest))).parse(input)?;
+    Ok((rem, IrcMiscellaneousMessages::PONG))
+}
+
+pub fn valid_kill_parser(input: &str) -> IResult<&str, IrcMiscellaneousMessages> {
+    let (rem, (target, comment)) = preceded(
+        tag_no_case("KILL "),
+        (
+            nickname_parser,
+            opt(preceded((tag(" "), opt(tag(":"))), trailing_parser)),
+        ),
+    )
+    .parse(input)?;
+    let comment = comment.filter(|c| !c.is_empty()).map(str::to_owned);
+    Ok((rem, IrcMiscellaneousMessages::KILL(target, comment)))
+}
+
+pub fn valid_sanick_parser(input: &str) -> IResult<&str, IrcMiscellaneousMessages> {
+    let (rem, (target, new_nick)) = preceded(
+        tag_no_case("SANICK "),
+        (nickname_parser, preceded(tag(" "), nickname_parser)),
+    )
+    .parse(input)?;
+    Ok((rem, IrcMiscellaneousMessages::SANICK(target, new_nick)))
+}
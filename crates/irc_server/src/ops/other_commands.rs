@@ -1,19 +1,557 @@
+use nom::{
+    IResult, Parser,
+    branch::alt,
+    bytes::complete::{tag, tag_no_case},
+    character::complete::digit1,
+    combinator::{eof, map, map_opt, map_res, opt, value, verify},
+    sequence::{pair, preceded, terminated},
+};
+
+use crate::{
+    errors::InternalIrcError,
+    handlers::{
+        accounts::{handle_identify, handle_register, handle_register_channel},
+        chathistory::handle_chathistory,
+        optional_features::{
+            handle_away, handle_die, handle_globops, handle_jupe, handle_rehash, handle_summon,
+            handle_unjupe, handle_users,
+        },
+        redaction::handle_redact,
+        user_queries::{handle_who, handle_whois},
+    },
+    ops::parsers::{
+        channel_parser, channel_target_parser, middle_parser, nickname_parser, trailing_parser,
+    },
+    server_state::ServerState,
+    types::{ChannelName, ClientId, Nickname},
+    user_state::{UserState, UserStatus},
+    utils::parse_server_time,
+};
+
 pub enum IrcServiceQueryCommands {
     SERVLIST,
     SQUERY,
-    WHO,
-    WHOIS,
+    WHO(Option<String>, bool),
+    // [ <target> ] <mask>
+    WHOIS(Option<String>, Nickname),
     WHOWAS,
 }
+impl IrcServiceQueryCommands {
+    pub fn irc_command_parser(input: &str) -> IResult<&str, Self> {
+        // WHOIS first, "WHO" is a prefix of it
+        let mut parser = alt((valid_whois_parser, valid_who_parser));
+        parser.parse(input)
+    }
+
+    pub async fn handle_command(
+        command: &str,
+        _client_id: ClientId,
+        server_state: &ServerState,
+        user_state: &UserState,
+    ) -> Result<UserStatus, InternalIrcError> {
+        match IrcServiceQueryCommands::irc_command_parser(command) {
+            Ok((_rem, valid_commmand)) => match valid_commmand {
+                IrcServiceQueryCommands::WHO(mask, operators_only) => {
+                    handle_who(mask, operators_only, server_state, user_state).await
+                }
+                IrcServiceQueryCommands::WHOIS(server, target) => {
+                    handle_whois(server, target, server_state, user_state).await
+                }
+                _ => todo!(),
+            },
+            Err(_e) => Err(InternalIrcError::InvalidCommand),
+        }
+    }
+}
+
+// 3.6.1 Who query
+
+//       Command: WHO
+//    Parameters: [ <mask> [ "o" ] ]
+
+//    The WHO command is used by a client to generate a query which returns
+//    a list of information which 'matches' the <mask> parameter given by
+//    the client.  In the absence of the <mask> parameter, all visible
+//    (users who aren't invisible (user mode +i) and who don't have a
+//    common channel with the requesting client) are listed.  The same
+//    result can be achieved by using a <mask> of "0" or any wildcard which
+//    will end up matching every visible user.
+
+//    If the "o" parameter is passed only operators are returned according
+//    to the <mask> supplied.
+fn valid_who_parser(input: &str) -> IResult<&str, IrcServiceQueryCommands> {
+    let (rem, params) = preceded(
+        tag_no_case("WHO"),
+        alt((
+            map(eof, |_| None),
+            map(
+                preceded(
+                    tag(" "),
+                    pair(middle_parser, opt(preceded(tag(" "), tag_no_case("o")))),
+                ),
+                Some,
+            ),
+        )),
+    )
+    .parse(input)?;
+    let command = match params {
+        Some((mask, operators_only)) => {
+            IrcServiceQueryCommands::WHO(Some(mask.to_owned()), operators_only.is_some())
+        }
+        None => IrcServiceQueryCommands::WHO(None, false),
+    };
+    Ok((rem, command))
+}
+
+// 3.6.2 Whois query
+
+//       Command: WHOIS
+//    Parameters: [ <target> ] <mask> *( "," <mask> )
+
+//    This command is used to query information about particular user.
+//    The server will answer this command with several numeric messages
+//    indicating different statuses of each user which matches the mask (if
+//    you are entitled to see them).
+fn valid_whois_parser(input: &str) -> IResult<&str, IrcServiceQueryCommands> {
+    let (rem, (target, nick)) = preceded(
+        tag_no_case("WHOIS "),
+        pair(opt(terminated(middle_parser, tag(" "))), nickname_parser),
+    )
+    .parse(input)?;
+    Ok((
+        rem,
+        IrcServiceQueryCommands::WHOIS(target.map(str::to_owned), nick),
+    ))
+}
 
 pub enum IrcOptionalFeatures {
-    AWAY,
-    REHASH,
+    // None (or an empty text) removes the AWAY message
+    AWAY(Option<String>),
+    // REHASH MOTD only re-reads the MOTD file
+    REHASH {
+        motd_only: bool,
+    },
     DIE,
     RESTART,
-    SUMMON,
+    // None when the <user> parameter is missing
+    SUMMON(Option<String>),
     USERS,
     WALLOPS,
+    GLOBOPS(String),
     USERHOST,
     ISON,
+    // None when the subcommand or its parameters are invalid
+    CHATHISTORY(Option<(String, ChatHistoryQuery, usize)>),
+    // <target> <msgid> [ <reason> ], None when parameters are missing
+    REDACT(Option<(String, String, Option<String>)>),
+    // JUPE <channel> [ <reason> ]
+    JUPE(ChannelName, Option<String>),
+    UNJUPE(ChannelName),
+    // REGISTER <password>, None when the password is missing
+    REGISTER(Option<String>),
+    // REGISTER <channel>
+    #[allow(non_camel_case_types)]
+    REGISTER_CHANNEL(ChannelName),
+    // IDENTIFY [ <account> ] <password>, None when the password is missing
+    IDENTIFY(Option<(Option<String>, String)>),
+}
+impl IrcOptionalFeatures {
+    pub fn irc_command_parser(input: &str) -> IResult<&str, Self> {
+        let mut parser = alt((
+            valid_globops_parser,
+            valid_chathistory_parser,
+            valid_redact_parser,
+            valid_summon_parser,
+            valid_users_parser,
+            valid_rehash_parser,
+            valid_die_parser,
+            valid_away_parser,
+            valid_jupe_parser,
+            valid_unjupe_parser,
+            valid_register_channel_parser,
+            valid_register_parser,
+            valid_identify_parser,
+        ));
+        parser.parse(input)
+    }
+
+    pub async fn handle_command(
+        command: &str,
+        client_id: ClientId,
+        server_state: &ServerState,
+        user_state: &UserState,
+    ) -> Result<UserStatus, InternalIrcError> {
+        match IrcOptionalFeatures::irc_command_parser(command) {
+            Ok((_rem, valid_commmand)) => match valid_commmand {
+                IrcOptionalFeatures::GLOBOPS(text) => {
+                    handle_globops(text, server_state, user_state).await
+                }
+                IrcOptionalFeatures::SUMMON(user) => {
+                    handle_summon(user, server_state, user_state).await
+                }
+                IrcOptionalFeatures::USERS => handle_users(server_state, user_state).await,
+                IrcOptionalFeatures::REHASH { motd_only } => {
+                    handle_rehash(motd_only, server_state, user_state).await
+                }
+                IrcOptionalFeatures::REGISTER(password) => {
+                    handle_register(password, client_id, server_state, user_state).await
+                }
+                IrcOptionalFeatures::REGISTER_CHANNEL(channel) => {
+                    handle_register_channel(channel, client_id, server_state, user_state).await
+                }
+                IrcOptionalFeatures::IDENTIFY(credentials) => {
+                    handle_identify(credentials, client_id, server_state, user_state).await
+                }
+                IrcOptionalFeatures::DIE => handle_die(server_state, user_state).await,
+                IrcOptionalFeatures::AWAY(text) => {
+                    handle_away(text, server_state, user_state).await
+                }
+                IrcOptionalFeatures::CHATHISTORY(query) => {
+                    handle_chathistory(query, client_id, server_state, user_state).await
+                }
+                IrcOptionalFeatures::REDACT(params) => {
+                    handle_redact(params, client_id, server_state, user_state).await
+                }
+                IrcOptionalFeatures::JUPE(channel, reason) => {
+                    handle_jupe(channel, reason, server_state, user_state).await
+                }
+                IrcOptionalFeatures::UNJUPE(channel) => {
+                    handle_unjupe(channel, server_state, user_state).await
+                }
+                _ => todo!(),
+            },
+            Err(_e) => Err(InternalIrcError::InvalidCommand),
+        }
+    }
+}
+
+// GLOBOPS (non-RFC, common ircd extension)
+
+//       Command: GLOBOPS
+//    Parameters: <text to be sent>
+
+//    Sends a server NOTICE to every user with the operator ('o') mode.
+//    Unlike WALLOPS, which reaches users with the 'w' mode, GLOBOPS is
+//    an operator-only channel of communication between operators.
+fn valid_globops_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, text) = preceded(
+        tag_no_case("GLOBOPS "),
+        preceded(
+            opt(tag(":")),
+            verify(trailing_parser, |s: &str| !s.is_empty()),
+        ),
+    )
+    .parse(input)?;
+    Ok((rem, IrcOptionalFeatures::GLOBOPS(text.to_owned())))
+}
+
+// 4.1 Away
+
+//       Command: AWAY
+//    Parameters: [ <text> ]
+
+//    With the AWAY command, clients can set an automatic reply string for
+//    any PRIVMSG commands directed at them (not to a channel they are on).
+//    The server sends an automatic reply to the client sending the PRIVMSG
+//    command.  The only replying server is the one to which the sending
+//    client is connected to.
+
+//    The AWAY command is used either with one parameter, to set an AWAY
+//    message, or with no parameters, to remove the AWAY message.
+fn valid_away_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, (_away, text)) = (
+        tag_no_case("AWAY"),
+        opt(preceded(
+            tag(" "),
+            alt((preceded(tag(":"), trailing_parser), trailing_parser)),
+        )),
+    )
+        .parse(input)?;
+    let text = text.filter(|text| !text.is_empty()).map(str::to_owned);
+    Ok((rem, IrcOptionalFeatures::AWAY(text)))
+}
+
+// 4.2 Rehash message
+
+//       Command: REHASH
+//    Parameters: None
+
+//    The rehash command is an administrative command which can be used by
+//    an operator to force the server to re-read and process its
+//    configuration file.
+//
+//    REHASH MOTD is the common extension re-reading only the MOTD.
+fn valid_rehash_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, (_rehash, motd)) =
+        terminated((tag_no_case("REHASH"), opt(tag_no_case(" MOTD"))), eof).parse(input)?;
+    Ok((
+        rem,
+        IrcOptionalFeatures::REHASH {
+            motd_only: motd.is_some(),
+        },
+    ))
+}
+
+// 4.3 Die message
+
+//       Command: DIE
+//    Parameters: None
+
+//    An operator can use the DIE command to shutdown the server.
+fn valid_die_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, _die) = terminated(tag_no_case("DIE"), eof).parse(input)?;
+    Ok((rem, IrcOptionalFeatures::DIE))
+}
+
+// 4.5 Summon message
+
+//       Command: SUMMON
+//    Parameters: <user> [ <target> [ <channel> ] ]
+
+//    The SUMMON command can be used to give users who are on a host
+//    running an IRC server a message asking them to please join IRC.
+//    Here <user> is matched against the username of connected clients.
+fn valid_summon_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    // Single server: <target> and <channel> don't change the reply
+    let (rem, (_summon, user, _rest)) = (
+        tag_no_case("SUMMON"),
+        opt(preceded(tag(" "), middle_parser)),
+        opt(preceded(tag(" "), trailing_parser)),
+    )
+        .parse(input)?;
+    Ok((rem, IrcOptionalFeatures::SUMMON(user.map(str::to_owned))))
+}
+
+// REGISTER / IDENTIFY (non-RFC, a minimal NickServ)
+
+//       Command: REGISTER
+//    Parameters: <password> / <channel>
+
+//       Command: IDENTIFY
+//    Parameters: [ <account> ] <password>
+
+//    REGISTER creates an account named after the current nick, IDENTIFY
+//    logs in to one, by default the one named after the current nick.
+//    REGISTER <channel> makes the caller's account the owner of a channel
+//    it is an operator of, so a password can't start with a channel prefix.
+fn valid_register_channel_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, channel) =
+        terminated(preceded(tag_no_case("REGISTER "), channel_parser), eof).parse(input)?;
+    Ok((rem, IrcOptionalFeatures::REGISTER_CHANNEL(channel)))
+}
+
+fn valid_register_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, password) = terminated(
+        preceded(
+            tag_no_case("REGISTER"),
+            opt(preceded(tag(" "), middle_parser)),
+        ),
+        eof,
+    )
+    .parse(input)?;
+    Ok((
+        rem,
+        IrcOptionalFeatures::REGISTER(password.map(str::to_owned)),
+    ))
+}
+
+fn valid_identify_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, params) = terminated(
+        preceded(
+            tag_no_case("IDENTIFY"),
+            opt(preceded(
+                tag(" "),
+                pair(middle_parser, opt(preceded(tag(" "), middle_parser))),
+            )),
+        ),
+        eof,
+    )
+    .parse(input)?;
+    let credentials = params.map(|(first, second)| match second {
+        Some(password) => (Some(first.to_owned()), password.to_owned()),
+        None => (None, first.to_owned()),
+    });
+    Ok((rem, IrcOptionalFeatures::IDENTIFY(credentials)))
+}
+
+// 4.6 Users
+
+//       Command: USERS
+//    Parameters: [ <target> ]
+
+//    The USERS command returns a list of users logged into the server in
+//    a format similar to the UNIX commands who(1), rusers(1) and finger(1).
+fn valid_users_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, _) = (
+        tag_no_case("USERS"),
+        opt(preceded(tag(" "), trailing_parser)),
+    )
+        .parse(input)?;
+    Ok((rem, IrcOptionalFeatures::USERS))
+}
+
+// REDACT (IRCv3 draft/message-redaction)
+
+//       Command: REDACT
+//    Parameters: <target> <msgid> [ <reason> ]
+
+//    Asks for a previously sent message, named by its msgid tag, to be
+//    removed. Clients that negotiated draft/message-redaction are relayed
+//    the REDACT, the others get a NOTICE.
+fn valid_redact_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, params) = preceded(
+        tag_no_case("REDACT"),
+        alt((
+            map(
+                (
+                    preceded(tag(" "), middle_parser),
+                    preceded(tag(" "), middle_parser),
+                    opt(preceded(tag(" "), preceded(opt(tag(":")), trailing_parser))),
+                    eof,
+                ),
+                |(target, msgid, reason, _)| {
+                    let reason = reason.filter(|r| !r.is_empty()).map(str::to_owned);
+                    Some((target.to_owned(), msgid.to_owned(), reason))
+                },
+            ),
+            map(opt(preceded(tag(" "), trailing_parser)), |_| None),
+        )),
+    )
+    .parse(input)?;
+    Ok((rem, IrcOptionalFeatures::REDACT(params)))
+}
+
+// JUPE / UNJUPE (non-RFC, common ircd extension)
+
+//       Command: JUPE
+//    Parameters: <channel> [ <reason> ]
+//       Command: UNJUPE
+//    Parameters: <channel>
+
+//    Operators reserve a channel name: its members are removed and any
+//    JOIN is refused with ERR_UNAVAILRESOURCE until it is unjuped.
+fn valid_jupe_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, (channel, reason)) = preceded(
+        tag_no_case("JUPE "),
+        (
+            channel_target_parser,
+            opt(preceded((tag(" "), opt(tag(":"))), trailing_parser)),
+        ),
+    )
+    .parse(input)?;
+    let reason = reason.filter(|r| !r.is_empty()).map(str::to_owned);
+    Ok((rem, IrcOptionalFeatures::JUPE(channel, reason)))
+}
+
+fn valid_unjupe_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, channel) =
+        terminated(preceded(tag_no_case("UNJUPE "), channel_target_parser), eof).parse(input)?;
+    Ok((rem, IrcOptionalFeatures::UNJUPE(channel)))
+}
+
+// CHATHISTORY (IRCv3 draft/chathistory)
+
+//       Command: CHATHISTORY
+//    Parameters: LATEST <target> <* | timestamp=x> <limit>
+//                BEFORE <target> <timestamp=x> <limit>
+//                AFTER <target> <timestamp=x> <limit>
+//                BETWEEN <target> <timestamp=x> <timestamp=y> <limit>
+
+//    Replays buffered channel messages inside a "chathistory" batch, each
+//    line carrying its server-time. Only timestamp references are supported,
+//    timestamps are milliseconds since the epoch once parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatHistoryQuery {
+    Latest(Option<u64>),
+    Before(u64),
+    After(u64),
+    Between(u64, u64),
+}
+
+fn timestamp_reference_parser(input: &str) -> IResult<&str, u64> {
+    map_opt(
+        preceded(tag("timestamp="), middle_parser),
+        parse_server_time,
+    )
+    .parse(input)
+}
+
+fn chathistory_query_parser(input: &str) -> IResult<&str, (String, ChatHistoryQuery, usize)> {
+    let target = terminated(middle_parser, tag(" "));
+    let limit = || preceded(tag(" "), map_res(digit1, str::parse::<usize>));
+    let (rem, (target, query, limit)) = alt((
+        (
+            preceded(tag_no_case("LATEST "), target),
+            map(
+                alt((value(None, tag("*")), map(timestamp_reference_parser, Some))),
+                ChatHistoryQuery::Latest,
+            ),
+            limit(),
+        ),
+        (
+            preceded(tag_no_case("BEFORE "), terminated(middle_parser, tag(" "))),
+            map(timestamp_reference_parser, ChatHistoryQuery::Before),
+            limit(),
+        ),
+        (
+            preceded(tag_no_case("AFTER "), terminated(middle_parser, tag(" "))),
+            map(timestamp_reference_parser, ChatHistoryQuery::After),
+            limit(),
+        ),
+        (
+            preceded(tag_no_case("BETWEEN "), terminated(middle_parser, tag(" "))),
+            map(
+                (
+                    timestamp_reference_parser,
+                    preceded(tag(" "), timestamp_reference_parser),
+                ),
+                |(start, end)| ChatHistoryQuery::Between(start, end),
+            ),
+            limit(),
+        ),
+    ))
+    .parse(input)?;
+    Ok((rem, (target.to_owned(), query, limit)))
+}
+
+fn valid_chathistory_parser(input: &str) -> IResult<&str, IrcOptionalFeatures> {
+    let (rem, query) = preceded(
+        tag_no_case("CHATHISTORY"),
+        opt(preceded(tag(" "), chathistory_query_parser)),
+    )
+    .parse(input)?;
+    Ok((rem, IrcOptionalFeatures::CHATHISTORY(query)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chathistory_query_parser() {
+        let parse = |input| match valid_chathistory_parser(input) {
+            Ok((_, IrcOptionalFeatures::CHATHISTORY(query))) => query,
+            _ => panic!("CHATHISTORY not recognized: {input}"),
+        };
+        assert_eq!(
+            parse("CHATHISTORY LATEST #chan * 50"),
+            Some(("#chan".to_owned(), ChatHistoryQuery::Latest(None), 50))
+        );
+        assert_eq!(
+            parse("CHATHISTORY BEFORE #chan timestamp=2019-01-04T14:33:26.123Z 10"),
+            Some((
+                "#chan".to_owned(),
+                ChatHistoryQuery::Before(1_546_612_406_123),
+                10
+            ))
+        );
+        assert_eq!(
+            parse(
+                "CHATHISTORY BETWEEN #chan timestamp=1970-01-01T00:00:01.000Z timestamp=1970-01-01T00:00:02.000Z 5"
+            ),
+            Some(("#chan".to_owned(), ChatHistoryQuery::Between(1000, 2000), 5))
+        );
+        assert_eq!(parse("CHATHISTORY LATEST #chan msgid=abc 50"), None);
+        assert_eq!(parse("CHATHISTORY AROUND #chan * 50"), None);
+    }
 }
@@ -5,7 +5,7 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take_while, take_while_m_n, take_while1},
     character::complete::{char, satisfy},
-    combinator::{map_res, opt, recognize, verify},
+    combinator::{all_consuming, map_res, opt, recognize, verify},
     error::{Error, ErrorKind},
     multi::{count, many0, many1, separated_list1},
     sequence::{pair, preceded},
@@ -121,6 +121,7 @@ pub fn wildcards_parser(input: &str) -> IResult<&str, &str> {
 //   special    =  %x5B-60 / %x7B-7D
 //                    ; "[", "]", "\", "`", "_", "^", "{", "|", "}"
 
+#[allow(dead_code)]
 fn hexdigit(input: &str) -> IResult<&str, &str> {
     take_while1(|c: char| c.is_ascii_hexdigit())(input)
 }
@@ -128,16 +129,50 @@ fn hexdigit(input: &str) -> IResult<&str, &str> {
 // 00.  target     =  nickname / server
 pub fn target_parser(input: &str) -> IResult<&str, Target> {
     let mut parser = alt((
-        nickname_parser.map(|n| Target::Nickname(n)),
-        servername_parser.map(|s| Target::ServerName(s)),
+        nickname_parser.map(Target::Nickname),
+        servername_parser.map(Target::ServerName),
     ));
     parser.parse(input)
 }
 
+/// Most items kept from one comma list (targets, channels, keys, users),
+/// above any configurable limit such as `limits.max_targets`.
+pub const MAX_LIST_ITEMS: usize = 64;
+/// The ABNF allows 15 parameters, 14 middles and a trailing.
+pub const MAX_PARAMS: usize = 15;
+
+/// `separated_list1` on ",", except that the items past `MAX_LIST_ITEMS`
+/// are skipped, not parsed: a crafted line with thousands of targets can't
+/// make the server allocate for each of them.
+pub fn bounded_list1<'a, O, F>(mut item: F) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<O>>
+where
+    F: Parser<&'a str, Output = O, Error = Error<&'a str>>,
+{
+    move |input: &'a str| {
+        let (mut rem, first) = item.parse(input)?;
+        let mut items = vec![first];
+        while let Some(rest) = rem.strip_prefix(',') {
+            if items.len() == MAX_LIST_ITEMS {
+                rem = &rest[rest.find(' ').unwrap_or(rest.len())..];
+                break;
+            }
+            match item.parse(rest) {
+                Ok((next_rem, next)) => {
+                    items.push(next);
+                    rem = next_rem;
+                }
+                // Like separated_list1, the "," stays for the next parser
+                Err(nom::Err::Error(_)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((rem, items))
+    }
+}
+
 // 01.  msgtarget  =  msgto *( "," msgto )
-// TODO à revoir
 pub fn msgtarget_parser(input: &str) -> IResult<&str, Vec<MessageTo>> {
-    let mut parser = separated_list1(tag(", "), msgto_parser);
+    let mut parser = bounded_list1(msgto_parser);
     parser.parse(input)
 }
 
@@ -197,28 +232,52 @@ pub fn msgto_nick_user_host_parser(input: &str) -> IResult<&str, (Nickname, User
 
 pub fn msgto_parser(input: &str) -> IResult<&str, MessageTo> {
     let mut parser = alt((
-        channel_parser.map(|c| MessageTo::ChannelName(c)),
-        msgto_user_host_server_parser.map(|uhs| MessageTo::UserHostServer(uhs)),
-        msgto_user_host_parser.map(|uh| MessageTo::UserHost(uh)),
-        targetmask_parser.map(|tm| MessageTo::TargetMask(tm)),
-        msgto_nick_user_host_parser.map(|nuh| MessageTo::NickUserHost(nuh)),
-        nickname_parser.map(|n| MessageTo::Nickname(n)),
+        channel_parser.map(MessageTo::ChannelName),
+        // Before user@server, whose user part would swallow "nick!user"
+        msgto_nick_user_host_parser.map(MessageTo::NickUserHost),
+        msgto_user_host_server_parser.map(MessageTo::UserHostServer),
+        msgto_user_host_parser.map(MessageTo::UserHost),
+        targetmask_parser.map(MessageTo::TargetMask),
+        nickname_parser.map(MessageTo::Nickname),
     ));
     parser.parse(input)
 }
 
 // 03.  channel    =  ( "#" / "+" / ( "!" channelid ) / "&" ) chanstring
 //                 [ ":" chanstring ]
+// RFC 2811 3.2: safe channels are also named "!!" <short name> to create
+// one and "!" <short name> to join it without knowing its channelid.
 fn channel_prefix_parser(input: &str) -> IResult<&str, &str> {
     let mut parser = alt((
         tag("#"),
         tag("+"),
         recognize(pair(tag("!"), channelid_parser)),
+        tag("!!"),
+        tag("!"),
         tag("&"),
     ));
     parser.parse(input)
 }
 
+// Channels names are strings of length up to fifty (50) characters
+pub const CHANNEL_NAME_MAX_LENGTH: usize = 50;
+
+/// Whether `name` is a whole, well-formed channel name. Channel commands
+/// parse their targets loosely and answer ERR_BADCHANMASK when this fails.
+pub fn validate_channel_name(name: &str) -> bool {
+    name.len() <= CHANNEL_NAME_MAX_LENGTH && all_consuming(channel_parser).parse(name).is_ok()
+}
+
+// A channel parameter as sent, up to the next space or comma
+pub fn channel_target_parser(input: &str) -> IResult<&str, ChannelName> {
+    let (rem, target) = verify(
+        take_while1(|c: char| c != ' ' && c != ','),
+        |target: &str| !target.starts_with(':'),
+    )
+    .parse(input)?;
+    Ok((rem, ChannelName(target.to_owned())))
+}
+
 // channel = ( "#" / "+" / ( "!" channelid ) / "&" ) chanstring [ ":" chanstring ]
 pub fn channel_parser(input: &str) -> IResult<&str, ChannelName> {
     let mut parser = recognize((
@@ -239,8 +298,8 @@ pub fn servername_parser(input: &str) -> IResult<&str, Hostname> {
 // host = hostname / hostaddr
 pub fn host_parser(input: &str) -> IResult<&str, Host> {
     let mut parser = alt((
-        hostname_parser.map(|h| Host::Hostname(h)),
-        hostaddr_parser.map(|ip| Host::IpAddr(ip)),
+        hostname_parser.map(Host::Hostname),
+        hostaddr_parser.map(Host::IpAddr),
     ));
     parser.parse(input)
 }
@@ -281,8 +340,8 @@ pub fn shortname_parser(input: &str) -> IResult<&str, &str> {
 // hostaddr = ip4addr / ip6addr
 pub fn hostaddr_parser(input: &str) -> IResult<&str, IpAddr> {
     let mut parser = alt((
-        ip4addr_parser.map(|ip| IpAddr::from(ip)),
-        ip6addr_parser.map(|ip| IpAddr::from(ip)),
+        ip4addr_parser.map(IpAddr::from),
+        ip6addr_parser.map(IpAddr::from),
     ));
     parser.parse(input)
 }
@@ -368,6 +427,10 @@ fn is_nickname_first_char(c: char) -> bool {
     c.is_ascii_alphabetic() || "-[]\\`^{}".contains(c)
 }
 
+// Longest nick the parser accepts at all, the enforced limit is the
+// configurable `limits.max_nick_length` (NICKLEN)
+pub const NICKNAME_MAX_LENGTH: usize = 32;
+
 pub fn nickname_parser(input: &str) -> IResult<&str, Nickname> {
     // First char: letter OR special
     let first = satisfy(is_nickname_first_char);
@@ -378,14 +441,15 @@ pub fn nickname_parser(input: &str) -> IResult<&str, Nickname> {
 
     let parser = recognize(pair(first, tail));
 
-    // Enforce max length = 9
-    let (rem, nick) = verify(parser, |s: &str| s.len() <= 9).parse(input)?; // first char control ensure that no empty string can be valid
+    // Enforce the ceiling, registration checks the configured NICKLEN
+    let (rem, nick) = verify(parser, |s: &str| s.len() <= NICKNAME_MAX_LENGTH).parse(input)?; // first char control ensure that no empty string can be valid
     let nickname = Nickname(nick.to_string());
     Ok((rem, nickname))
 }
 
 // 12.  targetmask =  ( "$" / "#" ) mask
 //                   ; see details on allowed masks in section 3.3.1
+#[allow(dead_code)]
 fn mask_parser(input: &str) -> IResult<&str, &str> {
     // Placeholder — ask me if you need full mask rules!
     take_while1(|c: char| c != ' ' && c != ',')(input)
@@ -658,6 +722,15 @@ mod tests {
         let (_rem, res) = shortname_parser(input).unwrap();
         assert_eq!(res, "testuser".to_owned());
     }
+
+    #[test]
+    fn test_msgtarget_list_is_bounded() {
+        let targets = vec!["nick"; 10_000].join(",");
+        let input = format!("{targets} :hi");
+        let (rem, res) = msgtarget_parser(&input).unwrap();
+        assert_eq!(res.len(), MAX_LIST_ITEMS);
+        assert_eq!(rem, " :hi");
+    }
 }
 
 // #[cfg(test)]
@@ -1,8 +1,9 @@
 use nom::{
     IResult, Parser,
     branch::alt,
-    bytes::complete::{tag_no_case, take_till},
-    combinator::recognize,
+    bytes::complete::{tag, tag_no_case, take_till},
+    combinator::{opt, recognize},
+    sequence::preceded,
 };
 
 use crate::{
@@ -50,7 +51,7 @@ pub enum IrcCapPreRegistration {
 
 impl IrcCapPreRegistration {
     pub fn irc_cap_parser(input: &str) -> IResult<&str, Self> {
-        let mut parser = alt((valid_cap_ls, valid_cap_list, valid_cap_end));
+        let mut parser = alt((valid_cap_ls, valid_cap_list, valid_cap_req, valid_cap_end));
         parser.parse(input)
     }
 
@@ -68,7 +69,10 @@ impl IrcCapPreRegistration {
                 IrcCapPreRegistration::LIST => {
                     handle_cap_list_response(client_id, server_state, user_state).await
                 }
-                IrcCapPreRegistration::END => handle_cap_end_response(),
+                IrcCapPreRegistration::REQ(capabilities) => {
+                    handle_cap_req_response(capabilities, server_state, user_state).await
+                }
+                IrcCapPreRegistration::END => handle_cap_end_response(user_state).await,
                 _ => todo!(),
             },
             Err(_e) => Err(InternalIrcError::InvalidCommand),
@@ -109,6 +113,18 @@ fn valid_cap_list(input: &str) -> IResult<&str, IrcCapPreRegistration> {
 // Example:
 // CAP REQ :sasl echo-message
 
+fn valid_cap_req(input: &str) -> IResult<&str, IrcCapPreRegistration> {
+    let (rem, capabilities) = preceded(
+        tag_no_case("CAP REQ "),
+        preceded(opt(tag(":")), take_till(|c| c == '\r' || c == '\n')),
+    )
+    .parse(input)?;
+    Ok((
+        rem,
+        IrcCapPreRegistration::REQ(capabilities.trim().to_owned()),
+    ))
+}
+
 // 3.4 CAP ACK <capabilities>
 // Server → client.
 // Server accepted the request.
@@ -3,7 +3,7 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, tag_no_case, take_till, take_while1},
     character::complete::{char, space1},
-    combinator::{opt, recognize, verify},
+    combinator::{opt, verify},
     multi::many1,
     sequence::{pair, preceded},
 };
@@ -11,8 +11,8 @@ use nom::{
 use crate::{
     errors::InternalIrcError,
     handlers::registration::{
-        handle_mode_registration, handle_nick_registration, handle_quit_registration,
-        handle_user_registration,
+        handle_mode_registration, handle_nick_registration, handle_oper_registration,
+        handle_pass_registration, handle_quit_registration, handle_user_registration,
     },
     ops::parsers::{
         host_parser, hostname_parser, nickname_parser, servername_parser, trailing_parser,
@@ -62,6 +62,9 @@ impl IrcConnectionRegistration {
     ) -> Result<UserStatus, InternalIrcError> {
         match IrcConnectionRegistration::irc_command_parser(command) {
             Ok((_rem, valid_commmand)) => match valid_commmand {
+                IrcConnectionRegistration::PASS(password) => {
+                    handle_pass_registration(password, user_state).await
+                }
                 IrcConnectionRegistration::NICK(nick) => {
                     handle_nick_registration(nick, client_id, user_state, server_state).await
                 }
@@ -87,6 +90,9 @@ impl IrcConnectionRegistration {
                     )
                     .await
                 }
+                IrcConnectionRegistration::OPER(name, password) => {
+                    handle_oper_registration(name, password, server_state, user_state).await
+                }
                 IrcConnectionRegistration::MODE(nick, modes) => {
                     handle_mode_registration(nick, modes, user_state).await
                 }
@@ -202,12 +208,14 @@ fn valid_user_message_rfc1459_parser(input: &str) -> IResult<&str, IrcConnection
 
 fn user_mode_parser(input: &str) -> IResult<&str, u8> {
     // take digits
-    let (rem, digits) = recognize(take_while1(|c: char| c.is_ascii_digit())).parse(input)?;
+    let (rem, digits) = take_while1(|c: char| c.is_ascii_digit()).parse(input)?;
 
-    // convert to integer
-    let mode = digits.parse().map_err(|_| {
-        nom::Err::Failure(nom::error::Error::new(digits, nom::error::ErrorKind::Digit))
-    })?;
+    // Only bits 2 and 3 are meaningful, so keep the low byte of the value
+    // (wrapping arithmetic is the value modulo 256) rather than rejecting
+    // anything above u8::MAX and losing the bits the client did set.
+    let mode = digits.bytes().fold(0_u8, |acc, digit| {
+        acc.wrapping_mul(10).wrapping_add(digit - b'0')
+    });
 
     Ok((rem, mode))
 }
@@ -489,6 +497,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_user_mode_parser_keeps_low_bits() {
+        assert_eq!(user_mode_parser("0"), Ok(("", 0)));
+        assert_eq!(user_mode_parser("12 *"), Ok((" *", 12)));
+        // 264 = 256 + 8: out of u8 range but bit 3 ('i') is still set
+        assert_eq!(user_mode_parser("264"), Ok(("", 8)));
+        assert_eq!(user_mode_parser("100000000000000000000012"), Ok(("", 12)));
+        let input = "USER guest 264 * :Ronnie Reagan";
+        let (_rem, user) = valid_user_message_rfc2812_parser(input).unwrap();
+        assert_eq!(
+            user,
+            IrcConnectionRegistration::USER_RFC_2812(
+                Username("guest".to_owned()),
+                8_u8,
+                Realname("Ronnie Reagan".to_owned())
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_user_mode_round_trip_sets_flags() {
+        use crate::{server_state::ServerState, test_utils::TestClient};
+        use std::collections::HashSet;
+
+        // Bit 2 is +w, bit 3 is +i, the rest is ignored
+        for (mode, expected) in [
+            ("0", HashSet::new()),
+            ("4", HashSet::from(['w'])),
+            ("8", HashSet::from(['i'])),
+            ("12", HashSet::from(['i', 'w'])),
+            ("264", HashSet::from(['i'])),
+        ] {
+            let server_state = ServerState::default();
+            let mut client = TestClient::connect(&server_state).await;
+            client.send(&server_state, "NICK guest").await.unwrap();
+            client
+                .send(&server_state, &format!("USER guest {mode} * :name"))
+                .await
+                .unwrap();
+            let caracs = client.user_state.get_caracs().await;
+            assert!(caracs.registered, "mode {mode}");
+            assert_eq!(caracs.modes, expected, "mode {mode}");
+        }
+    }
+
     #[test]
     fn test_valid_oper_message_parser() {
         // Example:
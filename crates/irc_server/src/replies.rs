@@ -1,14 +1,23 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::{
     constants::*,
-    types::{ChannelName, Nickname, Topic, Username},
+    message_models::MessageTags,
+    types::{ChannelName, Nickname, Realname, Topic, Username},
 };
 
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq)]
 pub enum IrcReply<'a> {
+    // Keepalive sent to a silent client
+    Ping,
     Pong {
         destination: &'a str,
     },
+    ServerNotice {
+        nick: &'a Nickname,
+        text: &'a str,
+    },
     // Capabilities
     CapLs {
         nick: &'a Nickname,
@@ -18,6 +27,37 @@ pub enum IrcReply<'a> {
         nick: &'a Nickname,
         capabilities: &'a str,
     },
+    CapAck {
+        nick: &'a Nickname,
+        capabilities: &'a str,
+    },
+    CapNak {
+        nick: &'a Nickname,
+        capabilities: &'a str,
+    },
+    CapNew {
+        nick: &'a Nickname,
+        capabilities: &'a str,
+    },
+    CapDel {
+        nick: &'a Nickname,
+        capabilities: &'a str,
+    },
+    // IRCv3 batches and standard replies
+    BatchStart {
+        reference: &'a str,
+        kind: &'a str,
+        params: &'a str,
+    },
+    BatchEnd {
+        reference: &'a str,
+    },
+    Fail {
+        command: &'a str,
+        code: &'a str,
+        context: &'a str,
+        description: &'a str,
+    },
     // Connection registration
     Welcome {
         nick: &'a Nickname,
@@ -26,20 +66,52 @@ pub enum IrcReply<'a> {
     },
 
     YourHost {
-        servername: &'a str,
+        nick: &'a Nickname,
         version: &'a str,
     },
     Created {
+        nick: &'a Nickname,
         date: &'a str,
     },
     MyInfo {
-        servername: &'a str,
+        nick: &'a Nickname,
         version: &'a str,
-        modes: &'a str,
+        user_modes: &'a str,
+        // All the channel modes, then those taking a parameter
+        channel_modes: &'a str,
+        param_modes: &'a str,
+    },
+    ISupport {
+        nick: &'a Nickname,
+        tokens: &'a str,
+    },
+    Version {
+        nick: &'a Nickname,
+        version: &'a str,
+        comments: &'a str,
+    },
+    ErrErroneusNickname {
+        nick: &'a Nickname,
     },
     ErrNicknameInUse {
         nick: &'a Nickname,
     },
+    ErrNickCollision {
+        nick: &'a Nickname,
+    },
+    ErrUnavailResource {
+        nick: &'a Nickname,
+        target: &'a str,
+    },
+    ErrRestricted {
+        nick: &'a Nickname,
+    },
+    // ERR_UNAVAILRESOURCE for a juped channel, its reason as the text
+    ErrJupedChannel {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+        reason: &'a str,
+    },
     // User modes
     UModeIs {
         nick: &'a Nickname,
@@ -52,7 +124,183 @@ pub enum IrcReply<'a> {
         nick: &'a Nickname,
     },
 
+    // Server queries
+    TraceUnknown {
+        nick: &'a Nickname,
+        ip: &'a str,
+    },
+    TraceOperator {
+        nick: &'a Nickname,
+        link: &'a str,
+    },
+    TraceUser {
+        nick: &'a Nickname,
+        link: &'a str,
+    },
+    TraceEnd {
+        nick: &'a Nickname,
+        version: &'a str,
+    },
+    // This server, the only one known
+    Links {
+        nick: &'a Nickname,
+        mask: &'a str,
+    },
+    EndOfLinks {
+        nick: &'a Nickname,
+        mask: &'a str,
+    },
+    MotdStart {
+        nick: &'a Nickname,
+    },
+    Motd {
+        nick: &'a Nickname,
+        line: &'a str,
+    },
+    EndOfMotd {
+        nick: &'a Nickname,
+    },
+    StatsLinkInfo {
+        nick: &'a Nickname,
+        link: &'a str,
+        sent_messages: u64,
+        sent_kbytes: u64,
+        received_messages: u64,
+        received_kbytes: u64,
+        time_open: u64,
+    },
+    StatsCommands {
+        nick: &'a Nickname,
+        command: &'a str,
+        count: u64,
+        bytes: u64,
+    },
+    EndOfStats {
+        nick: &'a Nickname,
+        query: char,
+    },
+    LuserClient {
+        nick: &'a Nickname,
+        users: usize,
+        invisible: usize,
+    },
+    LuserOp {
+        nick: &'a Nickname,
+        operators: usize,
+    },
+    LuserUnknown {
+        nick: &'a Nickname,
+        unknown: usize,
+    },
+    LuserChannels {
+        nick: &'a Nickname,
+        channels: usize,
+    },
+    LuserMe {
+        nick: &'a Nickname,
+        clients: usize,
+    },
+    LocalUsers {
+        nick: &'a Nickname,
+        current: usize,
+        max: usize,
+    },
+    GlobalUsers {
+        nick: &'a Nickname,
+        current: usize,
+        max: usize,
+    },
+    // User based queries
+    WhoReply {
+        nick: &'a Nickname,
+        channel: &'a str,
+        user: &'a Username,
+        host: &'a str,
+        target: &'a Nickname,
+        flags: &'a str,
+        real_name: &'a Realname,
+    },
+    EndOfWho {
+        nick: &'a Nickname,
+        mask: &'a str,
+    },
+    WhoisUser {
+        nick: &'a Nickname,
+        target: &'a Nickname,
+        user: &'a Username,
+        host: &'a str,
+        real_name: &'a Realname,
+    },
+    WhoisServer {
+        nick: &'a Nickname,
+        target: &'a Nickname,
+    },
+    WhoisActually {
+        nick: &'a Nickname,
+        target: &'a Nickname,
+        host: &'a str,
+    },
+    WhoisIdle {
+        nick: &'a Nickname,
+        target: &'a Nickname,
+        idle: u64,
+        signon: u64,
+    },
+    WhoisChannels {
+        nick: &'a Nickname,
+        target: &'a Nickname,
+        channels: &'a str,
+    },
+    EndOfWhois {
+        nick: &'a Nickname,
+        target: &'a Nickname,
+    },
+
     // Channel operations
+    InviteList {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+        mask: &'a str,
+    },
+    EndOfInviteList {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+    },
+    InvitedList {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+    },
+    EndOfInvitedList {
+        nick: &'a Nickname,
+    },
+    QuietList {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+        mask: &'a str,
+    },
+    LoggedIn {
+        nick: &'a Nickname,
+        hostmask: &'a str,
+        account: &'a str,
+    },
+    ServerChannelMode {
+        channel: &'a ChannelName,
+        modes: &'a str,
+    },
+    EndOfQuietList {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+    },
+    ChannelModeIs {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+        modes: &'a str,
+    },
+    CreationTime {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+        created_at: u64,
+    },
     Topic {
         nick: &'a Nickname,
         channel: &'a ChannelName,
@@ -73,35 +321,137 @@ pub enum IrcReply<'a> {
         channel: &'a ChannelName,
     },
     List {
+        nick: &'a Nickname,
         channel: &'a ChannelName,
-        visible: u32,
-        topic: &'a Topic,
+        visible: usize,
+        topic: &'a str,
+    },
+    ListEnd {
+        nick: &'a Nickname,
+    },
+
+    // Optional features
+    Away {
+        nick: &'a Nickname,
+        target: &'a Nickname,
+        message: &'a str,
+    },
+    UnAway {
+        nick: &'a Nickname,
+    },
+    NowAway {
+        nick: &'a Nickname,
+    },
+    YoureOper {
+        nick: &'a Nickname,
+    },
+    Rehashing {
+        nick: &'a Nickname,
+        config_file: &'a str,
+    },
+    Summoning {
+        nick: &'a Nickname,
+        user: &'a str,
+    },
+    UsersStart {
+        nick: &'a Nickname,
+    },
+    Users {
+        nick: &'a Nickname,
+        user: &'a Username,
+        host: &'a str,
+    },
+    EndOfUsers {
+        nick: &'a Nickname,
+    },
+    NoUsers {
+        nick: &'a Nickname,
     },
-    ListEnd,
 
     // Errors
+    ErrNoMotd {
+        nick: &'a Nickname,
+    },
+    ErrNoLogin {
+        nick: &'a Nickname,
+        user: &'a str,
+    },
+    ErrSummonDisabled {
+        nick: &'a Nickname,
+    },
+    ErrUsersDisabled {
+        nick: &'a Nickname,
+    },
     ErrNeedMoreParams {
         nick: &'a Nickname,
         command: &'a str,
     },
+    ErrAlreadyRegistred {
+        nick: &'a Nickname,
+    },
     ErrUnknownCommand {
         nick: &'a Nickname,
         command: &'a str,
     },
     ErrNoSuchNick {
         nick: &'a Nickname,
+        target: &'a str,
+    },
+    ErrNoSuchServer {
+        nick: &'a Nickname,
+        server: &'a str,
     },
     ErrNoSuchChannel {
         nick: &'a Nickname,
         channel: &'a ChannelName,
     },
+    ErrChanOPrivsNeeded {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+    },
+    ErrCannotSendToChan {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+    },
+    ErrTooManyTargets {
+        nick: &'a Nickname,
+        target: &'a str,
+    },
+    ErrInputTooLong {
+        nick: &'a Nickname,
+    },
+    ErrTooManyChannels {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+    },
     ErrNotOnChannel {
         nick: &'a Nickname,
         channel: &'a ChannelName,
     },
+    ErrUserNotInChannel {
+        nick: &'a Nickname,
+        target: &'a str,
+        channel: &'a ChannelName,
+    },
+    ErrUserOnChannel {
+        nick: &'a Nickname,
+        target: &'a Nickname,
+        channel: &'a ChannelName,
+    },
+    Inviting {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+        target: &'a Nickname,
+    },
     ErrNotRegistered {
         nick: &'a Nickname,
     },
+    ErrNoPrivileges {
+        nick: &'a Nickname,
+    },
+    ErrPasswdMismatch {
+        nick: &'a Nickname,
+    },
     ErrBannedFromChan {
         channel: &'a ChannelName,
     },
@@ -111,6 +461,10 @@ pub enum IrcReply<'a> {
     ErrBadChannelKey {
         channel: &'a ChannelName,
     },
+    ErrBadChanMask {
+        nick: &'a Nickname,
+        channel: &'a ChannelName,
+    },
     ErrChannelIsFull {
         channel: &'a ChannelName,
     },
@@ -126,9 +480,13 @@ impl<'a> IrcReply<'a> {
             .unwrap_or("unknown.server");
         match self {
             // misceallanneous
+            IrcReply::Ping => format!("PING :{server_name}"),
             IrcReply::Pong { destination } => {
                 format!(":{server_name} PONG {destination}")
             }
+            IrcReply::ServerNotice { nick, text } => {
+                format!(":{server_name} NOTICE {nick} :{text}")
+            }
             // Capabilities
             IrcReply::CapList { nick, capabilities } => {
                 format!(":{server_name} CAP {nick} LIST :{capabilities}")
@@ -136,10 +494,68 @@ impl<'a> IrcReply<'a> {
             IrcReply::CapLs { nick, capabilities } => {
                 format!(":{server_name} CAP {nick} LS :{capabilities}")
             }
+            IrcReply::CapAck { nick, capabilities } => {
+                format!(":{server_name} CAP {nick} ACK :{capabilities}")
+            }
+            IrcReply::CapNak { nick, capabilities } => {
+                format!(":{server_name} CAP {nick} NAK :{capabilities}")
+            }
+            IrcReply::CapNew { nick, capabilities } => {
+                format!(":{server_name} CAP {nick} NEW :{capabilities}")
+            }
+            IrcReply::CapDel { nick, capabilities } => {
+                format!(":{server_name} CAP {nick} DEL :{capabilities}")
+            }
+            // IRCv3 batches and standard replies
+            IrcReply::BatchStart {
+                reference,
+                kind,
+                params,
+            } => format!(":{server_name} BATCH +{reference} {kind} {params}")
+                .trim_end()
+                .to_owned(),
+            IrcReply::BatchEnd { reference } => format!(":{server_name} BATCH -{reference}"),
+            IrcReply::Fail {
+                command,
+                code,
+                context: "",
+                description,
+            } => format!(":{server_name} FAIL {command} {code} :{description}"),
+            IrcReply::Fail {
+                command,
+                code,
+                context,
+                description,
+            } => format!(":{server_name} FAIL {command} {code} {context} :{description}"),
             // registration replies & errors
             IrcReply::Welcome { nick, user, host } => format!(
                 ":{server_name} {RPL_WELCOME_NB:03} {nick} :{RPL_WELCOME_STR} {nick}!{user}@{host}"
             ),
+            IrcReply::YourHost { nick, version } => format!(
+                ":{server_name} {RPL_YOURHOST_NB:03} {nick} :{RPL_YOURHOST_STR} {server_name}, running version {version}"
+            ),
+            IrcReply::MyInfo {
+                nick,
+                version,
+                user_modes,
+                channel_modes,
+                param_modes,
+            } => format!(
+                ":{server_name} {RPL_MYINFO_NB:03} {nick} {server_name} {version} {user_modes} {channel_modes} {param_modes}"
+            ),
+            IrcReply::Created { nick, date } => {
+                format!(":{server_name} {RPL_CREATED_NB:03} {nick} :{RPL_CREATED_STR} {date}")
+            }
+            IrcReply::Version {
+                nick,
+                version,
+                comments,
+            } => format!(
+                ":{server_name} {RPL_VERSION_NB:03} {nick} {version}. {server_name} :{comments}"
+            ),
+            IrcReply::ISupport { nick, tokens } => {
+                format!(":{server_name} {RPL_ISUPPORT_NB:03} {nick} {tokens} :{RPL_ISUPPORT_STR}")
+            }
 
             IrcReply::UModeIs { nick, modes } => {
                 format!(":{server_name} {RPL_UMODEIS_NB:03} {nick} :{modes}")
@@ -153,10 +569,240 @@ impl<'a> IrcReply<'a> {
             IrcReply::ErrNotRegistered { nick } => {
                 format!(":{server_name} {ERR_NOTREGISTERED_NB:03} {nick} :{ERR_NOTREGISTERED_STR}")
             }
+            IrcReply::ErrNoMotd { nick } => {
+                format!(":{server_name} {ERR_NOMOTD_NB:03} {nick} :{ERR_NOMOTD_STR}")
+            }
+            IrcReply::ErrNoLogin { nick, user } => {
+                format!(":{server_name} {ERR_NOLOGIN_NB:03} {nick} {user} :{ERR_NOLOGIN_STR}")
+            }
+            IrcReply::ErrSummonDisabled { nick } => {
+                format!(
+                    ":{server_name} {ERR_SUMMONDISABLED_NB:03} {nick} :{ERR_SUMMONDISABLED_STR}"
+                )
+            }
+            IrcReply::ErrUsersDisabled { nick } => {
+                format!(":{server_name} {ERR_USERSDISABLED_NB:03} {nick} :{ERR_USERSDISABLED_STR}")
+            }
+            IrcReply::ErrPasswdMismatch { nick } => {
+                format!(
+                    ":{server_name} {ERR_PASSWDMISMATCH_NB:03} {nick} :{ERR_PASSWDMISMATCH_STR}"
+                )
+            }
+            IrcReply::ErrNoPrivileges { nick } => {
+                format!(":{server_name} {ERR_NOPRIVILEGES_NB:03} {nick} :{ERR_NOPRIVILEGES_STR}")
+            }
             IrcReply::ErrUnknownCommand { nick, command } => format!(
                 ":{server_name} {ERR_UNKNOWNCOMMAND_NB:03} {nick} {command} :{ERR_UNKNOWNCOMMAND_STR}"
             ),
+            // Server queries
+            IrcReply::TraceUnknown { nick, ip } => {
+                format!(":{server_name} {RPL_TRACEUNKNOWN_NB:03} {nick} ???? users {ip}")
+            }
+            IrcReply::TraceOperator { nick, link } => {
+                format!(":{server_name} {RPL_TRACEOPERATOR_NB:03} {nick} Oper opers {link}")
+            }
+            IrcReply::TraceUser { nick, link } => {
+                format!(":{server_name} {RPL_TRACEUSER_NB:03} {nick} User users {link}")
+            }
+            IrcReply::TraceEnd { nick, version } => format!(
+                ":{server_name} {RPL_TRACEEND_NB:03} {nick} {server_name} {version} :{RPL_TRACEEND_STR}"
+            ),
+            IrcReply::Links { nick, mask } => format!(
+                ":{server_name} {RPL_LINKS_NB:03} {nick} {mask} {server_name} :0 {SERVER_INFO}"
+            ),
+            IrcReply::EndOfLinks { nick, mask } => {
+                format!(":{server_name} {RPL_ENDOFLINKS_NB:03} {nick} {mask} :{RPL_ENDOFLINKS_STR}")
+            }
+            IrcReply::MotdStart { nick } => format!(
+                ":{server_name} {RPL_MOTDSTART_NB:03} {nick} :- {server_name} Message of the day - "
+            ),
+            IrcReply::Motd { nick, line } => {
+                format!(":{server_name} {RPL_MOTD_NB:03} {nick} :- {line}")
+            }
+            IrcReply::EndOfMotd { nick } => {
+                format!(":{server_name} {RPL_ENDOFMOTD_NB:03} {nick} :{RPL_ENDOFMOTD_STR}")
+            }
+            IrcReply::StatsLinkInfo {
+                nick,
+                link,
+                sent_messages,
+                sent_kbytes,
+                received_messages,
+                received_kbytes,
+                time_open,
+            } => format!(
+                ":{server_name} {RPL_STATSLINKINFO_NB:03} {nick} {link} 0 {sent_messages} {sent_kbytes} {received_messages} {received_kbytes} {time_open}"
+            ),
+            IrcReply::StatsCommands {
+                nick,
+                command,
+                count,
+                bytes,
+            } => format!(
+                ":{server_name} {RPL_STATSCOMMANDS_NB:03} {nick} {command} {count} {bytes} 0"
+            ),
+            IrcReply::EndOfStats { nick, query } => {
+                format!(
+                    ":{server_name} {RPL_ENDOFSTATS_NB:03} {nick} {query} :{RPL_ENDOFSTATS_STR}"
+                )
+            }
+            IrcReply::LuserClient {
+                nick,
+                users,
+                invisible,
+            } => format!(
+                ":{server_name} {RPL_LUSERCLIENT_NB:03} {nick} :There are {users} users and {invisible} invisible on 1 servers"
+            ),
+            IrcReply::LuserOp { nick, operators } => {
+                format!(":{server_name} {RPL_LUSEROP_NB:03} {nick} {operators} :{RPL_LUSEROP_STR}")
+            }
+            IrcReply::LuserUnknown { nick, unknown } => format!(
+                ":{server_name} {RPL_LUSERUNKNOWN_NB:03} {nick} {unknown} :{RPL_LUSERUNKNOWN_STR}"
+            ),
+            IrcReply::LuserChannels { nick, channels } => format!(
+                ":{server_name} {RPL_LUSERCHANNELS_NB:03} {nick} {channels} :{RPL_LUSERCHANNELS_STR}"
+            ),
+            IrcReply::LuserMe { nick, clients } => format!(
+                ":{server_name} {RPL_LUSERME_NB:03} {nick} :I have {clients} clients and 0 servers"
+            ),
+            IrcReply::LocalUsers { nick, current, max } => format!(
+                ":{server_name} {RPL_LOCALUSERS_NB:03} {nick} {current} {max} :Current local users {current}, max {max}"
+            ),
+            IrcReply::GlobalUsers { nick, current, max } => format!(
+                ":{server_name} {RPL_GLOBALUSERS_NB:03} {nick} {current} {max} :Current global users {current}, max {max}"
+            ),
+            // Optional features
+            IrcReply::Away {
+                nick,
+                target,
+                message,
+            } => format!(":{server_name} {RPL_AWAY_NB:03} {nick} {target} :{message}"),
+            IrcReply::UnAway { nick } => {
+                format!(":{server_name} {RPL_UNAWAY_NB:03} {nick} :{RPL_UNAWAY_STR}")
+            }
+            IrcReply::NowAway { nick } => {
+                format!(":{server_name} {RPL_NOWAWAY_NB:03} {nick} :{RPL_NOWAWAY_STR}")
+            }
+            IrcReply::YoureOper { nick } => {
+                format!(":{server_name} {RPL_YOUREOPER_NB:03} {nick} :{RPL_YOUREOPER_STR}")
+            }
+            IrcReply::Rehashing { nick, config_file } => format!(
+                ":{server_name} {RPL_REHASHING_NB:03} {nick} {config_file} :{RPL_REHASHING_STR}"
+            ),
+            IrcReply::Summoning { nick, user } => {
+                format!(":{server_name} {RPL_SUMMONING_NB:03} {nick} {user} :{RPL_SUMMONING_STR}")
+            }
+            IrcReply::UsersStart { nick } => {
+                format!(":{server_name} {RPL_USERSSTART_NB:03} {nick} :{RPL_USERSSTART_STR}")
+            }
+            IrcReply::Users { nick, user, host } => {
+                // No terminals here, the ttyline column is a placeholder
+                format!(
+                    ":{server_name} {RPL_USERS_NB:03} {nick} :{:<8} {:<9} {host:<8}",
+                    user.0, "-"
+                )
+            }
+            IrcReply::EndOfUsers { nick } => {
+                format!(":{server_name} {RPL_ENDOFUSERS_NB:03} {nick} :{RPL_ENDOFUSERS_STR}")
+            }
+            IrcReply::NoUsers { nick } => {
+                format!(":{server_name} {RPL_NOUSERS_NB:03} {nick} :{RPL_NOUSERS_STR}")
+            }
+            // User based queries
+            IrcReply::WhoReply {
+                nick,
+                channel,
+                user,
+                host,
+                target,
+                flags,
+                real_name,
+            } => format!(
+                ":{server_name} {RPL_WHOREPLY_NB:03} {nick} {channel} {user} {host} {server_name} {target} {flags} :0 {real_name}"
+            ),
+            IrcReply::EndOfWho { nick, mask } => {
+                format!(":{server_name} {RPL_ENDOFWHO_NB:03} {nick} {mask} :{RPL_ENDOFWHO_STR}")
+            }
+            IrcReply::WhoisUser {
+                nick,
+                target,
+                user,
+                host,
+                real_name,
+            } => format!(
+                ":{server_name} {RPL_WHOISUSER_NB:03} {nick} {target} {user} {host} * :{real_name}"
+            ),
+            IrcReply::WhoisServer { nick, target } => format!(
+                ":{server_name} {RPL_WHOISSERVER_NB:03} {nick} {target} {server_name} :{SERVER_INFO}"
+            ),
+            IrcReply::WhoisActually { nick, target, host } => format!(
+                ":{server_name} {RPL_WHOISACTUALLY_NB:03} {nick} {target} {host} :{RPL_WHOISACTUALLY_STR}"
+            ),
+            IrcReply::WhoisIdle {
+                nick,
+                target,
+                idle,
+                signon,
+            } => format!(
+                ":{server_name} {RPL_WHOISIDLE_NB:03} {nick} {target} {idle} {signon} :{RPL_WHOISIDLE_STR}"
+            ),
+            IrcReply::WhoisChannels {
+                nick,
+                target,
+                channels,
+            } => format!(":{server_name} {RPL_WHOISCHANNELS_NB:03} {nick} {target} :{channels}"),
+            IrcReply::EndOfWhois { nick, target } => format!(
+                ":{server_name} {RPL_ENDOFWHOIS_NB:03} {nick} {target} :{RPL_ENDOFWHOIS_STR}"
+            ),
+            IrcReply::ErrNoSuchNick { nick, target } => format!(
+                ":{server_name} {ERR_NOSUCHNICK_NB:03} {nick} {target} :{ERR_NOSUCHNICK_STR}"
+            ),
+            IrcReply::ErrNoSuchServer { nick, server } => format!(
+                ":{server_name} {ERR_NOSUCHSERVER_NB:03} {nick} {server} :{ERR_NOSUCHSERVER_STR}"
+            ),
             //Channels replies & errors
+            IrcReply::InviteList {
+                nick,
+                channel,
+                mask,
+            } => format!(":{server_name} {RPL_INVITELIST_NB:03} {nick} {channel} {mask}"),
+            IrcReply::EndOfInviteList { nick, channel } => format!(
+                ":{server_name} {RPL_ENDOFINVITELIST_NB:03} {nick} {channel} :{RPL_ENDOFINVITELIST_STR}"
+            ),
+            IrcReply::InvitedList { nick, channel } => {
+                format!(":{server_name} {RPL_INVITEDLIST_NB:03} {nick} {channel}")
+            }
+            IrcReply::EndOfInvitedList { nick } => format!(
+                ":{server_name} {RPL_ENDOFINVITEDLIST_NB:03} {nick} :{RPL_ENDOFINVITEDLIST_STR}"
+            ),
+            IrcReply::QuietList {
+                nick,
+                channel,
+                mask,
+            } => format!(":{server_name} {RPL_QUIETLIST_NB:03} {nick} {channel} q {mask}"),
+            IrcReply::LoggedIn {
+                nick,
+                hostmask,
+                account,
+            } => format!(
+                ":{server_name} {RPL_LOGGEDIN_NB:03} {nick} {hostmask} {account} :You are now logged in as {account}"
+            ),
+            IrcReply::ServerChannelMode { channel, modes } => {
+                format!(":{server_name} MODE {channel} {modes}")
+            }
+            IrcReply::EndOfQuietList { nick, channel } => format!(
+                ":{server_name} {RPL_ENDOFQUIETLIST_NB:03} {nick} {channel} q :{RPL_ENDOFQUIETLIST_STR}"
+            ),
+            IrcReply::ChannelModeIs {
+                nick,
+                channel,
+                modes,
+            } => format!(":{server_name} {RPL_CHANNELMODEIS_NB:03} {nick} {channel} {modes}"),
+            IrcReply::CreationTime {
+                nick,
+                channel,
+                created_at,
+            } => format!(":{server_name} {RPL_CREATIONTIME_NB:03} {nick} {channel} {created_at}"),
             IrcReply::NoTopic { nick, channel } => {
                 format!(":{server_name} {RPL_NOTOPIC_NB:03} {nick} {channel} :{RPL_NOTOPIC_STR}")
             }
@@ -164,7 +810,7 @@ impl<'a> IrcReply<'a> {
                 nick,
                 channel,
                 topic,
-            } => format!(":{server_name} {RPL_TOPIC_NB:03} {nick}  {channel} :{topic}"),
+            } => format!(":{server_name} {RPL_TOPIC_NB:03} {nick} {channel} :{topic}"),
             IrcReply::Names {
                 nick,
                 channel,
@@ -178,6 +824,15 @@ impl<'a> IrcReply<'a> {
                     ":{server_name} {RPL_ENDOFNAMES_NB:03} {nick} {channel} :{RPL_ENDOFNAMES_STR}"
                 )
             }
+            IrcReply::List {
+                nick,
+                channel,
+                visible,
+                topic,
+            } => format!(":{server_name} {RPL_LIST_NB:03} {nick} {channel} {visible} :{topic}"),
+            IrcReply::ListEnd { nick } => {
+                format!(":{server_name} {RPL_LISTEND_NB:03} {nick} :{RPL_LISTEND_STR}")
+            }
             IrcReply::ErrBannedFromChan { channel } => format!(
                 ":{server_name} {ERR_BANNEDFROMCHAN_NB:03} {channel} :{ERR_BANNEDFROMCHAN_STR}"
             ),
@@ -187,16 +842,55 @@ impl<'a> IrcReply<'a> {
             IrcReply::ErrBadChannelKey { channel } => format!(
                 ":{server_name} {ERR_BADCHANNELKEY_NB:03} {channel} :{ERR_BADCHANNELKEY_STR}"
             ),
+            IrcReply::ErrBadChanMask { nick, channel } => format!(
+                ":{server_name} {ERR_BADCHANMASK_NB:03} {nick} {channel} :{ERR_BADCHANMASK_STR}"
+            ),
             IrcReply::ErrChannelIsFull { channel } => {
                 format!(
                     ":{server_name} {ERR_CHANNELISFULL_NB:03} {channel} :{ERR_INVITEONLYCHAN_STR}"
                 )
             }
+            IrcReply::ErrChanOPrivsNeeded { nick, channel } => format!(
+                ":{server_name} {ERR_CHANOPRIVSNEEDED_NB:03} {nick} {channel} :{ERR_CHANOPRIVSNEEDED_STR}"
+            ),
+            IrcReply::ErrCannotSendToChan { nick, channel } => format!(
+                ":{server_name} {ERR_CANNOTSENDTOCHAN_NB:03} {nick} {channel} :{ERR_CANNOTSENDTOCHAN_STR}"
+            ),
             IrcReply::ErrNoSuchChannel { nick, channel } => {
                 format!(
                     ":{server_name} {ERR_NOSUCHCHANNEL_NB:03} {nick} {channel} :{ERR_NOSUCHCHANNEL_STR}"
                 )
             }
+            IrcReply::ErrTooManyChannels { nick, channel } => format!(
+                ":{server_name} {ERR_TOOMANYCHANNELS_NB:03} {nick} {channel} :{ERR_TOOMANYCHANNELS_STR}"
+            ),
+            IrcReply::ErrTooManyTargets { nick, target } => {
+                format!(
+                    ":{server_name} {ERR_TOOMANYTARGETS_NB:03} {nick} {target} :{ERR_TOOMANYTARGETS_STR}"
+                )
+            }
+            IrcReply::ErrInputTooLong { nick } => {
+                format!(":{server_name} {ERR_INPUTTOOLONG_NB:03} {nick} :{ERR_INPUTTOOLONG_STR}")
+            }
+            IrcReply::ErrUserNotInChannel {
+                nick,
+                target,
+                channel,
+            } => format!(
+                ":{server_name} {ERR_USERNOTINCHANNEL_NB:03} {nick} {target} {channel} :{ERR_USERNOTINCHANNEL_STR}"
+            ),
+            IrcReply::ErrUserOnChannel {
+                nick,
+                target,
+                channel,
+            } => format!(
+                ":{server_name} {ERR_USERONCHANNEL_NB:03} {nick} {target} {channel} :{ERR_USERONCHANNEL_STR}"
+            ),
+            IrcReply::Inviting {
+                nick,
+                channel,
+                target,
+            } => format!(":{server_name} {RPL_INVITING_NB:03} {nick} {channel} {target}"),
             IrcReply::ErrNotOnChannel { nick, channel } => {
                 format!(
                     ":{server_name} {ERR_NOTONCHANNEL_NB:03} {nick} {channel} :{ERR_NOTONCHANNEL_STR}"
@@ -210,11 +904,31 @@ impl<'a> IrcReply<'a> {
                 )
             }
             // Registration
+            IrcReply::ErrAlreadyRegistred { nick } => format!(
+                ":{server_name} {ERR_ALREADYREGISTRED_NB:03} {nick} :{ERR_ALREADYREGISTRED_STR}"
+            ),
             IrcReply::ErrNicknameInUse { nick } => {
                 format!(":{server_name} {ERR_NICKNAMEINUSE_NB:03} {nick } :{ERR_NICKNAMEINUSE_STR}")
             }
-
-            _ => todo!("Implement remaining reply variants"),
+            IrcReply::ErrErroneusNickname { nick } => {
+                format!(
+                    ":{server_name} {ERR_ERRONEUSNICKNAME_NB:03} {nick} :{ERR_ERRONEUSNICKNAME_STR}"
+                )
+            }
+            IrcReply::ErrNickCollision { nick } => {
+                format!(":{server_name} {ERR_NICKCOLLISION_NB:03} {nick} :{ERR_NICKCOLLISION_STR}")
+            }
+            IrcReply::ErrUnavailResource { nick, target } => format!(
+                ":{server_name} {ERR_UNAVAILRESOURCE_NB:03} {nick} {target} :{ERR_UNAVAILRESOURCE_STR}"
+            ),
+            IrcReply::ErrJupedChannel {
+                nick,
+                channel,
+                reason,
+            } => format!(":{server_name} {ERR_UNAVAILRESOURCE_NB:03} {nick} {channel} :{reason}"),
+            IrcReply::ErrRestricted { nick } => {
+                format!(":{server_name} {ERR_RESTRICTED_NB:03} {nick} :{ERR_RESTRICTED_STR}")
+            }
         }
     }
 }
@@ -234,7 +948,9 @@ pub enum MessageReply<'a> {
         host: &'a str,
         channel: &'a ChannelName,
     },
+    // PRIVMSG or NOTICE
     NicknamePrivMsg {
+        command: &'a str,
         nick_from: &'a Nickname,
         user_from: &'a Username,
         host_from: &'a str,
@@ -242,6 +958,7 @@ pub enum MessageReply<'a> {
         message: &'a str,
     },
     ChannelPrivMsg {
+        command: &'a str,
         nick_from: &'a Nickname,
         user_from: &'a Username,
         host_from: &'a str,
@@ -255,6 +972,67 @@ pub enum MessageReply<'a> {
         channel: &'a ChannelName,
         message: &'a str,
     },
+    ChannelMode {
+        nick_from: &'a Nickname,
+        user_from: &'a Username,
+        host_from: &'a str,
+        channel: &'a ChannelName,
+        modes: &'a str,
+    },
+    Invite {
+        nick_from: &'a Nickname,
+        user_from: &'a Username,
+        host_from: &'a str,
+        nick_to: &'a Nickname,
+        channel: &'a ChannelName,
+    },
+    Kick {
+        nick_from: &'a Nickname,
+        user_from: &'a Username,
+        host_from: &'a str,
+        channel: &'a ChannelName,
+        nick_to: &'a Nickname,
+        comment: &'a str,
+    },
+    Topic {
+        nick_from: &'a Nickname,
+        user_from: &'a Username,
+        host_from: &'a str,
+        channel: &'a ChannelName,
+        topic: &'a str,
+    },
+    Quit {
+        nick_from: &'a Nickname,
+        user_from: &'a Username,
+        host_from: &'a str,
+        reason: &'a str,
+    },
+    ChannelNotice {
+        nick_from: &'a Nickname,
+        user_from: &'a Username,
+        host_from: &'a str,
+        channel: &'a ChannelName,
+        message: &'a str,
+    },
+    Redact {
+        nick_from: &'a Nickname,
+        user_from: &'a Username,
+        host_from: &'a str,
+        channel: &'a ChannelName,
+        msgid: &'a str,
+        reason: Option<&'a str>,
+    },
+    Error {
+        nick: &'a Nickname,
+        reason: &'a str,
+    },
+    // None when coming back
+    Away {
+        nick_from: &'a Nickname,
+        user_from: &'a Username,
+        host_from: &'a str,
+        message: Option<&'a str>,
+    },
 }
 impl<'a> MessageReply<'a> {
     pub fn format(&self) -> String {
@@ -266,19 +1044,21 @@ impl<'a> MessageReply<'a> {
                 channel,
             } => format!(":{nick}!{user}@{host} JOIN :{channel}"),
             MessageReply::NicknamePrivMsg {
+                command,
                 nick_from,
                 user_from,
                 host_from,
                 nick_to,
                 message,
-            } => format!(":{nick_from}!{user_from}@{host_from} PRIVMSG {nick_to} :{message}"),
+            } => format!(":{nick_from}!{user_from}@{host_from} {command} {nick_to} :{message}"),
             MessageReply::ChannelPrivMsg {
+                command,
                 nick_from,
                 user_from,
                 host_from,
                 channel,
                 message,
-            } => format!(":{nick_from}!{user_from}@{host_from} PRIVMSG {channel} :{message}"),
+            } => format!(":{nick_from}!{user_from}@{host_from} {command} {channel} :{message}"),
             MessageReply::PartMsg {
                 nick_from,
                 user_from,
@@ -286,12 +1066,162 @@ impl<'a> MessageReply<'a> {
                 channel,
                 message,
             } => format!(":{nick_from}!{user_from}@{host_from} PART {channel} {message}"),
+            MessageReply::ChannelMode {
+                nick_from,
+                user_from,
+                host_from,
+                channel,
+                modes,
+            } => format!(":{nick_from}!{user_from}@{host_from} MODE {channel} {modes}"),
+            MessageReply::Invite {
+                nick_from,
+                user_from,
+                host_from,
+                nick_to,
+                channel,
+            } => format!(":{nick_from}!{user_from}@{host_from} INVITE {nick_to} :{channel}"),
+            MessageReply::Kick {
+                nick_from,
+                user_from,
+                host_from,
+                channel,
+                nick_to,
+                comment,
+            } => {
+                format!(":{nick_from}!{user_from}@{host_from} KICK {channel} {nick_to} :{comment}")
+            }
             MessageReply::UpdateNick {
                 old_nick,
                 new_nick,
                 user,
                 host,
             } => format!(":{old_nick}!{user}@{host} NICK :{new_nick}"),
+            MessageReply::Topic {
+                nick_from,
+                user_from,
+                host_from,
+                channel,
+                topic,
+            } => format!(":{nick_from}!{user_from}@{host_from} TOPIC {channel} :{topic}"),
+            MessageReply::Quit {
+                nick_from,
+                user_from,
+                host_from,
+                reason,
+            } => format!(":{nick_from}!{user_from}@{host_from} QUIT :{reason}"),
+            MessageReply::ChannelNotice {
+                nick_from,
+                user_from,
+                host_from,
+                channel,
+                message,
+            } => format!(":{nick_from}!{user_from}@{host_from} NOTICE {channel} :{message}"),
+            MessageReply::Redact {
+                nick_from,
+                user_from,
+                host_from,
+                channel,
+                msgid,
+                reason,
+            } => match reason {
+                Some(reason) => {
+                    format!(
+                        ":{nick_from}!{user_from}@{host_from} REDACT {channel} {msgid} :{reason}"
+                    )
+                }
+                None => format!(":{nick_from}!{user_from}@{host_from} REDACT {channel} {msgid}"),
+            },
+            MessageReply::Error { nick, reason } => {
+                format!("ERROR :Closing Link: {nick} ({reason})")
+            }
+            MessageReply::Away {
+                nick_from,
+                user_from,
+                host_from,
+                message,
+            } => match message {
+                Some(message) => format!(":{nick_from}!{user_from}@{host_from} AWAY :{message}"),
+                None => format!(":{nick_from}!{user_from}@{host_from} AWAY"),
+            },
         }
     }
 }
+
+static NEXT_BATCH_REFERENCE: AtomicUsize = AtomicUsize::new(1);
+
+/// IRCv3 `batch` framing for multi-line replies (NAMES, WHOIS, CHATHISTORY).
+/// A disabled batch, for clients that didn't negotiate the capability,
+/// sends neither BATCH line and leaves the enclosed lines untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    reference: Option<String>,
+}
+impl Batch {
+    pub fn new(enabled: bool) -> Self {
+        let reference = enabled.then(|| {
+            NEXT_BATCH_REFERENCE
+                .fetch_add(1, Ordering::Relaxed)
+                .to_string()
+        });
+        Batch { reference }
+    }
+
+    /// `BATCH +<ref> <type> [params]`
+    pub fn start(&self, kind: &str, params: &str) -> Option<String> {
+        self.reference.as_ref().map(|reference| {
+            IrcReply::BatchStart {
+                reference,
+                kind,
+                params,
+            }
+            .format()
+        })
+    }
+
+    /// Adds the `@batch=<ref>` tag to a line, merging it with existing tags.
+    pub fn tag(&self, line: String) -> String {
+        match &self.reference {
+            Some(reference) => MessageTags::new().with("batch", reference).render(&line),
+            None => line,
+        }
+    }
+
+    /// `BATCH -<ref>`
+    pub fn end(&self) -> Option<String> {
+        self.reference
+            .as_ref()
+            .map(|reference| IrcReply::BatchEnd { reference }.format())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_tags_enclosed_lines() {
+        let batch = Batch::new(true);
+        let reference = batch.reference.clone().unwrap();
+        assert_eq!(
+            batch.start("names", "").unwrap(),
+            format!(":unknown.server BATCH +{reference} names")
+        );
+        assert_eq!(
+            batch.tag(":s 353 a = #c :a".to_owned()),
+            format!("@batch={reference} :s 353 a = #c :a")
+        );
+        assert_eq!(
+            batch.tag("@time=x :a PRIVMSG #c :hi".to_owned()),
+            format!("@batch={reference};time=x :a PRIVMSG #c :hi")
+        );
+        assert_eq!(
+            batch.end().unwrap(),
+            format!(":unknown.server BATCH -{reference}")
+        );
+
+        let unbatched = Batch::new(false);
+        assert_eq!(unbatched.start("names", ""), None);
+        assert_eq!(unbatched.tag(":s 366".to_owned()), ":s 366");
+        assert_eq!(unbatched.end(), None);
+    }
+}
@@ -1,47 +1,487 @@
 use crate::{
-    channels_models::{IrcChannel, IrcChannelOperationStatus},
+    accounts::{AccountStore, LocalAccountStore},
+    auth::{AuthProvider, ConfigAuthProvider},
+    channels_models::{IrcChannel, IrcChannelOperationStatus, Role},
+    config::Config,
     errors::InternalIrcError,
-    message_models::{BroadcastIrcMessage, DirectIrcMessage},
+    message_models::IrcMessage,
+    ops::parsers::validate_channel_name,
+    replies::{IrcReply, MessageReply},
     types::{ChannelName, ClientId, Nickname},
     user_state::UserState,
+    utils::{
+        format_date, is_safe_channel_id, safe_channel_id, unix_timestamp, unix_timestamp_millis,
+    },
 };
-use dashmap::DashMap;
-use log::{debug, info};
-use std::{collections::HashSet, net::IpAddr, sync::Arc};
+use dashmap::{DashMap, mapref::entry::Entry};
+use log::{debug, info, warn};
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{Notify, RwLock};
+
+// Operator actions kept for review, the oldest are dropped first
+const OPER_AUDIT_SIZE: usize = 512;
+// Distinct bot signals tracked before the stale ones are swept
+const BOT_SIGNALS_SIZE: usize = 10_000;
+
+/// One operator command, as recorded by `record_oper_action`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OperAuditEntry {
+    pub time: u64,
+    pub nick: Nickname,
+    pub command: String,
+    pub args: String,
+}
+
+/// A logged in user whose connection was lost, still in its channels
+/// until a reconnect under the same nick proves the same account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParkedSession {
+    pub client_id: ClientId,
+    pub account: String,
+}
 
 #[derive(Clone, Debug)]
 pub struct ServerState {
     pub channels: Arc<DashMap<ChannelName, Arc<IrcChannel>>>,
     pub ip_counts: Arc<DashMap<IpAddr, usize>>,
+    // Recent connection times (ms) per IP, for the connection rate window
+    pub ip_connects: Arc<DashMap<IpAddr, VecDeque<u64>>>,
+    // When `ip_connects` was last swept of addresses gone quiet (ms)
+    pub ip_connects_swept_at: Arc<AtomicU64>,
     pub nick: Arc<DashMap<Nickname, ClientId>>,
+    // Safe channels by short name, e.g. "rust" -> "!ABC12rust"
+    pub safe_channels: Arc<DashMap<String, ChannelName>>,
     // pub nick_user_host_server: Arc<DashMap<(String, String, String, String), ClientId>>,
     pub users: Arc<DashMap<ClientId, UserState>>,
+    // Most connections seen at once, for RPL_LOCALUSERS
+    pub max_local_users: Arc<AtomicUsize>,
+    pub config: Arc<RwLock<Config>>,
+    // MOTD lines cached from the config, None when the MOTD file is missing
+    pub motd: Arc<RwLock<Option<Vec<String>>>>,
+    // STATS m: command -> (times used, bytes received)
+    pub command_counts: Arc<DashMap<String, (u64, u64)>>,
+    // Ring buffer of operator commands, served by the admin API
+    pub oper_audit: Arc<RwLock<VecDeque<OperAuditEntry>>>,
+    // Next IRCv3 msgid, seeded with the start time so ids stay unique across restarts
+    pub next_msgid: Arc<AtomicU64>,
+    // What CAP LS advertised as of the last (re)load, diffed for cap-notify
+    pub advertised_capabilities: Arc<RwLock<Vec<&'static str>>>,
+    // Nicks and channel names held back (name -> until, in seconds)
+    pub held_resources: Arc<DashMap<String, u64>>,
+    // Channels reserved by JUPE, with the reason JOINs are refused with
+    pub juped_channels: Arc<DashMap<ChannelName, String>>,
+    // Logged in sessions lost without QUIT, within `limits.reconnect_grace_secs`
+    pub parked_sessions: Arc<DashMap<Nickname, ParkedSession>>,
+    // Checks OPER credentials, the config's [[opers]] unless replaced
+    pub auth_provider: Arc<dyn AuthProvider>,
+    // Accounts for REGISTER / IDENTIFY, `accounts.file` unless replaced
+    pub account_store: Arc<dyn AccountStore>,
+    // When this server started, as sent in RPL_CREATED
+    pub created: Arc<str>,
+    // Recent times (ms) one IP registered a realname or sent a text, by hash
+    pub bot_signals: Arc<DashMap<(IpAddr, u64), VecDeque<u64>>>,
+    // Registrations and messages flagged by the bot heuristics
+    pub suspected_bots: Arc<AtomicU64>,
+    // Woken by DIE, the accept loop then runs `shutdown::shutdown`
+    pub shutdown_requested: Arc<Notify>,
+    // Set once shutdown started, new connections are refused
+    pub shutting_down: Arc<AtomicBool>,
+}
+
+/// One admitted connection, counted in `ip_counts` until dropped.
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    server_state: ServerState,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        if let Entry::Occupied(mut count) = self.server_state.ip_counts.entry(self.ip) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
 }
 
 impl ServerState {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
+        let motd = config.load_motd();
+        let advertised_capabilities = config.get_capabilities();
+        let account_store = LocalAccountStore::load(config.get_accounts_file().map(PathBuf::from));
+        let config = Arc::new(RwLock::new(config));
         ServerState {
             channels: Arc::new(DashMap::new()),
             ip_counts: Arc::new(DashMap::new()),
+            ip_connects: Arc::new(DashMap::new()),
+            ip_connects_swept_at: Arc::new(AtomicU64::new(0)),
             nick: Arc::new(DashMap::new()),
+            safe_channels: Arc::new(DashMap::new()),
             // nick_user_host_server: Arc::new(DashMap::new()),
             users: Arc::new(DashMap::new()),
+            max_local_users: Arc::new(AtomicUsize::new(0)),
+            motd: Arc::new(RwLock::new(motd)),
+            advertised_capabilities: Arc::new(RwLock::new(advertised_capabilities)),
+            auth_provider: Arc::new(ConfigAuthProvider::new(config.clone())),
+            account_store: Arc::new(account_store),
+            config,
+            command_counts: Arc::new(DashMap::new()),
+            oper_audit: Arc::new(RwLock::new(VecDeque::with_capacity(OPER_AUDIT_SIZE))),
+            next_msgid: Arc::new(AtomicU64::new(unix_timestamp_millis())),
+            held_resources: Arc::new(DashMap::new()),
+            juped_channels: Arc::new(DashMap::new()),
+            parked_sessions: Arc::new(DashMap::new()),
+            created: format_date(unix_timestamp()).into(),
+            bot_signals: Arc::new(DashMap::new()),
+            suspected_bots: Arc::new(AtomicU64::new(0)),
+            shutdown_requested: Arc::new(Notify::new()),
+            shutting_down: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Checks OPER credentials with `auth_provider` instead of the config.
+    pub fn with_auth_provider(mut self, auth_provider: Arc<dyn AuthProvider>) -> Self {
+        self.auth_provider = auth_provider;
+        self
+    }
+
+    /// Keeps accounts in `account_store` instead of `accounts.file`.
+    pub fn with_account_store(mut self, account_store: Arc<dyn AccountStore>) -> Self {
+        self.account_store = account_store;
+        self
+    }
+
+    /// A new `msgid` tag value, never handed out twice.
+    pub fn new_msgid(&self) -> String {
+        self.next_msgid.fetch_add(1, Ordering::Relaxed).to_string()
+    }
+
+    /// Diffs the capabilities the config now enables against the ones last
+    /// advertised, and sends CAP NEW / CAP DEL to the cap-notify clients.
+    /// A removed capability is disabled for every client.
+    pub async fn notify_capability_changes(&self) {
+        let current = self.config.read().await.get_capabilities();
+        let previous = std::mem::replace(
+            &mut *self.advertised_capabilities.write().await,
+            current.clone(),
+        );
+        let added = current
+            .iter()
+            .filter(|capability| !previous.contains(capability))
+            .copied()
+            .collect::<Vec<_>>();
+        let removed = previous
+            .iter()
+            .filter(|capability| !current.contains(capability))
+            .copied()
+            .collect::<Vec<_>>();
+        if added.is_empty() && removed.is_empty() {
+            return;
+        }
+        let (added, removed) = (added.join(" "), removed.join(" "));
+        let users: Vec<UserState> = self.users.iter().map(|e| e.value().clone()).collect();
+        for user_state in users {
+            let (nick, notify) = {
+                let mut user = user_state.user.write().await;
+                for capability in removed.split_whitespace() {
+                    user.capabilities.remove(capability);
+                }
+                let nick = match &user.nick {
+                    Some(nick) if user.registered.load(Ordering::Relaxed) => nick.clone(),
+                    _ => Nickname("*".to_owned()),
+                };
+                (nick, user.capabilities.contains("cap-notify"))
+            };
+            if !notify {
+                continue;
+            }
+            if !added.is_empty() {
+                let irc_reply = IrcReply::CapNew {
+                    nick: &nick,
+                    capabilities: &added,
+                };
+                let cap_new = IrcMessage::new(irc_reply.format());
+                let _ = user_state.tx_outbound.send(cap_new).await;
+            }
+            if !removed.is_empty() {
+                let irc_reply = IrcReply::CapDel {
+                    nick: &nick,
+                    capabilities: &removed,
+                };
+                let cap_del = IrcMessage::new(irc_reply.format());
+                let _ = user_state.tx_outbound.send(cap_del).await;
+            }
+        }
+    }
+
+    /// Re-reads the MOTD from the current config.
+    pub async fn reload_motd(&self) {
+        let motd = self.config.read().await.load_motd();
+        *self.motd.write().await = motd;
+    }
+
+    pub fn count_command(&self, request: &str) {
+        let Some(command) = request.split(' ').next().filter(|c| !c.is_empty()) else {
+            return;
+        };
+        let mut counts = self
+            .command_counts
+            .entry(command.to_ascii_uppercase())
+            .or_insert((0, 0));
+        counts.0 += 1;
+        counts.1 += request.len() as u64;
+    }
+
+    /// Admits a connection from `ip` unless it is over the concurrent cap
+    /// (`limits.max_connections_per_ip`) or has already connected
+    /// `limits.ip_connects_per_window` times within the window.
+    pub async fn admit_connection(&self, ip: IpAddr) -> Option<ConnectionSlot> {
+        let (max_concurrent, window_ms, per_window) = {
+            let config = self.config.read().await;
+            (
+                config.limits.max_connections_per_ip,
+                config.get_ip_connect_window() * 1000,
+                config.get_ip_connects_per_window(),
+            )
+        };
+        if self.shutting_down.load(Ordering::Relaxed) {
+            return None;
+        }
+        let now = unix_timestamp_millis();
+        self.sweep_ip_connects(now, window_ms);
+        let admitted = {
+            // Held across both checks so racing connects are admitted one by one
+            let mut count = self.ip_counts.entry(ip).or_insert(0);
+            if *count >= max_concurrent {
+                info!("Rejecting {ip}: {} connections open", *count);
+                false
+            } else {
+                let mut connects = self.ip_connects.entry(ip).or_default();
+                while connects.front().is_some_and(|&at| at + window_ms <= now) {
+                    connects.pop_front();
+                }
+                if connects.len() >= per_window {
+                    info!(
+                        "Rejecting {ip}: {} connections in the window",
+                        connects.len()
+                    );
+                    false
+                } else {
+                    connects.push_back(now);
+                    *count += 1;
+                    true
+                }
+            }
+        };
+        if !admitted {
+            // A first connect that was refused leaves nothing behind
+            self.ip_counts.remove_if(&ip, |_, count| *count == 0);
+            return None;
+        }
+        Some(ConnectionSlot {
+            server_state: self.clone(),
+            ip,
+        })
+    }
+
+    /// Forgets the connect history of addresses with nothing left in the
+    /// window. Runs at most once per window, so it costs little per connect.
+    fn sweep_ip_connects(&self, now: u64, window_ms: u64) {
+        let swept_at = self.ip_connects_swept_at.load(Ordering::Relaxed);
+        if now < swept_at + window_ms
+            || self
+                .ip_connects_swept_at
+                .compare_exchange(swept_at, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+        self.ip_connects
+            .retain(|_, connects| connects.back().is_some_and(|&at| at + window_ms > now));
+    }
+
+    /// Counts one occurrence of `signal` (e.g. a realname or a message text)
+    /// from `ip`. Reaching `security.bot_threshold` within the window flags
+    /// a suspected bot: it is logged and counted, and true is returned when
+    /// `security.autokill_bots` wants the client gone. Always false when
+    /// the heuristics are off.
+    pub async fn audit_bot_signal(&self, ip: IpAddr, nick: &Nickname, signal: &str) -> bool {
+        let (detection, autokill) = {
+            let config = self.config.read().await;
+            (config.get_bot_detection(), config.get_autokill_bots())
+        };
+        let Some((threshold, window_secs)) = detection else {
+            return false;
+        };
+        let now = unix_timestamp_millis();
+        let window_ms = window_secs * 1000;
+        if self.bot_signals.len() > BOT_SIGNALS_SIZE {
+            self.bot_signals
+                .retain(|_, seen| seen.back().is_some_and(|&at| at + window_ms > now));
+        }
+        let mut hasher = DefaultHasher::new();
+        signal.hash(&mut hasher);
+        let mut seen = self.bot_signals.entry((ip, hasher.finish())).or_default();
+        while seen.front().is_some_and(|&at| at + window_ms <= now) {
+            seen.pop_front();
+        }
+        seen.push_back(now);
+        if seen.len() < threshold {
+            return false;
+        }
+        warn!(
+            "Suspected bot {nick} from {ip}: {} times {signal:?} in {window_secs}s",
+            seen.len()
+        );
+        self.suspected_bots.fetch_add(1, Ordering::Relaxed);
+        autokill
+    }
+
+    pub async fn record_oper_action(&self, nick: &Nickname, command: &str, args: &str) {
+        info!("[oper {nick}] {command} {args}");
+        let mut audit = self.oper_audit.write().await;
+        if audit.len() >= OPER_AUDIT_SIZE {
+            audit.pop_front();
+        }
+        audit.push_back(OperAuditEntry {
+            time: unix_timestamp(),
+            nick: nick.clone(),
+            command: command.to_owned(),
+            args: args.to_owned(),
+        });
+    }
+
     pub async fn add_connecting_user(
         &self,
         user_state: &UserState,
     ) -> Result<ClientId, InternalIrcError> {
+        let (restricted, cloaked_host) = {
+            let ip = user_state.user.read().await.addr.ip();
+            let config = self.config.read().await;
+            (config.is_restricted_ip(ip), config.get_cloaked_host(ip))
+        };
+        {
+            let mut user_data = user_state.user.write().await;
+            if restricted {
+                // RFC 2812 3.1.5: a restricted connection starts with +r
+                user_data.modes.insert('r');
+            }
+            user_data.cloaked_host = cloaked_host;
+        }
         let user_data = user_state.user.read().await;
         let user_id = user_data.user_id;
-        if let Some(nick) = user_data.nick.clone() {
-            self.nick.insert(nick, user_id);
+        if let Some(nick) = &user_data.nick
+            && !self.claim_nick(nick, user_id)
+        {
+            return Err(InternalIrcError::ServerStateError("nick collision"));
         }
         self.users.insert(user_id, user_state.clone());
+        self.max_local_users
+            .fetch_max(self.users.len(), Ordering::Relaxed);
         Ok(user_id)
     }
 
+    /// Current and highest number of connected clients.
+    pub fn local_users(&self) -> (usize, usize) {
+        let current = self.users.len();
+        let max = self.max_local_users.load(Ordering::Relaxed);
+        (current, max.max(current))
+    }
+
+    /// Maps `nick` to `client_id` through the entry API, so the check and the
+    /// insert happen under the same shard lock and two racing NICKs can't
+    /// both win. A nick left behind by a client that is gone is taken over.
+    pub fn claim_nick(&self, nick: &Nickname, client_id: ClientId) -> bool {
+        match self.nick.entry(nick.clone()) {
+            Entry::Occupied(mut entry) => {
+                let holder = *entry.get();
+                if holder == client_id || !self.users.contains_key(&holder) {
+                    entry.insert(client_id);
+                    true
+                } else {
+                    false
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(client_id);
+                true
+            }
+        }
+    }
+
+    /// Frees `nick` if, and only if, it is still held by `client_id`.
+    pub fn release_nick(&self, nick: &Nickname, client_id: ClientId) {
+        self.nick.remove_if(nick, |_, holder| *holder == client_id);
+    }
+
+    /// Makes a nick or channel name unavailable for `secs` seconds, they
+    /// are answered with ERR_UNAVAILRESOURCE meanwhile.
+    pub fn hold_resource(&self, name: &str, secs: u64) {
+        if secs > 0 {
+            self.held_resources
+                .insert(name.to_owned(), unix_timestamp() + secs);
+        }
+    }
+
+    /// Whether `hostmask` would get into `channel_name` with a plain JOIN,
+    /// no key nor invitation, as done for `channels.auto_join`: channels
+    /// that would only answer with an error are skipped silently.
+    pub async fn is_joinable(&self, channel_name: &ChannelName, hostmask: &str) -> bool {
+        {
+            let config = self.config.read().await;
+            if !validate_channel_name(&channel_name.0)
+                || !channel_name
+                    .0
+                    .starts_with(|prefix| config.get_chantypes().contains(prefix))
+            {
+                return false;
+            }
+            if self.juped_channels.contains_key(channel_name) || self.is_held(&channel_name.0) {
+                return false;
+            }
+            if !self.channels_exists(channel_name) {
+                return !config.get_oper_only_create();
+            }
+        }
+        let Some(channel) = self.get_channel(channel_name) else {
+            return false;
+        };
+        let modes = channel.modes.read().await;
+        let is_full = modes
+            .user_limit
+            .is_some_and(|limit| channel.members.len() >= limit);
+        !is_full
+            && !modes.is_banned(hostmask)
+            && (!modes.invite_only || modes.is_invite_excepted(hostmask))
+            && modes.key.is_none()
+    }
+
+    /// Whether `name` is still held, forgetting it once the hold expired.
+    pub fn is_held(&self, name: &str) -> bool {
+        let now = unix_timestamp();
+        self.held_resources
+            .remove_if(name, |_, until| *until <= now);
+        self.held_resources.contains_key(name)
+    }
+
+    /// The live client holding `nick`, if any.
+    pub fn nick_holder(&self, nick: &Nickname) -> Option<ClientId> {
+        let holder = self.nick.get(nick).map(|holder| *holder)?;
+        self.users.contains_key(&holder).then_some(holder)
+    }
+
     pub fn handle_nick_change(
         &self,
         client_id: ClientId,
@@ -49,7 +489,7 @@ impl ServerState {
         old_nick: &Nickname,
     ) {
         // 3. Update the global Nick -> ClientId map
-        self.nick.remove(old_nick);
+        self.release_nick(old_nick, client_id);
         self.nick.insert(new_nick.clone(), client_id);
     }
 
@@ -58,35 +498,108 @@ impl ServerState {
     }
 
     pub fn get_cliend_id_from_nick(&self, nick: &Nickname) -> Option<ClientId> {
-        if let Some(client_ref) = self.nick.get(nick) {
-            Some(*client_ref)
-        } else {
-            None
-        }
+        self.nick.get(nick).map(|client_ref| *client_ref)
     }
 
     pub fn get_user_state_from_client_id(&self, client_id: &ClientId) -> Option<UserState> {
-        if let Some(client_ref) = self.users.get(client_id) {
-            Some((*client_ref).clone())
-        } else {
-            None
-        }
+        self.users
+            .get(client_id)
+            .map(|client_ref| (*client_ref).clone())
     }
 
     pub fn get_user_state_from_nick(&self, nick: &Nickname) -> Option<UserState> {
-        let client_id_opt = self.get_cliend_id_from_nick(nick).map(|r| r.clone());
+        let client_id_opt = self.get_cliend_id_from_nick(nick);
         if let Some(client_id) = client_id_opt {
-            let client_ref_opt = self.users.get(&client_id).map(|r| r.clone());
-            client_ref_opt
+            self.users.get(&client_id).map(|r| r.clone())
         } else {
             None
         }
     }
 
+    pub async fn users_with_mode(&self, mode: char) -> Vec<UserState> {
+        let users = self
+            .users
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<UserState>>();
+        let mut matching = Vec::new();
+        for user_state in users {
+            if user_state.user.read().await.modes.contains(&mode) {
+                matching.push(user_state);
+            }
+        }
+        matching
+    }
+
     pub fn get_channel(&self, channel: &ChannelName) -> Option<Arc<IrcChannel>> {
         self.channels.get(channel).map(|r| r.clone())
     }
 
+    /// Every member of `channel` with a nick, and its role there. No
+    /// visibility rule (+i, +a) is applied, callers showing it to a user do.
+    pub async fn member_roles(&self, channel: &IrcChannel) -> Vec<(Nickname, Role)> {
+        let members = channel.members.iter().map(|m| *m).collect::<Vec<_>>();
+        let mut roles = Vec::with_capacity(members.len());
+        for client_id in members {
+            let Some(user_state) = self.get_user_state_from_client_id(&client_id) else {
+                continue;
+            };
+            if let Some(nick) = user_state.user.read().await.nick.clone() {
+                roles.push((nick, channel.role(client_id)));
+            }
+        }
+        roles
+    }
+
+    /// Resolves a `!` JOIN target (RFC 2811 3.2): `!!short` asks for a new
+    /// channel `!<channelid>short`, `!short` names the existing one. The
+    /// bool says whether this JOIN may create the channel.
+    pub fn resolve_safe_channel(
+        &self,
+        name: &ChannelName,
+    ) -> Result<(ChannelName, bool), IrcChannelOperationStatus> {
+        if let Some(short_name) = name.0.strip_prefix("!!") {
+            if self.safe_channels.contains_key(short_name) {
+                return Err(IrcChannelOperationStatus::UnavailableResource);
+            }
+            // The id is the creation time, bumped past any id still in use
+            let mut time = unix_timestamp();
+            let id = loop {
+                let id = safe_channel_id(time);
+                let in_use = self
+                    .safe_channels
+                    .iter()
+                    .any(|entry| entry.value().0[1..6] == id);
+                if !in_use {
+                    break id;
+                }
+                time += 1;
+            };
+            return Ok((ChannelName(format!("!{id}{short_name}")), true));
+        }
+        if let Some(short_name) = name.0.strip_prefix('!') {
+            if is_safe_channel_id(&name.0) && self.channels_exists(name) {
+                return Ok((name.clone(), false));
+            }
+            return match self.safe_channels.get(short_name) {
+                Some(full_name) => Ok((full_name.clone(), false)),
+                None if is_safe_channel_id(&name.0) => Ok((name.clone(), false)),
+                None => Err(IrcChannelOperationStatus::NoSuchChannel),
+            };
+        }
+        Ok((name.clone(), true))
+    }
+
+    fn remove_channel(&self, channel_name: &ChannelName) {
+        self.channels.remove(channel_name);
+        if is_safe_channel_id(&channel_name.0) {
+            self.safe_channels
+                .remove_if(&channel_name.0[6..], |_, full_name| {
+                    full_name == channel_name
+                });
+        }
+    }
+
     fn get_or_create_channel(&self, channel_name: &ChannelName) -> (Arc<IrcChannel>, bool) {
         let mut is_new = false;
         let channel = self
@@ -99,6 +612,10 @@ impl ServerState {
             .clone();
 
         if is_new {
+            if is_safe_channel_id(&channel_name.0) {
+                self.safe_channels
+                    .insert(channel_name.0[6..].to_owned(), channel_name.clone());
+            }
             debug!(
                 "new channel: {} (ptr: {:p}, tx_ptr: {:p})",
                 channel_name,
@@ -120,29 +637,47 @@ impl ServerState {
         &self,
         channel_name: ChannelName,
         client_id: ClientId,
+        hostmask: &str,
         key: Option<String>,
         is_invited: bool,
+        can_create: bool,
     ) -> Result<(IrcChannelOperationStatus, Option<Arc<IrcChannel>>), InternalIrcError> {
-        let (channel, is_new_channel) = self.get_or_create_channel(&channel_name);
+        let (channel, is_new_channel) = if can_create {
+            self.get_or_create_channel(&channel_name)
+        } else {
+            match self.get_channel(&channel_name) {
+                Some(channel) => (channel, false),
+                None => return Ok((IrcChannelOperationStatus::NoSuchChannel, None)),
+            }
+        };
+        if channel.members.contains(&client_id) {
+            // Redundant JOIN, the caller only resends topic and names
+            return Ok((IrcChannelOperationStatus::AlreadyMember, Some(channel)));
+        }
+        let is_invited = is_invited || channel.invited.contains(&client_id);
         {
-            let modes = channel.modes.read().await;
+            // Held through add_member so concurrent JOINs can't both pass
+            // the +l check: joins to this channel are serialized here
+            let modes = channel.modes.write().await;
             if modes.user_limit.is_some() && channel.members.len() >= modes.user_limit.unwrap() {
                 return Ok((IrcChannelOperationStatus::ChannelIsFull, None));
             }
-            if modes.ban_list.contains(&client_id) && !modes.except_list.contains(&client_id) {
+            if modes.is_banned(hostmask) {
                 return Ok((IrcChannelOperationStatus::BannedFromChan, None));
             }
-            if modes.invite_only && !is_invited && !modes.invite_exceptions.contains(&client_id) {
+            if modes.invite_only && !is_invited && !modes.is_invite_excepted(hostmask) {
                 return Ok((IrcChannelOperationStatus::InviteOnlyChan, None));
             }
             if modes.key.is_some() && (modes.key != key) {
                 return Ok((IrcChannelOperationStatus::BadChannelKey, None));
             }
+            if !channel.add_member(client_id) {
+                // Lost a race with a concurrent JOIN of the same user
+                let channel = Arc::clone(&channel);
+                return Ok((IrcChannelOperationStatus::AlreadyMember, Some(channel)));
+            }
         }
-        if !channel.add_member(client_id) {
-            // User is already in the channel, do nothing
-            return Ok((IrcChannelOperationStatus::AlreadyMember, None));
-        }
+        channel.invited.remove(&client_id);
         if is_new_channel {
             channel.add_operator(client_id);
         }
@@ -152,36 +687,118 @@ impl ServerState {
     pub async fn quit_channel(&self, client_id: &ClientId, channel_name: &ChannelName) {
         let channel_opt = self.get_channel(channel_name);
         if let Some(channel) = channel_opt {
-            channel.remove_member(&client_id);
+            channel.remove_member(client_id);
             if channel.members.is_empty() {
                 info!("Channel {channel_name} is empty, destroying.");
-                self.channels.remove(channel_name);
+                self.remove_channel(channel_name);
             }
         }
     }
 
+    /// A connection that ended without QUIT. The user quits as usual, but
+    /// with a reconnection grace a logged in user is parked instead: it
+    /// stays in its channels, and its peers only see a QUIT once the grace
+    /// is over without a reconnect, see `resume_session`.
+    pub async fn handle_lost_connection(&self, client_id: ClientId) {
+        let Some(user_state) = self.users.get(&client_id).map(|r| r.clone()) else {
+            // Already gone through QUIT, KILL...
+            return;
+        };
+        let caracs = user_state.get_caracs().await;
+        let grace = self.config.read().await.get_reconnect_grace_secs();
+        if grace > 0
+            && caracs.registered
+            && let (Some(nick), Some(account)) = (caracs.nick, caracs.account)
+        {
+            let parked = ParkedSession { client_id, account };
+            self.parked_sessions.insert(nick.clone(), parked);
+            let server_state = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(grace)).await;
+                server_state.expire_parked_session(&nick, client_id).await;
+            });
+            return;
+        }
+        self.handle_quit(client_id, Some("Connection lost".to_owned()))
+            .await;
+    }
+
+    /// The QUIT of a parked session nobody came back for.
+    pub async fn expire_parked_session(&self, nick: &Nickname, client_id: ClientId) {
+        if self
+            .parked_sessions
+            .remove_if(nick, |_, parked| parked.client_id == client_id)
+            .is_some()
+        {
+            self.handle_quit(client_id, Some("Connection lost".to_owned()))
+                .await;
+        }
+    }
+
+    /// The account a reconnect must prove to take `nick` back, if parked.
+    pub fn parked_account(&self, nick: &Nickname) -> Option<String> {
+        self.parked_sessions
+            .get(nick)
+            .map(|parked| parked.account.clone())
+    }
+
+    /// Lets a reconnect that proved the account claim the parked nick.
+    pub fn release_parked_nick(&self, nick: &Nickname) {
+        if let Some(parked) = self.parked_sessions.get(nick) {
+            self.release_nick(nick, parked.client_id);
+        }
+    }
+
+    /// Takes the session parked under `nick` for `account`, returning the
+    /// lost client whose channels are handed over.
+    pub fn resume_session(&self, nick: &Nickname, account: &str) -> Option<ClientId> {
+        let (_, parked) = self
+            .parked_sessions
+            .remove_if(nick, |_, parked| parked.account == account)?;
+        Some(parked.client_id)
+    }
+
     pub async fn handle_quit(&self, client_id: ClientId, reason: Option<String>) {
         let quit_reason = reason.unwrap_or_else(|| "Client Quit".to_string());
 
         if let Some((_, user_state)) = self.users.remove(&client_id) {
             let caracs = user_state.get_caracs().await;
-            let quit_msg = format!(
-                ":{}!{}@{:?} QUIT :{}",
-                caracs.nick.unwrap(),
-                caracs.user.unwrap(),
-                caracs.addr,
-                quit_reason
-            );
-            let quit_channel_message = DirectIrcMessage::new(quit_msg);
-            self.broadcast_to_neighbors(&caracs.member_of, quit_channel_message, Some(client_id))
+            if let Some(nick) = &caracs.nick {
+                self.release_nick(nick, client_id);
+                if caracs.registered {
+                    // Nobody else picks the nick up while the user reconnects
+                    let hold_secs = self.config.read().await.get_resource_hold_secs();
+                    self.hold_resource(&nick.0, hold_secs);
+                }
+            }
+            // A client quitting mid-handshake may have a nick, or a user,
+            // but was never seen by anyone
+            if caracs.registered
+                && let (Some(nick), Some(user)) = (&caracs.nick, &caracs.user)
+            {
+                let mrep = MessageReply::Quit {
+                    nick_from: nick,
+                    user_from: user,
+                    host_from: &caracs.displayed_host(),
+                    reason: &quit_reason,
+                };
+                // Neighbours are deduplicated, one QUIT per peer however
+                // many channels they share
+                let quit_channel_message = IrcMessage::new(mrep.format());
+                self.broadcast_to_neighbors(
+                    &caracs.member_of,
+                    quit_channel_message,
+                    Some(client_id),
+                )
                 .await;
+            }
             for channel_name in caracs.member_of.iter() {
                 let channel_opt = self.channels.get(channel_name).map(|r| Arc::clone(&r));
                 if let Some(channel) = channel_opt {
                     channel.remove_member(&client_id);
                     if channel.members.is_empty() {
                         info!("Channel {channel_name} is empty, destroying.");
-                        self.channels.remove(channel_name);
+                        self.remove_channel(channel_name);
                     }
                 }
             }
@@ -211,7 +828,7 @@ impl ServerState {
     pub async fn broadcast_to_neighbors(
         &self,
         channel_names: &HashSet<ChannelName>,
-        message: DirectIrcMessage,
+        message: IrcMessage,
         exclude_id: Option<ClientId>, // Usually the person changing NICK
     ) {
         let unique_neighbors = self.get_unique_neighboors(channel_names, exclude_id).await;
@@ -224,8 +841,127 @@ impl ServerState {
     }
 }
 
+impl ServerState {
+    /// `broadcast_to_neighbors`, limited to the neighbors who negotiated
+    /// `capability`.
+    pub async fn broadcast_to_capable_neighbors(
+        &self,
+        channel_names: &HashSet<ChannelName>,
+        capability: &str,
+        message: IrcMessage,
+        exclude_id: Option<ClientId>,
+    ) {
+        let unique_neighbors = self.get_unique_neighboors(channel_names, exclude_id).await;
+        for client_id in unique_neighbors {
+            let user_opt = self.users.get(&client_id).map(|r| r.clone());
+            if let Some(user_state) = user_opt
+                && user_state.has_capability(capability).await
+            {
+                let _ = user_state.tx_outbound.send(message.clone()).await;
+            }
+        }
+    }
+}
+
 impl Default for ServerState {
     fn default() -> Self {
-        Self::new()
+        Self::new(Config::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestClient;
+
+    #[tokio::test]
+    async fn test_connect_rate_is_limited_under_the_concurrent_cap() {
+        let server_state = ServerState::default();
+        {
+            let mut config = server_state.config.write().await;
+            config.limits.max_connections_per_ip = 5;
+            config.limits.ip_connects_per_window = Some(3);
+        }
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+
+        // Connect and disconnect: never more than one open at once
+        for _ in 0..3 {
+            let slot = server_state.admit_connection(ip).await;
+            assert!(slot.is_some());
+        }
+        assert!(!server_state.ip_counts.contains_key(&ip));
+        assert!(server_state.admit_connection(ip).await.is_none());
+
+        // Other addresses have their own window
+        let other: IpAddr = "192.0.2.8".parse().unwrap();
+        assert!(server_state.admit_connection(other).await.is_some());
+
+        // Past the window the IP may connect again
+        server_state.config.write().await.limits.ip_connect_window = Some(0);
+        let slots = (0..5)
+            .map(|_| server_state.admit_connection(ip))
+            .collect::<Vec<_>>();
+        let mut admitted = Vec::new();
+        for slot in slots {
+            admitted.push(slot.await.expect("under both limits"));
+        }
+        assert_eq!(*server_state.ip_counts.get(&ip).unwrap(), 5);
+        assert!(server_state.admit_connection(ip).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refused_and_stale_addresses_are_forgotten() {
+        let server_state = ServerState::default();
+        server_state
+            .config
+            .write()
+            .await
+            .limits
+            .max_connections_per_ip = 0;
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+        assert!(server_state.admit_connection(ip).await.is_none());
+        assert!(!server_state.ip_counts.contains_key(&ip));
+        assert!(!server_state.ip_connects.contains_key(&ip));
+
+        {
+            let mut config = server_state.config.write().await;
+            config.limits.max_connections_per_ip = 5;
+            config.limits.ip_connect_window = Some(0);
+        }
+        let other: IpAddr = "192.0.2.8".parse().unwrap();
+        drop(server_state.admit_connection(ip).await);
+        drop(server_state.admit_connection(other).await);
+        // The next connect sweeps the address that connected before it
+        assert!(!server_state.ip_connects.contains_key(&ip));
+        assert!(server_state.ip_connects.contains_key(&other));
+    }
+
+    #[tokio::test]
+    async fn test_member_roles_of_an_op_a_voiced_user_and_a_member() {
+        let server_state = ServerState::default();
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+        let mut bob = TestClient::registered(&server_state, "bob").await;
+        let mut carol = TestClient::registered(&server_state, "carol").await;
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client.send(&server_state, "JOIN #roles").await.unwrap();
+        }
+        alice
+            .send(&server_state, "MODE #roles +v bob")
+            .await
+            .unwrap();
+
+        let channel = server_state
+            .get_channel(&ChannelName("#roles".to_owned()))
+            .unwrap();
+        let mut roles = server_state.member_roles(&channel).await;
+        roles.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+        assert_eq!(
+            roles,
+            vec![
+                (Nickname("alice".to_owned()), Role::Operator),
+                (Nickname("bob".to_owned()), Role::Voiced),
+                (Nickname("carol".to_owned()), Role::Member),
+            ]
+        );
     }
 }
@@ -0,0 +1,76 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use log::{info, warn};
+use tokio::time::{Instant, sleep};
+
+use crate::server_state::ServerState;
+
+/// Sent in the closing ERROR, and as the QUIT reason, of every user.
+pub const SHUTDOWN_REASON: &str = "Server shutting down";
+// How often the drain loop looks at `users`
+const DRAIN_POLL: Duration = Duration::from_millis(50);
+
+/// Sends every user an ERROR, then waits up to `server.shutdown_drain_secs`
+/// for their writers to flush it. Writers remove their user once done, so
+/// whoever is left in `users` is stuck behind a client that doesn't read;
+/// their tasks die with the runtime. Returns how many were left.
+pub async fn shutdown(server_state: &ServerState) -> usize {
+    server_state.shutting_down.store(true, Ordering::Relaxed);
+    let drain = server_state.config.read().await.get_shutdown_drain();
+    let deadline = Instant::now() + drain;
+    info!(
+        "Shutting down, draining {} connections",
+        server_state.users.len()
+    );
+    for user in server_state.users.iter() {
+        let user_state = user.value().clone();
+        // A full outbound queue would block the send, each waits on its own
+        tokio::spawn(async move {
+            user_state.send_error_and_close(SHUTDOWN_REASON).await;
+        });
+    }
+    while !server_state.users.is_empty() && Instant::now() < deadline {
+        sleep(DRAIN_POLL).await;
+    }
+    let remaining = server_state.users.len();
+    if remaining > 0 {
+        warn!("Shutdown: {remaining} connections still open after {drain:?}, aborting them");
+    } else {
+        info!("Shutdown: every connection was flushed");
+    }
+    remaining
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::shutdown;
+    use crate::{server_state::ServerState, test_utils::TestClient};
+
+    #[tokio::test]
+    async fn test_shutdown_gives_up_on_clients_that_never_read() {
+        let server_state = ServerState::default();
+        server_state.config.write().await.server.shutdown_drain_secs = Some(1);
+        // A test client has no writer, so it never flushes anything
+        let mut alice = TestClient::registered(&server_state, "alice").await;
+
+        let started = Instant::now();
+        assert_eq!(shutdown(&server_state).await, 1);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(
+            alice.drain(),
+            vec!["ERROR :Closing Link: alice (Server shutting down)"]
+        );
+        // Nobody gets in while the server goes down
+        assert!(
+            server_state
+                .admit_connection("127.0.0.1".parse().unwrap())
+                .await
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,128 @@
+// Helpers to drive the request handlers in unit tests without a socket.
+// A `TestClient` plays the role of `handle_client` + `client_writer_task`:
+// it owns the receiving ends of the user's channels and collects every
+// line the server would have written to the connection.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{
+    channels_models::SubscriptionControl,
+    errors::InternalIrcError,
+    handlers::request::handle_request,
+    message_models::IrcMessage,
+    server_state::ServerState,
+    types::{ChannelName, ClientId},
+    user_state::{UserState, UserStatus},
+};
+
+// Large enough that a handler never blocks on a test that hasn't drained yet
+const TEST_CHANNEL_SIZE: usize = 1024;
+
+pub struct TestClient {
+    pub client_id: ClientId,
+    pub user_state: UserState,
+    rx_outbound: mpsc::Receiver<IrcMessage>,
+    rx_control: mpsc::Receiver<SubscriptionControl>,
+    _rx_status: mpsc::Receiver<UserStatus>,
+    subscriptions: HashMap<ChannelName, broadcast::Receiver<IrcMessage>>,
+}
+
+impl TestClient {
+    /// Connects a new, unregistered client to `server_state`.
+    pub async fn connect(server_state: &ServerState) -> Self {
+        Self::connect_from(server_state, "127.0.0.1:50000".parse().unwrap()).await
+    }
+
+    /// Connects a new, unregistered client from a given address.
+    pub async fn connect_from(server_state: &ServerState, addr: SocketAddr) -> Self {
+        let (tx_outbound, rx_outbound) = mpsc::channel(TEST_CHANNEL_SIZE);
+        let (tx_control, rx_control) = mpsc::channel(TEST_CHANNEL_SIZE);
+        let (tx_status, rx_status) = mpsc::channel(TEST_CHANNEL_SIZE);
+        let user_state = UserState::new(addr, tx_outbound, tx_control, tx_status);
+        let client_id = server_state
+            .add_connecting_user(&user_state)
+            .await
+            .expect("add_connecting_user");
+        TestClient {
+            client_id,
+            user_state,
+            rx_outbound,
+            rx_control,
+            _rx_status: rx_status,
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    /// Connects and registers a client with NICK/USER, discarding the welcome burst.
+    pub async fn registered(server_state: &ServerState, nick: &str) -> Self {
+        let mut client = Self::connect(server_state).await;
+        client.register(server_state, nick).await;
+        client
+    }
+
+    pub async fn register(&mut self, server_state: &ServerState, nick: &str) {
+        self.send(server_state, &format!("NICK {nick}"))
+            .await
+            .expect("NICK");
+        self.send(server_state, &format!("USER {nick} 0 * :{nick}"))
+            .await
+            .expect("USER");
+        self.drain();
+    }
+
+    pub async fn send(
+        &mut self,
+        server_state: &ServerState,
+        line: &str,
+    ) -> Result<UserStatus, InternalIrcError> {
+        handle_request(line, self.client_id, server_state, &self.user_state).await
+    }
+
+    /// Collects every line sent to this client so far (direct replies first,
+    /// then channel broadcasts), without the trailing CRLF.
+    pub fn drain(&mut self) -> Vec<String> {
+        while let Ok(control) = self.rx_control.try_recv() {
+            match control {
+                SubscriptionControl::Subscribe {
+                    channel_name,
+                    receiver,
+                } => {
+                    self.subscriptions.insert(channel_name, receiver);
+                }
+                SubscriptionControl::Unsubscribe(channel_name) => {
+                    self.subscriptions.remove(&channel_name);
+                }
+            }
+        }
+        let mut lines = Vec::new();
+        while let Ok(msg) = self.rx_outbound.try_recv() {
+            lines.push(msg.raw_line.trim_end().to_owned());
+        }
+        let capabilities = self
+            .user_state
+            .user
+            .try_read()
+            .map(|user| user.capabilities.clone())
+            .unwrap_or_default();
+        for receiver in self.subscriptions.values_mut() {
+            while let Ok(msg) = receiver.try_recv() {
+                if msg.is_for(self.client_id) {
+                    lines.push(msg.line_for(&capabilities).trim_end().to_owned());
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// Returns the numeric of a server reply line (`:server 407 nick ...` -> `Some("407")`).
+pub fn numeric(line: &str) -> Option<&str> {
+    line.split(' ').nth(1)
+}
+
+pub fn has_numeric(lines: &[String], expected: &str) -> bool {
+    lines.iter().any(|l| numeric(l) == Some(expected))
+}
@@ -22,6 +22,21 @@ pub enum MessageTo {
     UserHost((Username, Host)),
     NickUserHost((Nickname, Username, Host)),
 }
+impl Display for MessageTo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageTo::ChannelName(channel) => write!(f, "{channel}"),
+            MessageTo::Nickname(nick) => write!(f, "{nick}"),
+            MessageTo::TargetMask(mask) => write!(f, "{mask}"),
+            MessageTo::UserHostServer((user, Some(host), server)) => {
+                write!(f, "{user}%{host}@{server}")
+            }
+            MessageTo::UserHostServer((user, None, server)) => write!(f, "{user}@{server}"),
+            MessageTo::UserHost((user, host)) => write!(f, "{user}%{host}"),
+            MessageTo::NickUserHost((nick, user, host)) => write!(f, "{nick}!{user}@{host}"),
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct TargetMask(pub String);
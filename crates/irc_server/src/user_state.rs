@@ -1,12 +1,19 @@
 use crate::channels_models::SubscriptionControl;
-use crate::replies::IrcReply;
+use crate::ident::IdentStatus;
+use crate::replies::{IrcReply, MessageReply};
 use crate::types::{ChannelName, ClientId, Nickname, Realname, Username};
-use crate::{errors::InternalIrcError, message_models::DirectIrcMessage};
+use crate::utils::{unix_timestamp, unix_timestamp_millis};
+use crate::{errors::InternalIrcError, message_models::IrcMessage};
 use core::net::SocketAddr;
 use dashmap::DashSet;
+use log::{info, warn};
 use std::{
-    collections::HashSet,
-    sync::{Arc, atomic::AtomicBool},
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64},
+    },
+    time::Duration,
 };
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::Sender;
@@ -33,6 +40,17 @@ pub enum UserStatus {
     Leaving(Option<String>),
 }
 
+impl UserStatus {
+    /// The connection lifecycle: Handshaking -> Active -> Leaving. Leaving
+    /// is final and a registered user never goes back to handshaking.
+    pub fn can_transition_to(&self, next: &UserStatus) -> bool {
+        !matches!(
+            (self, next),
+            (UserStatus::Leaving(_), _) | (UserStatus::Active, UserStatus::Handshaking)
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct User {
     pub user_id: ClientId,
@@ -43,6 +61,26 @@ pub struct User {
     pub registered: AtomicBool,
     pub addr: SocketAddr,
     pub member_of: DashSet<ChannelName>,
+    // IRCv3 capabilities acknowledged through CAP REQ
+    pub capabilities: HashSet<String>,
+    // RFC 1413 lookup done on connect, decides the displayed username
+    pub ident: IdentStatus,
+    // When the last accepted nick changes happened, for the per-minute cap
+    pub nick_changes: VecDeque<u64>,
+    // AWAY message, None while present
+    pub away: Option<String>,
+    // When this user was last sent RPL_AWAY for each target
+    pub away_replies: HashMap<ClientId, u64>,
+    // Where the connection is in its lifecycle, see `transition_status`
+    pub status: UserStatus,
+    // Flood token bucket (tokens left, last refill in ms), None while full
+    pub flood_bucket: Option<(f64, u64)>,
+    // Shown instead of the IP when `security.cloak_key` is set
+    pub cloaked_host: Option<String>,
+    // Set by REGISTER or IDENTIFY
+    pub account: Option<String>,
+    // Given with PASS before registering, proves the account of a parked nick
+    pub password: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -55,9 +93,68 @@ pub struct UserSnapshot {
     pub registered: bool,
     pub addr: SocketAddr,
     pub member_of: HashSet<ChannelName>,
+    pub capabilities: HashSet<String>,
+    pub away: Option<String>,
+    pub cloaked_host: Option<String>,
+    pub account: Option<String>,
+}
+
+impl UserSnapshot {
+    /// The host shown in `nick!user@host` prefixes and host replies: the
+    /// cloaked host if any, else the bare IP, never the client's source port.
+    pub fn displayed_host(&self) -> String {
+        self.cloaked_host
+            .clone()
+            .unwrap_or_else(|| self.real_host())
+    }
+
+    /// The bare IP, only ever shown to operators (RPL_WHOISACTUALLY).
+    pub fn real_host(&self) -> String {
+        self.addr.ip().to_string()
+    }
+
+    /// The AWAY message, None while present.
+    pub fn away_message(&self) -> Option<&str> {
+        self.away.as_deref()
+    }
+
+    /// Whether the user is AWAY, behind the 'a' mode and the WHO `G` flag.
+    pub fn is_away(&self) -> bool {
+        self.away_message().is_some()
+    }
+
+    /// The user modes as sent in RPL_UMODEIS, e.g. `+iw`. The 'a' mode
+    /// isn't stored, it follows the AWAY message.
+    pub fn mode_string(&self) -> String {
+        let away = self.is_away().then_some('a');
+        let mut modes = self.modes.iter().copied().chain(away).collect::<Vec<_>>();
+        modes.sort_unstable();
+        std::iter::once('+').chain(modes).collect()
+    }
+
+    /// The RPL_WHOREPLY flags: `H` (here) or `G` (gone, i.e. AWAY), then
+    /// `*` for an IRC operator, then the `@`/`+` channel prefix, e.g. `G*@`.
+    pub fn who_flags(&self, channel_prefix: &str) -> String {
+        let presence = if self.is_away() { 'G' } else { 'H' };
+        let operator = if self.modes.contains(&'o') { "*" } else { "" };
+        format!("{presence}{operator}{channel_prefix}")
+    }
+
+    /// Invisible (+i) users only show up in WHO/NAMES/WHOIS for themselves
+    /// and for users sharing at least one channel with them.
+    pub fn is_visible_to(&self, requester: &UserSnapshot) -> bool {
+        !self.modes.contains(&'i')
+            || self.user_id == requester.user_id
+            || !self.member_of.is_disjoint(&requester.member_of)
+    }
 }
 
 impl User {
+    /// Whether the user is AWAY, see `UserSnapshot::is_away`.
+    pub fn is_away(&self) -> bool {
+        self.away.is_some()
+    }
+
     pub fn new(addr: SocketAddr) -> Self {
         Self {
             user_id: get_next_user_id(),
@@ -68,21 +165,89 @@ impl User {
             registered: AtomicBool::new(false),
             addr,
             member_of: DashSet::new(),
+            capabilities: HashSet::new(),
+            ident: IdentStatus::NotChecked,
+            nick_changes: VecDeque::new(),
+            away: None,
+            away_replies: HashMap::new(),
+            status: UserStatus::Handshaking,
+            flood_bucket: None,
+            cloaked_host: None,
+            account: None,
+            password: None,
         }
     }
 }
 
+/// Per-connection traffic counters for STATS l, updated by the reader
+/// and writer tasks without taking the user lock.
+#[derive(Debug)]
+pub struct ConnectionStats {
+    pub connected_at: u64,
+    // Last PRIVMSG sent, for RPL_WHOISIDLE
+    pub last_message_at: AtomicU64,
+    // Last line received in milliseconds, the writer pings a client silent for too long
+    pub last_received_at: AtomicU64,
+    pub sent_messages: AtomicU64,
+    pub sent_bytes: AtomicU64,
+    pub received_messages: AtomicU64,
+    pub received_bytes: AtomicU64,
+}
+impl ConnectionStats {
+    pub(crate) fn new() -> Self {
+        ConnectionStats {
+            connected_at: unix_timestamp(),
+            last_message_at: AtomicU64::new(unix_timestamp()),
+            last_received_at: AtomicU64::new(unix_timestamp_millis()),
+            sent_messages: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            received_messages: AtomicU64::new(0),
+            received_bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_sent(&self, bytes: usize) {
+        self.sent_messages.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_message(&self) {
+        self.last_message_at
+            .store(unix_timestamp(), Ordering::Relaxed);
+    }
+
+    /// Seconds since the last PRIVMSG, or since signon.
+    pub fn idle_secs(&self) -> u64 {
+        unix_timestamp().saturating_sub(self.last_message_at.load(Ordering::Relaxed))
+    }
+
+    /// Time since the client last sent anything, or since it connected.
+    pub fn silent_for(&self) -> Duration {
+        let last = self.last_received_at.load(Ordering::Relaxed);
+        Duration::from_millis(unix_timestamp_millis().saturating_sub(last))
+    }
+
+    pub fn record_received(&self, bytes: usize) {
+        self.last_received_at
+            .store(unix_timestamp_millis(), Ordering::Relaxed);
+        self.received_messages.fetch_add(1, Ordering::Relaxed);
+        self.received_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UserState {
     pub user: Arc<RwLock<User>>,
-    pub tx_outbound: Sender<DirectIrcMessage>,
+    pub tx_outbound: Sender<IrcMessage>,
     pub tx_control: Sender<SubscriptionControl>,
     pub tx_status: Sender<UserStatus>,
+    pub stats: Arc<ConnectionStats>,
 }
 impl UserState {
     pub fn new(
         addr: SocketAddr,
-        tx_outbound: Sender<DirectIrcMessage>,
+        tx_outbound: Sender<IrcMessage>,
         tx_control: Sender<SubscriptionControl>,
         tx_status: Sender<UserStatus>,
     ) -> Self {
@@ -91,6 +256,7 @@ impl UserState {
             tx_outbound,
             tx_control,
             tx_status,
+            stats: Arc::new(ConnectionStats::new()),
         }
     }
 
@@ -101,35 +267,134 @@ impl UserState {
         old_nick
     }
 
-    pub async fn with_user(&self, user: Username, real_name: Realname, mode: u8) {
+    /// Moves the connection to the status a handler returned and logs the
+    /// change. Handlers answer Active for "carry on" even before
+    /// registration, which stays Handshaking; an illegal transition is
+    /// logged and the current status kept.
+    pub async fn transition_status(&self, next: UserStatus) -> UserStatus {
         let mut user_data = self.user.write().await;
-        user_data.user = Some(user);
-        user_data.real_name = Some(real_name);
-        user_data.modes = UserState::parse_basic_user_mode(mode);
+        let next = match next {
+            UserStatus::Active if !user_data.registered.load(Ordering::Acquire) => {
+                UserStatus::Handshaking
+            }
+            next => next,
+        };
+        if !user_data.status.can_transition_to(&next) {
+            warn!(
+                "[{}] illegal status transition {:?} -> {next:?}",
+                user_data.user_id, user_data.status
+            );
+            return user_data.status.clone();
+        }
+        if user_data.status != next {
+            info!(
+                "[{}] status {:?} -> {next:?}",
+                user_data.user_id, user_data.status
+            );
+            user_data.status = next.clone();
+        }
+        next
     }
 
-    pub async fn is_registered(&self) -> bool {
-        // first check under read lock
-        // 🚀 fast path: atomic read
-        if self.user.read().await.registered.load(Ordering::Acquire) {
-            return true;
-        }
+    /// Sends `ERROR :Closing Link: <nick> (<reason>)` and has the writer
+    /// flush it before closing the connection. Every disconnection goes
+    /// through here, so the client always learns why.
+    pub async fn send_error_and_close(&self, reason: &str) {
+        let nick = self.user.read().await.nick.clone();
+        let nick = nick.unwrap_or(Nickname("*".to_owned()));
+        let mrep = MessageReply::Error {
+            nick: &nick,
+            reason,
+        };
+        let _ = self.tx_outbound.send(IrcMessage::new(mrep.format())).await;
+        let _ = self
+            .tx_status
+            .send(UserStatus::Leaving(Some(reason.to_owned())))
+            .await;
+    }
 
-        // slow path: need to check nick/user and maybe register
-        let user_data = self.user.write().await;
+    /// Records a nick change unless `per_min` of them already happened in
+    /// the last 60 seconds.
+    pub async fn try_nick_change(&self, per_min: usize) -> bool {
+        let now = unix_timestamp();
+        let mut user_data = self.user.write().await;
+        while user_data
+            .nick_changes
+            .front()
+            .is_some_and(|&at| now.saturating_sub(at) >= 60)
+        {
+            user_data.nick_changes.pop_front();
+        }
+        if user_data.nick_changes.len() >= per_min {
+            return false;
+        }
+        user_data.nick_changes.push_back(now);
+        true
+    }
 
-        // double-check under write lock
-        if user_data.registered.load(Ordering::Relaxed) {
-            return true;
+    /// Takes one token from a bucket of `burst` tokens refilled at
+    /// `per_sec` a second. False, and nothing taken, once it is empty.
+    pub async fn try_flood_token(&self, burst: usize, per_sec: u64) -> bool {
+        let now = unix_timestamp_millis();
+        let mut user_data = self.user.write().await;
+        let burst = burst as f64;
+        let (tokens, refilled_at) = user_data.flood_bucket.unwrap_or((burst, now));
+        let elapsed = now.saturating_sub(refilled_at) as f64 / 1000.0;
+        let tokens = (tokens + elapsed * per_sec as f64).min(burst);
+        if tokens < 1.0 {
+            user_data.flood_bucket = Some((tokens, now));
+            return false;
         }
+        user_data.flood_bucket = Some((tokens - 1.0, now));
+        true
+    }
+
+    /// Sets or, with `None`, clears the AWAY message, and so the 'a' mode.
+    pub async fn with_away(&self, message: Option<String>) {
+        self.user.write().await.away = message;
+    }
 
-        if user_data.nick.is_none() || user_data.user.is_none() {
+    /// Whether RPL_AWAY about `target` may be sent to this user again, at
+    /// most once every `interval` seconds. Records the reply when it may.
+    pub async fn try_away_reply(&self, target: ClientId, interval: u64) -> bool {
+        let now = unix_timestamp();
+        let mut user_data = self.user.write().await;
+        // Forget targets whose interval is over, the map stays small
+        user_data
+            .away_replies
+            .retain(|_, &mut at| now.saturating_sub(at) < interval);
+        if user_data.away_replies.contains_key(&target) {
             return false;
         }
+        user_data.away_replies.insert(target, now);
+        true
+    }
 
-        // 👇 first and only registration
-        user_data.registered.store(true, Ordering::Release);
+    pub async fn with_user(&self, user: Username, real_name: Realname, mode: u8) {
+        let mut user_data = self.user.write().await;
+        user_data.user = Some(user_data.ident.displayed_username(user));
+        user_data.real_name = Some(real_name);
+        // Modes given on connect, such as +r, are kept
+        let modes = UserState::parse_basic_user_mode(mode);
+        user_data.modes.extend(modes);
+    }
+
+    pub async fn is_registered(&self) -> bool {
+        self.user.read().await.registered.load(Ordering::Acquire)
+    }
 
+    /// Marks the user registered once both NICK and USER were given. True
+    /// only for the call that did it, so the welcome burst is sent once
+    /// whichever of NICK and USER comes last.
+    pub async fn complete_registration(&self) -> bool {
+        let user_data = self.user.write().await;
+        if user_data.registered.load(Ordering::Relaxed)
+            || user_data.nick.is_none()
+            || user_data.user.is_none()
+        {
+            return false;
+        }
+        user_data.registered.store(true, Ordering::Release);
         true
     }
 
@@ -161,6 +426,10 @@ impl UserState {
             registered: user_data.registered.load(Ordering::Acquire),
             addr: user_data.addr,
             member_of,
+            capabilities: user_data.capabilities.clone(),
+            away: user_data.away.clone(),
+            cloaked_host: user_data.cloaked_host.clone(),
+            account: user_data.account.clone(),
         }
     }
 
@@ -182,7 +451,7 @@ impl UserState {
             .iter()
             .all(|(f, ms)| (*f == '-' || *f == '+') && ms.iter().all(|m| KNOWN_MODES.contains(m)));
         if !modes_are_valid {
-            return Ok(Some(IrcReply::ErrUModeUnknownFlag { nick: nick }));
+            return Ok(Some(IrcReply::ErrUModeUnknownFlag { nick }));
         }
         let mut user_data = self.user.write().await;
         if !user_data.registered.load(Ordering::Acquire) {
@@ -190,12 +459,13 @@ impl UserState {
                 "Cannot change of an unregistered user",
             ))
         } else if user_data.nick != Some(nick.clone()) {
-            Ok(Some(IrcReply::ErrUsersDontMatch { nick: nick }))
+            Ok(Some(IrcReply::ErrUsersDontMatch { nick }))
         } else {
             let current_flags = user_data.modes.clone();
             let mut new_user_mode_flags: HashSet<char> = current_flags.clone();
             for (flag, inner_modes) in modes {
-                for mode in inner_modes {
+                // 'a' follows the AWAY message, MODE doesn't toggle it
+                for mode in inner_modes.into_iter().filter(|&mode| mode != 'a') {
                     match flag {
                         '+' => {
                             if !current_flags.contains(&mode) {
@@ -220,6 +490,10 @@ impl UserState {
         user_data.member_of.insert(channel_name.clone());
     }
 
+    pub async fn has_capability(&self, capability: &str) -> bool {
+        self.user.read().await.capabilities.contains(capability)
+    }
+
     pub async fn leave_channel(&self, channel_name: &ChannelName) {
         let user_data = self.user.write().await;
         let _ = user_data.member_of.remove(channel_name);
@@ -227,3 +501,70 @@ impl UserState {
 
     // pub async fn send
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{server_state::ServerState, test_utils::TestClient};
+
+    #[test]
+    fn test_parse_basic_user_mode() {
+        let flags = |mode| {
+            let mut flags = UserState::parse_basic_user_mode(mode)
+                .into_iter()
+                .collect::<Vec<char>>();
+            flags.sort();
+            flags
+        };
+        assert_eq!(flags(0), Vec::<char>::new());
+        assert_eq!(flags(4), vec!['w']);
+        assert_eq!(flags(8), vec!['i']);
+        assert_eq!(flags(12), vec!['i', 'w']);
+        // bits other than 2 and 3 carry no meaning
+        assert_eq!(flags(3), Vec::<char>::new());
+    }
+
+    #[tokio::test]
+    async fn test_user_command_sets_invisible() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+        client.send(&server_state, "NICK guest").await.unwrap();
+        client
+            .send(&server_state, "USER guest 8 * :Ronnie Reagan")
+            .await
+            .unwrap();
+        let caracs = client.user_state.get_caracs().await;
+        assert!(caracs.registered);
+        assert_eq!(caracs.modes, HashSet::from(['i']));
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        let leaving = UserStatus::Leaving(None);
+        assert!(UserStatus::Handshaking.can_transition_to(&UserStatus::Active));
+        assert!(UserStatus::Handshaking.can_transition_to(&leaving));
+        assert!(UserStatus::Active.can_transition_to(&leaving));
+        assert!(!UserStatus::Active.can_transition_to(&UserStatus::Handshaking));
+        assert!(!leaving.can_transition_to(&UserStatus::Active));
+        assert!(!leaving.can_transition_to(&UserStatus::Handshaking));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_transitions_keep_status() {
+        let server_state = ServerState::default();
+        let mut client = TestClient::connect(&server_state).await;
+        // Unregistered clients stay handshaking whatever a handler says
+        let status = client.send(&server_state, "PING abc").await.unwrap();
+        assert_eq!(status, UserStatus::Handshaking);
+
+        let client = TestClient::registered(&server_state, "alice").await;
+        let user_state = &client.user_state;
+        assert_eq!(user_state.user.read().await.status, UserStatus::Active);
+        let status = user_state.transition_status(UserStatus::Handshaking).await;
+        assert_eq!(status, UserStatus::Active);
+        let leaving = UserStatus::Leaving(Some("bye".to_owned()));
+        assert_eq!(user_state.transition_status(leaving.clone()).await, leaving);
+        let status = user_state.transition_status(UserStatus::Active).await;
+        assert_eq!(status, leaving);
+    }
+}
@@ -0,0 +1,336 @@
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crypto::{hmac_sha256, to_hex};
+
+/// Seconds since the Unix epoch, used for every server-side timestamp.
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Milliseconds since the Unix epoch, the precision of IRCv3 server-time.
+pub fn unix_timestamp_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Days <-> civil date conversions, from Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Formats a millisecond timestamp as an IRCv3 server-time,
+/// e.g. `2019-01-04T14:33:26.123Z`.
+pub fn format_server_time(millis: u64) -> String {
+    let secs = (millis / 1000) as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let secs_of_day = secs.rem_euclid(86_400);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        millis % 1000
+    )
+}
+
+/// Formats a timestamp in seconds the way ircds date things for humans,
+/// e.g. `Fri Jan 4 2019 at 14:33:26 UTC`.
+pub fn format_date(secs: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = secs as i64;
+    let days = secs.div_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    let secs_of_day = secs.rem_euclid(86_400);
+    // 1970-01-01 was a Thursday
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+    format!(
+        "{weekday} {} {day} {year} at {:02}:{:02}:{:02} UTC",
+        MONTHS[month as usize - 1],
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Parses an IRCv3 server-time back into milliseconds since the epoch.
+pub fn parse_server_time(time: &str) -> Option<u64> {
+    let (date, clock) = time.strip_suffix('Z')?.split_once('T')?;
+    let mut date_parts = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (
+        date_parts.next()?.ok()?,
+        date_parts.next()?.ok()?,
+        date_parts.next()?.ok()?,
+    );
+    let (clock, millis) = match clock.split_once('.') {
+        Some((clock, millis)) => (clock, millis.parse::<u64>().ok()?),
+        None => (clock, 0),
+    };
+    let mut clock_parts = clock.splitn(3, ':').map(str::parse::<i64>);
+    let (hours, minutes, seconds) = (
+        clock_parts.next()?.ok()?,
+        clock_parts.next()?.ok()?,
+        clock_parts.next()?.ok()?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || millis > 999 {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    u64::try_from(secs).ok().map(|secs| secs * 1000 + millis)
+}
+
+// RFC 2811 3.2.2 Channel Identifier
+//    The current time [...] is converted in a string of five (5)
+//    characters using the following base:
+//    "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789" (each character has a decimal
+//    value starting from 0 for 'A' to 35 for '0').
+/// The 5-character safe channel identifier for `time` (in seconds).
+pub fn safe_channel_id(time: u64) -> String {
+    const BASE: &[u8; 36] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    let mut value = time % 36u64.pow(5);
+    let mut id = [b'A'; 5];
+    for slot in id.iter_mut().rev() {
+        *slot = BASE[(value % 36) as usize];
+        value /= 36;
+    }
+    String::from_utf8_lossy(&id).into_owned()
+}
+
+/// Whether `name` is a full safe channel name, `!` followed by a
+/// channelid and a short name.
+pub fn is_safe_channel_id(name: &str) -> bool {
+    name.len() > 6
+        && name.starts_with('!')
+        && name[1..6]
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+// 3.3.1 Private messages [...] Wildcards are the  '*' and '?'  characters.
+/// Case-insensitive IRC mask matching: `*` matches any run of characters
+/// (including none) and `?` matches exactly one character.
+/// Whether a `<target>` server parameter (a name or a mask) designates
+/// this server
+pub fn is_local_server(target: &str) -> bool {
+    let server_name = crate::constants::SERVER_NAME
+        .get()
+        .map(|s| s.as_str())
+        .unwrap_or("unknown.server");
+    wildcard_match(target, server_name)
+}
+
+pub fn wildcard_match(mask: &str, text: &str) -> bool {
+    let mask: Vec<char> = mask.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut m, mut t) = (0, 0);
+    // position of the last '*' seen in the mask and the text index it matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if m < mask.len() && (mask[m] == '?' || mask[m] == text[t]) {
+            m += 1;
+            t += 1;
+        } else if m < mask.len() && mask[m] == '*' {
+            backtrack = Some((m, t));
+            m += 1;
+        } else if let Some((star_m, star_t)) = backtrack {
+            m = star_m + 1;
+            t = star_t + 1;
+            backtrack = Some((star_m, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+/// Whether `ip` falls in `range`, a CIDR block like `10.0.0.0/8` or
+/// `2001:db8::/32`, or a single address. A malformed range matches nothing.
+pub fn ip_in_range(ip: IpAddr, range: &str) -> bool {
+    let (network, prefix) = range.split_once('/').unwrap_or((range, "128"));
+    let (Ok(network), Ok(prefix)) = (network.parse::<IpAddr>(), prefix.parse::<u32>()) else {
+        return false;
+    };
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            (u128::from(ip.to_bits()), u128::from(network.to_bits()), 32)
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (ip.to_bits(), network.to_bits(), 128),
+        _ => return false,
+    };
+    let prefix = prefix.min(bits);
+    // Only the top `prefix` of the address's `bits` bits are compared
+    let shift = bits - prefix;
+    shift == bits || ip >> shift == network >> shift
+}
+
+/// The cloaked host shown instead of `ip`, e.g.
+/// `282d6e39b2366115786e0c4077542494.cloak`: 128 bits of HMAC-SHA256(key, ip).
+/// Stable for a given key, so bans on the cloak keep matching.
+pub fn cloak_ip(ip: IpAddr, key: &str) -> String {
+    let mac = hmac_sha256(key.as_bytes(), ip.to_string().as_bytes());
+    format!("{}.cloak", to_hex(&mac[..16]))
+}
+
+/// Completes a partial ban-style mask: `bob` -> `bob!*@*`,
+/// `*@evil.net` -> `*!*@evil.net`, `bob!x` -> `bob!x@*`.
+pub fn normalize_hostmask(mask: &str) -> String {
+    let (nick_user, host) = mask.split_once('@').unwrap_or((mask, "*"));
+    let (nick, user) = match nick_user.split_once('!') {
+        Some((nick, user)) => (nick, user),
+        None if mask.contains('@') => ("*", nick_user),
+        None => (nick_user, "*"),
+    };
+    let or_any = |part: &str| {
+        if part.is_empty() {
+            "*".to_owned()
+        } else {
+            part.to_owned()
+        }
+    };
+    format!("{}!{}@{}", or_any(nick), or_any(user), or_any(host))
+}
+
+/// Removes mIRC formatting: bold, italics, underline, strikethrough,
+/// monospace, reverse and reset codes, and colors along with their
+/// `fg[,bg]` digits (or hex values for `\x04`), as done for +c channels.
+pub fn strip_formatting(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let (is_digit, max_digits): (fn(&char) -> bool, usize) = match c {
+            '\x02' | '\x0f' | '\x11' | '\x16' | '\x1d' | '\x1e' | '\x1f' => continue,
+            '\x03' => (char::is_ascii_digit, 2),
+            '\x04' => (char::is_ascii_hexdigit, 6),
+            _ => {
+                stripped.push(c);
+                continue;
+            }
+        };
+        let skip_digits = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let mut count = 0;
+            while count < max_digits && chars.next_if(is_digit).is_some() {
+                count += 1;
+            }
+            count
+        };
+        // A comma only belongs to the color when a background follows it
+        if skip_digits(&mut chars) > 0 && chars.peek() == Some(&',') {
+            let mut lookahead = chars.clone();
+            lookahead.next();
+            if lookahead.peek().is_some_and(is_digit) {
+                chars.next();
+                skip_digits(&mut chars);
+            }
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("#rust", "#rust"));
+        assert!(wildcard_match("#RUST", "#rust"));
+        assert!(wildcard_match("#r*", "#rust"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("#ru?t", "#rust"));
+        assert!(wildcard_match(
+            "*!*@*.example.com",
+            "nick!user@host.example.com"
+        ));
+        assert!(!wildcard_match("#r?", "#rust"));
+        assert!(!wildcard_match("#rust", "#rusty"));
+        assert!(!wildcard_match("*!*@evil.net", "nick!user@good.net"));
+    }
+
+    #[test]
+    fn test_cloak_is_a_stable_hmac() {
+        let ip = "192.0.2.7".parse().unwrap();
+        assert_eq!(
+            cloak_ip(ip, "s3cret"),
+            "282d6e39b2366115786e0c4077542494.cloak"
+        );
+        assert_ne!(cloak_ip(ip, "other"), cloak_ip(ip, "s3cret"));
+    }
+
+    #[test]
+    fn test_safe_channel_id() {
+        assert_eq!(safe_channel_id(0), "AAAAA");
+        assert_eq!(safe_channel_id(37), "AAABB");
+        assert_eq!(safe_channel_id(36u64.pow(5)), "AAAAA");
+        assert!(is_safe_channel_id("!ABC12rust"));
+        assert!(!is_safe_channel_id("!rust"));
+    }
+
+    #[test]
+    fn test_normalize_hostmask() {
+        assert_eq!(normalize_hostmask("bob"), "bob!*@*");
+        assert_eq!(normalize_hostmask("*@evil.net"), "*!*@evil.net");
+        assert_eq!(normalize_hostmask("bob!x"), "bob!x@*");
+        assert_eq!(normalize_hostmask("a!b@c"), "a!b@c");
+    }
+
+    #[test]
+    fn test_server_time_round_trip() {
+        assert_eq!(format_server_time(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_date(0), "Thu Jan 1 1970 at 00:00:00 UTC");
+        assert_eq!(format_date(1_546_612_406), "Fri Jan 4 2019 at 14:33:26 UTC");
+        assert_eq!(
+            format_server_time(1_546_612_406_123),
+            "2019-01-04T14:33:26.123Z"
+        );
+        assert_eq!(
+            parse_server_time("2019-01-04T14:33:26.123Z"),
+            Some(1_546_612_406_123)
+        );
+        assert_eq!(
+            parse_server_time("2024-02-29T23:59:59Z"),
+            Some(1_709_251_199_000)
+        );
+        assert_eq!(parse_server_time("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse_server_time("yesterday"), None);
+    }
+
+    #[test]
+    fn test_ip_in_range() {
+        let ip = "10.1.2.3".parse().unwrap();
+        assert!(ip_in_range(ip, "10.0.0.0/8"));
+        assert!(ip_in_range(ip, "10.1.2.3"));
+        assert!(ip_in_range(ip, "0.0.0.0/0"));
+        assert!(!ip_in_range(ip, "10.1.2.0/31"));
+        assert!(!ip_in_range(ip, "2001:db8::/32"));
+        assert!(!ip_in_range(ip, "not-a-range"));
+        assert!(ip_in_range("2001:db8::1".parse().unwrap(), "2001:db8::/32"));
+    }
+}
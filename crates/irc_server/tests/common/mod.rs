@@ -0,0 +1,121 @@
+//! End-to-end harness: a real server on a loopback socket, driven by
+//! clients that speak raw IRC lines.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use irc_server::handlers::client::handle_client;
+use irc_server::server_state::ServerState;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+// How long a client waits for a line before the test fails
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts a server on an ephemeral loopback port, accepting clients the
+/// way the `irc_server` binary does.
+pub async fn start_server(server_state: ServerState) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let state = server_state.clone();
+            tokio::spawn(async move { handle_client(socket, peer, &state).await });
+        }
+    });
+    addr
+}
+
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    pub async fn connect(addr: SocketAddr) -> Self {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        Client {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    /// Connects and registers with NICK/USER, up to the end of the MOTD.
+    pub async fn registered(addr: SocketAddr, nick: &str) -> Self {
+        let mut client = Self::connect(addr).await;
+        client.send(&format!("NICK {nick}")).await;
+        client.send(&format!("USER {nick} 0 * :{nick}")).await;
+        client.read_until(|line| is_numeric(line, "376")).await;
+        client
+    }
+
+    pub async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\r\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    /// The next line from the server, panicking after `READ_TIMEOUT`.
+    pub async fn read_line(&mut self) -> String {
+        timeout(READ_TIMEOUT, self.lines.next_line())
+            .await
+            .expect("timed out waiting for a line")
+            .unwrap()
+            .expect("connection closed")
+    }
+
+    /// Every line up to and including the first one matching `last`.
+    pub async fn read_until(&mut self, last: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line().await;
+            let done = last(&line);
+            lines.push(line);
+            if done {
+                return lines;
+            }
+        }
+    }
+
+    /// Reads until every line of `expected` was seen, in any order: direct
+    /// replies and channel broadcasts reach the socket through different
+    /// queues. Returns everything read.
+    pub async fn expect_lines(&mut self, expected: &[&str]) -> Vec<String> {
+        let mut lines = Vec::new();
+        while !expected.iter().all(|line| lines.iter().any(|l| l == line)) {
+            lines.push(self.read_line().await);
+        }
+        lines
+    }
+
+    /// Sends a PING and returns the direct replies that came before its
+    /// PONG, so the test knows the previous commands were handled.
+    pub async fn sync(&mut self) -> Vec<String> {
+        self.send("PING sync").await;
+        let mut lines = self.read_until(|line| line.ends_with(" PONG sync")).await;
+        lines.pop();
+        lines
+    }
+
+    /// Reads what is left until the server closes the connection.
+    pub async fn read_to_close(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Some(line) = timeout(READ_TIMEOUT, self.lines.next_line())
+            .await
+            .expect("timed out waiting for the connection to close")
+            .unwrap()
+        {
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+/// Whether `line` is the `code` numeric reply.
+pub fn is_numeric(line: &str, code: &str) -> bool {
+    line.split(' ').nth(1) == Some(code)
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{Client, is_numeric, start_server};
+use irc_server::server_state::ServerState;
+
+#[tokio::test]
+async fn test_register_join_and_message_round_trip() {
+    let addr = start_server(ServerState::default()).await;
+
+    let mut alice = Client::connect(addr).await;
+    alice.send("NICK alice").await;
+    alice.send("USER alice 0 * :Alice").await;
+    let burst = alice.read_until(|line| is_numeric(line, "376")).await;
+    assert_eq!(
+        burst[0],
+        ":unknown.server 001 alice :Welcome to the Internet Relay Network alice!alice@127.0.0.1"
+    );
+    assert!(burst[2].starts_with(":unknown.server 003 alice :This server was created "));
+
+    alice.send("JOIN #rust").await;
+    alice
+        .expect_lines(&[
+            ":alice!alice@127.0.0.1 JOIN :#rust",
+            ":unknown.server 331 alice #rust :No topic is set",
+            ":unknown.server 353 alice = #rust :@alice",
+        ])
+        .await;
+    // The rest of the NAMES reply
+    alice.sync().await;
+
+    let mut bob = Client::registered(addr, "bob").await;
+    bob.send("JOIN #rust").await;
+    bob.expect_lines(&[
+        ":bob!bob@127.0.0.1 JOIN :#rust",
+        ":unknown.server 331 bob #rust :No topic is set",
+    ])
+    .await;
+    bob.sync().await;
+    alice
+        .expect_lines(&[":bob!bob@127.0.0.1 JOIN :#rust"])
+        .await;
+
+    alice.send("PRIVMSG #rust :hello").await;
+    assert_eq!(
+        bob.read_line().await,
+        ":alice!alice@127.0.0.1 PRIVMSG #rust :hello"
+    );
+    // No echo-message negotiated, the sender doesn't get its line back
+    assert!(alice.sync().await.is_empty());
+
+    bob.send("PRIVMSG alice :hi there").await;
+    assert_eq!(
+        alice.read_line().await,
+        ":bob!bob@127.0.0.1 PRIVMSG alice :hi there"
+    );
+
+    bob.send("QUIT :bye").await;
+    assert_eq!(
+        bob.read_to_close().await,
+        vec!["ERROR :Closing Link: bob (Quit: bye)"]
+    );
+}